@@ -0,0 +1,100 @@
+/**
+ * Compares the recursive traversal of a BVH tree of every element with the stack traversal of the two levels of
+ * flattened BVH of the scene, on the primary rays of a scene and on shadow rays from their hits to the lights.
+ * Usage: cargo bench --bench bvh [scene files]
+ */
+use std::time::{Duration, Instant};
+use rt::{
+    bvh::{node::Node, traversal::recursive_traversal},
+    model::{maths::{hit::Hit, ray::Ray, vec3::Vec3}, scene::Scene, shapes::{aabb::Aabb, shape::Shape}},
+    parsing::get_scene,
+    render::raycasting::{get_closest_hit_from_elements_with_index, get_closest_hit_from_scene_bvh, get_ray}
+};
+
+const DEFAULT_SCENES: [&str; 2] = ["scenes/chessboard.json", "scenes/shape_museum.json"];
+const PIXEL_STEP: usize = 4;
+const ITERATIONS: usize = 3;
+
+fn recursive_closest_hit<'a>(scene: &'a Scene, ray: &Ray, root: &Node) -> Option<Hit<'a>> {
+    let closest = get_closest_hit_from_elements_with_index(scene, ray, None, scene.elements(), scene.non_bvh_elements());
+    let hit = recursive_traversal(ray, root, scene, scene.elements(), closest.clone(), root.aabb().intersect(ray).unwrap_or(vec![]), 1);
+    match (hit, closest) {
+        (Some(hit), Some(previous)) if !(*hit.dist() > 0. && (hit.dist() < previous.dist() || *previous.dist() < 0.)) => Some(previous),
+        (Some(hit), _) => Some(hit),
+        (None, closest) => closest,
+    }
+}
+
+fn time<F: FnMut() -> usize>(mut run: F) -> (Duration, usize) {
+    let mut best = Duration::MAX;
+    let mut result = 0;
+    for _ in 0..ITERATIONS {
+        let start = Instant::now();
+        result = run();
+        best = best.min(start.elapsed());
+    }
+    (best, result)
+}
+
+fn bench_scene(path: &str) {
+    let mut scene = match get_scene(&path.to_string()) {
+        Ok(scene) => scene,
+        Err(err) => {
+            println!("{}: {}", path, err);
+            return;
+        }
+    };
+    scene.update_bvh();
+    let aabbs = scene.all_aabb();
+    let mut root = Node::new(&Aabb::from_aabbs(&aabbs));
+    root.build_tree(scene.elements());
+
+    let rays: Vec<Ray> = (0..rt::SCREEN_HEIGHT).step_by(PIXEL_STEP)
+        .flat_map(|y| (0..rt::SCREEN_WIDTH).step_by(PIXEL_STEP).map(move |x| (x, y)))
+        .map(|(x, y)| get_ray(&scene, x, y))
+        .collect();
+
+    let (recursive_time, recursive_hits) = time(|| rays.iter().filter(|ray| recursive_closest_hit(&scene, ray, &root).is_some_and(|hit| *hit.dist() > 0.)).count());
+    let (flat_time, flat_hits) = time(|| rays.iter().filter(|ray| get_closest_hit_from_scene_bvh(&scene, ray).is_some_and(|hit| *hit.dist() > 0.)).count());
+
+    // Shadow rays from every hit to the point and spot lights, or to the camera without them
+    let mut targets: Vec<Vec3> = scene.lights().iter()
+        .filter_map(|light| light.light().as_point_light().map(|light| *light.pos())
+            .or_else(|| light.light().as_spot_light().map(|light| *light.pos())))
+        .collect();
+    if targets.is_empty() {
+        targets.push(*scene.camera().pos());
+    }
+    let shadow_rays: Vec<(Ray, f64)> = rays.iter()
+        .filter_map(|ray| get_closest_hit_from_scene_bvh(&scene, ray).filter(|hit| *hit.dist() > 0.))
+        .flat_map(|hit| {
+            let pos = hit.pos() + hit.norm() * 0.0001;
+            targets.iter().map(move |target| {
+                let to_target = target - pos;
+                (Ray::new(pos, to_target.normalize(), 0), to_target.length())
+            })
+        })
+        .collect();
+    let (closest_shadow_time, closest_occluded) = time(|| shadow_rays.iter()
+        .filter(|(ray, dist)| get_closest_hit_from_scene_bvh(&scene, ray).is_some_and(|hit| *hit.dist() > 0. && hit.dist() < dist))
+        .count());
+    let (occlusion_time, occlusion_occluded) = time(|| shadow_rays.iter()
+        .filter(|(ray, dist)| scene.transmittance(ray, *dist).length() <= f64::EPSILON)
+        .count());
+
+    println!("{}: {} elements, {} rays, {} shadow rays", path, scene.elements().len(), rays.len(), shadow_rays.len());
+    println!("  closest hit, recursive tree:  {:>10.2?} ({} hits)", recursive_time, recursive_hits);
+    println!("  closest hit, scene BVH:       {:>10.2?} ({} hits)", flat_time, flat_hits);
+    println!("  shadow rays, closest hit:     {:>10.2?} ({} occluded)", closest_shadow_time, closest_occluded);
+    println!("  shadow rays, occlusion query: {:>10.2?} ({} occluded)", occlusion_time, occlusion_occluded);
+}
+
+fn main() {
+    // cargo bench passes its own flags to the benchmarks
+    let scenes: Vec<String> = std::env::args().skip(1).filter(|arg| !arg.starts_with("--")).collect();
+    if scenes.is_empty() {
+        DEFAULT_SCENES.iter().for_each(|path| bench_scene(path));
+    } else {
+        scenes.iter().for_each(|path| bench_scene(path));
+    }
+}
@@ -0,0 +1,429 @@
+use super::node::Node;
+use crate::{
+    model::{
+        element::Element,
+        maths::{hit::Hit, ray::Ray, vec3::Vec3},
+        scene::Scene,
+        shapes::aabb::Aabb
+    },
+    render::{raycasting::get_closest_hit_from_elements_with_index, stats::{count, Counter}},
+    BVH_TRAVERSAL_COST
+};
+
+/**
+ * Node of a flattened BVH. The nodes are stored depth first, so the first child of an interior node is the node right
+ * after it, and only the index of the second one is needed.
+ */
+#[derive(Debug, Clone)]
+pub struct FlatNode {
+    min: [f64; 3],
+    max: [f64; 3],
+    // Index of the second child for an interior node, index of the first element in the indices for a leaf
+    offset: usize,
+    // 0 for an interior node
+    count: usize,
+}
+
+/**
+ * BVH laid out in contiguous arrays, built from the tree of `Node` and traversed with a stack.
+ * The parents of the nodes and the leaves of the elements are kept to refit the boxes when elements move.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct FlatBvh {
+    nodes: Vec<FlatNode>,
+    indices: Vec<usize>,
+    parents: Vec<Option<usize>>,
+    leaves: Vec<Option<usize>>,
+    build_area: f64,
+    build_cost: f64,
+}
+
+impl FlatNode {
+    // Accessors
+    pub fn aabb(&self) -> Aabb {
+        Aabb::new(self.min[0], self.max[0], self.min[1], self.max[1], self.min[2], self.max[2])
+    }
+    pub fn is_leaf(&self) -> bool {
+        self.count > 0
+    }
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+    pub fn count(&self) -> usize {
+        self.count
+    }
+    pub fn surface_area(&self) -> f64 {
+        let size = [0, 1, 2].map(|axis| self.max[axis] - self.min[axis]);
+        2. * (size[0] * size[1] + size[1] * size[2] + size[2] * size[0])
+    }
+
+    // Mutators
+    pub fn set_aabb(&mut self, aabb: &Aabb) {
+        self.min = [aabb.x_min(), aabb.y_min(), aabb.z_min()];
+        self.max = [aabb.x_max(), aabb.y_max(), aabb.z_max()];
+    }
+
+    // Methods
+    /**
+     * Distances at which the line of the ray enters and leaves the box, with the inverse of the direction of the ray.
+     */
+    fn intersect(&self, pos: &[f64; 3], inv_dir: &[f64; 3]) -> Option<(f64, f64)> {
+        let mut t_enter = f64::MIN;
+        let mut t_exit = f64::MAX;
+        for axis in 0..3 {
+            let t0 = (self.min[axis] - pos[axis]) * inv_dir[axis];
+            let t1 = (self.max[axis] - pos[axis]) * inv_dir[axis];
+            t_enter = t_enter.max(t0.min(t1));
+            t_exit = t_exit.min(t0.max(t1));
+        }
+        match t_enter <= t_exit {
+            true => Some((t_enter, t_exit)),
+            false => None,
+        }
+    }
+}
+
+impl FlatBvh {
+    // Constructor
+    pub fn new(root: &Node) -> FlatBvh {
+        let mut bvh = FlatBvh::default();
+        bvh.flatten(root, None);
+        bvh.build_area = bvh.nodes.first().map_or(0., |root| root.surface_area());
+        bvh.build_cost = bvh.cost();
+        bvh
+    }
+
+    // Accessors
+    pub fn nodes(&self) -> &Vec<FlatNode> {
+        &self.nodes
+    }
+    pub fn nodes_mut(&mut self) -> &mut Vec<FlatNode> {
+        &mut self.nodes
+    }
+    pub fn indices(&self) -> &Vec<usize> {
+        &self.indices
+    }
+    pub fn aabb(&self) -> Option<Aabb> {
+        self.nodes.first().map(|node| node.aabb())
+    }
+    pub fn memory(&self) -> usize {
+        self.nodes.len() * std::mem::size_of::<FlatNode>() + self.indices.len() * std::mem::size_of::<usize>()
+    }
+    pub fn build_cost(&self) -> f64 {
+        self.build_cost
+    }
+
+    // Methods
+    fn flatten(&mut self, node: &Node, parent: Option<usize>) -> usize {
+        let index = self.nodes.len();
+        let aabb = node.aabb();
+        self.nodes.push(FlatNode {
+            min: [aabb.x_min(), aabb.y_min(), aabb.z_min()],
+            max: [aabb.x_max(), aabb.y_max(), aabb.z_max()],
+            offset: self.indices.len(),
+            count: 0,
+        });
+        self.parents.push(parent);
+
+        match (node.a(), node.b()) {
+            (Some(a), Some(b)) => {
+                self.flatten(a, Some(index));
+                let second = self.flatten(b, Some(index));
+                self.nodes[index].offset = second;
+            }
+            // A single child takes the place of its parent
+            (Some(child), None) | (None, Some(child)) => {
+                self.nodes.pop();
+                self.parents.pop();
+                return self.flatten(child, parent);
+            }
+            (None, None) => {
+                for element_index in node.elements() {
+                    if *element_index >= self.leaves.len() {
+                        self.leaves.resize(element_index + 1, None);
+                    }
+                    self.leaves[*element_index] = Some(index);
+                }
+                self.indices.extend(node.elements());
+                self.nodes[index].count = node.elements().len();
+                if node.elements().is_empty() {
+                    self.nodes.pop();
+                    self.parents.pop();
+                }
+            }
+        }
+        index
+    }
+
+    /**
+     * Surface area heuristic of the tree, relative to the box of the root when it was built: expected number of
+     * intersection tests of a ray that hits this box. Refitting doesn't change the box of reference, so the cost of
+     * elements moving apart isn't hidden by the growth of the root.
+     */
+    pub fn cost(&self) -> f64 {
+        let cost: f64 = self.nodes.iter()
+            .map(|node| node.surface_area() * if node.is_leaf() { node.count as f64 } else { BVH_TRAVERSAL_COST })
+            .sum();
+        cost / self.build_area.max(f64::EPSILON)
+    }
+
+    /**
+     * Refits the boxes of the leaves of the changed elements and of their parents, bottom-up. The tree itself is kept,
+     * so it returns false when an element entered or left the BVH and it needs to be built again.
+     */
+    pub fn refit(&mut self, elements: &[Element], changed: &[usize]) -> bool {
+        self.refit_with(changed, |index| elements[index].aabb().cloned())
+    }
+
+    /**
+     * Same as refit, for a BVH of any set of AABBs given by their index.
+     */
+    pub fn refit_with<F: Fn(usize) -> Option<Aabb>>(&mut self, changed: &[usize], aabb_of: F) -> bool {
+        let mut leaves: Vec<usize> = vec![];
+        for index in changed {
+            match (self.leaves.get(*index).copied().flatten(), aabb_of(*index)) {
+                (Some(leaf), Some(_)) => leaves.push(leaf),
+                (None, None) => (),
+                _ => return false,
+            }
+        }
+
+        // The children come after their parent, so refitting by decreasing index only sees children already refitted
+        let mut nodes: Vec<usize> = leaves.iter()
+            .flat_map(|leaf| std::iter::successors(Some(*leaf), |node| self.parents[*node]))
+            .collect();
+        nodes.sort_unstable_by(|a, b| b.cmp(a));
+        nodes.dedup();
+
+        for index in nodes {
+            let node = &self.nodes[index];
+            let aabbs: Vec<Aabb> = match node.is_leaf() {
+                true => self.indices[node.offset..node.offset + node.count].iter()
+                    .filter_map(|index| aabb_of(*index))
+                    .collect(),
+                false => vec![self.nodes[index + 1].aabb(), self.nodes[node.offset].aabb()],
+            };
+            if let Some(aabb) = aabbs.into_iter().reduce(|mut aabb, other| {
+                aabb.grow_to_include(&other);
+                aabb
+            }) {
+                self.nodes[index].set_aabb(&aabb);
+            }
+        }
+        true
+    }
+
+    /**
+     * Closest hit of the ray with the elements of the BVH, merged with the one already found. The children are visited
+     * from the closest to the farthest, and the ones behind the closest hit are skipped unless the scene needs every
+     * intersection (for the refraction indices).
+     */
+    pub fn closest_hit<'a>(&self, scene: &'a Scene, ray: &Ray, elements: &'a [Element], closest: Option<Hit<'a>>) -> Option<Hit<'a>> {
+        self.closest_hit_with(ray, 1., scene.settings().bvh_full_traversal, closest, |indices, closest| {
+            get_closest_hit_from_elements_with_index(scene, ray, closest, elements, indices)
+        })
+    }
+
+    /**
+     * Same as closest_hit, with the indices of the leaves handed to `hit_leaf`. The ray is the one of the space of the
+     * BVH, and factor turns its distances into the ones of the hits.
+     */
+    pub fn closest_hit_with<'a, F>(&self, ray: &Ray, factor: f64, full_traversal: bool, mut closest: Option<Hit<'a>>, mut hit_leaf: F) -> Option<Hit<'a>>
+    where F: FnMut(&[usize], Option<Hit<'a>>) -> Option<Hit<'a>> {
+        let (pos, inv_dir) = get_ray_arrays(ray);
+        let mut stack: Vec<(usize, f64)> = Vec::with_capacity(64);
+        if let Some((t_enter, t_exit)) = self.nodes.first().and_then(|root| root.intersect(&pos, &inv_dir)) {
+            if full_traversal || t_exit >= 0. {
+                stack.push((0, t_enter));
+            }
+        }
+
+        while let Some((index, t_enter)) = stack.pop() {
+            let max_dist = match &closest {
+                Some(hit) if *hit.dist() > 0. && !full_traversal => *hit.dist(),
+                _ => f64::MAX,
+            };
+            if t_enter * factor > max_dist {
+                continue;
+            }
+
+            let node = &self.nodes[index];
+            count(Counter::BvhNode);
+            if node.is_leaf() {
+                closest = hit_leaf(&self.indices[node.offset..node.offset + node.count], closest);
+                continue;
+            }
+
+            let mut children = [index + 1, node.offset].map(|child| {
+                self.nodes[child].intersect(&pos, &inv_dir)
+                    .filter(|(_, t_exit)| full_traversal || *t_exit >= 0.)
+                    .map(|(t_enter, _)| (child, t_enter))
+            });
+            // The closest child is pushed last to be visited first
+            if let [Some(a), Some(b)] = children {
+                if a.1 < b.1 {
+                    children = [Some(b), Some(a)];
+                }
+            }
+            stack.extend(children.into_iter().flatten());
+        }
+        closest
+    }
+
+    /**
+     * Every intersection of the ray with the elements of the BVH, in no particular order.
+     */
+    pub fn intersect(&self, ray: &Ray, elements: &[Element], t_list: &mut Vec<f64>) {
+        let (pos, inv_dir) = get_ray_arrays(ray);
+        let mut stack: Vec<usize> = Vec::with_capacity(64);
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            count(Counter::BvhNode);
+            if node.intersect(&pos, &inv_dir).is_none() {
+                continue;
+            }
+            if node.is_leaf() {
+                for element_index in &self.indices[node.offset..node.offset + node.count] {
+                    if let Some(t) = elements[*element_index].intersect(ray) {
+                        t_list.extend(t);
+                    }
+                }
+            } else {
+                stack.push(node.offset);
+                stack.push(index + 1);
+            }
+        }
+    }
+
+    /**
+     * Whether the ray hits any element between 0 and max_dist, stopping at the first one found.
+     */
+    pub fn any_hit(&self, ray: &Ray, elements: &[Element], max_dist: f64) -> bool {
+        self.any_hit_with(ray, 1., max_dist, |indices| {
+            indices.iter().any(|index| match elements[*index].intersect(ray) {
+                Some(t_list) => t_list.iter().any(|t| *t > 0. && *t < max_dist),
+                None => false,
+            })
+        })
+    }
+
+    /**
+     * Same as any_hit, with the indices of the leaves handed to `hit_leaf`. The ray and max_dist are in the same space
+     * as for closest_hit_with.
+     */
+    pub fn any_hit_with<F: FnMut(&[usize]) -> bool>(&self, ray: &Ray, factor: f64, max_dist: f64, mut hit_leaf: F) -> bool {
+        let (pos, inv_dir) = get_ray_arrays(ray);
+        let mut stack: Vec<usize> = Vec::with_capacity(64);
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            count(Counter::BvhNode);
+            match node.intersect(&pos, &inv_dir) {
+                Some((t_enter, t_exit)) if t_exit >= 0. && t_enter * factor <= max_dist => (),
+                _ => continue,
+            }
+
+            if node.is_leaf() {
+                if hit_leaf(&self.indices[node.offset..node.offset + node.count]) {
+                    return true;
+                }
+            } else {
+                stack.push(node.offset);
+                stack.push(index + 1);
+            }
+        }
+        false
+    }
+}
+
+fn get_ray_arrays(ray: &Ray) -> ([f64; 3], [f64; 3]) {
+    let pos: &Vec3 = ray.get_pos();
+    let dir: &Vec3 = ray.get_dir();
+    (
+        [*pos.x(), *pos.y(), *pos.z()],
+        [1. / dir.x(), 1. / dir.y(), 1. / dir.z()],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::element::Element;
+    use crate::model::materials::diffuse::Diffuse;
+    use crate::model::maths::ray::Ray;
+    use crate::model::maths::transform::Transform;
+    use crate::model::maths::vec3::Vec3;
+    use crate::model::shapes::aabb::Aabb;
+    use crate::model::shapes::sphere::Sphere;
+    use crate::bvh::flat::FlatBvh;
+    use crate::bvh::node::Node;
+
+    fn grid_of_spheres() -> Vec<Element> {
+        (0..100)
+            .map(|i| {
+                let pos = Vec3::new((i % 10) as f64 * 3., (i / 10) as f64 * 3., 0.);
+                Element::new(Box::new(Sphere::new(pos, Vec3::new(0., 1., 0.), 1.)), Diffuse::default())
+            })
+            .collect()
+    }
+
+    fn build(elements: &[Element]) -> FlatBvh {
+        let mut node = Node::new(&Aabb::new(0., 0., 0., 0., 0., 0.));
+        node.build_tree(elements);
+        FlatBvh::new(&node)
+    }
+
+    #[test]
+    fn test_flat_bvh() {
+        let elements = grid_of_spheres();
+        let bvh = build(&elements);
+
+        // Every element is in one leaf, and the first child of an interior node is right after it
+        let mut indices = bvh.indices().clone();
+        indices.sort();
+        assert_eq!(indices, (0..elements.len()).collect::<Vec<usize>>());
+        for (index, flat_node) in bvh.nodes().iter().enumerate().filter(|(_, node)| !node.is_leaf()) {
+            assert!(bvh.nodes()[index + 1].aabb().is_child_of(&flat_node.aabb()));
+            assert!(bvh.nodes()[flat_node.offset()].aabb().is_child_of(&flat_node.aabb()));
+        }
+
+        // A ray along the first row goes through its 10 spheres
+        let ray = Ray::new(Vec3::new(-5., 0., 0.), Vec3::new(1., 0., 0.), 0);
+        let mut t_list = vec![];
+        bvh.intersect(&ray, &elements, &mut t_list);
+        assert_eq!(t_list.len(), 20);
+
+        assert!(bvh.any_hit(&ray, &elements, 4.5));
+        assert!(!bvh.any_hit(&ray, &elements, 3.5));
+        let missing = Ray::new(Vec3::new(-5., 1.5, 0.), Vec3::new(1., 0., 0.), 0);
+        assert!(!bvh.any_hit(&missing, &elements, f64::MAX));
+    }
+
+    #[test]
+    fn test_refit() {
+        let mut elements = grid_of_spheres();
+        let mut bvh = build(&elements);
+        let build_cost = bvh.cost();
+        assert!((bvh.build_cost() - build_cost).abs() < 1e-9);
+
+        // The first sphere leaves the grid: its leaf and the root grow to follow it
+        elements[0].set_transform(Some(Transform::new(Vec3::new(0., 0., 50.), Vec3::from_value(0.), Vec3::from_value(1.))));
+        assert!(bvh.refit(&elements, &[0]));
+        assert!((bvh.aabb().unwrap().z_max() - 51.).abs() < 1e-9);
+        let ray = Ray::new(Vec3::new(0., 0., 60.), Vec3::new(0., 0., -1.), 0);
+        assert!(bvh.any_hit(&ray, &elements, 9.5));
+        assert!(bvh.cost() > build_cost);
+
+        // And it shrinks back when the sphere comes back
+        elements[0].set_transform(None);
+        assert!(bvh.refit(&elements, &[0]));
+        assert!((bvh.aabb().unwrap().z_max() - 1.).abs() < 1e-9);
+        assert!((bvh.cost() - build_cost).abs() < 1e-9);
+    }
+}
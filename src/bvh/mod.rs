@@ -1,2 +1,3 @@
 pub mod node;
+pub mod flat;
 pub mod traversal;
\ No newline at end of file
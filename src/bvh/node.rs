@@ -1,6 +1,6 @@
-use std::io::Write;
+use std::{fmt::Display, thread, time::{Duration, Instant}};
 
-use crate::{model::{scene::Scene, shapes::aabb::Aabb}, BVH_SPLIT_STEPS};
+use crate::{model::{element::Element, shapes::aabb::Aabb}, BVH_BINS, BVH_MAX_LEAF_SIZE, BVH_PARALLEL_THRESHOLD, BVH_TRAVERSAL_COST};
 
 #[derive(Debug, Clone)]
 pub struct Node {
@@ -11,6 +11,43 @@ pub struct Node {
     is_leaf: bool
 }
 
+/**
+ * Element bounded by an AABB, as seen by the builder.
+ */
+#[derive(Debug, Clone)]
+struct Primitive {
+    index: usize,
+    aabb: Aabb,
+    centroid: [f64; 3],
+}
+
+/**
+ * Split of the primitives of a node: the ones whose centroid falls in a bin up to `bin` (included) go to the first child.
+ */
+struct Split {
+    axis: usize,
+    bin: usize,
+    min: f64,
+    extent: f64,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct BvhStats {
+    pub build_time: Duration,
+    pub elements: usize,
+    pub nodes: usize,
+    pub leaves: usize,
+    pub max_depth: usize,
+    pub max_leaf_size: usize,
+}
+
+impl Display for BvhStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "BVH built in {:.2?}: {} elements, {} nodes ({} leaves), depth {}, at most {} elements per leaf",
+            self.build_time, self.elements, self.nodes, self.leaves, self.max_depth, self.max_leaf_size)
+    }
+}
+
 impl Node {
     // Constructor
     pub fn new(aabb: &Aabb) -> Node {
@@ -41,138 +78,241 @@ impl Node {
     pub fn set_is_leaf(&mut self, is_leaf: bool) { self.is_leaf = is_leaf; }
 
     // Methods
-    pub fn build_tree(&mut self, scene: &Scene) {
-        let parent_children = self.aabb.get_children_and_shrink(scene, &(0..scene.elements().len()).collect());
-        println!("-------- BUILDING BVH  --------");
-        println!("-  This may take a long time  -");
-        println!("-    Depending on the scene   -");
-        self.set_elements(parent_children);
-        self.build_node(scene, 0, 0., 100.);
-        println!("- │████████████████████│ 100%  -");
-        println!("---------- BVH BUILT ----------");
-    }
-
-    fn build_node(&mut self, scene: &Scene, depth: usize, mut completion: f64, mut increment: f64) {
-        increment /= 10.;
-        let (node_a, node_b) = self.split_node(scene, depth, completion, increment * 4.);
-        completion += increment * 4.;
-        if node_a.is_none() && node_b.is_none() {
-            self.set_is_leaf(true);
+    /**
+     * Builds the tree of the elements that have an AABB with a binned surface area heuristic on their centroids.
+     * The elements are only stored in the leaves.
+     */
+    pub fn build_tree(&mut self, elements: &[Element]) -> BvhStats {
+        let stats = self.build_tree_from(elements.iter()
+            .enumerate()
+            .filter_map(|(index, element)| element.aabb().map(|aabb| (index, aabb))));
+        println!("{}", stats);
+        stats
+    }
+
+    /**
+     * Builds the tree of any set of AABBs, given with the index stored in the leaves.
+     */
+    pub fn build_tree_from<'a>(&mut self, aabbs: impl Iterator<Item = (usize, &'a Aabb)>) -> BvhStats {
+        let start = Instant::now();
+        let mut primitives: Vec<Primitive> = aabbs
+            .map(|(index, aabb)| Primitive::new(index, aabb))
+            .collect();
+
+        if primitives.is_empty() {
+            self.a = None;
+            self.b = None;
+            self.elements = vec![];
+            self.is_leaf = true;
+        } else {
+            *self = Node::build_node(&mut primitives);
         }
-        if let Some(mut node_a) = node_a {
-            node_a.build_node(scene, depth + 1, completion, increment * 3.);
-            completion += increment * 3.;
-            self.a = Some(Box::new(node_a));
+
+        let mut stats = self.stats();
+        stats.build_time = start.elapsed();
+        stats
+    }
+
+    pub fn stats(&self) -> BvhStats {
+        let mut stats = BvhStats::default();
+        self.collect_stats(1, &mut stats);
+        stats
+    }
+
+    fn collect_stats(&self, depth: usize, stats: &mut BvhStats) {
+        stats.nodes += 1;
+        stats.elements += self.elements.len();
+        stats.max_depth = stats.max_depth.max(depth);
+        if self.is_leaf {
+            stats.leaves += 1;
+            stats.max_leaf_size = stats.max_leaf_size.max(self.elements.len());
         }
-        if let Some(mut node_b) = node_b {
-            node_b.build_node(scene, depth + 1, completion, increment * 3.);
-            self.b = Some(Box::new(node_b));
+        for child in [&self.a, &self.b].into_iter().flatten() {
+            child.collect_stats(depth + 1, stats);
         }
     }
 
-    pub fn split_node(&mut self, scene: &Scene, _depth: usize, mut completion: f64, mut increment: f64) -> (Option<Node>, Option<Node>) {
-        let mut current_completion = completion;
-        increment /= 3. * BVH_SPLIT_STEPS as f64;
-        let t_vec = get_t_vec(BVH_SPLIT_STEPS);
+    fn build_node(primitives: &mut [Primitive]) -> Node {
+        let mut aabb = primitives[0].aabb.clone();
+        for primitive in primitives.iter().skip(1) {
+            aabb.grow_to_include(&primitive.aabb);
+        }
+        let mut node = Node::new(&aabb);
 
-        let mut best_configuration: Option<(f64, (Node, Node, Vec<usize>))> = None;
+        let split = match find_split(primitives, &aabb) {
+            Some(split) => split,
+            None => {
+                node.elements = primitives.iter().map(|primitive| primitive.index).collect();
+                node.is_leaf = true;
+                return node;
+            }
+        };
 
-        for axis in 0..3 {
-            for t in &t_vec {
-                let (mut aabb1_tmp, mut aabb2_tmp) = self.aabb.split_aabb(axis, *t);
-                
-                let aabb1_children = aabb1_tmp.get_children_and_shrink(scene, self.elements());
-                let mut elements_left = vec![];
-                for child in &self.elements {
-                    if !aabb1_children.contains(child) {
-                        elements_left.push(*child);
-                    }
-                }
-                let aabb2_children = aabb2_tmp.get_children_and_shrink(scene, &elements_left);
-
-                let mut node_a = Node::new(&aabb1_tmp);
-                let mut node_b = Node::new(&aabb2_tmp);
-                node_a.set_elements(aabb1_children);
-                node_b.set_elements(aabb2_children);
-
-                if let Some((cost, new_parent_child)) = self.try_configuration(&mut node_a, &mut node_b) {
-                    if let Some((best_cost, _)) = best_configuration {
-                        if cost < best_cost {
-                            best_configuration = Some((cost, (node_a, node_b, new_parent_child)));
-                        }
-                    } else {
-                        best_configuration = Some((cost, (node_a, node_b, new_parent_child)));
-                    }
-                }
-                current_completion += increment;
-                if current_completion - completion.floor() > 1. {
-                    completion = current_completion;
-                    print!("\r{}\r", " ".repeat(40));
-                    print!("- │");
-                    std::io::stdout().flush().unwrap();
-                    for _ in 0..(completion / 5.) as usize {
-                        print!("█");
-                    }
-                    for _ in 0..(20. - completion / 5.) as usize {
-                        print!(" ");
-                    }
-                    print!("│ {: >3.0}%  -", completion);
-                    write!(std::io::stdout(), "\x1b[?25l").unwrap();
-                    std::io::stdout().flush().unwrap();
-                }
+        let mut middle = 0;
+        for i in 0..primitives.len() {
+            if split.contains(&primitives[i]) {
+                primitives.swap(i, middle);
+                middle += 1;
             }
         }
+        let nb_primitives = primitives.len();
+        let (left, right) = primitives.split_at_mut(middle);
 
-        if let Some((_, (a, b, new_parent_child))) = best_configuration {
-            if new_parent_child.len() == 0 && (a.elements().len() == 0 || b.elements().len() == 0) {
-                return (None, None);
-            }
-            let a = match a.elements().len() == 0 { true => None, false => Some(a) };
-            let b = match b.elements().len() == 0 { true => None, false => Some(b) };
-            self.set_elements(new_parent_child);
-            (a, b)
+        // The biggest nodes are built in parallel, the smaller ones aren't worth a thread
+        let (a, b) = if nb_primitives >= BVH_PARALLEL_THRESHOLD {
+            thread::scope(|scope| {
+                let handle = scope.spawn(|| Node::build_node(left));
+                let b = Node::build_node(right);
+                (handle.join().unwrap(), b)
+            })
         } else {
-            (None, None)
+            (Node::build_node(left), Node::build_node(right))
+        };
+        node.a = Some(Box::new(a));
+        node.b = Some(Box::new(b));
+        node
+    }
+}
+
+impl Primitive {
+    fn new(index: usize, aabb: &Aabb) -> Primitive {
+        Primitive {
+            index,
+            aabb: aabb.clone(),
+            centroid: [
+                (aabb.x_min() + aabb.x_max()) / 2.,
+                (aabb.y_min() + aabb.y_max()) / 2.,
+                (aabb.z_min() + aabb.z_max()) / 2.,
+            ],
         }
     }
+}
 
-    pub fn try_configuration(&mut self, a: &mut Node, b: &mut Node) -> Option<(f64, Vec<usize>)> {
-        let parent_surface_area = self.aabb.surface_area();
-        let initial_cost = self.elements.len() as f64 * parent_surface_area;
+impl Split {
+    fn contains(&self, primitive: &Primitive) -> bool {
+        get_bin(primitive.centroid[self.axis], self.min, self.extent) <= self.bin
+    }
+}
 
-        let mut new_parent_children = vec![];
-        for child in self.elements() {
-            if !a.elements().contains(&child) && !b.elements().contains(&child) {
-                new_parent_children.push(*child);
-            }
-        }
+fn get_bin(centroid: f64, min: f64, extent: f64) -> usize {
+    (((centroid - min) / extent * BVH_BINS as f64) as usize).min(BVH_BINS - 1)
+}
 
-        let aabb1_surface_area = a.aabb.surface_area();
-        let aabb2_surface_area = b.aabb.surface_area();
+/**
+ * Cheapest split of the primitives among the bin boundaries of every axis, or None if keeping them in a leaf is cheaper.
+ */
+fn find_split(primitives: &[Primitive], aabb: &Aabb) -> Option<Split> {
+    let nb_primitives = primitives.len();
+    if nb_primitives <= 1 {
+        return None;
+    }
 
+    let mut centroid_min = [f64::MAX; 3];
+    let mut centroid_max = [f64::MIN; 3];
+    for primitive in primitives {
+        for axis in 0..3 {
+            centroid_min[axis] = centroid_min[axis].min(primitive.centroid[axis]);
+            centroid_max[axis] = centroid_max[axis].max(primitive.centroid[axis]);
+        }
+    }
 
-        if a.elements().len() < 1 {
-            return None;
+    let mut best: Option<(f64, Split)> = None;
+    for axis in 0..3 {
+        let extent = centroid_max[axis] - centroid_min[axis];
+        if extent <= f64::EPSILON {
+            continue;
         }
 
-        let c1 = aabb1_surface_area * a.elements().len() as f64;
-        let c2 = aabb2_surface_area * b.elements().len() as f64;
-        let cp = parent_surface_area * new_parent_children.len() as f64;
+        let mut bins: Vec<(Option<Aabb>, usize)> = vec![(None, 0); BVH_BINS];
+        for primitive in primitives {
+            let (bin_aabb, count) = &mut bins[get_bin(primitive.centroid[axis], centroid_min[axis], extent)];
+            grow(bin_aabb, &primitive.aabb);
+            *count += 1;
+        }
 
-        if initial_cost <  (c1 + c2 + cp) {
-            return None;
+        // Cost of the primitives above each boundary, then sweep from the bottom to get the ones under it
+        let mut right_costs = [0.; BVH_BINS];
+        let mut right_aabb = None;
+        let mut right_count = 0;
+        for bin in (1..BVH_BINS).rev() {
+            if let Some(bin_aabb) = &bins[bin].0 {
+                grow(&mut right_aabb, bin_aabb);
+            }
+            right_count += bins[bin].1;
+            right_costs[bin] = right_aabb.as_ref().map_or(0., |aabb: &Aabb| aabb.surface_area() * right_count as f64);
         }
 
-        Some((c1 + c2 + cp, new_parent_children))
+        let mut left_aabb = None;
+        let mut left_count = 0;
+        for bin in 0..BVH_BINS - 1 {
+            if let Some(bin_aabb) = &bins[bin].0 {
+                grow(&mut left_aabb, bin_aabb);
+            }
+            left_count += bins[bin].1;
+            if left_count == 0 || left_count == nb_primitives {
+                continue;
+            }
+            let cost = left_aabb.as_ref().map_or(0., |aabb: &Aabb| aabb.surface_area() * left_count as f64) + right_costs[bin + 1];
+            if best.as_ref().is_none_or(|(best_cost, _)| cost < *best_cost) {
+                best = Some((cost, Split { axis, bin, min: centroid_min[axis], extent }));
+            }
+        }
     }
-}
 
-pub fn get_t_vec(steps: usize) -> Vec<f64> {
-    let mut t_vec = vec![];
+    let (cost, split) = best?;
+    let split_cost = BVH_TRAVERSAL_COST + cost / aabb.surface_area().max(f64::EPSILON);
+    if split_cost >= nb_primitives as f64 && nb_primitives <= BVH_MAX_LEAF_SIZE {
+        return None;
+    }
+    Some(split)
+}
 
-    for i in 0..steps {
-        t_vec.push((i as f64 + 1.0) / (steps as f64 + 1.0));
+fn grow(aabb: &mut Option<Aabb>, other: &Aabb) {
+    match aabb {
+        Some(aabb) => aabb.grow_to_include(other),
+        None => *aabb = Some(other.clone()),
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::element::Element;
+    use crate::model::materials::diffuse::Diffuse;
+    use crate::model::maths::vec3::Vec3;
+    use crate::model::shapes::aabb::Aabb;
+    use crate::model::shapes::sphere::Sphere;
+    use crate::bvh::node::Node;
+    use crate::BVH_MAX_LEAF_SIZE;
 
-    t_vec
-}
\ No newline at end of file
+    #[test]
+    fn test_build_tree() {
+        let elements: Vec<Element> = (0..1000)
+            .map(|i| {
+                let pos = Vec3::new((i % 10) as f64 * 3., (i / 10 % 10) as f64 * 3., (i / 100) as f64 * 3.);
+                Element::new(Box::new(Sphere::new(pos, Vec3::new(0., 1., 0.), 1.)), Diffuse::default())
+            })
+            .collect();
+        let mut node = Node::new(&Aabb::new(0., 0., 0., 0., 0., 0.));
+        let stats = node.build_tree(&elements);
+
+        // Every element is in exactly one leaf, and every leaf is in the AABB of its parents
+        assert_eq!(stats.elements, elements.len());
+        assert!(stats.max_leaf_size <= BVH_MAX_LEAF_SIZE);
+        assert!(stats.max_depth < 30);
+        assert!((node.aabb().x_min() + 1.).abs() < 1e-9 && (node.aabb().x_max() - 28.).abs() < 1e-9);
+        fn check(node: &Node, elements: &[Element], seen: &mut Vec<bool>) {
+            for index in node.elements() {
+                assert!(elements[*index].aabb().unwrap().is_child_of(node.aabb()));
+                assert!(!seen[*index]);
+                seen[*index] = true;
+            }
+            for child in [node.a(), node.b()].into_iter().flatten() {
+                assert!(child.aabb().is_child_of(node.aabb()));
+                check(child, elements, seen);
+            }
+        }
+        let mut seen = vec![false; elements.len()];
+        check(&node, &elements, &mut seen);
+        assert!(seen.iter().all(|seen| *seen));
+    }
+}
@@ -0,0 +1,182 @@
+use winit::{event::MouseButton, keyboard::{Key, NamedKey}};
+use crate::{
+    model::{maths::vec3::Vec3, scene::Scene, shapes::aabb::Aabb},
+    ui::{ui::{ui_scrolled, UI}, utils::ui_utils::UIContext},
+    DEFAULT_PIVOT_DISTANCE, DOLLY_STEP, ORBIT_STEP
+};
+use super::selection::subject_elements;
+
+/**
+ * Point the camera orbits around in each scene, and the drag of the camera with the mouse. The pivot is the last point
+ * picked in the viewport or the centre of the last framed selection.
+ */
+#[derive(Default)]
+pub struct CameraControls {
+    pivot: Option<(usize, Vec3)>,
+    pub drag: Option<CameraDrag>,
+}
+
+pub enum CameraDrag {
+    Orbit { pivot: Vec3, last: (u32, u32) },
+    // Depth of the pivot, where the scene follows the mouse exactly
+    Pan { depth: f64, last: (u32, u32) },
+}
+
+impl CameraControls {
+    // Mutators
+    pub fn set_pivot(&mut self, scene_id: usize, pivot: Vec3) {
+        self.pivot = Some((scene_id, pivot));
+    }
+
+    // Methods
+    fn pivot(&self, scene_id: usize, scene: &Scene) -> Vec3 {
+        match self.pivot {
+            Some((pivot_scene, pivot)) if pivot_scene == scene_id => pivot,
+            _ => scene.camera().pos() + scene.camera().dir().normalize() * DEFAULT_PIVOT_DISTANCE,
+        }
+    }
+}
+
+fn on_scene(context: &UIContext, (x, y): (u32, u32)) -> bool {
+    x < context.ui_img.width() && y < context.ui_img.height() && context.ui_img.get_pixel(x, y).0 == [1; 4]
+}
+
+/**
+ * Alt+left drag orbits around the pivot and middle drag pans, from the pixels of the scene. Returns false if no drag
+ * started.
+ */
+pub fn start_camera_drag(context: &mut UIContext, ui: &UI, button: MouseButton) -> bool {
+    let mouse = ui.mouse_position();
+    if !on_scene(context, mouse) || ui.editing().is_some() {
+        return false;
+    }
+    let (Some(scene_id), Some(scene)) = (context.active_scene, context.get_active_scene()) else {
+        return false;
+    };
+    let scene = scene.read().unwrap();
+    let pivot = context.camera_controls.pivot(scene_id, &scene);
+    let drag = match button {
+        MouseButton::Left if ui.inputs().contains(&Key::Named(NamedKey::Alt)) => CameraDrag::Orbit { pivot, last: mouse },
+        MouseButton::Middle => CameraDrag::Pan { depth: (pivot - scene.camera().pos()).dot(scene.camera().dir()), last: mouse },
+        _ => return false,
+    };
+    drop(scene);
+    context.camera_controls.drag = Some(drag);
+    true
+}
+
+pub fn drag_camera(context: &mut UIContext, mouse: (u32, u32)) {
+    let (Some(scene), Some(drag)) = (context.active_scene.and_then(|id| context.scene_list.get(&id)), &mut context.camera_controls.drag) else {
+        return;
+    };
+    let mut scene = scene.write().unwrap();
+    match drag {
+        CameraDrag::Orbit { pivot, last } => {
+            let (dx, dy) = (mouse.0 as f64 - last.0 as f64, mouse.1 as f64 - last.1 as f64);
+            scene.camera_mut().orbit(pivot, -dx * ORBIT_STEP, -dy * ORBIT_STEP);
+            *last = mouse;
+        }
+        CameraDrag::Pan { depth, last } => {
+            // The scene follows the mouse at the depth of the pivot
+            let size = scene.camera().pixel_size(depth.max(0.1));
+            let (dx, dy) = (mouse.0 as f64 - last.0 as f64, mouse.1 as f64 - last.1 as f64);
+            scene.camera_mut().pan(-dx * size, dy * size);
+            *last = mouse;
+        }
+    }
+    scene.set_dirty(true);
+}
+
+/**
+ * Returns false if the camera wasn't dragged, so that releasing the button is a click.
+ */
+pub fn end_camera_drag(context: &mut UIContext) -> bool {
+    context.camera_controls.drag.take().is_some()
+}
+
+/**
+ * The wheel moves the camera towards the pivot over the scene, by a part of its distance, and scrolls the boxes of the
+ * UI elsewhere.
+ */
+pub fn dolly_camera(context: &mut UIContext, ui: &mut UI, lines: f32) {
+    let mouse = ui.mouse_position();
+    let (Some(scene_id), Some(scene)) = (context.active_scene, context.get_active_scene()) else {
+        ui_scrolled(mouse, -lines, context, ui);
+        return;
+    };
+    if !on_scene(context, mouse) {
+        ui_scrolled(mouse, -lines, context, ui);
+        return;
+    }
+    let mut scene = scene.write().unwrap();
+    let distance = (context.camera_controls.pivot(scene_id, &scene) - scene.camera().pos()).length().max(0.1);
+    scene.camera_mut().dolly(distance * DOLLY_STEP * lines as f64);
+    scene.set_dirty(true);
+}
+
+/**
+ * Moves the camera back along its direction until the selected elements fill the screen, and orbits around their
+ * centre. Infinite elements like planes are left out, returns false if nothing could be framed.
+ */
+pub fn frame_selected(context: &mut UIContext) -> bool {
+    let (Some(scene_id), Some(scene)) = (context.active_scene, context.get_active_scene()) else {
+        return false;
+    };
+    let mut scene = scene.write().unwrap();
+    let aabbs: Vec<Aabb> = context.selection.subjects().iter()
+        .flat_map(|subject| subject_elements(&scene, *subject))
+        .filter_map(|id| scene.elements()[id].aabb().cloned())
+        .filter(|aabb| aabb.volume().is_finite())
+        .collect();
+    if aabbs.is_empty() {
+        return false;
+    }
+    let aabb = Aabb::from_aabbs(&aabbs.iter().collect());
+    let min = Vec3::new(aabb.x_min(), aabb.y_min(), aabb.z_min());
+    let max = Vec3::new(aabb.x_max(), aabb.y_max(), aabb.z_max());
+    let center = (min + max) / 2.;
+    scene.camera_mut().frame(&center, ((max - min).length() / 2.).max(0.1));
+    scene.set_dirty(true);
+    drop(scene);
+    context.camera_controls.set_pivot(scene_id, center);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        display::gizmo::project,
+        model::{maths::vec3::Vec3, objects::camera::Camera, scene::Scene},
+        SCREEN_HEIGHT, SCREEN_WIDTH
+    };
+
+    #[test]
+    fn test_orbit_and_frame() {
+        let mut scene = Scene::new();
+        scene.set_camera(Camera::new(Vec3::new(0., 0., -5.), Vec3::new(0., 0., 1.), 60.));
+        let pivot = Vec3::new(1., 0., 0.);
+        let distance = (pivot - scene.camera().pos()).length();
+
+        // The pivot stays at the same distance and place on the screen
+        let on_screen = project(&scene, &pivot).unwrap();
+        scene.camera_mut().orbit(&pivot, 0.4, -0.3);
+        assert!(((pivot - scene.camera().pos()).length() - distance).abs() < 1e-9);
+        let moved = project(&scene, &pivot).unwrap();
+        assert!((moved.0 - on_screen.0).abs() < 1e-6 && (moved.1 - on_screen.1).abs() < 1e-6);
+        assert!(scene.camera().u().dot(&Vec3::new(0., 1., 0.)).abs() < 1e-9);
+
+        // The framed sphere is in the middle of the screen and fits in it
+        let center = Vec3::new(2., 1., 3.);
+        scene.camera_mut().frame(&center, 1.);
+        let (x, y) = project(&scene, &center).unwrap();
+        assert!((x - SCREEN_WIDTH as f64 / 2.).abs() < 1e-6 && (y - SCREEN_HEIGHT as f64 / 2.).abs() < 1e-6);
+        let edge = project(&scene, &(center + scene.camera().v() * 0.999)).unwrap();
+        assert!(edge.1 > 0. && edge.1 < SCREEN_HEIGHT as f64 / 2.);
+
+        // The roll turns the axes of the screen around the direction
+        let dir = *scene.camera().dir();
+        scene.camera_mut().set_roll(std::f64::consts::FRAC_PI_2);
+        assert!((scene.camera().v().dot(&Vec3::new(0., 1., 0.)).abs()) < 1e-9);
+        assert!((scene.camera().dir() - dir).length() < 1e-9);
+    }
+}
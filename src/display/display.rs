@@ -4,6 +4,8 @@ use std::
     time::Instant
 ;
 use crate::{
+    model::history::EditSubject,
+    render::common::Region,
     ui::{
         ui::UI,
         uibox::UIBox,
@@ -11,16 +13,31 @@ use crate::{
     }, SCREEN_HEIGHT_U32, SCREEN_WIDTH_U32
 };
 
+use super::{gizmo::{draw_gizmo, selected_subject}, selection::draw_selection};
 
-pub fn blend_scene_and_ui(context: &UIContext, active_box: Option<&UIBox>) -> RgbaImage {
+const REGION_COLOR: [u8; 4] = [255, 200, 0, 255];
+
+
+/**
+ * The outline of the selection and the gizmo of the selected element are drawn over the scene, unless a box is active.
+ */
+pub fn blend_scene_and_ui(context: &UIContext, active_box: Option<&UIBox>, selected: Option<EditSubject>) -> RgbaImage {
     let mut active_hitbox: Option<((u32, u32), (u32, u32))> = None;
     if let Some(active_box) = active_box {
         active_hitbox = Some((active_box.absolute_pos, active_box.size));
     }
+    // The region being selected is shown instead of the one being rendered
+    let region = match context.region_drag {
+        Some((start, end)) => Region::from_corners((start.0 as usize, start.1 as usize), (end.0 as usize, end.1 as usize)),
+        None => context.active_scene.and_then(|scene_id| context.regions.get(&scene_id).copied()),
+    };
     let mut image = context.ui_img.clone();
     for (x, y, pixel) in image.enumerate_pixels_mut() {
         if pixel.0 == [1; 4] {
-            pixel.0 = context.scene_img.get_pixel(x, y).0
+            pixel.0 = match region {
+                Some(region) if region.on_border(x as usize, y as usize) => REGION_COLOR,
+                _ => context.scene_img.get_pixel(x, y).0,
+            };
         }
         if let Some(active_hitbox) = active_hitbox {
             if !is_inside_box((x, y), active_hitbox.0, active_hitbox.1) {
@@ -30,6 +47,10 @@ pub fn blend_scene_and_ui(context: &UIContext, active_box: Option<&UIBox>) -> Rg
             }
         }
     }
+    if active_hitbox.is_none() {
+        draw_selection(&mut image, context);
+        draw_gizmo(&mut image, context, selected);
+    }
     return image;
 }
 
@@ -60,7 +81,7 @@ pub fn redraw_if_necessary(ui: &mut UI, context: &mut UIContext, mut pixels: &mu
     }
     if redraw {
         let time = Instant::now();
-        let mut img = blend_scene_and_ui(&context, ui.active_box());
+        let mut img = blend_scene_and_ui(context, ui.active_box(), selected_subject(ui));
         display(&mut pixels, &mut img);
         let nb_samples = context.draw_time_samples as f64;
         context.draw_time_avg = nb_samples * context.draw_time_avg / (nb_samples + 1.)
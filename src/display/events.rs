@@ -1,17 +1,24 @@
-use super::{display::blend_scene_and_ui, ui_setup::setup_element_ui};
+use super::{
+    camera_controls::{dolly_camera, drag_camera, end_camera_drag, frame_selected, start_camera_drag},
+    display::blend_scene_and_ui,
+    gizmo::{drag_gizmo, end_gizmo_drag, selected_subject, start_gizmo_drag, GizmoMode},
+    selection::select,
+    ui_setup::step_history
+};
 use chrono::{DateTime, Utc};
 use std::
     path::Path
 ;
 use winit::{
-    event::{MouseScrollDelta, WindowEvent},
+    event::{ElementState, MouseButton, MouseScrollDelta, WindowEvent},
     event_loop::EventLoopWindowTarget,
     keyboard::{Key, NamedKey},
 };
 use crate::{
-    render::raycasting::{get_closest_hit, get_lighting_from_ray, get_ray_debug}, ui::{
-        ui::{ui_clicked, ui_scrolled, UI}, uielement::UIElement, utils::{misc::{ElemType, Value}, ui_utils::{get_parent_ref, Editing, UIContext}}
-    }
+    model::history::EditSubject,
+    render::{common::Region, raycasting::{get_closest_hit, get_lighting_from_ray, get_ray_debug}, render_thread::UIOrder}, ui::{
+        ui::{ui_clicked, UI}, uielement::UIElement, utils::{misc::{ElemType, Value}, ui_utils::{get_parent_ref, Editing, UIContext}}
+    }, SPEED_FACTOR, TILE_SIZE
 };
 
 pub fn handle_event(
@@ -22,30 +29,53 @@ pub fn handle_event(
 ) {
     match event {
         WindowEvent::CursorMoved { position, .. } => {
-            ui.set_mouse_position((position.x as u32, position.y as u32))
+            ui.set_mouse_position((position.x as u32, position.y as u32));
+            if let Some((_, end)) = &mut context.region_drag {
+                *end = ui.mouse_position();
+                ui.set_dirty();
+            }
+            if context.gizmo.drag.is_some() {
+                drag_gizmo(context, ui.mouse_position());
+            }
+            if context.camera_controls.drag.is_some() {
+                drag_camera(context, ui.mouse_position());
+            }
         }
         WindowEvent::MouseWheel { delta, .. } => {
             if let MouseScrollDelta::LineDelta(_, y) = delta {
-                ui_scrolled(ui.mouse_position(), -y, context, ui);
+                dolly_camera(context, ui, y);
             }
         }
-        WindowEvent::MouseInput { state, .. } => {
-            if state == winit::event::ElementState::Released {
+        WindowEvent::MouseInput { state, button: MouseButton::Right, .. } => {
+            match state {
+                ElementState::Pressed => context.region_drag = Some((ui.mouse_position(), ui.mouse_position())),
+                ElementState::Released => select_region(context),
+            }
+            ui.set_dirty();
+        }
+        WindowEvent::MouseInput { state, button, .. } => {
+            // The camera or the gizmo of the selected element are grabbed on press, and releasing them isn't a click
+            if state == ElementState::Pressed {
+                if !start_camera_drag(context, ui, button) && button == MouseButton::Left {
+                    start_gizmo_drag(context, ui);
+                }
+            } else if !end_camera_drag(context) && !end_gizmo_drag(context, ui) {
                 let pos = ui.mouse_position();
                 if !ui_clicked(pos, context, ui) {
                     if let None = ui.editing() {
                         if ui.active_box_reference() == "" {
-                            if let Some(scene) = match context.active_scene {
-                                Some(active_scene_index) => Some(context.scene_list.get(&active_scene_index).unwrap()),
-                                None => None,
-                            } {
+                            let picked = context.get_active_scene().and_then(|scene| {
                                 let scene_read = scene.read().unwrap();
                                 let mut ray = get_ray_debug(&scene_read, pos.0 as usize, pos.1 as usize, true);
                                 get_lighting_from_ray(&scene_read, &ray);
                                 ray.debug = false;
-                                if let Some(hit) = get_closest_hit(&scene_read, &ray) {
-                                    setup_element_ui(hit.element(), ui, scene);
-                                }
+                                get_closest_hit(&scene_read, &ray).map(|hit| (hit.element().id(), *hit.pos()))
+                            });
+                            // Ctrl+click adds the element to the selection or removes it, and the camera orbits around the picked point
+                            if let (Some(scene_id), Some((id, pos))) = (context.active_scene, picked) {
+                                context.camera_controls.set_pivot(scene_id, pos);
+                                let add = ui.inputs().contains(&Key::Named(NamedKey::Control));
+                                select(ui, context, EditSubject::Element(id), add);
                             }
                         }
                     } else {
@@ -70,9 +100,33 @@ pub fn handle_event(
     }
 }
 
+/**
+ * Dragging with the right button selects the region of the screen to render alone, a right click removes it.
+ */
+fn select_region(context: &mut UIContext) {
+    let (Some(scene_id), Some((start, end))) = (context.active_scene, context.region_drag.take()) else {
+        return;
+    };
+    let region = Region::from_corners((start.0 as usize, start.1 as usize), (end.0 as usize, end.1 as usize))
+        // A rectangle smaller than a tile is a simple click
+        .filter(|region| region.width >= TILE_SIZE && region.height >= TILE_SIZE);
+    match region {
+        Some(region) => {
+            context.regions.insert(scene_id, region);
+        }
+        None => {
+            if context.regions.remove(&scene_id).is_none() {
+                return;
+            }
+        }
+    }
+    context.transmitter.send(UIOrder::SetRegion(scene_id, region)).unwrap();
+    context.final_img = false;
+}
+
 fn handle_keyboard_press(
     ui: &mut UI,
-    context: &UIContext,
+    context: &mut UIContext,
     flow: &EventLoopWindowTarget<()>,
     input: Key,
 ) {
@@ -215,8 +269,9 @@ fn key_pressed_editing(
     }
 }
 
-pub fn key_held(context: &UIContext, _: &mut UI, _: &EventLoopWindowTarget<()>, input: Key) {
-    if context.active_scene.is_none() {
+pub fn key_held(context: &UIContext, ui: &mut UI, _: &EventLoopWindowTarget<()>, input: Key) {
+    // The shortcuts with Ctrl don't move the camera
+    if context.active_scene.is_none() || ui.inputs().contains(&Key::Named(NamedKey::Control)) {
         return;
     }
     let scene = context.scene_list.get(&context.active_scene.unwrap()).unwrap();
@@ -265,6 +320,14 @@ pub fn key_held(context: &UIContext, _: &mut UI, _: &EventLoopWindowTarget<()>,
                         scene.write().unwrap().camera_mut().move_right();
                         scene.write().unwrap().set_dirty(true);
                     }
+                    'q' => {
+                        scene.write().unwrap().camera_mut().roll_left();
+                        scene.write().unwrap().set_dirty(true);
+                    }
+                    'e' => {
+                        scene.write().unwrap().camera_mut().roll_right();
+                        scene.write().unwrap().set_dirty(true);
+                    }
                     _ => (),
                 }
             }
@@ -275,7 +338,7 @@ pub fn key_held(context: &UIContext, _: &mut UI, _: &EventLoopWindowTarget<()>,
 
 fn key_pressed_non_editing(
     ui: &mut UI,
-    context: &UIContext,
+    context: &mut UIContext,
     input: &Key,
 ) {
     match input {
@@ -291,7 +354,35 @@ fn key_pressed_non_editing(
         Key::Character(c) => {
             if c.len() == 1 {
                 let c = c.chars().next().unwrap();
-                if c == 'p' {
+                // Ctrl+Z undoes the last edit of the scene, Ctrl+Shift+Z redoes it
+                if c.eq_ignore_ascii_case(&'z') && ui.inputs().contains(&Key::Named(NamedKey::Control)) {
+                    let redo = ui.inputs().contains(&Key::Named(NamedKey::Shift));
+                    step_history(ui, context, redo, 1);
+                } else if let Some(mode) = match c {
+                    '1' => Some(GizmoMode::Translate),
+                    '2' => Some(GizmoMode::Rotate),
+                    '3' => Some(GizmoMode::Scale),
+                    _ => None,
+                } {
+                    // Modes of the gizmo, which can't change during a drag
+                    if context.gizmo.drag.is_none() {
+                        context.gizmo.mode = mode;
+                        ui.set_dirty();
+                    }
+                } else if c == 'g' {
+                    // Grid snapping of the gizmo
+                    context.gizmo.snap = !context.gizmo.snap;
+                } else if c == 'f' {
+                    frame_selected(context);
+                } else if c == '+' || c == '-' {
+                    // Speed of the camera
+                    if let Some(scene) = context.get_active_scene() {
+                        let mut scene = scene.write().unwrap();
+                        let factor = if c == '+' { SPEED_FACTOR } else { 1. / SPEED_FACTOR };
+                        let speed = (scene.camera().speed() * factor).clamp(0.01, 100.);
+                        scene.camera_mut().set_speed(speed);
+                    }
+                } else if c == 'p' {
                     // Save a screenshot
                     let date: DateTime<Utc> = Utc::now();
                     let datestring = format!("{}", date.format("%y%m%d_%H%M%S%3f"));
@@ -299,7 +390,7 @@ fn key_pressed_non_editing(
                         std::fs::create_dir("screenshots").unwrap();
                     }
                     let path = format!("screenshots/screenshot_{}.png", datestring);
-                    blend_scene_and_ui(context, ui.active_box())
+                    blend_scene_and_ui(context, ui.active_box(), selected_subject(ui))
                         .save(path)
                         .unwrap();
                 }
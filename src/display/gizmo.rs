@@ -0,0 +1,556 @@
+use image::RgbaImage;
+use crate::{
+    model::{
+        history::{EditSubject, Snapshot},
+        maths::{quaternion::Quaternion, transform::Transform, vec3::Vec3},
+        scene::Scene,
+        shapes::aabb::Aabb
+    },
+    ui::{prefabs::transform_ui::apply_transform, ui::UI, utils::ui_utils::UIContext},
+    ELEMENT, GIZMO_ANGLE_STEP, GIZMO_GRID, GIZMO_PICK_DISTANCE, GIZMO_SCALE_STEP, GIZMO_SIZE, SCREEN_HEIGHT, SCREEN_WIDTH
+};
+use super::ui_setup::setup_element_ui;
+
+const AXIS_COLORS: [[u8; 4]; 3] = [[230, 60, 60, 255], [60, 200, 60, 255], [60, 110, 240, 255]];
+const UNIFORM_COLOR: [u8; 4] = [230, 230, 230, 255];
+const DRAGGED_COLOR: [u8; 4] = [255, 220, 0, 255];
+const CIRCLE_SEGMENTS: usize = 48;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum GizmoMode {
+    #[default]
+    Translate,
+    Rotate,
+    Scale,
+}
+
+impl GizmoMode {
+    // Named in the history
+    fn verb(&self) -> &str {
+        match self {
+            GizmoMode::Translate => "Move",
+            GizmoMode::Rotate => "Rotate",
+            GizmoMode::Scale => "Scale",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GizmoHandle {
+    // Along an axis, or around it for a rotation
+    Axis(usize),
+    // In the plane orthogonal to an axis
+    Plane(usize),
+    // The three axes scaled at once
+    Uniform,
+}
+
+pub struct GizmoDrag {
+    subject: EditSubject,
+    mode: GizmoMode,
+    handle: GizmoHandle,
+    start_mouse: (f64, f64),
+    start_transform: Transform,
+    target: Target,
+    // Point of the axis or of the plane under the mouse when the drag started
+    start_point: Option<Vec3>,
+    before: Option<Snapshot>,
+}
+
+/**
+ * Translate, rotate and scale handles drawn over the element opened in the element box, and dragged with the left
+ * button. The translations and rotations follow the axes of the world, the scales the ones of the element.
+ */
+#[derive(Default)]
+pub struct Gizmo {
+    pub mode: GizmoMode,
+    pub snap: bool,
+    pub drag: Option<GizmoDrag>,
+}
+
+// Element holding the transform of what is edited, and the axes of the gizmo in the world
+#[derive(Clone)]
+struct Target {
+    element_id: usize,
+    group_transform: Option<Transform>,
+    pivot: Vec3,
+    axes: [Vec3; 3],
+    // Length of the axes in the world
+    length: f64,
+}
+
+/**
+ * What the element box edits, the gizmo is shown over it.
+ */
+pub fn selected_subject(ui: &UI) -> Option<EditSubject> {
+    ui.get_box(ELEMENT)?.edit_bar.as_ref()?.subject
+}
+
+fn target(scene: &Scene, subject: EditSubject, mode: GizmoMode) -> Option<Target> {
+    let element_ids = match subject {
+        EditSubject::Element(id) => match scene.composed_element_by_element_id(id) {
+            Some(composed_element) => composed_element.elements_index().clone(),
+            None => vec![id],
+        },
+        EditSubject::ComposedElement(composed_id) => scene.composed_elements().get(composed_id)?.elements_index().clone(),
+        _ => return None,
+    };
+    let first = scene.elements().get(*element_ids.first()?)?;
+    let world = first.world_transform().cloned().unwrap_or_default();
+
+    // Centre of the element, or its origin if it's infinite like a plane
+    let aabbs: Vec<&Aabb> = element_ids.iter()
+        .filter_map(|id| scene.elements().get(*id)?.aabb())
+        .filter(|aabb| aabb.volume().is_finite())
+        .collect();
+    let pivot = match aabbs.is_empty() {
+        true => *world.translation(),
+        false => {
+            let aabb = Aabb::from_aabbs(&aabbs);
+            Vec3::new((aabb.x_min() + aabb.x_max()) / 2., (aabb.y_min() + aabb.y_max()) / 2., (aabb.z_min() + aabb.z_max()) / 2.)
+        }
+    };
+    let depth = (pivot - scene.camera().pos()).dot(scene.camera().dir());
+    if depth <= 0. {
+        return None;
+    }
+    let unit = [Vec3::new(1., 0., 0.), Vec3::new(0., 1., 0.), Vec3::new(0., 0., 1.)];
+    let axes = match mode {
+        GizmoMode::Scale => unit.map(|axis| world.dir_to_world(&axis).normalize()),
+        _ => unit,
+    };
+    Some(Target {
+        element_id: first.id(),
+        group_transform: first.group_transform().cloned(),
+        pivot,
+        axes,
+        length: GIZMO_SIZE * depth * screen_width(scene) / SCREEN_WIDTH as f64,
+    })
+}
+
+// Width of the screen at a distance of 1 from the camera, like in `get_ray`
+fn screen_width(scene: &Scene) -> f64 {
+    (scene.camera().fov() / 2.).tan() * 2.
+}
+
+pub(super) fn project(scene: &Scene, point: &Vec3) -> Option<(f64, f64)> {
+    let camera = scene.camera();
+    let to_point = point - camera.pos();
+    let depth = to_point.dot(camera.dir());
+    if depth <= 0.01 {
+        return None;
+    }
+    let width = screen_width(scene);
+    let height = width * SCREEN_HEIGHT as f64 / SCREEN_WIDTH as f64;
+    Some((
+        (to_point.dot(camera.u()) / depth / width + 0.5) * SCREEN_WIDTH as f64,
+        (0.5 - to_point.dot(camera.v()) / depth / height) * SCREEN_HEIGHT as f64,
+    ))
+}
+
+// Direction of the ray going through a point of the screen, without the jitter of the antialiasing
+pub(super) fn mouse_ray(scene: &Scene, mouse: (f64, f64)) -> Vec3 {
+    let camera = scene.camera();
+    let width = screen_width(scene);
+    let height = width * SCREEN_HEIGHT as f64 / SCREEN_WIDTH as f64;
+    (camera.dir()
+        + camera.u() * (width * (mouse.0 / SCREEN_WIDTH as f64 - 0.5))
+        + camera.v() * (height * (0.5 - mouse.1 / SCREEN_HEIGHT as f64)))
+        .normalize()
+}
+
+/**
+ * Handles of the gizmo as lines on the screen, the ones grabbed first when they overlap coming first.
+ */
+fn handles(scene: &Scene, mode: GizmoMode, target: &Target) -> Vec<(GizmoHandle, Vec<(f64, f64)>)> {
+    let pivot = target.pivot;
+    let axes = &target.axes;
+    let length = target.length;
+    let mut handles: Vec<(GizmoHandle, Vec<Vec3>)> = vec![];
+    match mode {
+        GizmoMode::Translate => {
+            for k in 0..3 {
+                let (i, j) = (axes[(k + 1) % 3] * length, axes[(k + 2) % 3] * length);
+                let square = [(0.25, 0.25), (0.4, 0.25), (0.4, 0.4), (0.25, 0.4), (0.25, 0.25)];
+                handles.push((GizmoHandle::Plane(k), square.iter().map(|(a, b)| pivot + i * *a + j * *b).collect()));
+            }
+            for (k, axis) in axes.iter().enumerate() {
+                handles.push((GizmoHandle::Axis(k), vec![pivot, pivot + axis * length]));
+            }
+        }
+        GizmoMode::Rotate => {
+            for k in 0..3 {
+                let (i, j) = (axes[(k + 1) % 3] * (length * 0.8), axes[(k + 2) % 3] * (length * 0.8));
+                let circle = (0..=CIRCLE_SEGMENTS).map(|n| {
+                    let angle = n as f64 * std::f64::consts::TAU / CIRCLE_SEGMENTS as f64;
+                    pivot + i * angle.cos() + j * angle.sin()
+                }).collect();
+                handles.push((GizmoHandle::Axis(k), circle));
+            }
+        }
+        GizmoMode::Scale => {
+            for (k, axis) in axes.iter().enumerate() {
+                handles.push((GizmoHandle::Axis(k), vec![pivot, pivot + axis * length]));
+            }
+        }
+    }
+    let mut projected: Vec<(GizmoHandle, Vec<(f64, f64)>)> = handles.into_iter()
+        .filter_map(|(handle, points)| Some((handle, points.iter().map(|point| project(scene, point)).collect::<Option<Vec<_>>>()?)))
+        .collect();
+    // The center square is drawn on the screen, it keeps its size
+    if let (GizmoMode::Scale, Some(center)) = (mode, project(scene, &pivot)) {
+        let size = GIZMO_PICK_DISTANCE;
+        let square = [(-size, -size), (size, -size), (size, size), (-size, size), (-size, -size)];
+        projected.insert(0, (GizmoHandle::Uniform, square.iter().map(|(x, y)| (center.0 + x, center.1 + y)).collect()));
+    }
+    projected
+}
+
+fn distance_to_segment(point: (f64, f64), a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let length2 = dx * dx + dy * dy;
+    let t = match length2 > 0. {
+        true => (((point.0 - a.0) * dx + (point.1 - a.1) * dy) / length2).clamp(0., 1.),
+        false => 0.,
+    };
+    ((a.0 + t * dx - point.0).powi(2) + (a.1 + t * dy - point.1).powi(2)).sqrt()
+}
+
+// Inside a closed line
+fn is_inside(point: (f64, f64), polygon: &[(f64, f64)]) -> bool {
+    let mut inside = false;
+    for edge in polygon.windows(2) {
+        let ((x1, y1), (x2, y2)) = (edge[0], edge[1]);
+        if (y1 > point.1) != (y2 > point.1) && point.0 < x1 + (point.1 - y1) * (x2 - x1) / (y2 - y1) {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+fn pick_handle(scene: &Scene, mode: GizmoMode, target: &Target, mouse: (f64, f64)) -> Option<GizmoHandle> {
+    let mut picked = None;
+    let mut min_distance = GIZMO_PICK_DISTANCE;
+    for (handle, points) in handles(scene, mode, target) {
+        let closed = points.len() > 2 && points.first() == points.last() && mode != GizmoMode::Rotate;
+        let distance = match closed && is_inside(mouse, &points) {
+            true => 0.,
+            false => points.windows(2).map(|segment| distance_to_segment(mouse, segment[0], segment[1])).fold(f64::MAX, f64::min),
+        };
+        if distance < min_distance {
+            min_distance = distance;
+            picked = Some(handle);
+        }
+    }
+    picked
+}
+
+// Point of the axis closest to the ray of the mouse, or point of the plane under it
+fn grabbed_point(scene: &Scene, handle: GizmoHandle, target: &Target, mouse: (f64, f64)) -> Option<Vec3> {
+    let origin = *scene.camera().pos();
+    let dir = mouse_ray(scene, mouse);
+    let to_pivot = target.pivot - origin;
+    match handle {
+        GizmoHandle::Axis(k) => {
+            let axis = target.axes[k];
+            let cos = axis.dot(&dir);
+            let denominator = 1. - cos * cos;
+            if denominator < 1e-6 {
+                return None;
+            }
+            let t = (cos * dir.dot(&to_pivot) - axis.dot(&to_pivot)) / denominator;
+            Some(target.pivot + axis * t)
+        }
+        GizmoHandle::Plane(k) => {
+            let normal = target.axes[k];
+            let cos = dir.dot(&normal);
+            if cos.abs() < 1e-6 {
+                return None;
+            }
+            let dist = to_pivot.dot(&normal) / cos;
+            match dist > 0. {
+                true => Some(origin + dir * dist),
+                false => None,
+            }
+        }
+        GizmoHandle::Uniform => None,
+    }
+}
+
+fn snap(value: f64, step: f64) -> f64 {
+    (value / step).round() * step
+}
+
+/**
+ * Transform of the dragged element with the mouse at the given position, computed from the one it had when the drag
+ * started.
+ */
+fn dragged_transform(scene: &Scene, drag: &GizmoDrag, snapping: bool, mouse: (f64, f64)) -> Option<Transform> {
+    let target = &drag.target;
+    let mut transform = drag.start_transform.clone();
+    match (drag.mode, drag.handle) {
+        (GizmoMode::Translate, handle) => {
+            let point = grabbed_point(scene, handle, target, mouse)?;
+            let mut moved = target.pivot + (point - drag.start_point?);
+            if snapping {
+                let constrained = match handle {
+                    GizmoHandle::Axis(k) => vec![k],
+                    GizmoHandle::Plane(k) => vec![(k + 1) % 3, (k + 2) % 3],
+                    GizmoHandle::Uniform => vec![],
+                };
+                for k in constrained {
+                    match k {
+                        0 => moved.set_x(snap(*moved.x(), GIZMO_GRID)),
+                        1 => moved.set_y(snap(*moved.y(), GIZMO_GRID)),
+                        _ => moved.set_z(snap(*moved.z(), GIZMO_GRID)),
+                    }
+                }
+            }
+            let delta = moved - target.pivot;
+            let delta = match &target.group_transform {
+                Some(group_transform) => group_transform.dir_to_local(&delta),
+                None => delta,
+            };
+            transform.set_translation(transform.translation() + delta);
+        }
+        (GizmoMode::Rotate, GizmoHandle::Axis(k)) => {
+            let center = project(scene, &target.pivot)?;
+            let angle = |mouse: (f64, f64)| (mouse.1 - center.1).atan2(mouse.0 - center.0);
+            let mut degrees = (angle(mouse) - angle(drag.start_mouse)).to_degrees();
+            // Seen from the tip of the axis the rotation is counterclockwise, and the y axis of the screen goes down
+            if target.axes[k].dot(scene.camera().dir()) < 0. {
+                degrees = -degrees;
+            }
+            if snapping {
+                degrees = snap(degrees, GIZMO_ANGLE_STEP);
+            }
+            let (axis, pivot) = match &target.group_transform {
+                Some(group_transform) => (group_transform.dir_to_local(&target.axes[k]), group_transform.point_to_local(&target.pivot)),
+                None => (target.axes[k], target.pivot),
+            };
+            let rotation = Transform::from_quaternion(
+                Vec3::from_value(0.),
+                &Quaternion::new_from_axis_angle(&axis, degrees.to_radians()),
+                Vec3::from_value(1.),
+            );
+            // The element turns around the pivot
+            transform = rotation.compose(&transform);
+            transform.set_translation(transform.translation() + pivot - rotation.dir_to_world(&pivot));
+        }
+        (GizmoMode::Scale, handle) => {
+            let (dx, dy) = (mouse.0 - drag.start_mouse.0, mouse.1 - drag.start_mouse.1);
+            let (factor, scaled) = match handle {
+                GizmoHandle::Axis(k) => {
+                    let center = project(scene, &target.pivot)?;
+                    let end = project(scene, &(target.pivot + target.axes[k] * target.length))?;
+                    let (ax, ay) = (end.0 - center.0, end.1 - center.1);
+                    let length2 = ax * ax + ay * ay;
+                    if length2 < 1. {
+                        return None;
+                    }
+                    (1. + (dx * ax + dy * ay) / length2, vec![k])
+                }
+                _ => (1. + (dx - dy) / GIZMO_SIZE, vec![0, 1, 2]),
+            };
+            let start = drag.start_transform.scale();
+            let mut scale = [*start.x(), *start.y(), *start.z()];
+            for k in scaled {
+                scale[k] *= factor.max(0.01);
+                if snapping {
+                    scale[k] = snap(scale[k], GIZMO_SCALE_STEP).max(GIZMO_SCALE_STEP);
+                }
+            }
+            transform.set_scale(Vec3::new(scale[0], scale[1], scale[2]));
+        }
+        _ => return None,
+    }
+    Some(transform)
+}
+
+/**
+ * Grabs the handle under the mouse, returns false if there is none.
+ */
+pub fn start_gizmo_drag(context: &mut UIContext, ui: &UI) -> bool {
+    let (x, y) = ui.mouse_position();
+    let on_scene = x < context.ui_img.width() && y < context.ui_img.height() && context.ui_img.get_pixel(x, y).0 == [1; 4];
+    if !on_scene || ui.editing().is_some() || ui.active_box_reference() != "" {
+        return false;
+    }
+    let (Some(subject), Some(scene)) = (selected_subject(ui), context.get_active_scene()) else {
+        return false;
+    };
+    let mode = context.gizmo.mode;
+    let mouse = (x as f64, y as f64);
+    let drag = {
+        let scene = scene.read().unwrap();
+        let Some(target) = target(&scene, subject, mode) else {
+            return false;
+        };
+        let Some(handle) = pick_handle(&scene, mode, &target, mouse) else {
+            return false;
+        };
+        let start_point = grabbed_point(&scene, handle, &target, mouse);
+        if mode == GizmoMode::Translate && start_point.is_none() {
+            return false;
+        }
+        let Some(element) = scene.elements().get(target.element_id) else {
+            return false;
+        };
+        let start_transform = match scene.composed_element_by_element_id(element.id()) {
+            Some(composed_element) => composed_element.transform().cloned(),
+            None => element.transform().cloned(),
+        }.unwrap_or_default();
+        GizmoDrag {
+            subject,
+            mode,
+            handle,
+            start_mouse: mouse,
+            start_transform,
+            target,
+            start_point,
+            before: scene.snapshot(subject),
+        }
+    };
+    context.gizmo.drag = Some(drag);
+    true
+}
+
+/**
+ * Moves the dragged element, the scene is rendered again at a low resolution while it changes.
+ */
+pub fn drag_gizmo(context: &UIContext, mouse: (u32, u32)) {
+    let (Some(scene), Some(drag)) = (context.get_active_scene(), &context.gizmo.drag) else {
+        return;
+    };
+    let mut scene = scene.write().unwrap();
+    if let Some(transform) = dragged_transform(&scene, drag, context.gizmo.snap, (mouse.0 as f64, mouse.1 as f64)) {
+        apply_transform(&mut scene, drag.target.element_id, |current| *current = transform);
+        scene.set_dirty(true);
+    }
+}
+
+/**
+ * Records the drag in the history and shows the new transform in the element box, returns false if nothing was
+ * dragged.
+ */
+pub fn end_gizmo_drag(context: &mut UIContext, ui: &mut UI) -> bool {
+    let Some(drag) = context.gizmo.drag.take() else {
+        return false;
+    };
+    let Some(scene) = context.get_active_scene() else {
+        return true;
+    };
+    let element = {
+        let mut scene_write = scene.write().unwrap();
+        let name = format!("{} {}", drag.mode.verb(), drag.subject.label(&scene_write));
+        let after = scene_write.snapshot(drag.subject);
+        scene_write.record(name, drag.before, after);
+        scene_write.elements().get(drag.target.element_id).cloned()
+    };
+    if let Some(element) = element {
+        setup_element_ui(&element, ui, scene);
+    }
+    true
+}
+
+/**
+ * Draws the gizmo on the pixels of the scene, under the boxes of the UI.
+ */
+pub fn draw_gizmo(image: &mut RgbaImage, context: &UIContext, subject: Option<EditSubject>) {
+    let (Some(subject), Some(scene)) = (subject, context.get_active_scene()) else {
+        return;
+    };
+    let scene = scene.read().unwrap();
+    let mode = context.gizmo.mode;
+    // While dragging, the gizmo keeps the axes it had at the start
+    let target = match &context.gizmo.drag {
+        Some(drag) => target(&scene, subject, mode).map(|target| Target { axes: drag.target.axes, ..target }),
+        None => target(&scene, subject, mode),
+    };
+    let Some(target) = target else {
+        return;
+    };
+    let dragged = context.gizmo.drag.as_ref().map(|drag| drag.handle);
+    for (handle, points) in handles(&scene, mode, &target) {
+        let color = match handle {
+            _ if dragged == Some(handle) => DRAGGED_COLOR,
+            GizmoHandle::Axis(k) | GizmoHandle::Plane(k) => AXIS_COLORS[k],
+            GizmoHandle::Uniform => UNIFORM_COLOR,
+        };
+        for segment in points.windows(2) {
+            draw_line(image, context, segment[0], segment[1], color);
+        }
+    }
+}
+
+fn draw_line(image: &mut RgbaImage, context: &UIContext, from: (f64, f64), to: (f64, f64), color: [u8; 4]) {
+    let steps = (to.0 - from.0).abs().max((to.1 - from.1).abs()).ceil().min(4. * SCREEN_WIDTH as f64) as usize;
+    for step in 0..=steps {
+        let t = match steps {
+            0 => 0.,
+            _ => step as f64 / steps as f64,
+        };
+        let (x, y) = (from.0 + (to.0 - from.0) * t, from.1 + (to.1 - from.1) * t);
+        if x < 0. || y < 0. || x >= image.width() as f64 || y >= image.height() as f64 {
+            continue;
+        }
+        let (x, y) = (x as u32, y as u32);
+        if context.ui_img.get_pixel(x, y).0 == [1; 4] {
+            image.get_pixel_mut(x, y).0 = color;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        model::{history::EditSubject, maths::vec3::Vec3, objects::camera::Camera, scene::Scene},
+        parsing::add_object
+    };
+    use super::{dragged_transform, grabbed_point, mouse_ray, pick_handle, project, target, GizmoDrag, GizmoMode};
+
+    fn drag(scene: &Scene, mode: GizmoMode, from: Vec3, to: Vec3, snapping: bool) -> Vec3 {
+        let subject = EditSubject::Element(0);
+        let target = target(scene, subject, mode).unwrap();
+        let start_mouse = project(scene, &from).unwrap();
+        let handle = pick_handle(scene, mode, &target, start_mouse).unwrap();
+        let drag = GizmoDrag {
+            subject,
+            mode,
+            handle,
+            start_mouse,
+            start_transform: Default::default(),
+            start_point: grabbed_point(scene, handle, &target, start_mouse),
+            target,
+            before: None,
+        };
+        let transform = dragged_transform(scene, &drag, snapping, project(scene, &to).unwrap()).unwrap();
+        transform.point_to_world(&drag.target.pivot)
+    }
+
+    #[test]
+    fn test_gizmo_drag() {
+        let mut scene = Scene::new();
+        scene.set_camera(Camera::new(Vec3::new(1., 2., -10.), Vec3::new(0.1, -0.2, 1.).normalize(), 60.));
+        add_object(&mut scene, "sphere").unwrap();
+        let pivot = target(&scene, EditSubject::Element(0), GizmoMode::Translate).unwrap().pivot;
+        let length = target(&scene, EditSubject::Element(0), GizmoMode::Translate).unwrap().length;
+
+        // The ray of the projection of a point goes through it
+        let point = Vec3::new(2., -1., 3.);
+        let ray = mouse_ray(&scene, project(&scene, &point).unwrap());
+        assert!((ray.dot(&(point - scene.camera().pos()).normalize()) - 1.).abs() < 1e-9);
+
+        // Along the x axis
+        let x = Vec3::new(1., 0., 0.);
+        let moved = drag(&scene, GizmoMode::Translate, pivot + x * (length * 0.7), pivot + x * (length * 0.7 + 1.3), false);
+        assert!((moved - pivot - x * 1.3).length() < 1e-6);
+        let snapped = drag(&scene, GizmoMode::Translate, pivot + x * (length * 0.7), pivot + x * (length * 0.7 + 1.3), true);
+        assert!((*snapped.x() / 0.25 - (*snapped.x() / 0.25).round()).abs() < 1e-9);
+
+        // The element turns around its centre
+        let z = Vec3::new(0., 0., 1.);
+        let turned = drag(&scene, GizmoMode::Rotate, pivot + z * (length * 0.8), pivot + x * (length * 0.8), false);
+        assert!((turned - pivot).length() < 1e-6);
+    }
+}
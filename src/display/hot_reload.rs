@@ -0,0 +1,124 @@
+use std::{fs::metadata, time::SystemTime};
+use crate::{
+    model::{objects::camera::Camera, scene::Scene},
+    parsing::{get_scene, get_scene_includes},
+    ui::{ui::UI, utils::ui_utils::UIContext},
+    ELEMENT, SETTINGS
+};
+use super::ui_setup::{refresh_objects_ui, setup_settings, show_error};
+
+/**
+ * Files an opened scene was made from with their last modification, and the camera its file gave. The scene is
+ * parsed again when one of them changes.
+ */
+pub struct SceneWatch {
+    files: Vec<(String, Option<SystemTime>)>,
+    camera: Camera,
+}
+
+impl SceneWatch {
+    // Constructor
+    pub fn new(scene: &Scene) -> Self {
+        Self { files: with_times(scene_files(scene)), camera: scene.camera().clone() }
+    }
+
+    // Methods
+    fn changed(&self) -> bool {
+        self.files.iter().any(|(file, last_modified)| modified(file) != *last_modified)
+    }
+
+    /**
+     * Watches the new files too, and takes the current modification of every file as the last one seen.
+     */
+    fn refresh(&mut self, new_files: Vec<String>) {
+        let files = self.files.drain(..).map(|(file, _)| file).chain(new_files).collect();
+        self.files = with_times(files);
+    }
+}
+
+fn with_times(mut files: Vec<String>) -> Vec<(String, Option<SystemTime>)> {
+    files.sort();
+    files.dedup();
+    files.into_iter()
+        .map(|file| {
+            let modified = modified(&file);
+            (file, modified)
+        })
+        .collect()
+}
+
+fn modified(file: &str) -> Option<SystemTime> {
+    metadata(file).and_then(|metadata| metadata.modified()).ok()
+}
+
+// The scene file, its includes, its OBJ files and its textures. The generated textures have no file and never change
+fn scene_files(scene: &Scene) -> Vec<String> {
+    let mut files: Vec<String> = scene.path().into_iter().chain(scene.includes()).cloned().collect();
+    files.extend(scene.composed_elements().iter()
+        .filter_map(|composed_element| composed_element.composed_shape().as_obj())
+        .map(|obj| obj.filepath().clone()));
+    files.extend(scene.textures().keys().cloned());
+    files
+}
+
+fn same_view(camera: &Camera, other: &Camera) -> bool {
+    camera.pos() == other.pos() && camera.dir() == other.dir() && camera.fov() == other.fov() && camera.roll() == other.roll()
+}
+
+/**
+ * Parses again the opened scenes whose files changed, and puts the new version in place of the previous one, whose
+ * render starts over. The camera stays where the user moved it, unless the scene file moved it too. After an error the
+ * previous version stays, until the files change again.
+ */
+pub fn reload_changed_scenes(ui: &mut UI, context: &mut UIContext) {
+    let changed: Vec<usize> = context.scene_watches.iter()
+        .filter(|(_, watch)| watch.changed())
+        .map(|(scene_id, _)| *scene_id)
+        .collect();
+    for scene_id in changed {
+        let Some(scene) = context.scene_list.get(&scene_id).cloned() else {
+            context.scene_watches.remove(&scene_id);
+            continue;
+        };
+        let Some(path) = scene.read().unwrap().path().cloned() else {
+            continue;
+        };
+        let mut new_scene = match get_scene(&path) {
+            Ok(new_scene) => new_scene,
+            Err(err) => {
+                // The times are refreshed so that the error isn't shown again at every check, and the files included by
+                // the broken version are watched too, so that fixing one of them reloads the scene
+                if let Some(watch) = context.scene_watches.get_mut(&scene_id) {
+                    watch.refresh(get_scene_includes(&path));
+                }
+                show_error(ui, context, &format!("Error reloading scene : {}", err));
+                continue;
+            }
+        };
+        new_scene.prepare_render();
+        let watch = SceneWatch::new(&new_scene);
+
+        let mut scene_write = scene.write().unwrap();
+        let moved = context.scene_watches.get(&scene_id).is_some_and(|previous| !same_view(&previous.camera, scene_write.camera()));
+        if moved && same_view(&watch.camera, &context.scene_watches[&scene_id].camera) {
+            new_scene.set_camera(scene_write.camera().clone());
+        }
+        new_scene.set_paused(scene_write.paused());
+        *scene_write = new_scene;
+        scene_write.set_dirty(true);
+        drop(scene_write);
+        context.scene_watches.insert(scene_id, watch);
+
+        if context.active_scene == Some(scene_id) {
+            ui.destroy_box(ELEMENT);
+            refresh_objects_ui(ui, context);
+            if ui.get_box(SETTINGS).is_some() {
+                ui.destroy_box(SETTINGS);
+                setup_settings(ui, context);
+            }
+            context.history_shown = None;
+            context.selection.invalidate();
+            ui.set_dirty();
+        }
+    }
+}
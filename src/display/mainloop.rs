@@ -1,6 +1,6 @@
 use super::{
     display::redraw_if_necessary,
-    events::{handle_event, key_held}, ui_setup::setup_ui,
+    events::{handle_event, key_held}, hot_reload::{reload_changed_scenes, SceneWatch}, selection::refresh_selection, ui_setup::{refresh_history_ui, setup_ui, show_error},
 };
 use pixels::Pixels;
 use std::{
@@ -13,25 +13,22 @@ use winit::{
     window::WindowBuilder,
 };
 use crate::{
-    error, parsing::get_scene, render::render_thread::UIOrder, ui::{ui::UI, ui_setup::scene_ui::{add_scene_to_ui, change_scene}, utils::ui_utils::UIContext}, DISPLAY_WIREFRAME, SCREEN_HEIGHT, SCREEN_HEIGHT_U32, SCREEN_WIDTH, SCREEN_WIDTH_U32
+    parsing::get_scene, render::render_thread::UIOrder, ui::{ui::UI, ui_setup::scene_ui::{add_scene_to_ui, change_scene}, utils::ui_utils::UIContext}, HOT_RELOAD_TIME, SCREEN_HEIGHT, SCREEN_HEIGHT_U32, SCREEN_WIDTH, SCREEN_WIDTH_U32
 };
 
 pub fn load_scene(scene_path: &str, context: &mut UIContext, ui: &mut UI) {
     let path = String::from(scene_path);
     let scene = get_scene(&path);
     if let Err(err) = scene {
-        error(format!("Error loading scene : {}", err).as_str());
+        show_error(ui, context, &format!("Error loading scene : {}", err));
         return ;
     }
     let mut scene = scene.unwrap();
-    
-    if DISPLAY_WIREFRAME {
-        scene.add_wireframes();
-    }
-    scene.update_bvh();
-    scene.determine_full_bvh_traversal();
+    scene.prepare_render();
+    context.scene_watches.insert(context.next_scene_id, SceneWatch::new(&scene));
     let scene = Arc::new(RwLock::new(scene));
     context.transmitter.send(UIOrder::NewScene(scene.clone())).unwrap();
+    context.transmitter.send(UIOrder::Resume(context.next_scene_id)).unwrap();
     context.scene_list.insert(context.next_scene_id, scene);
     context.transmitter.send(UIOrder::AskImage(context.next_scene_id)).unwrap();
     context.image_asked = true;
@@ -61,6 +58,7 @@ pub fn main_loop(event_loop: EventLoop<()>, mut pixels: Pixels) {
     let mut last_draw = Instant::now();
     let mut last_input = Instant::now();
     let mut last_scene_change = Instant::now();
+    let mut last_reload_check = Instant::now();
 
     for argument in std::env::args().skip(1) {
         load_scene(argument.as_str(), &mut context, &mut ui);
@@ -78,6 +76,16 @@ pub fn main_loop(event_loop: EventLoop<()>, mut pixels: Pixels) {
                 last_draw = Instant::now();
             }
 
+            // The opened scenes are parsed again when their files change
+            if last_reload_check.elapsed().as_millis() > HOT_RELOAD_TIME as u128 {
+                reload_changed_scenes(&mut ui, &mut context);
+                last_reload_check = Instant::now();
+            }
+
+            // The history box follows the edits of the active scene
+            refresh_history_ui(&mut ui, &mut context);
+            refresh_selection(&mut ui, &mut context);
+
             // We handle every held inputs every 20ms. This basically is only used to handle camera movements
             if ui.editing().is_none()
                 && ui.inputs().len() > 0
@@ -105,8 +113,10 @@ pub fn main_loop(event_loop: EventLoop<()>, mut pixels: Pixels) {
                     // We overlay the previous context, so the compiler drops it when we stop using it (after the transmitter send). This allows us to borrow it mutable the line after.wrap();
                     let scene = context.scene_list.get(&active_scene_index).unwrap();
                     if scene.read().unwrap().dirty() {
+                        scene.write().unwrap().refit_bvh();
                         context.transmitter.send(UIOrder::SceneChange(active_scene_index)).unwrap();
                         scene.write().unwrap().set_dirty(false);
+                        context.selection.invalidate();
                         last_scene_change = Instant::now();
                         context.final_img = false;
                     }
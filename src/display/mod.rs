@@ -7,4 +7,8 @@ pub mod display;
 pub mod events;
 pub mod ui_setup;
 pub mod anaglyph;
-pub mod filters;
\ No newline at end of file
+pub mod filters;
+pub mod gizmo;
+pub mod camera_controls;
+pub mod selection;
+pub mod hot_reload;
\ No newline at end of file
@@ -0,0 +1,260 @@
+use std::collections::HashSet;
+use image::RgbaImage;
+use crate::{
+    model::{element::Element, history::EditSubject, maths::{ray::Ray, vec3::Vec3}, scene::Scene},
+    render::raycasting::get_closest_hit,
+    ui::{ui::UI, utils::ui_utils::UIContext},
+    ELEMENT, SCREEN_HEIGHT, SCREEN_WIDTH, SELECTION_CELL_SIZE
+};
+use super::{
+    gizmo::{mouse_ray, project, selected_subject},
+    ui_setup::{highlight_outliner, setup_element_ui}
+};
+
+const OUTLINE_COLOR: [u8; 4] = [255, 140, 0, 255];
+
+/**
+ * Elements and composed elements selected in the viewport or in the outliner. The last one is opened in the element
+ * box, with the gizmo, and the selection follows this box when it is opened or closed from elsewhere.
+ */
+#[derive(Default)]
+pub struct Selection {
+    subjects: Vec<EditSubject>,
+    mask: Option<SelectionMask>,
+}
+
+impl Selection {
+    // Accessors
+    pub fn subjects(&self) -> &Vec<EditSubject> {
+        &self.subjects
+    }
+
+    // Methods
+    /**
+     * The outline is computed again after the scene or its camera changed.
+     */
+    pub fn invalidate(&mut self) {
+        self.mask = None;
+    }
+}
+
+// Object-ID buffer of a rectangle of the screen around the selection, reduced to whether each cell shows a selected
+// element
+struct SelectionMask {
+    scene_id: usize,
+    // First cell and size of the rectangle, in cells
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+    cells: Vec<bool>,
+}
+
+impl SelectionMask {
+    fn selected(&self, x: isize, y: isize) -> bool {
+        x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height
+            && self.cells[y as usize * self.width + x as usize]
+    }
+}
+
+// The element box opens the whole composed element of an element
+fn normalize(scene: &Scene, subject: EditSubject) -> EditSubject {
+    match subject {
+        EditSubject::Element(id) => match scene.element_by_id(id).and_then(|element| element.composed_id()) {
+            Some(composed_id) => EditSubject::ComposedElement(composed_id),
+            None => subject,
+        },
+        _ => subject,
+    }
+}
+
+pub(super) fn subject_elements(scene: &Scene, subject: EditSubject) -> Vec<usize> {
+    match subject {
+        EditSubject::Element(id) if id < scene.elements().len() => vec![id],
+        EditSubject::ComposedElement(composed_id) => scene.composed_elements().get(composed_id)
+            .map(|composed_element| composed_element.elements_index().clone())
+            .unwrap_or_default(),
+        _ => vec![],
+    }
+}
+
+/**
+ * Selects an element and opens it, or with `add` adds it to the selection, or removes it if it was already selected.
+ */
+pub fn select(ui: &mut UI, context: &mut UIContext, subject: EditSubject, add: bool) {
+    let Some(scene) = context.get_active_scene().cloned() else {
+        return;
+    };
+    let scene_read = scene.read().unwrap();
+    let subject = normalize(&scene_read, subject);
+    let subjects = &mut context.selection.subjects;
+    match (add, subjects.iter().position(|selected| *selected == subject)) {
+        (true, Some(index)) => {
+            subjects.remove(index);
+        }
+        (true, None) => subjects.push(subject),
+        (false, _) => *subjects = vec![subject],
+    }
+    context.selection.invalidate();
+
+    let opened: Option<Element> = context.selection.subjects.last()
+        .and_then(|last| subject_elements(&scene_read, *last).first().copied())
+        .and_then(|id| scene_read.element_by_id(id).cloned());
+    drop(scene_read);
+    match opened {
+        Some(element) => setup_element_ui(&element, ui, &scene),
+        None => ui.destroy_box(ELEMENT),
+    }
+    highlight_outliner(ui, context.selection.subjects());
+    ui.set_dirty();
+}
+
+/**
+ * Follows the element box, and computes the outline of the selection when it's missing.
+ */
+pub fn refresh_selection(ui: &mut UI, context: &mut UIContext) {
+    let selection = &mut context.selection;
+    let changed = match selected_subject(ui) {
+        None => {
+            let changed = !selection.subjects.is_empty();
+            selection.subjects.clear();
+            changed
+        }
+        Some(opened) if selection.subjects.last() != Some(&opened) => {
+            // Opened alone from elsewhere, or brought to the front of the selection
+            match selection.subjects.iter().position(|selected| *selected == opened) {
+                Some(index) => {
+                    selection.subjects.remove(index);
+                }
+                None => selection.subjects.clear(),
+            }
+            selection.subjects.push(opened);
+            true
+        }
+        Some(_) => false,
+    };
+    if changed {
+        selection.invalidate();
+        highlight_outliner(ui, context.selection.subjects());
+        ui.set_dirty();
+    }
+
+    let (Some(scene_id), Some(scene)) = (context.active_scene, context.get_active_scene()) else {
+        return;
+    };
+    let selection = &context.selection;
+    let up_to_date = selection.mask.as_ref().is_some_and(|mask| mask.scene_id == scene_id);
+    if selection.subjects.is_empty() || up_to_date {
+        return;
+    }
+    let mask = compute_mask(&scene.read().unwrap(), scene_id, &selection.subjects);
+    context.selection.mask = Some(mask);
+    ui.set_dirty();
+}
+
+/**
+ * Casts a ray per cell of the rectangle of the screen around the selected elements, the whole screen if one of them is
+ * infinite or partly behind the camera.
+ */
+fn compute_mask(scene: &Scene, scene_id: usize, subjects: &[EditSubject]) -> SelectionMask {
+    let ids: HashSet<usize> = subjects.iter().flat_map(|subject| subject_elements(scene, *subject)).collect();
+    let mut bounds = Some((f64::MAX, f64::MAX, f64::MIN, f64::MIN));
+    for id in &ids {
+        let corners = scene.elements()[*id].aabb()
+            .filter(|aabb| aabb.volume().is_finite())
+            .and_then(|aabb| (0..8).map(|i| project(scene, &Vec3::new(
+                if i & 1 == 0 { aabb.x_min() } else { aabb.x_max() },
+                if i & 2 == 0 { aabb.y_min() } else { aabb.y_max() },
+                if i & 4 == 0 { aabb.z_min() } else { aabb.z_max() },
+            ))).collect::<Option<Vec<(f64, f64)>>>());
+        bounds = match (bounds, corners) {
+            (Some(bounds), Some(corners)) => Some(corners.iter().fold(bounds, |(x_min, y_min, x_max, y_max), (x, y)| {
+                (x_min.min(*x), y_min.min(*y), x_max.max(*x), y_max.max(*y))
+            })),
+            _ => None,
+        };
+    }
+
+    let cell = SELECTION_CELL_SIZE as f64;
+    let (columns, rows) = (SCREEN_WIDTH.div_ceil(SELECTION_CELL_SIZE), SCREEN_HEIGHT.div_ceil(SELECTION_CELL_SIZE));
+    // One more cell around, so that the outline is closed
+    let (x_min, y_min, x_max, y_max) = match bounds {
+        Some((x_min, y_min, x_max, y_max)) => (
+            ((x_min / cell).floor() - 1.).clamp(0., columns as f64) as usize,
+            ((y_min / cell).floor() - 1.).clamp(0., rows as f64) as usize,
+            ((x_max / cell).ceil() + 1.).clamp(0., columns as f64) as usize,
+            ((y_max / cell).ceil() + 1.).clamp(0., rows as f64) as usize,
+        ),
+        None => (0, 0, columns, rows),
+    };
+    let (width, height) = (x_max.saturating_sub(x_min), y_max.saturating_sub(y_min));
+
+    let origin = *scene.camera().pos();
+    let cells = (0..width * height).map(|index| {
+        let (x, y) = (x_min + index % width, y_min + index / width);
+        let ray = Ray::new(origin, mouse_ray(scene, ((x as f64 + 0.5) * cell, (y as f64 + 0.5) * cell)), 0);
+        get_closest_hit(scene, &ray).is_some_and(|hit| ids.contains(&hit.element().id()))
+    }).collect();
+    SelectionMask { scene_id, x: x_min, y: y_min, width, height, cells }
+}
+
+/**
+ * Draws the outline of the selected elements on the pixels of the scene, where their cells touch other ones.
+ */
+pub fn draw_selection(image: &mut RgbaImage, context: &UIContext) {
+    let Some(mask) = &context.selection.mask else {
+        return;
+    };
+    if context.active_scene != Some(mask.scene_id) {
+        return;
+    }
+    for y in 0..mask.height as isize {
+        for x in 0..mask.width as isize {
+            let inside = [(1, 0), (-1, 0), (0, 1), (0, -1)].iter().all(|(dx, dy)| mask.selected(x + dx, y + dy));
+            if !mask.selected(x, y) || inside {
+                continue;
+            }
+            for dy in 0..SELECTION_CELL_SIZE {
+                for dx in 0..SELECTION_CELL_SIZE {
+                    let pixel_x = ((mask.x + x as usize) * SELECTION_CELL_SIZE + dx) as u32;
+                    let pixel_y = ((mask.y + y as usize) * SELECTION_CELL_SIZE + dy) as u32;
+                    if pixel_x < image.width() && pixel_y < image.height() && context.ui_img.get_pixel(pixel_x, pixel_y).0 == [1; 4] {
+                        image.get_pixel_mut(pixel_x, pixel_y).0 = OUTLINE_COLOR;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        model::{history::EditSubject, maths::vec3::Vec3, objects::camera::Camera, scene::Scene},
+        parsing::add_object
+    };
+    use super::compute_mask;
+
+    #[test]
+    fn test_selection_mask() {
+        let mut scene = Scene::new();
+        scene.set_camera(Camera::new(Vec3::new(0., 0., -5.), Vec3::new(0., 0., 1.), 60.));
+        add_object(&mut scene, "sphere").unwrap();
+        add_object(&mut scene, "helix").unwrap();
+
+        // The sphere hides the helix, which is behind it at the same place
+        let sphere = compute_mask(&scene, 0, &[EditSubject::Element(0)]);
+        assert!(sphere.width < 1800 / 2 && sphere.cells.iter().any(|selected| *selected));
+        let helix = EditSubject::ComposedElement(0);
+        let hidden_helix = compute_mask(&scene, 0, &[helix]).cells.iter().filter(|selected| **selected).count();
+
+        scene.set_element_visible(0, false);
+        assert!(compute_mask(&scene, 0, &[EditSubject::Element(0)]).cells.iter().all(|selected| !selected));
+        assert!(compute_mask(&scene, 0, &[helix]).cells.iter().filter(|selected| **selected).count() > hidden_helix);
+
+        // The elements generated again keep the visibility of their composed element
+        scene.set_element_visible(1, false);
+        scene.update_composed_element_shape(0);
+        assert!(scene.elements().iter().all(|element| !element.visible()));
+    }
+}
@@ -1,11 +1,13 @@
 use std::sync::{Arc, RwLock};
 use image::Rgba;
+use winit::keyboard::{Key, NamedKey};
 
 use crate::{
-    model::{element::Element, materials::texture::Texture, scene::Scene}, render::common::start_threads, ui::{
-        prefabs::{material_ui::get_material_ui, texture_ui::get_texture_ui}, ui::UI, ui_setup::scene_ui::setup_scene_toolbar, uibox::{BoxPosition, UIBox}, uielement::{Category, UIElement}, utils::{misc::ElemType, style::StyleBuilder, ui_utils::UIContext, Displayable}
-    }, ELEMENT, OBJECTS, SCENE_TOOLBAR, SCREEN_HEIGHT_U32, SETTINGS
+    model::{element::Element, history::EditSubject, materials::texture::Texture, objects::lights::light::AnyLight, scene::Scene}, parsing::{add_object, COMPOSED_SHAPE_TYPES, LIGHT_TYPES, SHAPE_TYPES}, render::{common::{default_nb_threads, start_threads}, stats::{human_count, StatsReport}}, ui::{
+        prefabs::{material_ui::get_material_ui, texture_ui::get_texture_ui, transform_ui::{get_group_transform_ui, get_transform_ui}}, ui::UI, ui_setup::scene_ui::setup_scene_toolbar, uibox::{BoxPosition, UIBox}, uielement::{Category, UIElement}, uisettings::UISettings, utils::{misc::ElemType, style::StyleBuilder, ui_utils::UIContext, Displayable}
+    }, error, ELEMENT, ERRORS, HISTORY, OBJECTS, SCENE_TOOLBAR, SCREEN_HEIGHT_U32, SETTINGS, STATS
     };
+use super::selection::select;
 
 pub fn setup_settings(ui: &mut UI, context: &mut UIContext) {
     let scene = match context.active_scene {
@@ -15,9 +17,146 @@ pub fn setup_settings(ui: &mut UI, context: &mut UIContext) {
     let mut settings_box = UIBox::new(SETTINGS, BoxPosition::CenterLeft(10), ui.uisettings().gui_width, ui.uisettings());
     settings_box.add_elements(scene.read().unwrap().settings().get_fields("Render settings", context, ui.uisettings()));
     settings_box.set_edit_bar(ui.uisettings(), None);
+    settings_box.set_edit_subject(EditSubject::Settings);
     ui.add_box(settings_box);
 }
 
+/**
+ * Adds an error to the ones listed in the error box, at the top of the screen until it's closed. The error is also
+ * written on the error output.
+ */
+pub fn show_error(ui: &mut UI, context: &mut UIContext, msg: &str) {
+    error(msg);
+    context.errors.push(msg.to_string());
+    ui.destroy_box(ERRORS);
+    let settings = ui.uisettings().clone();
+    let mut errors_box = UIBox::new(ERRORS, BoxPosition::CenterTop(10), settings.gui_width, &settings);
+    let mut category = Category::default();
+    for (index, err) in context.errors.iter().enumerate() {
+        category.elems.push(UIElement::new(err, &format!("error{}", index), ElemType::Text, &settings));
+    }
+    category.elems.push(UIElement::new("Close", "close", ElemType::Button(Some(Box::new(|_, context, ui| {
+        context.errors.clear();
+        ui.destroy_box(ERRORS);
+    }))), &settings));
+    errors_box.add_elements(vec![UIElement::new("Errors", "list", ElemType::Category(category), &settings)]);
+    ui.add_box(errors_box);
+    ui.set_dirty();
+}
+
+type StatFormat = fn(&StatsReport) -> String;
+
+/**
+ * Stats of the render of the active scene, refreshed with the UI.
+ */
+pub fn setup_stats_ui(ui: &mut UI) {
+    let settings = ui.uisettings().clone();
+    let mut stats_box = UIBox::new(STATS, BoxPosition::BotRight(10, 10), settings.gui_width, &settings);
+    let stats: [(&str, &str, StatFormat); 10] = [
+        ("Camera rays", "camera_rays", |report| human_count(report.counters.camera_rays as f64)),
+        ("Secondary rays", "secondary_rays", |report| human_count(report.counters.secondary_rays as f64)),
+        ("Shadow rays", "shadow_rays", |report| human_count(report.counters.shadow_rays as f64)),
+        ("BVH nodes visited", "bvh_nodes", |report| human_count(report.counters.bvh_nodes as f64)),
+        ("Intersection tests", "intersection_tests", |report| human_count(report.counters.intersection_tests as f64)),
+        ("Samples/s", "samples_per_second", |report| human_count(report.samples_per_second())),
+        ("Iterations", "iterations", |report| format!("{}/{}", report.iterations_done, report.iterations)),
+        ("ETA", "eta", |report| report.eta.map_or("-".to_string(), |eta| format!("{:.0}s", eta.as_secs_f64()))),
+        ("Textures memory", "texture_memory", |report| format!("{:.1} MB", report.texture_memory as f64 / 1e6)),
+        ("BVH memory", "bvh_memory", |report| format!("{:.1} MB", report.bvh_memory as f64 / 1e6)),
+    ];
+    let mut category = Category::default();
+    for (name, reference, format) in stats {
+        category.elems.push(UIElement::new(name, reference, ElemType::Stat(Box::new(move |context, _| {
+            match context.get_active_scene() {
+                Some(scene) => format(&StatsReport::new(&scene.read().unwrap())),
+                None => String::new(),
+            }
+        })), &settings));
+    }
+    stats_box.add_elements(vec![UIElement::new("Render statistics", "render_stats", ElemType::Category(category), &settings)]);
+    ui.add_box(stats_box);
+}
+
+/**
+ * Edits of the active scene, followed by the undone ones. Clicking one undoes or redoes the edits up to it.
+ */
+pub fn setup_history_ui(ui: &mut UI, context: &mut UIContext) {
+    ui.destroy_box(HISTORY);
+    let (Some(scene_id), Some(scene)) = (context.active_scene, context.get_active_scene().cloned()) else {
+        return;
+    };
+    let scene_read = scene.read().unwrap();
+    let history = scene_read.history();
+    let settings = ui.uisettings().clone();
+    let mut history_box = UIBox::new(HISTORY, BoxPosition::BotLeft(10, 10), settings.gui_width, &settings);
+    history_box.max_height = SCREEN_HEIGHT_U32 / 2;
+    let mut category = Category::default();
+    if history.done().is_empty() && history.undone().is_empty() {
+        category.elems.push(UIElement::new("No edit yet", "empty", ElemType::Text, &settings));
+    }
+    let nb_done = history.done().len();
+    for (index, command) in history.done().iter().enumerate() {
+        let steps = nb_done - 1 - index;
+        category.elems.push(UIElement::new(command.name(), &format!("done{}", index), ElemType::Button(Some(Box::new(move |_, context, ui| {
+            step_history(ui, context, false, steps);
+        }))), &settings));
+    }
+    for (index, command) in history.undone().iter().rev().enumerate() {
+        let name = format!("{} (undone)", command.name());
+        category.elems.push(UIElement::new(&name, &format!("undone{}", index), ElemType::Button(Some(Box::new(move |_, context, ui| {
+            step_history(ui, context, true, index + 1);
+        }))), &settings));
+    }
+    history_box.add_elements(vec![UIElement::new("History", "edits", ElemType::Category(category), &settings)]);
+    ui.add_box(history_box);
+    context.history_shown = Some((scene_id, history.version()));
+}
+
+/**
+ * Shows the history again when the active scene was edited since it was shown.
+ */
+pub fn refresh_history_ui(ui: &mut UI, context: &mut UIContext) {
+    if ui.get_box(HISTORY).is_none() {
+        return;
+    }
+    let shown = match (context.active_scene, context.get_active_scene()) {
+        (Some(scene_id), Some(scene)) => Some((scene_id, scene.read().unwrap().history().version())),
+        _ => None,
+    };
+    if shown != context.history_shown {
+        setup_history_ui(ui, context);
+        ui.set_dirty();
+    }
+}
+
+/**
+ * Undoes the last edits of the active scene, or redoes the last undone ones, then builds the boxes showing the scene
+ * again.
+ */
+pub fn step_history(ui: &mut UI, context: &mut UIContext, redo: bool, steps: usize) {
+    let scene = match context.get_active_scene() {
+        Some(scene) => scene.clone(),
+        None => return,
+    };
+    for _ in 0..steps {
+        let mut scene_write = scene.write().unwrap();
+        let name = match redo {
+            true => scene_write.redo(),
+            false => scene_write.undo(),
+        };
+        if name.is_none() {
+            break;
+        }
+    }
+    ui.destroy_box(ELEMENT);
+    refresh_objects_ui(ui, context);
+    if ui.get_box(SETTINGS).is_some() {
+        ui.destroy_box(SETTINGS);
+        setup_settings(ui, context);
+    }
+    ui.set_dirty();
+}
+
 pub fn setup_objects_ui(ui: &mut UI, context: &mut UIContext) {
     let scene = match context.active_scene {
         Some(active_scene_index) => context.scene_list.get(&active_scene_index).unwrap(),
@@ -59,8 +198,21 @@ pub fn setup_objects_ui(ui: &mut UI, context: &mut UIContext) {
 
     ui_elements.push(scene.read().unwrap().ambient_light().get_ui(ui, scene));
 
+    ui_elements.push(get_add_ui("Add shape", "add_shape", &SHAPE_TYPES, ui));
+    ui_elements.push(get_add_ui("Add composed shape", "add_composed_shape", &COMPOSED_SHAPE_TYPES, ui));
+    ui_elements.push(get_add_ui("Add light", "add_light", &LIGHT_TYPES, ui));
+    ui_elements.push(get_outliner_ui(ui, scene));
+
     for light in scene.read().unwrap().lights() {
-        ui_elements.push(light.get_ui(light, ui, scene));
+        if light.group_id().is_none() {
+            ui_elements.push(get_light_ui(light, ui, scene));
+        }
+    }
+
+    for group in scene.read().unwrap().groups() {
+        if group.parent_id().is_none() {
+            ui_elements.push(get_group_ui(group.id(), ui, scene));
+        }
     }
     
     for elem in ui_elements {
@@ -68,11 +220,293 @@ pub fn setup_objects_ui(ui: &mut UI, context: &mut UIContext) {
     }
     objects_box.add_elements(vec![category]);
     objects_box.set_edit_bar(ui.uisettings(), None);
+    objects_box.set_edit_subject(EditSubject::Objects);
     ui.add_box(objects_box);
+    highlight_outliner(ui, context.selection.subjects());
+}
+
+/**
+ * Builds the objects box again if it is opened, after objects were added or removed.
+ */
+pub fn refresh_objects_ui(ui: &mut UI, context: &mut UIContext) {
+    if ui.get_box(OBJECTS).is_some() {
+        ui.destroy_box(OBJECTS);
+        setup_objects_ui(ui, context);
+    }
+}
+
+/**
+ * Every element, composed element and light of the scene. Clicking one selects it, or adds it to the selection with
+ * Ctrl, and it can be hidden from the render.
+ */
+fn get_outliner_ui(ui: &mut UI, scene: &Arc<RwLock<Scene>>) -> UIElement {
+    let scene_read = scene.read().unwrap();
+    let mut category = UIElement::new("Outliner", "outliner", ElemType::Category(Category::collapsed()), ui.uisettings());
+
+    let mut composed_ids = vec![];
+    for element in scene_read.elements() {
+        let (name, reference, visible) = match element.composed_id() {
+            Some(composed_id) if composed_ids.contains(&composed_id) => continue,
+            Some(composed_id) => {
+                composed_ids.push(composed_id);
+                let composed_element = &scene_read.composed_elements()[composed_id];
+                let name = match composed_element.name() {
+                    Some(name) => name.clone(),
+                    None => format!("{} (composed {})", composed_element.composed_shape().shape_name(), composed_id),
+                };
+                (name, subject_reference(&EditSubject::ComposedElement(composed_id)), composed_element.visible())
+            }
+            None => (EditSubject::Element(element.id()).label(&scene_read), subject_reference(&EditSubject::Element(element.id())), element.visible()),
+        };
+        let id = element.id();
+        let mut row = UIElement::new("", &reference, ElemType::Row(vec![]), ui.uisettings());
+        row.add_element(UIElement::new(&name, "select", ElemType::Button(Some(Box::new(move |button, context, ui| {
+            let add = ui.inputs().contains(&Key::Named(NamedKey::Control));
+            select(ui, context, EditSubject::Element(id), add);
+            // The clicked button is out of the outliner while it's clicked
+            if let Some(button) = button {
+                let selected = context.selection.subjects().iter().any(|subject| button.reference.ends_with(&format!(".{}.select", subject_reference(subject))));
+                set_selected_style(button, selected, &ui.uisettings().clone());
+            }
+        }))), ui.uisettings()));
+        row.add_element(UIElement::new(visibility_text(visible), "visibility", ElemType::Button(Some(Box::new(move |button, context, _| {
+            if let Some(scene) = context.get_active_scene() {
+                let mut scene = scene.write().unwrap();
+                let subject = EditSubject::Element(id);
+                let Some(visible) = scene.element_by_id(id).map(|element| !element.visible()) else {
+                    return;
+                };
+                let before = scene.snapshot(subject);
+                scene.set_element_visible(id, visible);
+                let after = scene.snapshot(subject);
+                let name = format!("{} {}", if visible { "Show" } else { "Hide" }, subject.label(&scene));
+                scene.record(name, before, after);
+                scene.set_dirty(true);
+                if let Some(button) = button {
+                    button.text = visibility_text(visible).to_string();
+                }
+            }
+        }))), ui.uisettings()));
+        category.add_element(row);
+    }
+
+    for light in scene_read.lights() {
+        let id = light.id();
+        let mut row = UIElement::new("", &format!("light{}", id), ElemType::Row(vec![]), ui.uisettings());
+        let name = light.label();
+        row.add_element(UIElement::new(&name, "select", ElemType::Button(Some(Box::new(move |_, context, ui| {
+            if let Some(scene) = context.get_active_scene() {
+                expand_light_ui(ui, &scene.read().unwrap(), id);
+            }
+        }))), ui.uisettings()));
+        row.add_element(UIElement::new(visibility_text(light.visible()), "visibility", ElemType::Button(Some(Box::new(move |button, context, _| {
+            if let Some(scene) = context.get_active_scene() {
+                let mut scene = scene.write().unwrap();
+                let Some(visible) = scene.lights().iter().find(|light| light.id() == id).map(|light| !light.visible()) else {
+                    return;
+                };
+                let before = scene.light_snapshot(id);
+                scene.set_light_visible(id, visible);
+                let after = scene.light_snapshot(id);
+                let label = scene.light_by_id(id).map(|light| light.label()).unwrap_or_default();
+                scene.record(format!("{} {}", if visible { "Show" } else { "Hide" }, label), before, after);
+                scene.set_dirty(true);
+                if let Some(button) = button {
+                    button.text = visibility_text(visible).to_string();
+                }
+            }
+        }))), ui.uisettings()));
+        category.add_element(row);
+    }
+    category
+}
+
+fn visibility_text(visible: bool) -> &'static str {
+    match visible {
+        true => "Hide",
+        false => "Show",
+    }
+}
+
+/**
+ * Lights have no shape to select, clicking one in the outliner opens its settings and the groups it's in.
+ */
+fn expand_light_ui(ui: &mut UI, scene: &Scene, id: usize) {
+    let Some(light) = scene.lights().iter().find(|light| light.id() == id) else {
+        return;
+    };
+    let mut path = vec![format!("light{}", id)];
+    let mut group_id = light.group_id();
+    while let Some(id) = group_id {
+        path.insert(0, format!("group{}", id));
+        group_id = scene.group_by_id(id).and_then(|group| group.parent_id());
+    }
+    let mut reference = format!("{}.objects", OBJECTS);
+    for part in path {
+        reference = format!("{}.{}", reference, part);
+        if let Some(ElemType::Category(category)) = ui.get_element_mut(reference.clone()).map(|element| &mut element.elem_type) {
+            category.collapsed = false;
+        }
+    }
+    ui.set_dirty();
+}
+
+/**
+ * Shows the selected elements in the outliner.
+ */
+pub fn highlight_outliner(ui: &mut UI, subjects: &[EditSubject]) {
+    let settings = ui.uisettings().clone();
+    let Some(ElemType::Category(outliner)) = ui.get_element_mut(format!("{}.objects.outliner", OBJECTS)).map(|element| &mut element.elem_type) else {
+        return;
+    };
+    for row in &mut outliner.elems {
+        let selected = subjects.iter().any(|subject| row.id == subject_reference(subject));
+        if let ElemType::Row(elems) = &mut row.elem_type {
+            if let Some(button) = elems.iter_mut().find(|elem| elem.id == "select") {
+                set_selected_style(button, selected, &settings);
+            }
+        }
+    }
+}
+
+// Reference of the row of the outliner
+fn subject_reference(subject: &EditSubject) -> String {
+    match subject {
+        EditSubject::Element(id) => format!("element{}", id),
+        EditSubject::ComposedElement(composed_id) => format!("composed{}", composed_id),
+        _ => String::new(),
+    }
+}
+
+fn set_selected_style(button: &mut UIElement, selected: bool, settings: &UISettings) {
+    let color = if selected { 100 } else { 200 };
+    button.set_style(StyleBuilder::from_existing(&button.style, settings)
+        .bg_color(Some(Rgba([color, color, color, 255])))
+        .build()
+    );
+}
+
+/**
+ * A button per type of object, which adds one in front of the camera and opens it.
+ */
+fn get_add_ui(name: &str, reference: &str, object_types: &[&'static str], ui: &mut UI) -> UIElement {
+    let mut category = UIElement::new(name, reference, ElemType::Category(Category::collapsed()), ui.uisettings());
+    for object_type in object_types.iter().copied() {
+        let button_name = object_type[..1].to_uppercase() + &object_type[1..];
+        let button = UIElement::new(&button_name, object_type, ElemType::Button(Some(Box::new(move |_, context, ui| {
+            let scene = match context.get_active_scene() {
+                Some(scene) => scene.clone(),
+                None => return,
+            };
+            let first_element = scene.read().unwrap().elements().len();
+            if let Err(err) = add_object(&mut scene.write().unwrap(), object_type) {
+                show_error(ui, context, &format!("Can't add a {}: {}", object_type, err));
+                return;
+            }
+            let mut scene_write = scene.write().unwrap();
+            let added = match scene_write.elements().len() > first_element {
+                true => scene_write.snapshot(EditSubject::Element(first_element)),
+                false => scene_write.lights().last().and_then(|light| scene_write.light_snapshot(light.id())),
+            };
+            scene_write.record(format!("Add {}", object_type), None, added);
+            scene_write.set_dirty(true);
+            drop(scene_write);
+            refresh_objects_ui(ui, context);
+            let scene_read = scene.read().unwrap();
+            if let Some(element) = scene_read.elements().get(first_element) {
+                setup_element_ui(element, ui, &scene);
+            }
+        }))), ui.uisettings());
+        category.add_element(button);
+    }
+    category
+}
+
+/**
+ * UI of a light, with buttons to duplicate and delete it.
+ */
+fn get_light_ui(light: &AnyLight, ui: &mut UI, scene: &Arc<RwLock<Scene>>) -> UIElement {
+    let id = light.id();
+    let mut category = light.get_ui(light, ui, scene);
+    let mut row = UIElement::new("", "actions", ElemType::Row(vec![]), ui.uisettings());
+    let duplicate = UIElement::new("Duplicate", "duplicate", ElemType::Button(Some(Box::new(move |_, context, ui| {
+        if let Some(scene) = context.get_active_scene() {
+            let mut scene = scene.write().unwrap();
+            let label = scene.light_by_id(id).map(|light| light.label()).unwrap_or_default();
+            let added = scene.duplicate_light(id).and_then(|new_id| scene.light_snapshot(new_id));
+            scene.record(format!("Duplicate {}", label), None, added);
+            scene.set_dirty(true);
+        }
+        refresh_objects_ui(ui, context);
+    }))), ui.uisettings());
+    let delete = UIElement::new("Delete", "delete", ElemType::Button(Some(Box::new(move |_, context, ui| {
+        if let Some(scene) = context.get_active_scene() {
+            let mut scene = scene.write().unwrap();
+            let label = scene.light_by_id(id).map(|light| light.label()).unwrap_or_default();
+            let removed = scene.light_snapshot(id);
+            scene.remove_light(id);
+            scene.record(format!("Delete {}", label), removed, None);
+            scene.set_dirty(true);
+        }
+        refresh_objects_ui(ui, context);
+    }))), ui.uisettings());
+    row.add_element(duplicate);
+    row.add_element(delete);
+    category.add_element(row);
+    category
+}
+
+/**
+ * Collapsible tree of a group: its transform, its subgroups, its lights and buttons to open its elements.
+ */
+fn get_group_ui(group_id: usize, ui: &mut UI, scene: &Arc<RwLock<Scene>>) -> UIElement {
+    let scene_read = scene.read().unwrap();
+    let group = scene_read.group_by_id(group_id).unwrap();
+    let reference = format!("group{}", group_id);
+    let mut category = UIElement::new(group.name(), &reference, ElemType::Category(Category::collapsed()), ui.uisettings());
+    category.add_element(get_group_transform_ui(group, ui));
+
+    for child in scene_read.groups() {
+        if child.parent_id() == Some(group_id) {
+            category.add_element(get_group_ui(child.id(), ui, scene));
+        }
+    }
+    for light in scene_read.lights() {
+        if light.group_id() == Some(group_id) {
+            category.add_element(get_light_ui(light, ui, scene));
+        }
+    }
+
+    let mut composed_ids = vec![];
+    for element in scene_read.elements() {
+        if element.group_id() != Some(group_id) {
+            continue;
+        }
+        // The elements generated by a composed element are opened together
+        let name = match element.composed_id() {
+            Some(composed_id) if composed_ids.contains(&composed_id) => continue,
+            Some(composed_id) => {
+                composed_ids.push(composed_id);
+                EditSubject::ComposedElement(composed_id).label(&scene_read)
+            }
+            None => EditSubject::Element(element.id()).label(&scene_read),
+        };
+        let id = element.id();
+        let button = UIElement::new(&name, &format!("element{}", id), ElemType::Button(Some(Box::new(move |_, context, ui| {
+            if let Some(scene) = context.get_active_scene().cloned() {
+                let scene_read = scene.read().unwrap();
+                if let Some(element) = scene_read.element_by_id(id) {
+                    setup_element_ui(element, ui, &scene);
+                }
+            }
+        }))), ui.uisettings());
+        category.add_element(button);
+    }
+    category
 }
 
 pub fn setup_ui() -> (UI, UIContext) {
-    let (ra, tb) = start_threads();
+    let (ra, tb) = start_threads(default_nb_threads(), None);
     let mut ui = UI::default();
     let context= UIContext::new(ra, tb);
     setup_scene_toolbar(&mut ui, &context);
@@ -86,7 +520,8 @@ pub fn setup_element_ui(element: &Element, ui: &mut UI, scene: &Arc<RwLock<Scene
         None => "Element".to_string() + &element.id().to_string()
     };
     let mut elem_box = UIBox::new(ELEMENT, BoxPosition::CenterRight(10), ui.uisettings().gui_width, ui.uisettings());
-    let mut category = UIElement::new(&name, &name, ElemType::Category(Category::default()), ui.uisettings());
+    let title = EditSubject::Element(element.id()).label(&scene.read().unwrap());
+    let mut category = UIElement::new(&title, &name, ElemType::Category(Category::default()), ui.uisettings());
 
     category.on_click = Some(Box::new(move |_element,_scene, ui| {
         ui.destroy_box(ELEMENT);
@@ -102,7 +537,9 @@ pub fn setup_element_ui(element: &Element, ui: &mut UI, scene: &Arc<RwLock<Scene
         category.add_element(element.shape().get_ui(element, ui, scene));
     }
     let composed_id = element.composed_id().clone();
+    category.add_element(get_transform_ui(element, ui, scene));
     category.add_element(get_material_ui(element, ui, scene));
+    category.add_element(get_element_actions_ui(element.id(), ui));
     elem_box.add_elements(vec![category]);
     elem_box.set_edit_bar(ui.uisettings(), Some(Box::new(move |_, context, _| {
                     let scene = match context.active_scene {
@@ -110,13 +547,60 @@ pub fn setup_element_ui(element: &Element, ui: &mut UI, scene: &Arc<RwLock<Scene
                         None => return,
                     };
         let mut scene_write = scene.write().unwrap();
+        // The elements of a composed element are generated again, the BVH is refitted with the scene
         if let Some(composed_id) = composed_id {
             scene_write.update_composed_element_material(composed_id);
             scene_write.update_composed_element_shape(composed_id);
             scene_write.determine_full_bvh_traversal();
         }
-        scene_write.update_bvh();
         scene_write.set_dirty(true);
     })));
+    elem_box.set_edit_subject(match composed_id {
+        Some(composed_id) => EditSubject::ComposedElement(composed_id),
+        None => EditSubject::Element(element.id()),
+    });
     ui.add_box(elem_box);
-}
\ No newline at end of file
+}
+
+/**
+ * Duplicate and delete buttons of an element, which act on its whole composed element if it has one.
+ */
+fn get_element_actions_ui(id: usize, ui: &mut UI) -> UIElement {
+    let mut row = UIElement::new("", "actions", ElemType::Row(vec![]), ui.uisettings());
+    let duplicate = UIElement::new("Duplicate", "duplicate", ElemType::Button(Some(Box::new(move |_, context, ui| {
+        let scene = match context.get_active_scene() {
+            Some(scene) => scene.clone(),
+            None => return,
+        };
+        let mut scene_write = scene.write().unwrap();
+        let name = format!("Duplicate {}", EditSubject::Element(id).label(&scene_write));
+        let new_id = scene_write.duplicate_element(id);
+        let added = new_id.and_then(|new_id| scene_write.snapshot(EditSubject::Element(new_id)));
+        scene_write.record(name, None, added);
+        scene_write.set_dirty(true);
+        drop(scene_write);
+        refresh_objects_ui(ui, context);
+        let scene_read = scene.read().unwrap();
+        if let Some(element) = new_id.and_then(|new_id| scene_read.element_by_id(new_id)) {
+            setup_element_ui(element, ui, &scene);
+        }
+    }))), ui.uisettings());
+    let delete = UIElement::new("Delete", "delete", ElemType::Button(Some(Box::new(move |_, context, ui| {
+        let scene = match context.get_active_scene() {
+            Some(scene) => scene.clone(),
+            None => return,
+        };
+        let mut scene_write = scene.write().unwrap();
+        let name = format!("Delete {}", EditSubject::Element(id).label(&scene_write));
+        let removed = scene_write.snapshot(EditSubject::Element(id));
+        scene_write.remove_element(id);
+        scene_write.record(name, removed, None);
+        scene_write.set_dirty(true);
+        drop(scene_write);
+        ui.destroy_box(ELEMENT);
+        refresh_objects_ui(ui, context);
+    }))), ui.uisettings());
+    row.add_element(duplicate);
+    row.add_element(delete);
+    row
+}
@@ -1,5 +1,7 @@
 extern crate image;
 use display::mainloop::start_ui;
+use parsing::check::check_command;
+use render::headless::{render_command, worker_command};
 
 pub mod display;
 pub mod ui;
@@ -10,8 +12,8 @@ pub mod bvh;
 
 
 /************* General settings ***********/
-const SCREEN_WIDTH: usize = 1800;
-const SCREEN_HEIGHT: usize = 900;
+pub const SCREEN_WIDTH: usize = 1800;
+pub const SCREEN_HEIGHT: usize = 900;
 const SCREEN_WIDTH_U32: u32 = SCREEN_WIDTH as u32;
 const SCREEN_HEIGHT_U32: u32 = SCREEN_HEIGHT as u32;
 const SCENE_FOLDER: &str = "scenes";
@@ -22,6 +24,13 @@ const MAX_OPENED_SCENES: usize = 5;
 /************* Camera **************/
 const STEP: f64 = 0.2;
 const LOOK_STEP: f64 = 0.05;
+// Radians per pixel when orbiting with the mouse, and part of the distance to the pivot per step of the wheel
+const ORBIT_STEP: f64 = 0.005;
+const DOLLY_STEP: f64 = 0.1;
+// Factor of the speed of the camera for each press of + or -
+const SPEED_FACTOR: f64 = 1.5;
+// Distance of the pivot in front of the camera when nothing was picked
+const DEFAULT_PIVOT_DISTANCE: f64 = 5.;
 
 /************* Displacement **************/
 const DISPLACEMENT: bool = false;
@@ -31,7 +40,8 @@ const SPHERE_DISPLACED_DISTANCE: f64 = 0.05;
 const SPHERE_DISPLACEMENT_STEP: f64 = 0.1;
 
 /************ Render settings ************/
-const MAX_THREADS: usize = 4;
+// Number of workers when the number of cores is unknown
+const DEFAULT_THREADS: usize = 4;
 const BASE_SIMPLIFICATION: usize = 8;
 const TILE_SIZE: usize = 8;
 const MAX_DEPTH: usize = 5;
@@ -40,10 +50,57 @@ const MAX_ITERATIONS: usize = 1500;
 const BOUNCE_OFFSET: f64 = 0.0001;
 const ERROR_MARGIN: f64 = 0.000001;
 
+/************ Checkpoints ************/
+const CHECKPOINT_FOLDER: &str = "checkpoints";
+// Seconds between two checkpoints of a HighDef render, which are written at the end of an iteration
+const CHECKPOINT_INTERVAL: u64 = 60;
+
+/************ Headless and remote rendering ************/
+// Milliseconds between two images asked to the render thread when rendering without a window
+const HEADLESS_REFRESH_TIME: u64 = 1000;
+// Tiles sent to a remote worker before it answers, to hide the latency of the network
+const REMOTE_TILES_IN_FLIGHT: usize = 4;
+// Seconds without an answer after which a remote worker is considered lost and its tiles are given to others
+const REMOTE_TIMEOUT: u64 = 60;
+// Seconds between two attempts of a remote worker to connect to the coordinator
+const REMOTE_RECONNECT_DELAY: u64 = 2;
+
+/************ Edit history ************/
+// Edits of a scene that can be undone
+const MAX_HISTORY: usize = 100;
+
+/************ Gizmos ************/
+// Length in pixels of the axes of the gizmo drawn over the selected element
+const GIZMO_SIZE: f64 = 90.;
+// Distance in pixels under which the mouse grabs a handle of the gizmo
+const GIZMO_PICK_DISTANCE: f64 = 6.;
+// Steps of the grid snapping: units for the translations, degrees for the rotations, and scale
+const GIZMO_GRID: f64 = 0.25;
+const GIZMO_ANGLE_STEP: f64 = 15.;
+const GIZMO_SCALE_STEP: f64 = 0.1;
+// Pixels per cell of the buffer of the selected elements, from which their outline is drawn
+const SELECTION_CELL_SIZE: usize = 2;
+
 
 /*************** BVH Settings ************/
 const USING_BVH: bool = true;
-const BVH_SPLIT_STEPS: usize = 5;
+const BVH_BINS: usize = 16;
+const BVH_MAX_LEAF_SIZE: usize = 4;
+const BVH_TRAVERSAL_COST: f64 = 1.;
+const BVH_PARALLEL_THRESHOLD: usize = 4096;
+// The BVH is rebuilt when refitting it made its cost grow by this factor
+const BVH_REBUILD_THRESHOLD: f64 = 1.5;
+
+/*************** SDF Settings ************/
+const SDF_MAX_STEPS: usize = 512;
+const SDF_EPSILON: f64 = 0.0001;
+const SDF_MAX_DISTANCE: f64 = 1000.;
+
+/*************** Implicit surface Settings ************/
+const ANY_SAMPLES: usize = 1024;
+const ANY_MAX_ITERATIONS: usize = 64;
+const ANY_EPSILON: f64 = 0.0000001;
+const ANY_MAX_DISTANCE: f64 = 1000.;
 
 
 //Debug settings
@@ -66,17 +123,35 @@ const FIELD_PADDING_X: u32 = 10;
 const FIELD_PADDING_Y: u32 = 3;
 const BASE_FONT_SIZE: u32 = 16;
 const UI_REFRESH_TIME: u32 = 1000;
+// Milliseconds between two checks of the files of the opened scenes, which are parsed again when they change
+const HOT_RELOAD_TIME: u64 = 500;
 const SCROLL_PIXEL_AMOUNT: u32 = 20;
 
 /*********** UI default references *********/
 const SETTINGS: &str = "settings";
 const OBJECTS: &str = "objects";
+const STATS: &str = "stats";
+const HISTORY: &str = "history";
 const TOOLBAR: &str = "toolbar";
 const SCENE_TOOLBAR: &str = "scene_toolbar";
 const ELEMENT: &str = "element";
+const ERRORS: &str = "errors";
 
 pub fn run() {
-    start_ui();
+    let args: Vec<String> = std::env::args().collect();
+    let result = match args.get(1).map(|arg| arg.as_str()) {
+        Some("render") => render_command(&args[2..]),
+        Some("worker") => worker_command(&args[2..]),
+        Some("check") => check_command(&args[2..]),
+        _ => {
+            start_ui();
+            Ok(())
+        }
+    };
+    if let Err(err) = result {
+        error(&err);
+        std::process::exit(1);
+    }
 }
 
 pub fn error(msg: &str) {
@@ -1,11 +1,33 @@
-use super::{materials::material::Material, shapes::composed_shape::ComposedShape};
+use crate::{
+    bvh::{flat::FlatBvh, node::Node},
+    render::raycasting::get_closest_hit_from_elements_with_index
+};
+use super::{
+    element::Element,
+    materials::material::Material,
+    maths::{hit::Hit, ray::Ray, transform::Transform},
+    scene::Scene,
+    shapes::{aabb::Aabb, composed_shape::ComposedShape}
+};
 
+/**
+ * Shape made of several elements. The elements are kept in the scene, but they are in a BVH of their own, built in the
+ * local space of the composed element: the BVH of the scene only holds its box, so moving it doesn't touch its BVH.
+ */
 #[derive(Debug)]
 pub struct ComposedElement {
     composed_shape: Box<dyn Sync + ComposedShape>,
     elements_index: Vec<usize>,
     material: Box<dyn Material + Send +Sync>,
     id: usize,
+    group_id: Option<usize>,
+    transform: Option<Transform>,
+    group_transform: Option<Transform>,
+    world_transform: Option<Transform>,
+    bvh: Option<FlatBvh>,
+    aabb: Option<Aabb>,
+    visible: bool,
+    name: Option<String>,
 }
 
 impl ComposedElement {
@@ -14,7 +36,15 @@ impl ComposedElement {
             composed_shape,
             elements_index: vec![],
             material,
-            id: 0
+            id: 0,
+            group_id: None,
+            transform: None,
+            group_transform: None,
+            world_transform: None,
+            bvh: None,
+            aabb: None,
+            visible: true,
+            name: None,
         }
     }
 
@@ -51,4 +81,159 @@ impl ComposedElement {
     pub fn id(&self) -> usize {
         self.id
     }
-}
\ No newline at end of file
+
+    pub fn group_id(&self) -> Option<usize> {
+        self.group_id
+    }
+
+    /**
+     * The transforms and the group are copied to every element generated by the composed shape.
+     */
+    pub fn transform(&self) -> Option<&Transform> {
+        self.transform.as_ref()
+    }
+    pub fn group_transform(&self) -> Option<&Transform> {
+        self.group_transform.as_ref()
+    }
+    pub fn world_transform(&self) -> Option<&Transform> {
+        self.world_transform.as_ref()
+    }
+
+    pub fn bvh(&self) -> Option<&FlatBvh> {
+        self.bvh.as_ref()
+    }
+    pub fn bvh_mut(&mut self) -> Option<&mut FlatBvh> {
+        self.bvh.as_mut()
+    }
+
+    /**
+     * AABB of the composed element in the world, the one of its BVH moved by the transforms.
+     */
+    pub fn aabb(&self) -> Option<&Aabb> {
+        self.aabb.as_ref()
+    }
+
+    /**
+     * Like the transforms, the visibility is copied to the generated elements.
+     */
+    pub fn visible(&self) -> bool {
+        self.visible
+    }
+
+    /**
+     * Unlike the visibility, the name is only the one of the composed element.
+     */
+    pub fn name(&self) -> Option<&String> {
+        self.name.as_ref()
+    }
+
+    pub fn set_group_id(&mut self, group_id: Option<usize>) {
+        self.group_id = group_id;
+    }
+
+    pub fn set_visible(&mut self, visible: bool) {
+        self.visible = visible;
+    }
+
+    pub fn set_name(&mut self, name: Option<String>) {
+        self.name = name;
+    }
+
+    pub fn set_transform(&mut self, transform: Option<Transform>) {
+        self.transform = transform;
+        self.update_world_transform();
+    }
+    pub fn set_group_transform(&mut self, group_transform: Option<Transform>) {
+        self.group_transform = group_transform;
+        self.update_world_transform();
+    }
+
+    // Methods
+    /**
+     * Builds the BVH of the generated elements, given by their index in the elements of the scene, on their local AABB.
+     */
+    pub fn build_bvh(&mut self, elements: &[Element], elements_index: &[usize]) {
+        let mut node = Node::new(&Aabb::new(0., 0., 0., 0., 0., 0.));
+        node.build_tree_from(elements_index.iter()
+            .filter_map(|index| elements[*index].local_aabb().map(|aabb| (*index, aabb))));
+        let bvh = FlatBvh::new(&node);
+        self.bvh = (!bvh.nodes().is_empty()).then_some(bvh);
+        self.update_aabb();
+    }
+
+    /**
+     * Must be called after the BVH has been refitted.
+     */
+    pub fn update_aabb(&mut self) {
+        self.aabb = self.bvh.as_ref()
+            .and_then(|bvh| bvh.aabb())
+            .map(|aabb| match &self.world_transform {
+                Some(transform) => transform.aabb_to_world(&aabb),
+                None => aabb,
+            });
+    }
+
+    fn update_world_transform(&mut self) {
+        self.world_transform = match (&self.group_transform, &self.transform) {
+            (Some(group_transform), Some(transform)) => Some(group_transform.compose(transform)),
+            (Some(transform), None) | (None, Some(transform)) => Some(transform.clone()),
+            (None, None) => None,
+        };
+        self.update_aabb();
+    }
+
+    /**
+     * Closest hit of the ray with the elements of the BVH, merged with the one already found. The boxes are tested in
+     * the local space, the elements move the ray themselves.
+     */
+    pub fn get_closest_hit<'a>(&self, scene: &'a Scene, ray: &Ray, closest: Option<Hit<'a>>) -> Option<Hit<'a>> {
+        let bvh = match &self.bvh {
+            Some(bvh) => bvh,
+            None => return closest,
+        };
+        let (local_ray, factor) = match &self.world_transform {
+            Some(transform) => transform.ray_to_local(ray),
+            None => (ray.clone(), 1.),
+        };
+        bvh.closest_hit_with(&local_ray, factor, scene.settings().bvh_full_traversal, closest, |indices, closest| {
+            get_closest_hit_from_elements_with_index(scene, ray, closest, scene.elements(), indices)
+        })
+    }
+
+    /**
+     * Hands the index of the elements whose box the ray goes through before max_dist to `hit_element`, until it
+     * returns true.
+     */
+    pub fn any_hit_with<F: FnMut(usize) -> bool>(&self, ray: &Ray, max_dist: f64, mut hit_element: F) -> bool {
+        let bvh = match &self.bvh {
+            Some(bvh) => bvh,
+            None => return false,
+        };
+        let (local_ray, factor) = match &self.world_transform {
+            Some(transform) => transform.ray_to_local(ray),
+            None => (ray.clone(), 1.),
+        };
+        bvh.any_hit_with(&local_ray, factor, max_dist, |indices| indices.iter().any(|index| hit_element(*index)))
+    }
+}
+/**
+ * The copy has no elements nor BVH: the scene generates them again when it is added.
+ */
+impl Clone for ComposedElement {
+    fn clone(&self) -> Self {
+        Self {
+            composed_shape: self.composed_shape.clone_composed_shape(),
+            elements_index: vec![],
+            material: (*self.material).clone(),
+            id: self.id,
+            group_id: self.group_id,
+            transform: self.transform.clone(),
+            group_transform: self.group_transform.clone(),
+            world_transform: self.world_transform.clone(),
+            bvh: None,
+            aabb: None,
+            visible: self.visible,
+            name: self.name.clone(),
+        }
+    }
+}
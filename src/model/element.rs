@@ -1,11 +1,23 @@
-use super::{materials::material::Material, shapes::shape::Shape};
+use crate::render::stats::{count, Counter};
+use super::{
+    materials::material::{Material, Projection},
+    maths::{hit::Hit, ray::Ray, transform::Transform, vec3::Vec3},
+    shapes::{aabb::Aabb, shape::Shape}
+};
 
 #[derive(Debug)]
 pub struct Element {
     id: usize,
     material: Box<dyn Material + Send +Sync>,
     shape: Box<dyn Sync + Shape>,
-    composed_id: Option<usize>
+    composed_id: Option<usize>,
+    group_id: Option<usize>,
+    transform: Option<Transform>,
+    group_transform: Option<Transform>,
+    world_transform: Option<Transform>,
+    aabb: Option<Aabb>,
+    visible: bool,
+    name: Option<String>,
 }
 
 impl Element {
@@ -14,7 +26,14 @@ impl Element {
             shape,
             material,
             id: 0,
-            composed_id: None
+            composed_id: None,
+            group_id: None,
+            transform: None,
+            group_transform: None,
+            world_transform: None,
+            aabb: None,
+            visible: true,
+            name: None,
         }
     }
 
@@ -41,6 +60,62 @@ impl Element {
         self.composed_id
     }
 
+    pub fn group_id(&self) -> Option<usize> {
+        self.group_id
+    }
+
+    /**
+     * A hidden element is skipped by the rays, it can still be selected in the outliner.
+     */
+    pub fn visible(&self) -> bool {
+        self.visible
+    }
+
+    /**
+     * Name given in the scene file, which doesn't change with the id.
+     */
+    pub fn name(&self) -> Option<&String> {
+        self.name.as_ref()
+    }
+
+    /**
+     * Transform of the element in the space of its group.
+     */
+    pub fn transform(&self) -> Option<&Transform> {
+        self.transform.as_ref()
+    }
+
+    /**
+     * Transform of the groups of the element, in the world.
+     */
+    pub fn group_transform(&self) -> Option<&Transform> {
+        self.group_transform.as_ref()
+    }
+
+    /**
+     * Transform of the element in the world, combining the ones of its groups with its own.
+     */
+    pub fn world_transform(&self) -> Option<&Transform> {
+        self.world_transform.as_ref()
+    }
+
+    /**
+     * AABB of the element in the world, the one of the shape moved by the transform if there is one.
+     */
+    pub fn aabb(&self) -> Option<&Aabb> {
+        match self.world_transform {
+            Some(_) => self.aabb.as_ref(),
+            None => self.local_aabb(),
+        }
+    }
+
+    /**
+     * AABB of the shape, in the space of the element.
+     */
+    pub fn local_aabb(&self) -> Option<&Aabb> {
+        self.shape.as_aabb().or_else(|| self.shape.aabb())
+    }
+
     pub fn set_material(&mut self, material: Box<dyn Material + Send + Sync>) {
         self.material = material;
     }
@@ -56,4 +131,111 @@ impl Element {
     pub fn set_composed_id(&mut self, id: usize) {
         self.composed_id = Some(id);
     }
+
+    pub fn set_group_id(&mut self, group_id: Option<usize>) {
+        self.group_id = group_id;
+    }
+
+    pub fn set_visible(&mut self, visible: bool) {
+        self.visible = visible;
+    }
+
+    pub fn set_name(&mut self, name: Option<String>) {
+        self.name = name;
+    }
+
+    pub fn set_transform(&mut self, transform: Option<Transform>) {
+        self.transform = transform;
+        self.update_world_transform();
+    }
+
+    pub fn set_group_transform(&mut self, group_transform: Option<Transform>) {
+        self.group_transform = group_transform;
+        self.update_world_transform();
+    }
+
+    // Methods
+    /**
+     * Must be called after the shape of a transformed element has been modified.
+     */
+    pub fn update_aabb(&mut self) {
+        self.aabb = match &self.world_transform {
+            Some(transform) => self.shape.aabb().map(|aabb| transform.aabb_to_world(aabb)),
+            None => None,
+        };
+    }
+
+    fn update_world_transform(&mut self) {
+        self.world_transform = match (&self.group_transform, &self.transform) {
+            (Some(group_transform), Some(transform)) => Some(group_transform.compose(transform)),
+            (Some(transform), None) | (None, Some(transform)) => Some(transform.clone()),
+            (None, None) => None,
+        };
+        self.update_aabb();
+    }
+
+    pub fn intersect(&self, ray: &Ray) -> Option<Vec<f64>> {
+        self.intersect_with(ray, |ray| self.shape.intersect(ray))
+    }
+
+    /**
+     * Calls the intersection on the ray moved into the local space of the shape, and brings the distances back to
+     * the world.
+     */
+    pub fn intersect_with<F>(&self, ray: &Ray, intersect: F) -> Option<Vec<f64>>
+    where F: Fn(&Ray) -> Option<Vec<f64>> {
+        if !self.visible {
+            return None;
+        }
+        count(Counter::IntersectionTest);
+        match &self.world_transform {
+            Some(transform) => {
+                let (local_ray, factor) = transform.ray_to_local(ray);
+                intersect(&local_ray).map(|t_list| t_list.iter().map(|t| t * factor).collect())
+            }
+            None => intersect(ray),
+        }
+    }
+
+    pub fn norm(&self, pos: &Vec3) -> Vec3 {
+        match &self.world_transform {
+            Some(transform) => transform.norm_to_world(&self.shape.norm(&transform.point_to_local(pos))),
+            None => self.shape.norm(pos),
+        }
+    }
+
+    pub fn projection(&self, hit: &Hit) -> Projection {
+        match &self.world_transform {
+            Some(transform) => {
+                let local_hit = hit.with_geometry(transform.point_to_local(hit.pos()), transform.norm_to_local(hit.norm()));
+                let mut projection = self.shape.projection(&local_hit);
+                projection.i = transform.dir_to_world(&projection.i).normalize();
+                projection.j = transform.dir_to_world(&projection.j).normalize();
+                projection.k = *hit.norm();
+                projection
+            }
+            None => self.shape.projection(hit),
+        }
+    }
+}
+
+/**
+ * The copy keeps the id, the scene gives it a new one when it is added.
+ */
+impl Clone for Element {
+    fn clone(&self) -> Self {
+        Self {
+            id: self.id,
+            material: (*self.material).clone(),
+            shape: self.shape.clone_shape(),
+            composed_id: self.composed_id,
+            group_id: self.group_id,
+            transform: self.transform.clone(),
+            group_transform: self.group_transform.clone(),
+            world_transform: self.world_transform.clone(),
+            aabb: self.aabb.clone(),
+            visible: self.visible,
+            name: self.name.clone(),
+        }
+    }
 }
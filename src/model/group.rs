@@ -0,0 +1,47 @@
+use super::maths::transform::Transform;
+
+/**
+ * Node of the scene graph. The elements, composed elements, lights and groups it contains know their group by its id,
+ * and are moved by the transforms of all the groups above them.
+ */
+#[derive(Debug)]
+pub struct Group {
+    id: usize,
+    name: String,
+    parent_id: Option<usize>,
+    transform: Option<Transform>,
+}
+
+impl Group {
+    // Constructor
+    pub fn new(name: String, parent_id: Option<usize>, transform: Option<Transform>) -> Self {
+        Self {
+            id: 0,
+            name,
+            parent_id,
+            transform,
+        }
+    }
+
+    // Accessors
+    pub fn id(&self) -> usize {
+        self.id
+    }
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+    pub fn parent_id(&self) -> Option<usize> {
+        self.parent_id
+    }
+    pub fn transform(&self) -> Option<&Transform> {
+        self.transform.as_ref()
+    }
+
+    // Mutators
+    pub fn set_id(&mut self, id: usize) {
+        self.id = id;
+    }
+    pub fn set_transform(&mut self, transform: Option<Transform>) {
+        self.transform = transform;
+    }
+}
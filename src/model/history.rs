@@ -0,0 +1,333 @@
+use std::fmt::Debug;
+
+use crate::{render::settings::Settings, MAX_HISTORY};
+use super::{
+    composed_element::ComposedElement,
+    element::Element,
+    materials::texture::Texture,
+    maths::transform::Transform,
+    objects::{camera::Camera, lights::{ambient_light::AmbientLight, light::AnyLight}},
+    scene::Scene
+};
+
+/**
+ * What a box of the UI edits, saved before and after its changes are applied.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EditSubject {
+    Element(usize),
+    ComposedElement(usize),
+    // The camera, the skybox, the lights and the transforms of the groups
+    Objects,
+    Settings,
+}
+
+impl EditSubject {
+    /**
+     * Named like in the UI, for the history: by the name given in the scene file if there is one.
+     */
+    pub fn label(&self, scene: &Scene) -> String {
+        match self {
+            EditSubject::Element(id) => match scene.elements().get(*id) {
+                Some(element) => match (element.composed_id(), element.name()) {
+                    (Some(composed_id), _) => EditSubject::ComposedElement(composed_id).label(scene),
+                    (None, Some(name)) => name.clone(),
+                    (None, None) => format!("{} {}", element.shape().shape_name(), id),
+                },
+                None => format!("element {}", id),
+            },
+            EditSubject::ComposedElement(composed_id) => match scene.composed_elements().get(*composed_id).and_then(|composed_element| composed_element.name()) {
+                Some(name) => name.clone(),
+                None => format!("ComposedElement {}", composed_id),
+            },
+            EditSubject::Objects => "objects".to_string(),
+            EditSubject::Settings => "render settings".to_string(),
+        }
+    }
+}
+
+/**
+ * State of a part of the scene. The elements keep their ids, which are their indices, so that they are put back at
+ * the same place.
+ */
+#[derive(Clone, Debug)]
+pub enum Snapshot {
+    Element(Element),
+    ComposedElement(ComposedElement, Vec<Element>),
+    // A light with its index in the lights of the scene
+    Light(usize, AnyLight),
+    Objects {
+        camera: Camera,
+        skybox: Texture,
+        ambient_light: AmbientLight,
+        lights: Vec<AnyLight>,
+        group_transforms: Vec<Option<Transform>>,
+    },
+    Settings(Settings),
+}
+
+/**
+ * An edit goes from one state to the other: an object is added when there is no state before, and removed when there
+ * is none after.
+ */
+#[derive(Debug)]
+pub struct Command {
+    name: String,
+    before: Option<Snapshot>,
+    after: Option<Snapshot>,
+}
+
+impl Command {
+    // Constructor
+    pub fn new(name: String, before: Option<Snapshot>, after: Option<Snapshot>) -> Command {
+        Command { name, before, after }
+    }
+
+    // Accessors
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+    pub fn before(&self) -> Option<&Snapshot> {
+        self.before.as_ref()
+    }
+    pub fn after(&self) -> Option<&Snapshot> {
+        self.after.as_ref()
+    }
+}
+
+/**
+ * Edits of a scene, the last done at the end of `done` and the last undone at the end of `undone`. A new edit forgets
+ * the undone ones.
+ */
+#[derive(Debug, Default)]
+pub struct History {
+    done: Vec<Command>,
+    undone: Vec<Command>,
+    // Changes with every edit, undo and redo, for the UI to know when to show the history again
+    version: usize,
+}
+
+impl History {
+    // Accessors
+    pub fn done(&self) -> &Vec<Command> {
+        &self.done
+    }
+    pub fn undone(&self) -> &Vec<Command> {
+        &self.undone
+    }
+    pub fn version(&self) -> usize {
+        self.version
+    }
+
+    // Methods
+    pub fn push(&mut self, command: Command) {
+        self.undone.clear();
+        self.done.push(command);
+        if self.done.len() > MAX_HISTORY {
+            self.done.remove(0);
+        }
+        self.version += 1;
+    }
+
+    fn take_done(&mut self) -> Option<Command> {
+        self.done.pop()
+    }
+
+    fn take_undone(&mut self) -> Option<Command> {
+        self.undone.pop()
+    }
+
+    fn push_done(&mut self, command: Command) {
+        self.done.push(command);
+        self.version += 1;
+    }
+
+    fn push_undone(&mut self, command: Command) {
+        self.undone.push(command);
+        self.version += 1;
+    }
+}
+
+impl Scene {
+    /**
+     * State of what a box edits, None when it doesn't exist anymore.
+     */
+    pub fn snapshot(&self, subject: EditSubject) -> Option<Snapshot> {
+        match subject {
+            EditSubject::Element(id) => {
+                let element = self.elements().get(id)?;
+                match element.composed_id() {
+                    Some(composed_id) => self.snapshot(EditSubject::ComposedElement(composed_id)),
+                    None => Some(Snapshot::Element(element.clone())),
+                }
+            }
+            EditSubject::ComposedElement(composed_id) => {
+                let composed_element = self.composed_elements().get(composed_id)?;
+                let elements = self.elements().iter()
+                    .filter(|element| element.composed_id() == Some(composed_id))
+                    .cloned()
+                    .collect();
+                Some(Snapshot::ComposedElement(composed_element.clone(), elements))
+            }
+            EditSubject::Objects => Some(Snapshot::Objects {
+                camera: self.camera().clone(),
+                skybox: self.skybox().clone(),
+                ambient_light: self.ambient_light().clone(),
+                lights: self.lights().clone(),
+                group_transforms: self.groups().iter().map(|group| group.transform().cloned()).collect(),
+            }),
+            EditSubject::Settings => Some(Snapshot::Settings(self.settings().clone())),
+        }
+    }
+
+    pub fn light_snapshot(&self, id: usize) -> Option<Snapshot> {
+        let index = self.lights().iter().position(|light| light.id() == id)?;
+        Some(Snapshot::Light(index, self.lights()[index].clone()))
+    }
+
+    /**
+     * Adds the edit to the history, unless nothing changed.
+     */
+    pub fn record(&mut self, name: String, before: Option<Snapshot>, after: Option<Snapshot>) {
+        if format!("{:?}", before) != format!("{:?}", after) {
+            self.history_mut().push(Command::new(name, before, after));
+        }
+    }
+
+    /**
+     * Returns the name of the undone edit.
+     */
+    pub fn undo(&mut self) -> Option<String> {
+        let command = self.history_mut().take_done()?;
+        self.change_state(command.after(), command.before());
+        let name = command.name().clone();
+        self.history_mut().push_undone(command);
+        Some(name)
+    }
+
+    pub fn redo(&mut self) -> Option<String> {
+        let command = self.history_mut().take_undone()?;
+        self.change_state(command.before(), command.after());
+        let name = command.name().clone();
+        self.history_mut().push_done(command);
+        Some(name)
+    }
+
+    // The objects are removed in their state `from` and put back in their state `to`
+    fn change_state(&mut self, from: Option<&Snapshot>, to: Option<&Snapshot>) {
+        match (from, to) {
+            (Some(Snapshot::Objects { .. }), Some(to @ Snapshot::Objects { .. })) => self.set_objects(from.unwrap(), to),
+            (_, Some(Snapshot::Settings(settings))) => *self.settings_mut() = settings.clone(),
+            _ => {
+                match from {
+                    Some(Snapshot::Element(element)) => self.remove_element(element.id()),
+                    Some(Snapshot::ComposedElement(composed_element, _)) => self.remove_composed_element(composed_element.id()),
+                    Some(Snapshot::Light(_, light)) => self.remove_light(light.id()),
+                    _ => (),
+                }
+                match to {
+                    Some(Snapshot::Element(element)) => self.insert_elements(vec![element.clone()]),
+                    Some(Snapshot::ComposedElement(composed_element, elements)) => {
+                        self.insert_composed_element(composed_element.clone(), elements.clone())
+                    }
+                    Some(Snapshot::Light(index, light)) => self.insert_light(*index, light.clone()),
+                    _ => (),
+                }
+            }
+        }
+        self.update_bvh();
+        self.set_dirty(true);
+    }
+
+    // Only what changed is put back, so that undoing the edit of a light doesn't move the camera back
+    fn set_objects(&mut self, from: &Snapshot, to: &Snapshot) {
+        let (
+            Snapshot::Objects { camera: from_camera, skybox: from_skybox, ambient_light: from_ambient, lights: from_lights, group_transforms: from_transforms },
+            Snapshot::Objects { camera, skybox, ambient_light, lights, group_transforms },
+        ) = (from, to) else {
+            return;
+        };
+        let changed = |from: &dyn Debug, to: &dyn Debug| format!("{:?}", from) != format!("{:?}", to);
+        if changed(from_camera, camera) {
+            self.set_camera(camera.clone());
+        }
+        if changed(from_skybox, skybox) {
+            self.set_skybox(skybox.clone());
+        }
+        if changed(from_ambient, ambient_light) {
+            self.set_ambient_light(ambient_light.clone());
+        }
+        for (group_id, (from_transform, transform)) in from_transforms.iter().zip(group_transforms).enumerate() {
+            if changed(from_transform, transform) {
+                self.set_group_transform(group_id, transform.clone());
+            }
+        }
+        // After the groups, which move their lights
+        if changed(from_lights, lights) {
+            self.set_lights(lights.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{model::{maths::{transform::Transform, vec3::Vec3}, scene::Scene}, parsing::add_object};
+    use super::EditSubject;
+
+    // What undo and redo must put back exactly, ids included
+    fn state(scene: &Scene) -> String {
+        let elements: Vec<String> = scene.elements().iter()
+            .map(|element| format!("{} {:?} {:?} {:?}", element.id(), element.composed_id(), element.shape(), element.world_transform()))
+            .collect();
+        let composed_elements: Vec<String> = scene.composed_elements().iter()
+            .map(|composed_element| format!("{} {:?}", composed_element.id(), composed_element.elements_index()))
+            .collect();
+        format!("{:?} {:?} {:?}", elements, composed_elements, scene.lights())
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let mut scene = Scene::new();
+        let mut states = vec![state(&scene)];
+        for object_type in ["sphere", "torusphere", "plane", "helix", "light"] {
+            let first_element = scene.elements().len();
+            add_object(&mut scene, object_type).unwrap();
+            let added = match object_type {
+                "light" => scene.light_snapshot(0),
+                _ => scene.snapshot(EditSubject::Element(first_element)),
+            };
+            scene.record(format!("Add {}", object_type), None, added);
+            states.push(state(&scene));
+        }
+
+        let plane_id = scene.elements().iter().position(|element| element.shape().as_plane().is_some()).unwrap();
+        let before = scene.snapshot(EditSubject::Element(plane_id));
+        scene.element_mut_by_id(plane_id).unwrap().set_transform(Some(Transform::new(Vec3::new(1., 2., 3.), Vec3::from_value(0.), Vec3::from_value(1.))));
+        let after = scene.snapshot(EditSubject::Element(plane_id));
+        scene.record("Edit plane".to_string(), before.clone(), after);
+        scene.record("Nothing".to_string(), before.clone(), before);
+        states.push(state(&scene));
+
+        // The first composed element is in the middle of the elements, the ones after it are shifted
+        let removed = scene.snapshot(EditSubject::ComposedElement(0));
+        scene.remove_composed_element(0);
+        scene.record("Delete torusphere".to_string(), removed, None);
+        states.push(state(&scene));
+        assert_eq!(scene.history().done().len(), states.len() - 1);
+
+        for expected in states.iter().rev().skip(1) {
+            assert!(scene.undo().is_some());
+            assert_eq!(&state(&scene), expected);
+        }
+        assert!(scene.undo().is_none());
+        for expected in states.iter().skip(1) {
+            assert!(scene.redo().is_some());
+            assert_eq!(&state(&scene), expected);
+        }
+        assert!(scene.redo().is_none());
+
+        scene.undo();
+        scene.record("Delete light".to_string(), scene.light_snapshot(0), None);
+        assert!(scene.history().undone().is_empty());
+    }
+}
@@ -39,10 +39,26 @@ impl<'a> Hit<'a> {
         textures: &HashMap<String, RgbaImage>,
         all_dist: Vec<f64>
     ) -> Self {
-        let mut norm = element.shape().norm(&pos);
+        let mut norm = element.norm(&pos);
         if norm.dot(ray_dir) > 0. {
             norm = -norm;
         }
+        Hit::from_geometry(element, dist, pos, norm, None, textures, all_dist)
+    }
+
+    /**
+     * Hit whose norm (already facing the ray) and projection have been computed by the caller, for the shapes that
+     * can't get them from the position alone, like the instances.
+     */
+    pub fn from_geometry(
+        element: &'a Element,
+        dist: f64,
+        pos: Vec3,
+        norm: Vec3,
+        projection: Option<Projection>,
+        textures: &HashMap<String, RgbaImage>,
+        all_dist: Vec<f64>
+    ) -> Self {
         let mut hit = Hit {
             element,
             dist,
@@ -59,11 +75,27 @@ impl<'a> Hit<'a> {
             all_dist,
             t_list: vec![]
         };
+        hit.projection = projection.map(|projection| hit.scale_projection(projection));
         hit.map_norm(textures);
         hit.map_opacity(textures);
         hit
     }
 
+    /**
+     * Copy of the hit at another position, without its distances, used to move it into the local space of a shape.
+     */
+    pub fn with_geometry(&self, pos: Vec3, norm: Vec3) -> Hit<'a> {
+        Hit {
+            pos,
+            norm,
+            projection: None,
+            all_dist: vec![],
+            t_list: vec![],
+            color: self.color.clone(),
+            ..*self
+        }
+    }
+
     pub fn set_t_list(&mut self, t_list: Vec<(&'a Element, Vec<f64>)>) {
         self.t_list = t_list;
     }
@@ -135,7 +167,7 @@ impl<'a> Hit<'a> {
         }
     }
 
-    fn map_color(&mut self, textures: &HashMap<String, RgbaImage>) {
+    pub fn map_color(&mut self, textures: &HashMap<String, RgbaImage>) {
         self.color = Color::from_vec3(&self.map_texture(self.element.material().color(), textures, Vec3::from_value(1.)));
     }
 
@@ -192,10 +224,8 @@ impl<'a> Hit<'a> {
     pub fn projection(&mut self) -> &Projection {
         let projection = match self.projection.take() {
             None => {
-                let mut projection = self.element().shape().projection(self);
-                projection.u = (projection.u * self.element().material().u_scale() - self.element().material().u_shift()).rem_euclid(1.);
-                projection.v = (projection.v * self.element().material().v_scale() - self.element().material().v_shift()).rem_euclid(1.);
-                projection
+                let projection = self.element().projection(self);
+                self.scale_projection(projection)
             },
             Some(p) => p,
         };
@@ -203,6 +233,12 @@ impl<'a> Hit<'a> {
         self.projection.as_ref().unwrap()
     }
 
+    fn scale_projection(&self, mut projection: Projection) -> Projection {
+        projection.u = (projection.u * self.element().material().u_scale() - self.element().material().u_shift()).rem_euclid(1.);
+        projection.v = (projection.v * self.element().material().v_scale() - self.element().material().v_shift()).rem_euclid(1.);
+        projection
+    }
+
     pub fn map_textures(&mut self, textures: &HashMap<String, RgbaImage>) {
         self.map_color(textures);
         self.map_roughness(textures);
@@ -3,4 +3,5 @@ pub mod ray;
 pub mod hit;
 pub mod vec2;
 pub mod quaternion;
+pub mod transform;
 pub mod vec_utils;
\ No newline at end of file
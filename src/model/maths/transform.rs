@@ -0,0 +1,315 @@
+use nalgebra::Matrix3;
+use super::{quaternion::Quaternion, ray::Ray, vec3::Vec3};
+use crate::model::shapes::aabb::Aabb;
+
+/**
+ * Affine transform of an object from its local space to the world. It's usually built from a scale, a rotation
+ * (euler angles in degrees, x then y then z) and a translation applied in this order, but any invertible matrix
+ * can be given: its decomposition is then only kept for the UI, and editing it drops the shear of the matrix.
+ */
+#[derive(Debug, Clone)]
+pub struct Transform {
+    translation: Vec3,
+    rotation: Vec3,
+    scale: Vec3,
+    linear: Matrix3<f64>,
+    inverse: Matrix3<f64>,
+}
+
+impl Transform {
+    // Constructors
+    pub fn new(translation: Vec3, rotation: Vec3, scale: Vec3) -> Transform {
+        let mut transform = Transform {
+            translation,
+            rotation,
+            scale,
+            linear: Matrix3::identity(),
+            inverse: Matrix3::identity(),
+        };
+        transform.update_matrix();
+        transform
+    }
+
+    pub fn from_quaternion(translation: Vec3, quaternion: &Quaternion, scale: Vec3) -> Transform {
+        let (x, y, z, w) = (*quaternion.x(), *quaternion.y(), *quaternion.z(), *quaternion.w());
+        let length = (x * x + y * y + z * z + w * w).sqrt();
+        let (x, y, z, w) = (x / length, y / length, z / length, w / length);
+        let rotation = Matrix3::new(
+            1. - 2. * (y * y + z * z), 2. * (x * y - z * w), 2. * (x * z + y * w),
+            2. * (x * y + z * w), 1. - 2. * (x * x + z * z), 2. * (y * z - x * w),
+            2. * (x * z - y * w), 2. * (y * z + x * w), 1. - 2. * (x * x + y * y),
+        );
+        Transform::new(translation, euler_angles(&rotation), scale)
+    }
+
+    /**
+     * The matrix is given row by row, the last row must be (0, 0, 0, 1).
+     */
+    pub fn from_matrix(matrix: [[f64; 4]; 4]) -> Result<Transform, String> {
+        if matrix[3] != [0., 0., 0., 1.] {
+            return Err("The last row of the matrix of an object must be [0, 0, 0, 1]".to_string());
+        }
+        let linear = Matrix3::new(
+            matrix[0][0], matrix[0][1], matrix[0][2],
+            matrix[1][0], matrix[1][1], matrix[1][2],
+            matrix[2][0], matrix[2][1], matrix[2][2],
+        );
+        let inverse = match linear.try_inverse() {
+            Some(inverse) => inverse,
+            None => return Err("The matrix of an object must be invertible".to_string()),
+        };
+        Ok(Transform::from_linear(Vec3::new(matrix[0][3], matrix[1][3], matrix[2][3]), linear, inverse))
+    }
+
+    fn from_linear(translation: Vec3, linear: Matrix3<f64>, inverse: Matrix3<f64>) -> Transform {
+        let scale = Vec3::new(linear.column(0).norm(), linear.column(1).norm(), linear.column(2).norm());
+        let rotation = Matrix3::from_columns(&[
+            linear.column(0) / *scale.x(),
+            linear.column(1) / *scale.y(),
+            linear.column(2) / *scale.z(),
+        ]);
+        Transform {
+            translation,
+            rotation: euler_angles(&rotation),
+            scale,
+            linear,
+            inverse,
+        }
+    }
+
+    // Accessors
+    pub fn translation(&self) -> &Vec3 {
+        &self.translation
+    }
+    pub fn rotation(&self) -> &Vec3 {
+        &self.rotation
+    }
+    pub fn scale(&self) -> &Vec3 {
+        &self.scale
+    }
+
+    // Mutators
+    pub fn set_translation(&mut self, translation: Vec3) {
+        self.translation = translation;
+    }
+    pub fn set_rotation(&mut self, rotation: Vec3) {
+        self.rotation = rotation;
+        self.update_matrix();
+    }
+    pub fn set_scale(&mut self, scale: Vec3) {
+        self.scale = scale;
+        self.update_matrix();
+    }
+
+    // Methods
+    pub fn point_to_world(&self, point: &Vec3) -> Vec3 {
+        multiply(&self.linear, point) + self.translation
+    }
+    pub fn point_to_local(&self, point: &Vec3) -> Vec3 {
+        multiply(&self.inverse, &(point - self.translation))
+    }
+    pub fn dir_to_world(&self, dir: &Vec3) -> Vec3 {
+        multiply(&self.linear, dir)
+    }
+    pub fn dir_to_local(&self, dir: &Vec3) -> Vec3 {
+        multiply(&self.inverse, dir)
+    }
+
+    /**
+     * The normals are transformed by the inverse transpose of the matrix, so that they stay orthogonal to the surface.
+     */
+    pub fn norm_to_world(&self, norm: &Vec3) -> Vec3 {
+        multiply(&self.inverse.transpose(), norm).normalize()
+    }
+    pub fn norm_to_local(&self, norm: &Vec3) -> Vec3 {
+        multiply(&self.linear.transpose(), norm).normalize()
+    }
+
+    /**
+     * Returns the ray in the local space, with a normalized direction, and the factor to apply to the local distances
+     * to get the world ones.
+     */
+    pub fn ray_to_local(&self, ray: &Ray) -> (Ray, f64) {
+        let dir = self.dir_to_local(ray.get_dir());
+        let length = dir.length();
+        let mut local_ray = ray.clone();
+        local_ray.set_pos(self.point_to_local(ray.get_pos()));
+        local_ray.set_dir(dir / length);
+        (local_ray, 1. / length)
+    }
+
+    /**
+     * Transform of a child given in the local space of self, i.e. the child applied first.
+     */
+    pub fn compose(&self, child: &Transform) -> Transform {
+        Transform::from_linear(
+            self.point_to_world(&child.translation),
+            self.linear * child.linear,
+            child.inverse * self.inverse,
+        )
+    }
+
+    pub fn aabb_to_world(&self, aabb: &Aabb) -> Aabb {
+        let mut min = Vec3::from_value(f64::MAX);
+        let mut max = Vec3::from_value(f64::MIN);
+        for i in 0..8 {
+            let corner = Vec3::new(
+                if i & 1 == 0 { aabb.x_min() } else { aabb.x_max() },
+                if i & 2 == 0 { aabb.y_min() } else { aabb.y_max() },
+                if i & 4 == 0 { aabb.z_min() } else { aabb.z_max() },
+            );
+            let corner = self.point_to_world(&corner);
+            min = min.min(corner);
+            max = max.max(corner);
+        }
+        Aabb::from_min_max(min, max)
+    }
+
+    fn update_matrix(&mut self) {
+        let (sx, cx) = self.rotation.x().to_radians().sin_cos();
+        let (sy, cy) = self.rotation.y().to_radians().sin_cos();
+        let (sz, cz) = self.rotation.z().to_radians().sin_cos();
+
+        // Rz * Ry * Rx
+        let rotation = Matrix3::new(
+            cz * cy, cz * sy * sx - sz * cx, cz * sy * cx + sz * sx,
+            sz * cy, sz * sy * sx + cz * cx, sz * sy * cx - cz * sx,
+            -sy, cy * sx, cy * cx,
+        );
+        self.linear = rotation * Matrix3::from_diagonal(&nalgebra::Vector3::new(*self.scale.x(), *self.scale.y(), *self.scale.z()));
+        self.inverse = self.linear.try_inverse().unwrap_or(Matrix3::identity());
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform::new(Vec3::from_value(0.), Vec3::from_value(0.), Vec3::from_value(1.))
+    }
+}
+
+fn multiply(matrix: &Matrix3<f64>, vec: &Vec3) -> Vec3 {
+    let (x, y, z) = (*vec.x(), *vec.y(), *vec.z());
+    Vec3::new(
+        matrix[(0, 0)] * x + matrix[(0, 1)] * y + matrix[(0, 2)] * z,
+        matrix[(1, 0)] * x + matrix[(1, 1)] * y + matrix[(1, 2)] * z,
+        matrix[(2, 0)] * x + matrix[(2, 1)] * y + matrix[(2, 2)] * z,
+    )
+}
+
+// Euler angles in degrees of a rotation matrix built as Rz * Ry * Rx
+fn euler_angles(rotation: &Matrix3<f64>) -> Vec3 {
+    let sy = (-rotation[(2, 0)]).clamp(-1., 1.);
+    let y = sy.asin();
+    let (x, z) = if sy.abs() < 0.999999 {
+        (rotation[(2, 1)].atan2(rotation[(2, 2)]), rotation[(1, 0)].atan2(rotation[(0, 0)]))
+    } else {
+        // Gimbal lock, the x and z rotations are around the same axis
+        ((-rotation[(1, 2)]).atan2(rotation[(1, 1)]), 0.)
+    };
+    Vec3::new(x.to_degrees(), y.to_degrees(), z.to_degrees())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::element::Element;
+    use crate::model::materials::diffuse::Diffuse;
+    use crate::model::maths::quaternion::Quaternion;
+    use crate::model::maths::ray::Ray;
+    use crate::model::maths::transform::Transform;
+    use crate::model::maths::vec3::Vec3;
+    use crate::model::shapes::sphere::Sphere;
+
+    fn assert_vec_eq(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn test_point_roundtrip() {
+        let transform = Transform::new(Vec3::new(1., 2., 3.), Vec3::new(30., 45., 60.), Vec3::new(1., 2., 0.5));
+        let point = Vec3::new(-4., 0.5, 7.);
+        assert_vec_eq(transform.point_to_local(&transform.point_to_world(&point)), point);
+        assert_vec_eq(transform.point_to_world(&transform.point_to_local(&point)), point);
+    }
+
+    #[test]
+    fn test_rotation() {
+        let transform = Transform::new(Vec3::from_value(0.), Vec3::new(0., 0., 90.), Vec3::from_value(1.));
+        assert_vec_eq(transform.dir_to_world(&Vec3::new(1., 0., 0.)), Vec3::new(0., 1., 0.));
+    }
+
+    #[test]
+    fn test_norm_non_uniform_scale() {
+        // The plane x + y = 0 stretched along x keeps a normal orthogonal to its surface
+        let transform = Transform::new(Vec3::from_value(0.), Vec3::from_value(0.), Vec3::new(2., 1., 1.));
+        let norm = transform.norm_to_world(&Vec3::new(1., 1., 0.).normalize());
+        let tangent = transform.dir_to_world(&Vec3::new(1., -1., 0.));
+        assert!(norm.dot(&tangent).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_from_quaternion() {
+        // Quarter turn around z
+        let half = std::f64::consts::FRAC_PI_4;
+        let quaternion = Quaternion::new(0., 0., half.sin(), half.cos());
+        let transform = Transform::from_quaternion(Vec3::from_value(0.), &quaternion, Vec3::from_value(1.));
+        assert_vec_eq(*transform.rotation(), Vec3::new(0., 0., 90.));
+        assert_vec_eq(transform.dir_to_world(&Vec3::new(1., 0., 0.)), Vec3::new(0., 1., 0.));
+    }
+
+    #[test]
+    fn test_from_matrix() {
+        let transform = Transform::from_matrix([
+            [0., -2., 0., 1.],
+            [2., 0., 0., 2.],
+            [0., 0., 2., 3.],
+            [0., 0., 0., 1.],
+        ]).unwrap();
+        assert_vec_eq(*transform.translation(), Vec3::new(1., 2., 3.));
+        assert_vec_eq(*transform.rotation(), Vec3::new(0., 0., 90.));
+        assert_vec_eq(*transform.scale(), Vec3::from_value(2.));
+        let point = Vec3::new(1., 1., 1.);
+        assert_vec_eq(transform.point_to_local(&transform.point_to_world(&point)), point);
+
+        assert!(Transform::from_matrix([[1., 0., 0., 0.], [0., 0., 0., 0.], [0., 0., 1., 0.], [0., 0., 0., 1.]]).is_err());
+    }
+
+    #[test]
+    fn test_compose() {
+        let parent = Transform::new(Vec3::new(10., 0., 0.), Vec3::new(0., 90., 0.), Vec3::from_value(2.));
+        let child = Transform::new(Vec3::new(1., 2., 3.), Vec3::new(30., 0., 0.), Vec3::new(1., 3., 1.));
+        let composed = parent.compose(&child);
+        let point = Vec3::new(0.5, -1., 2.);
+        assert_vec_eq(composed.point_to_world(&point), parent.point_to_world(&child.point_to_world(&point)));
+        assert_vec_eq(composed.point_to_local(&composed.point_to_world(&point)), point);
+    }
+
+    #[test]
+    fn test_ray_to_local() {
+        let transform = Transform::new(Vec3::new(10., 0., 0.), Vec3::from_value(0.), Vec3::from_value(2.));
+        let ray = Ray::new(Vec3::new(0., 0., 0.), Vec3::new(1., 0., 0.), 0);
+        let (local_ray, factor) = transform.ray_to_local(&ray);
+        assert_vec_eq(*local_ray.get_pos(), Vec3::new(-5., 0., 0.));
+        // The point at t = 1 in the local space is at t = 2 in the world
+        assert!((factor - 2.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_transformed_element() {
+        // Unit sphere stretched into an ellipsoid of semi-axes (2, 1, 1) centered on (0, 0, 5)
+        let sphere = Sphere::new(Vec3::from_value(0.), Vec3::new(0., 1., 0.), 1.);
+        let mut element = Element::new(Box::new(sphere), Diffuse::default());
+        element.set_transform(Some(Transform::new(Vec3::new(0., 0., 5.), Vec3::from_value(0.), Vec3::new(2., 1., 1.))));
+
+        let ray = Ray::new(Vec3::new(-10., 0., 5.), Vec3::new(1., 0., 0.), 0);
+        let t = element.intersect(&ray).unwrap();
+        assert!((t[0] - 8.).abs() < 1e-9);
+        assert!((t[1] - 12.).abs() < 1e-9);
+
+        // Gradient of x²/4 + y² at (√2, √0.5)
+        let pos = Vec3::new(2f64.sqrt(), 0.5f64.sqrt(), 5.);
+        assert_vec_eq(element.norm(&pos), Vec3::new(2f64.sqrt() / 2., 2. * 0.5f64.sqrt(), 0.).normalize());
+
+        let aabb = element.aabb().unwrap();
+        assert!((aabb.x_max() - 2.).abs() < 1e-9 && (aabb.z_min() - 4.).abs() < 1e-9);
+    }
+}
@@ -4,4 +4,6 @@ pub mod maths;
 pub mod objects;
 pub mod scene;
 pub mod element;
-pub mod composed_element;
\ No newline at end of file
+pub mod composed_element;
+pub mod prototype;
+pub mod group;pub mod history;
@@ -4,7 +4,7 @@ use crate::{
     model::maths::{quaternion::Quaternion, vec3::Vec3}, ui::{ui::UI, uielement::{Category, UIElement}, utils::misc::{ElemType, Property, Value}}, LOOK_STEP, SCREEN_HEIGHT, SCREEN_WIDTH, STEP
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Camera {
     pos: Vec3,
     dir: Vec3,
@@ -13,6 +13,10 @@ pub struct Camera {
     vfov: f64,
     u: Vec3,
     v: Vec3,
+    // Angle in radians of the view around its direction
+    roll: f64,
+    // Factor of the steps of the movements
+    speed: f64,
     q_up: Quaternion,
     q_down: Quaternion,
     q_left: Quaternion,
@@ -39,15 +43,27 @@ impl Camera {
     pub fn v(&self) -> &Vec3 {
         &self.v
     }
+    pub fn roll(&self) -> f64 {
+        self.roll
+    }
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
 
     // Mutators
     pub fn set_pos(&mut self, pos: Vec3) {
         self.pos = pos
     }
     pub fn set_dir(&mut self, dir: Vec3) {
-        self.u = Vec3::new(*dir.z(), 0., -*dir.x()).normalize();
-        self.v = dir.cross(&self.u).normalize();
         self.dir = dir.normalize();
+        self.update_axes();
+    }
+    pub fn set_roll(&mut self, roll: f64) {
+        self.roll = roll;
+        self.update_axes();
+    }
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed = speed;
     }
     pub fn set_fov(&mut self, fov: f64) {
         self.fov = fov;
@@ -56,34 +72,28 @@ impl Camera {
 
     // Constructor
     pub fn new(pos: Vec3, dir: Vec3, fov: f64) -> Camera {
-		let u;
-		if *dir.x() == 0. && *dir.z() == 0. {
-			u = Vec3::new(1., 0., 0.);
-		}
-		else {
-			u = Vec3::new(*dir.z(), 0., -*dir.x()).normalize();
-		}
         let fov_deg = fov;
         let fov = fov_deg * PI / 180.;
-		let v = dir.cross(&u).normalize();
         let vfov = fov * SCREEN_HEIGHT as f64 / SCREEN_WIDTH as f64;
-        let q_up = Quaternion::new_from_axis_angle(&u, -LOOK_STEP);
-        let q_down = Quaternion::new_from_axis_angle(&u, LOOK_STEP);
         let q_left = Quaternion::new_from_axis_angle(&Vec3::new(0., 1., 0.), -LOOK_STEP);
         let q_right = Quaternion::new_from_axis_angle(&Vec3::new(0., 1., 0.), LOOK_STEP);
-        self::Camera {
+        let mut camera = self::Camera {
             pos,
             dir,
             fov_deg,
             fov,
-            u,
-            v,
+            u: Vec3::new(1., 0., 0.),
+            v: Vec3::new(0., 1., 0.),
             vfov,
-            q_left,
+            roll: 0.,
+            speed: 1.,
+            q_left: q_left.clone(),
             q_right,
-            q_up,
-            q_down,
-        }
+            q_up: q_left.clone(),
+            q_down: q_left,
+        };
+        camera.update_axes();
+        camera
     }
 
     pub fn default() -> Self {
@@ -95,6 +105,8 @@ impl Camera {
             u: Vec3::new(0.0, 0.0, 0.0),
             v: Vec3::new(0.0, 0.0, 0.0),
             vfov: 0.,
+            roll: 0.,
+            speed: 1.,
             q_up: Quaternion::new_from_axis_angle(&Vec3::new(1., 0., 0.), -LOOK_STEP),
             q_down: Quaternion::new_from_axis_angle(&Vec3::new(1., 0., 0.), LOOK_STEP),
             q_left: Quaternion::new_from_axis_angle(&Vec3::new(0., 1., 0.), -LOOK_STEP),
@@ -104,22 +116,22 @@ impl Camera {
 
     // Movement methods
     pub fn move_forward(&mut self) {
-        self.pos += self.dir() * STEP;
+        self.pos += self.dir() * STEP * self.speed;
     }
     pub fn move_backward(&mut self) {
-        self.pos -= self.dir() * STEP;
+        self.pos -= self.dir() * STEP * self.speed;
     }
     pub fn move_left(&mut self) {
-        self.pos -= self.u() * STEP;
+        self.pos -= self.u() * STEP * self.speed;
     }
     pub fn move_right(&mut self) {
-        self.pos += self.u() * STEP;
+        self.pos += self.u() * STEP * self.speed;
     }
     pub fn move_up(&mut self) {
-        self.pos -= Vec3::new(0., 1., 0.) * STEP;
+        self.pos -= Vec3::new(0., 1., 0.) * STEP * self.speed;
     }
     pub fn move_down(&mut self) {
-        self.pos += Vec3::new(0., 1., 0.) * STEP;
+        self.pos += Vec3::new(0., 1., 0.) * STEP * self.speed;
     }
     pub fn look_up(&mut self) {
         let new_dir = self.q_up.rotate(&self.dir());
@@ -135,14 +147,72 @@ impl Camera {
     }
     pub fn look_left(&mut self) {
         self.set_dir(self.q_left.rotate(&self.dir()));
-		self.q_up = Quaternion::new_from_axis_angle(&self.u(), -LOOK_STEP);
-		self.q_down = Quaternion::new_from_axis_angle(&self.u(), LOOK_STEP);
     }
     pub fn look_right(&mut self) {
         self.set_dir(self.q_right.rotate(&self.dir()));
-		self.q_up = Quaternion::new_from_axis_angle(&self.u(), -LOOK_STEP);
-		self.q_down = Quaternion::new_from_axis_angle(&self.u(), LOOK_STEP);
     }
+    pub fn roll_left(&mut self) {
+        self.set_roll(self.roll - LOOK_STEP);
+    }
+    pub fn roll_right(&mut self) {
+        self.set_roll(self.roll + LOOK_STEP);
+    }
+
+    // Mouse controls
+    /**
+     * Turns the camera around a point, around the vertical axis of the world then the horizontal axis of the view,
+     * with angles in radians.
+     */
+    pub fn orbit(&mut self, pivot: &Vec3, yaw: f64, pitch: f64) {
+        self.rotate_around(pivot, &Quaternion::new_from_axis_angle(&Vec3::new(0., 1., 0.), yaw));
+        let pitch = Quaternion::new_from_axis_angle(&self.u, pitch);
+        // Not past the vertical, like look_up and look_down
+        if pitch.rotate(&self.dir).normalize().dot(&Vec3::new(0., 1., 0.)).abs() < 0.9995 {
+            self.rotate_around(pivot, &pitch);
+        }
+    }
+    pub fn pan(&mut self, right: f64, up: f64) {
+        self.pos += self.u * right + self.v * up;
+    }
+    pub fn dolly(&mut self, distance: f64) {
+        self.pos += self.dir.normalize() * distance;
+    }
+
+    /**
+     * Moves the camera back from a sphere until it fills the smallest side of the screen.
+     */
+    pub fn frame(&mut self, center: &Vec3, radius: f64) {
+        let half_angle = ((self.fov / 2.).tan() * SCREEN_HEIGHT.min(SCREEN_WIDTH) as f64 / SCREEN_WIDTH as f64).atan();
+        self.pos = center - self.dir.normalize() * (radius / half_angle.sin());
+    }
+
+    /**
+     * Size in the world of a pixel at a distance from the camera.
+     */
+    pub fn pixel_size(&self, distance: f64) -> f64 {
+        (self.fov / 2.).tan() * 2. * distance / SCREEN_WIDTH as f64
+    }
+
+    fn rotate_around(&mut self, pivot: &Vec3, rotation: &Quaternion) {
+        self.pos = pivot + rotation.rotate(&(self.pos - pivot));
+        self.set_dir(rotation.rotate(&self.dir));
+    }
+
+    // The horizontal axis of the view stays in the horizontal plane of the world, unless the camera is rolled
+    fn update_axes(&mut self) {
+        let dir = self.dir;
+        let u = match *dir.x() == 0. && *dir.z() == 0. {
+            true => Vec3::new(1., 0., 0.),
+            false => Vec3::new(*dir.z(), 0., -*dir.x()).normalize(),
+        };
+        let v = dir.cross(&u).normalize();
+        let roll = Quaternion::new_from_axis_angle(&dir, self.roll);
+        self.u = roll.rotate(&u).normalize();
+        self.v = roll.rotate(&v).normalize();
+        self.q_up = Quaternion::new_from_axis_angle(&self.u, -LOOK_STEP);
+        self.q_down = Quaternion::new_from_axis_angle(&self.u, LOOK_STEP);
+    }
+
     pub fn debug_print(&self) {
         println!();
         println!("pos: {:.2} {:.2} {:.2}", self.pos.x(), self.pos.y(), self.pos.z());
@@ -177,7 +247,45 @@ impl Camera {
             Ok(())
         }), ui.uisettings())), ui.uisettings());
 
+        let roll = UIElement::new("Roll", "roll", ElemType::Property(Property::new(Value::Float(self.roll.to_degrees()),
+        Box::new(move |_, value, context, _| {
+            if let Some(scene) = context.get_active_scene() {
+                let mut scene = scene.write().unwrap();
+                if let Value::Float(value) = value {
+                    scene.camera_mut().set_roll(value.to_radians());
+                }
+            }
+        }),
+        Box::new(move |value, _, _| {
+            if let Value::Float(value) = value {
+                if value.abs() > 360. {
+                    return Err("The value should be between -360 and 360".to_string());
+                }
+            }
+            Ok(())
+        }), ui.uisettings())), ui.uisettings());
+
+        let speed = UIElement::new("Speed", "speed", ElemType::Property(Property::new(Value::Float(self.speed),
+        Box::new(move |_, value, context, _| {
+            if let Some(scene) = context.get_active_scene() {
+                let mut scene = scene.write().unwrap();
+                if let Value::Float(value) = value {
+                    scene.camera_mut().set_speed(value);
+                }
+            }
+        }),
+        Box::new(move |value, _, _| {
+            if let Value::Float(value) = value {
+                if *value <= 0. {
+                    return Err("The value should be superior to 0".to_string());
+                }
+            }
+            Ok(())
+        }), ui.uisettings())), ui.uisettings());
+
         category.add_element(fov);
+        category.add_element(roll);
+        category.add_element(speed);
         category
     }
 }
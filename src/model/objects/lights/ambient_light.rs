@@ -2,7 +2,7 @@ use std::sync::{Arc, RwLock};
 
 use crate::{model::{materials::color::Color, scene::Scene}, ui::{prefabs::vector_ui::get_vector_ui, ui::UI, uielement::{Category, UIElement}, utils::misc::{ElemType, Property, Value}}};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct AmbientLight {
     intensity: f64,
     color: Color,
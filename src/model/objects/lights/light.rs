@@ -1,27 +1,43 @@
 use std::{fmt::Debug, sync::{Arc, RwLock}};
-use crate::{model::{materials::color::Color, maths::{hit::Hit, ray::Ray, vec3::Vec3}, scene::Scene}, ui::{ui::UI, uielement::UIElement}};
+use crate::{model::{materials::color::Color, maths::{hit::Hit, ray::Ray, transform::Transform, vec3::Vec3}, scene::Scene}, ui::{ui::UI, uielement::UIElement}};
 use super::{parallel_light::ParallelLight, point_light::PointLight, spot_light::SpotLight};
 
 #[derive(Debug)]
 pub struct AnyLight {
     id: usize,
-    light: Box<dyn Light + Sync + Send>
+    group_id: Option<usize>,
+    light: Box<dyn Light + Sync + Send>,
+    // A hidden light doesn't light the scene
+    visible: bool,
+    name: Option<String>,
 }
 
 impl AnyLight {
     pub fn new(light: Box<dyn Light + Sync + Send>) -> Self {
         Self {
             id: 0,
-            light
+            group_id: None,
+            light,
+            visible: true,
+            name: None,
         }
     }
 
     pub fn id(&self) -> usize {
         self.id
     }
+    pub fn group_id(&self) -> Option<usize> {
+        self.group_id
+    }
     pub fn light(&self) -> &Box<dyn Light + Sync + Send> {
         &self.light
     }
+    pub fn visible(&self) -> bool {
+        self.visible
+    }
+    pub fn name(&self) -> Option<&String> {
+        self.name.as_ref()
+    }
     pub fn light_mut(&mut self) -> &mut Box<dyn Light + Sync + Send> {
         &mut self.light
     }
@@ -29,16 +45,74 @@ impl AnyLight {
     pub fn set_id(&mut self, id: usize) {
         self.id = id;
     }
+    pub fn set_group_id(&mut self, group_id: Option<usize>) {
+        self.group_id = group_id;
+    }
+    pub fn set_visible(&mut self, visible: bool) {
+        self.visible = visible;
+    }
+    pub fn set_name(&mut self, name: Option<String>) {
+        self.name = name;
+    }
+
+    /**
+     * Its name, or its type and id.
+     */
+    pub fn label(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => format!("{} {}", self.light.light_name(), self.id),
+        }
+    }
 
     pub fn get_ui(&self, light: &AnyLight, ui: &mut UI, scene: &Arc<RwLock<Scene>>) -> UIElement {
         self.light().get_ui(light, ui, scene)
     }
 }
 
-pub trait Light: Debug + Sync {
+/**
+ * The copy keeps the id, the scene gives it a new one when it is added.
+ */
+impl Clone for AnyLight {
+    fn clone(&self) -> Self {
+        Self {
+            id: self.id,
+            group_id: self.group_id,
+            light: self.light.clone_light(),
+            visible: self.visible,
+            name: self.name.clone(),
+        }
+    }
+}
+
+/**
+ * Copy of a light behind a box, for the duplication of lights.
+ */
+pub trait LightClone {
+    fn clone_light(&self) -> Box<dyn Light + Sync + Send>;
+}
+
+impl<T: 'static + Light + Send + Clone> LightClone for T {
+    fn clone_light(&self) -> Box<dyn Light + Sync + Send> {
+        Box::new(self.clone())
+    }
+}
+
+pub trait Light: Debug + Sync + LightClone {
     fn get_diffuse(&self, hit: &Hit) -> Color;
     fn get_specular(&self, hit: &Hit, ray: &Ray) -> Color;
     fn throughput(&self, scene: &Scene, hit: &Hit) -> Vec3;
+    fn light_name(&self) -> String {
+        if self.as_spot_light().is_some() {
+            "Spot light".to_string()
+        } else if self.as_point_light().is_some() {
+            "Point light".to_string()
+        } else if self.as_parallel_light().is_some() {
+            "Parallel light".to_string()
+        } else {
+            "Unknown".to_string()
+        }
+    }
 
     fn as_point_light(&self) -> Option<&PointLight> {
         None
@@ -59,4 +133,9 @@ pub trait Light: Debug + Sync {
         None
     }
     fn get_ui(&self, light: &AnyLight, ui: &mut UI, scene: &Arc<RwLock<Scene>>) -> UIElement;
+
+    /**
+     * Moves the light from the space of a transform to the one of another, when the group of the light is moved.
+     */
+    fn change_space(&mut self, from: &Transform, to: &Transform);
 }
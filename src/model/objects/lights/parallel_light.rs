@@ -1,5 +1,5 @@
 use std::sync::{Arc, RwLock};
-use crate::{model::{materials::color::Color, maths::{hit::Hit, ray::Ray, vec3::Vec3}, scene::Scene}, render::raycasting::get_closest_hit, ui::{prefabs::vector_ui::get_vector_ui, ui::UI, uielement::{Category, UIElement}, utils::misc::{ElemType, Property, Value}}, BOUNCE_OFFSET};
+use crate::{model::{materials::color::Color, maths::{hit::Hit, ray::Ray, transform::Transform, vec3::Vec3}, scene::Scene}, ui::{prefabs::vector_ui::get_vector_ui, ui::UI, uielement::{Category, UIElement}, utils::misc::{ElemType, Property, Value}}, BOUNCE_OFFSET};
 use super::light::{AnyLight, Light};
 
 #[derive(Debug, Clone)]
@@ -54,22 +54,8 @@ impl Light for ParallelLight {
     }
 
     fn throughput(&self, scene: &Scene, hit: &Hit) -> Vec3 {
-        let mut shadow_ray = Ray::new(hit.pos() + hit.norm() * BOUNCE_OFFSET, -self.dir(), 0);
-        let mut throughput = Vec3::from_value(1.);
-        while throughput.length() > f64::EPSILON {
-            if let Some(light_hit) = get_closest_hit(scene, &shadow_ray) {
-                if light_hit.opacity() > (1. - f64::EPSILON) {
-                    return Vec3::from_value(0.);
-                } else {
-                    throughput = throughput * (1. - light_hit.opacity()) * light_hit.color().to_vec3();
-                    shadow_ray.set_pos(*light_hit.pos() + *shadow_ray.get_dir() * BOUNCE_OFFSET);
-                }
-            } else {
-                return throughput;
-            }
-        }
-        
-        throughput
+        let shadow_ray = Ray::new(hit.pos() + hit.norm() * BOUNCE_OFFSET, -self.dir(), 0);
+        scene.transmittance(&shadow_ray, f64::MAX)
     }
 
     fn as_parallel_light(&self) -> Option<&ParallelLight> {
@@ -80,9 +66,13 @@ impl Light for ParallelLight {
         Some(self)
     }
 
+    fn change_space(&mut self, from: &Transform, to: &Transform) {
+        self.dir = to.dir_to_world(&from.dir_to_local(&self.dir)).normalize();
+    }
+
 	fn get_ui(&self, light: &AnyLight, ui: &mut UI, _: &Arc<RwLock<Scene>>) -> UIElement {
 		let id = light.id().clone();
-		let mut category = UIElement::new(format!("Parallel light {}", id).as_str(), format!("light{}", id).as_str(), ElemType::Category(Category::collapsed()), ui.uisettings());
+		let mut category = UIElement::new(&light.label(), format!("light{}", id).as_str(), ElemType::Category(Category::collapsed()), ui.uisettings());
 
 		let dir = get_vector_ui(*light.light().as_parallel_light().unwrap().dir(), "Direction", "dir", ui.uisettings(), 
 		Box::new(move |_, value, context, _| {
@@ -1,8 +1,8 @@
 use std::sync::{Arc, RwLock};
-use crate::{model::{materials::color::Color, maths::{hit::Hit, ray::Ray, vec3::Vec3}, scene::Scene}, render::raycasting::get_closest_hit, ui::{prefabs::vector_ui::get_vector_ui, ui::UI, uielement::{Category, UIElement}, utils::misc::{ElemType, Property, Value}}, BOUNCE_OFFSET};
+use crate::{model::{materials::color::Color, maths::{hit::Hit, ray::Ray, transform::Transform, vec3::Vec3}, scene::Scene}, ui::{prefabs::vector_ui::get_vector_ui, ui::UI, uielement::{Category, UIElement}, utils::misc::{ElemType, Property, Value}}, BOUNCE_OFFSET};
 use super::light::{AnyLight, Light};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct PointLight {
     pos: Vec3,
     intensity: f64,
@@ -59,21 +59,7 @@ impl Light for PointLight {
     fn throughput(&self, scene: &Scene, hit: &Hit) -> Vec3 {
         let to_light = (self.pos() - hit.pos()).normalize();
         let shadow_ray = Ray::new(hit.pos() + hit.norm() * BOUNCE_OFFSET, to_light, 0);
-        let mut throughput = Vec3::from_value(1.);
-        if let Some(light_hit) = get_closest_hit(scene, &shadow_ray) {
-            for (_, t_list) in light_hit.t_list() {
-                for t in t_list {
-                    if t > &0. && *t < (hit.pos() - self.pos()).length() {
-                        if light_hit.opacity() > (1. - f64::EPSILON) {
-                            return Vec3::from_value(0.);
-                        } else {
-                            throughput = throughput * (1. - light_hit.opacity()) * light_hit.color().to_vec3();
-                        }
-                    }
-                }
-            }
-        }
-        throughput
+        scene.transmittance(&shadow_ray, (self.pos() - hit.pos()).length())
     }
                     
     fn as_point_light(&self) -> Option<&PointLight> {
@@ -84,9 +70,13 @@ impl Light for PointLight {
         Some(self)
     }
 
+    fn change_space(&mut self, from: &Transform, to: &Transform) {
+        self.pos = to.point_to_world(&from.point_to_local(&self.pos));
+    }
+
     fn get_ui(&self, light: &AnyLight, ui: &mut UI, _: &Arc<RwLock<Scene>>) -> UIElement {
         let id = light.id().clone();
-        let mut category = UIElement::new(format!("Point light {}", id).as_str(), format!("light{}", id).as_str(), ElemType::Category(Category::collapsed()), ui.uisettings());
+        let mut category = UIElement::new(&light.label(), format!("light{}", id).as_str(), ElemType::Category(Category::collapsed()), ui.uisettings());
         let pos = get_vector_ui(*light.light().as_point_light().unwrap().pos(), "Position", "pos", ui.uisettings(), 
         Box::new(move |_, value, context, _| {
             if let Some(scene) = context.get_active_scene() {
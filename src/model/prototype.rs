@@ -0,0 +1,98 @@
+use crate::{
+    bvh::{flat::FlatBvh, node::Node},
+    render::raycasting::get_closest_hit_from_bvh
+};
+use super::{
+    element::Element,
+    materials::material::Material,
+    maths::{hit::Hit, ray::Ray},
+    scene::Scene,
+    shapes::aabb::Aabb
+};
+
+/**
+ * Named group of elements that are only rendered through instances. The elements are expressed in the local space
+ * of the instances, and their BVH is built once and shared by all of them.
+ */
+#[derive(Debug)]
+pub struct Prototype {
+    name: String,
+    elements: Vec<Element>,
+    material: Box<dyn Material + Send + Sync>,
+    bvh: Option<FlatBvh>,
+    non_bvh_elements_index: Vec<usize>,
+    aabb: Option<Aabb>,
+}
+
+impl Prototype {
+    // Constructor
+    pub fn new(name: String, mut elements: Vec<Element>, material: Box<dyn Material + Send + Sync>) -> Prototype {
+        for (id, element) in elements.iter_mut().enumerate() {
+            element.set_id(id);
+        }
+
+        let aabbs: Vec<&Aabb> = elements.iter().filter_map(|element| element.aabb()).collect();
+        let bvh = if aabbs.is_empty() {
+            None
+        } else {
+            let mut node = Node::new(&Aabb::from_aabbs(&aabbs));
+            node.build_tree(&elements);
+            Some(FlatBvh::new(&node))
+        };
+
+        let non_bvh_elements_index: Vec<usize> = (0..elements.len())
+            .filter(|index| elements[*index].aabb().is_none())
+            .collect();
+
+        // A single infinite element makes the whole prototype unbounded
+        let aabb = match (&bvh, non_bvh_elements_index.is_empty()) {
+            (Some(bvh), true) => bvh.aabb(),
+            _ => None,
+        };
+
+        Prototype { name, elements, material, bvh, non_bvh_elements_index, aabb }
+    }
+
+    // Accessors
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+    pub fn elements(&self) -> &Vec<Element> {
+        &self.elements
+    }
+    pub fn material(&self) -> &(dyn Material + Send + Sync) {
+        self.material.as_ref()
+    }
+    pub fn aabb(&self) -> Option<&Aabb> {
+        self.aabb.as_ref()
+    }
+    pub fn bvh(&self) -> Option<&FlatBvh> {
+        self.bvh.as_ref()
+    }
+
+    // Methods
+    pub fn get_closest_hit<'a>(&'a self, scene: &'a Scene, ray: &Ray) -> Option<Hit<'a>> {
+        get_closest_hit_from_bvh(scene, ray, &self.elements, &self.non_bvh_elements_index, &self.bvh)
+    }
+
+    /**
+     * Every intersection of the ray with the elements, sorted.
+     */
+    pub fn intersect(&self, ray: &Ray) -> Option<Vec<f64>> {
+        let mut t_list = vec![];
+        for index in &self.non_bvh_elements_index {
+            if let Some(t) = self.elements[*index].intersect(ray) {
+                t_list.extend(t);
+            }
+        }
+        if let Some(bvh) = &self.bvh {
+            bvh.intersect(ray, &self.elements, &mut t_list);
+        }
+
+        if t_list.is_empty() {
+            return None;
+        }
+        t_list.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        Some(t_list)
+    }
+}
@@ -1,23 +1,36 @@
-use std::{collections::HashMap, ops::SubAssign};
+use std::{collections::HashMap, fmt::{self, Write}, sync::Arc, time::Instant};
 
 use image::RgbaImage;
 
 use crate::{
     bvh::{self},
     model::objects::lights::ambient_light::AmbientLight,
-    render::settings::Settings
+    render::{raycasting::intersect_element, settings::Settings, stats::{count, Counter, RenderStats}},
+    BVH_REBUILD_THRESHOLD,
+    DISPLAY_WIREFRAME,
+    USING_BVH
 };
 use super::{
-    composed_element::ComposedElement, element::Element, materials::{
+    composed_element::ComposedElement, element::Element, group::Group, history::{EditSubject, History}, materials::{
         diffuse::Diffuse,
         material::Material,
         texture::{Texture, TextureType}
     },
-    maths::vec3::Vec3,
+    maths::{hit::Hit, ray::Ray, transform::Transform, vec3::Vec3},
     objects::{camera::Camera, lights::light::AnyLight},
+    prototype::Prototype,
     shapes::{self, aabb::Aabb}
 };
 
+/**
+ * What an index of the BVH of the scene refers to: the elements come first, in the same order, then the composed
+ * elements. The elements of the composed elements aren't in it, they are in the BVH of their composed element.
+ */
+pub enum BvhPrimitive<'a> {
+    Element(usize),
+    ComposedElement(&'a ComposedElement),
+}
+
 #[derive(Debug)]
 pub struct Scene {
     elements: Vec<Element>,
@@ -25,17 +38,28 @@ pub struct Scene {
     non_bvh_elements_index: Vec<usize>,
     non_bvh_composed_elements_index: Vec<usize>,
     composed_elements: Vec<ComposedElement>,
+    prototypes: HashMap<String, Arc<Prototype>>,
+    // Library of the materials that the objects of the scene file refer to by name
+    materials: HashMap<String, Box<dyn Material + Send + Sync>>,
+    groups: Vec<Group>,
     camera: Camera,
     lights: Vec<AnyLight>,
     ambient_light: AmbientLight,
     settings: Settings,
     textures: HashMap<String, image::RgbaImage>,
     dirty: bool,
-    bvh: Option<bvh::node::Node>,
+    bvh: Option<bvh::flat::FlatBvh>,
+    changed_elements: Vec<usize>,
+    changed_composed_elements: Vec<usize>,
     next_element_id: usize,
     next_composed_element_id: usize,
 	next_light_id: usize,
     paused: bool,
+    path: Option<String>,
+    includes: Vec<String>,
+    source: Option<String>,
+    stats: RenderStats,
+    history: History,
 }
 
 impl Scene {
@@ -46,6 +70,9 @@ impl Scene {
             non_bvh_elements_index: Vec::new(),
             non_bvh_composed_elements_index: Vec::new(),
             composed_elements: Vec::new(),
+            prototypes: HashMap::new(),
+            materials: HashMap::new(),
+            groups: Vec::new(),
             camera: Camera::default(),
             lights: Vec::new(),
             ambient_light: AmbientLight::default(),
@@ -53,7 +80,14 @@ impl Scene {
             textures: HashMap::new(),
             dirty: true,
             paused: false,
+            path: None,
+            includes: vec![],
+            source: None,
+            stats: RenderStats::new(),
+            history: History::default(),
             bvh: None,
+            changed_elements: Vec::new(),
+            changed_composed_elements: Vec::new(),
             next_element_id: 0,
             next_composed_element_id: 0,
 			next_light_id: 0,
@@ -75,6 +109,10 @@ impl Scene {
         for mut element in elements {
             element.set_composed_id(composed_element.id());
             element.set_id(self.next_element_id);
+            element.set_group_id(composed_element.group_id());
+            element.set_transform(composed_element.transform().cloned());
+            element.set_group_transform(composed_element.group_transform().cloned());
+            element.set_visible(composed_element.visible());
             elements_index.push(self.next_element_id);
             self.elements.push(element);
             self.next_element_id += 1;
@@ -84,13 +122,123 @@ impl Scene {
         self.next_composed_element_id += 1;
     }
 
+    pub fn add_prototype(&mut self, prototype: Prototype) {
+        self.prototypes.insert(prototype.name().clone(), Arc::new(prototype));
+    }
+
+    pub fn add_shared_prototype(&mut self, name: &str, prototype: Arc<Prototype>) {
+        self.prototypes.insert(name.to_string(), prototype);
+    }
+
+    pub fn add_material(&mut self, name: &str, material: Box<dyn Material + Send + Sync>) {
+        self.materials.insert(name.to_string(), material);
+    }
+
+    /**
+     * Returns the id of the group, which is its index.
+     */
+    pub fn add_group(&mut self, mut group: Group) -> usize {
+        let id = self.groups.len();
+        group.set_id(id);
+        self.groups.push(group);
+        id
+    }
+
+    /**
+     * Puts the elements, composed elements and lights added since the given counts in the group, in the same way as
+     * parsing an object inside of a group.
+     */
+    pub fn set_group_from(&mut self, group_id: usize, first_element: usize, first_composed_element: usize, first_light: usize) {
+        let group_transform = self.group_world_transform(group_id);
+        for composed_element in self.composed_elements.iter_mut().skip(first_composed_element) {
+            composed_element.set_group_id(Some(group_id));
+            composed_element.set_group_transform(group_transform.clone());
+        }
+        for element in self.elements.iter_mut().skip(first_element) {
+            element.set_group_id(Some(group_id));
+            element.set_group_transform(group_transform.clone());
+        }
+        for light in self.lights.iter_mut().skip(first_light) {
+            if let Some(group_transform) = &group_transform {
+                light.light_mut().change_space(&Transform::default(), group_transform);
+            }
+            light.set_group_id(Some(group_id));
+        }
+    }
+
+    /**
+     * Changes the transform of a group and moves everything it contains, the BVH must be updated afterwards.
+     */
+    pub fn set_group_transform(&mut self, group_id: usize, transform: Option<Transform>) {
+        let old_transforms: Vec<Option<Transform>> = (0..self.groups.len()).map(|id| self.group_world_transform(id)).collect();
+        self.groups[group_id].set_transform(transform);
+        let new_transforms: Vec<Option<Transform>> = (0..self.groups.len()).map(|id| self.group_world_transform(id)).collect();
+
+        for (composed_id, composed_element) in self.composed_elements.iter_mut().enumerate() {
+            if let Some(id) = composed_element.group_id() {
+                composed_element.set_group_transform(new_transforms[id].clone());
+                self.changed_composed_elements.push(composed_id);
+            }
+        }
+        // The elements of the composed elements don't move in the BVH of their composed element
+        for (index, element) in self.elements.iter_mut().enumerate() {
+            if let Some(id) = element.group_id() {
+                element.set_group_transform(new_transforms[id].clone());
+                if element.composed_id().is_none() {
+                    self.changed_elements.push(index);
+                }
+            }
+        }
+        for light in &mut self.lights {
+            if let Some(id) = light.group_id() {
+                let old_transform = old_transforms[id].clone().unwrap_or_default();
+                let new_transform = new_transforms[id].clone().unwrap_or_default();
+                light.light_mut().change_space(&old_transform, &new_transform);
+            }
+        }
+    }
+
+    /**
+     * Transform of a group in the world, combining the ones of its parents.
+     */
+    pub fn group_world_transform(&self, group_id: usize) -> Option<Transform> {
+        let group = &self.groups[group_id];
+        let parent_transform = group.parent_id().and_then(|parent_id| self.group_world_transform(parent_id));
+        match (parent_transform, group.transform()) {
+            (Some(parent_transform), Some(transform)) => Some(parent_transform.compose(transform)),
+            (Some(transform), None) => Some(transform),
+            (None, transform) => transform.cloned(),
+        }
+    }
+
     pub fn update_composed_element_shape(&mut self, composed_id: usize) {
         let material = (*self.composed_elements[composed_id].material()).clone();
+        let group_id = self.composed_elements[composed_id].group_id();
+        let transform = self.composed_elements[composed_id].transform().cloned();
+        let group_transform = self.composed_elements[composed_id].group_transform().cloned();
+        let visible = self.composed_elements[composed_id].visible();
         let mut new_elements = self.composed_elements[composed_id].composed_shape().generate_elements(material);
         let new_nb_elem = new_elements.len();
         let mut composed_ids: Vec<usize> = vec![];
         let ids = self.composed_elements[composed_id].elements_index().clone();
 
+        // With as many elements as before, they are replaced in place and keep their ids, so only the BVH of the composed
+        // element has to be refitted
+        let elements_index = self.composed_element_indices(composed_id);
+        if elements_index.len() == new_nb_elem {
+            for (index, mut element) in elements_index.into_iter().zip(new_elements) {
+                element.set_composed_id(composed_id);
+                element.set_id(self.elements[index].id());
+                element.set_group_id(group_id);
+                element.set_transform(transform.clone());
+                element.set_group_transform(group_transform.clone());
+                element.set_visible(visible);
+                self.elements[index] = element;
+                self.changed_elements.push(index);
+            }
+            return;
+        }
+
         // Remove old elements
         for id in ids {
             for element in &self.elements {
@@ -114,12 +262,58 @@ impl Scene {
             let mut element = new_elements.remove(0);
             element.set_composed_id(composed_id);
             element.set_id(id);
+            element.set_group_id(group_id);
+            element.set_transform(transform.clone());
+            element.set_group_transform(group_transform.clone());
+            element.set_visible(visible);
             composed_ids.push(id);
             self.elements.push(element);
         }
 
         // Update composed element with new ids
         self.composed_elements[composed_id].set_elements_index(composed_ids);
+        self.update_bvh();
+    }
+
+    /**
+     * Hides or shows an element, with the other elements of its composed element if it has one.
+     */
+    pub fn set_element_visible(&mut self, id: usize, visible: bool) {
+        match self.element_by_id(id).and_then(|element| element.composed_id()) {
+            Some(composed_id) => {
+                self.composed_elements[composed_id].set_visible(visible);
+                for element in &mut self.elements {
+                    if element.composed_id() == Some(composed_id) {
+                        element.set_visible(visible);
+                    }
+                }
+            }
+            None => {
+                if let Some(element) = self.element_mut_by_id(id) {
+                    element.set_visible(visible);
+                }
+            }
+        }
+    }
+
+    pub fn set_light_visible(&mut self, id: usize, visible: bool) {
+        if let Some(light) = self.lights.iter_mut().find(|light| light.id() == id) {
+            light.set_visible(visible);
+        }
+    }
+
+    /**
+     * Moves a composed element with its elements: they keep their place in its BVH, only its box changes in the one of
+     * the scene.
+     */
+    pub fn set_composed_element_transform(&mut self, composed_id: usize, transform: Option<Transform>) {
+        self.composed_elements[composed_id].set_transform(transform.clone());
+        for element in &mut self.elements {
+            if element.composed_id() == Some(composed_id) {
+                element.set_transform(transform.clone());
+            }
+        }
+        self.changed_composed_elements.push(composed_id);
     }
 
     pub fn defrag_next_element_id(&mut self) {
@@ -161,20 +355,168 @@ impl Scene {
         }
     }
 
-    pub fn remove_element(&mut self, index_to_remove: usize) {
-        for i in (index_to_remove + 1)..self.elements.len() {
-            let element = &mut self.elements[i];
-            element.set_id(i - 1);
+    // Removers
+    /**
+     * Removes an element, or the whole composed element it is part of. The ids of the elements stay their indices, so
+     * the following ones are shifted down.
+     */
+    pub fn remove_element(&mut self, id: usize) {
+        if let Some(composed_id) = self.elements.get(id).and_then(|element| element.composed_id()) {
+            return self.remove_composed_element(composed_id);
+        }
+        if id < self.elements.len() {
+            self.remove_elements(&[id]);
+            self.update_bvh();
+        }
+    }
+
+    /**
+     * Removes a composed element with its elements. Like the elements, the following composed elements are shifted
+     * down to keep their ids equal to their indices.
+     */
+    pub fn remove_composed_element(&mut self, composed_id: usize) {
+        if composed_id >= self.composed_elements.len() {
+            return;
+        }
+        let elements_index = self.composed_elements.remove(composed_id).elements_index().clone();
+        for composed_element in self.composed_elements.iter_mut().skip(composed_id) {
+            composed_element.set_id(composed_element.id() - 1);
+        }
+        for element in &mut self.elements {
+            match element.composed_id() {
+                Some(id) if id > composed_id => element.set_composed_id(id - 1),
+                _ => (),
+            }
+        }
+        self.next_composed_element_id = self.composed_elements.len();
+        self.remove_elements(&elements_index);
+        self.update_bvh();
+    }
+
+    // The remaining elements get their new index as id, in the composed elements too
+    fn remove_elements(&mut self, ids: &[usize]) {
+        self.elements.retain(|element| !ids.contains(&element.id()));
+        let new_ids: HashMap<usize, usize> = self.elements.iter()
+            .enumerate()
+            .map(|(index, element)| (element.id(), index))
+            .collect();
+        for (index, element) in self.elements.iter_mut().enumerate() {
+            element.set_id(index);
         }
         for composed_element in &mut self.composed_elements {
-            let elements_index = composed_element.elements_index_mut();
-            for index in elements_index {
-                if *index > index_to_remove {
-                    index.sub_assign(1);
-                }
+            for index in composed_element.elements_index_mut() {
+                *index = new_ids[index];
+            }
+        }
+        self.next_element_id = self.elements.len();
+    }
+
+    /**
+     * The ids of the lights aren't their indices, the others keep theirs.
+     */
+    pub fn remove_light(&mut self, id: usize) {
+        self.lights.retain(|light| light.id() != id);
+    }
+
+    // Inserters
+    /**
+     * Puts elements back at the index given by their id, the following ones are shifted up: the inverse of the
+     * removal, which gives the remaining elements their ids back.
+     */
+    pub fn insert_elements(&mut self, mut elements: Vec<Element>) {
+        elements.sort_by_key(|element| element.id());
+        let mut inserted = elements.into_iter().peekable();
+        let mut new_ids = HashMap::new();
+        for element in std::mem::take(&mut self.elements) {
+            while let Some(next) = inserted.next_if(|next| next.id() <= self.elements.len()) {
+                self.elements.push(next);
             }
+            new_ids.insert(element.id(), self.elements.len());
+            self.elements.push(element);
+        }
+        self.elements.extend(inserted);
+        for (index, element) in self.elements.iter_mut().enumerate() {
+            element.set_id(index);
+        }
+        for composed_element in &mut self.composed_elements {
+            for index in composed_element.elements_index_mut() {
+                *index = new_ids[index];
+            }
+        }
+        self.next_element_id = self.elements.len();
+    }
+
+    /**
+     * Puts back a composed element at the index of its id with its elements, the inverse of its removal.
+     */
+    pub fn insert_composed_element(&mut self, mut composed_element: ComposedElement, elements: Vec<Element>) {
+        let composed_id = composed_element.id().min(self.composed_elements.len());
+        for following in self.composed_elements.iter_mut().skip(composed_id) {
+            following.set_id(following.id() + 1);
         }
-        self.next_element_id -= 1;
+        for element in &mut self.elements {
+            match element.composed_id() {
+                Some(id) if id >= composed_id => element.set_composed_id(id + 1),
+                _ => (),
+            }
+        }
+        let elements_index = elements.iter().map(|element| element.id()).collect();
+        self.insert_elements(elements);
+        composed_element.set_id(composed_id);
+        composed_element.set_elements_index(elements_index);
+        self.composed_elements.insert(composed_id, composed_element);
+        self.next_composed_element_id = self.composed_elements.len();
+        self.update_bvh();
+    }
+
+    /**
+     * Puts back a light at its index, with its id.
+     */
+    pub fn insert_light(&mut self, index: usize, light: AnyLight) {
+        self.next_light_id = self.next_light_id.max(light.id() + 1);
+        self.lights.insert(index.min(self.lights.len()), light);
+    }
+
+    // Duplicators
+    /**
+     * Adds a copy of an element, or of the whole composed element it is part of, at the same place and in the same
+     * group. Returns the id of the new element, the first one of the copy of a composed element.
+     */
+    pub fn duplicate_element(&mut self, id: usize) -> Option<usize> {
+        let element = self.elements.get(id)?;
+        if let Some(composed_id) = element.composed_id() {
+            return self.duplicate_composed_element(composed_id);
+        }
+        let mut element = element.clone();
+        // The names stay unique, the copy has none
+        element.set_name(None);
+        let new_id = self.next_element_id;
+        self.add_element(element);
+        self.update_bvh();
+        Some(new_id)
+    }
+
+    /**
+     * The elements of the copy are generated again from its composed shape. Returns the id of the first one.
+     */
+    pub fn duplicate_composed_element(&mut self, composed_id: usize) -> Option<usize> {
+        let mut composed_element = self.composed_elements.get(composed_id)?.clone();
+        composed_element.set_name(None);
+        let new_id = self.next_element_id;
+        self.add_composed_element(composed_element);
+        self.update_bvh();
+        self.elements.get(new_id).map(|element| element.id())
+    }
+
+    /**
+     * Returns the id of the copy.
+     */
+    pub fn duplicate_light(&mut self, id: usize) -> Option<usize> {
+        let mut light = self.lights.iter().find(|light| light.id() == id)?.clone();
+        light.set_name(None);
+        let new_id = self.next_light_id;
+        self.add_light(light);
+        Some(new_id)
     }
 
     pub fn add_camera(&mut self, camera: Camera) {
@@ -248,11 +590,17 @@ impl Scene {
      * If we have a single composed objects with refraction enabled, we need to do a full bvh traversal to get every intersection.
      * This is to ensure that we have the good refraction indices, otherwise the bvh will stop at the first found intersection, and we
      * might not now if we are inside or outside an object.
+     * The same goes for the instances, whose prototypes are traversed with their own BVH.
      */
     pub fn determine_full_bvh_traversal(&mut self) {
+        let composed_materials = self.composed_elements.iter().map(|composed_element| composed_element.material());
+        let instance_materials = self.elements.iter()
+            .filter(|element| element.shape().as_instance().is_some())
+            .map(|element| element.material());
+
         let mut has_transparent_composed_objects = false;
-        for composed_element in &self.composed_elements {
-            match composed_element.material().transparency() {
+        for material in composed_materials.chain(instance_materials) {
+            match material.transparency() {
                 Texture::Texture(_, _) => has_transparent_composed_objects = true,
                 Texture::Value(vector_value, _) => has_transparent_composed_objects = vector_value.to_value() > f64::EPSILON
             }
@@ -263,6 +611,36 @@ impl Scene {
         self.settings.bvh_full_traversal = has_transparent_composed_objects;
     }
     
+    /**
+     * Hash of everything the image of the scene depends on, to tell whether a checkpoint was rendered from it. The
+     * iterations and the view mode only tell how long and how to render it.
+     */
+    pub fn content_hash(&self) -> u64 {
+        let mut hasher = Fnv1a::new();
+        for element in &self.elements {
+            write!(hasher, "{:?}{:?}{:?}", element.shape(), element.material(), element.world_transform()).unwrap();
+        }
+        let settings = &self.settings;
+        write!(hasher, "{:?}{:?}{:?}{:?}", self.lights, self.camera, self.ambient_light, self.skybox).unwrap();
+        write!(hasher, "{:?}", (
+            settings.reflections, settings.indirect, &settings.skybox_texture, settings.depth, settings.anti_alisaing,
+            settings.displacement, settings.plane_displaced_distance, settings.plane_displacement_step,
+            settings.sphere_displaced_distance, settings.sphere_displacement_step
+        )).unwrap();
+        hasher.0
+    }
+
+    /**
+     * Everything a parsed scene needs before being rendered.
+     */
+    pub fn prepare_render(&mut self) {
+        if DISPLAY_WIREFRAME {
+            self.add_wireframes();
+        }
+        self.update_bvh();
+        self.determine_full_bvh_traversal();
+    }
+
     pub fn add_wireframes(&mut self) {
         let aabbs = self.all_aabb();
         let mut new_elements = vec![];
@@ -290,22 +668,187 @@ impl Scene {
         }
     }
 
+    /**
+     * Builds the BVH of every composed element, then the one of the scene on the elements that aren't part of a
+     * composed element and on the boxes of the composed elements.
+     */
     pub fn update_bvh(&mut self) {
-        let aabbs = self.all_aabb();
-        let biggest_aabb = Aabb::from_aabbs(&aabbs);
-        let mut node = bvh::node::Node::new(&biggest_aabb);
-        node.build_tree(self);
+        let start = Instant::now();
+        for element in &mut self.elements {
+            element.update_aabb();
+        }
+        for composed_id in 0..self.composed_elements.len() {
+            let elements_index = self.composed_element_indices(composed_id);
+            self.composed_elements[composed_id].build_bvh(&self.elements, &elements_index);
+        }
 
         self.non_bvh_elements_index.clear();
-        let mut nb_elements = 0;
-        for element in &self.elements {
-            if element.shape().aabb().is_none() {
-                self.non_bvh_elements_index.push(nb_elements);
+        for (index, element) in self.elements.iter().enumerate() {
+            let aabb = match element.composed_id() {
+                Some(_) => element.local_aabb(),
+                None => element.aabb(),
+            };
+            if aabb.is_none() {
+                self.non_bvh_elements_index.push(index);
             }
-            nb_elements += 1;
         }
 
-        self.bvh = Some(node);
+        let nb_elements = self.elements.len();
+        let elements = self.elements.iter()
+            .enumerate()
+            .filter(|(_, element)| element.composed_id().is_none())
+            .filter_map(|(index, element)| element.aabb().map(|aabb| (index, aabb)));
+        let composed_elements = self.composed_elements.iter()
+            .enumerate()
+            .filter_map(|(composed_id, composed_element)| composed_element.aabb().map(|aabb| (nb_elements + composed_id, aabb)));
+        let mut node = bvh::node::Node::new(&Aabb::new(0., 0., 0., 0., 0., 0.));
+        let mut stats = node.build_tree_from(elements.chain(composed_elements));
+        stats.build_time = start.elapsed();
+        println!("{} (and {} composed elements with their own BVH)", stats, self.composed_elements.len());
+
+        self.bvh = Some(bvh::flat::FlatBvh::new(&node));
+        self.changed_elements.clear();
+        self.changed_composed_elements.clear();
+    }
+
+    /**
+     * Refits the BVH around the elements and composed elements changed since it was built. The elements of a composed
+     * element are refitted in its own BVH, and only its box changes in the one of the scene. The BVH are built again
+     * when an element entered or left them, or when the refitted boxes overlap too much.
+     */
+    pub fn refit_bvh(&mut self) {
+        if self.changed_elements.is_empty() && self.changed_composed_elements.is_empty() {
+            return;
+        }
+        let mut changed = std::mem::take(&mut self.changed_elements);
+        changed.retain(|index| *index < self.elements.len());
+        changed.sort_unstable();
+        changed.dedup();
+        let mut changed_composed = std::mem::take(&mut self.changed_composed_elements);
+
+        let mut changed_primitives = vec![];
+        let mut changed_by_composed: Vec<Vec<usize>> = vec![vec![]; self.composed_elements.len()];
+        for index in changed {
+            self.elements[index].update_aabb();
+            match self.elements[index].composed_id() {
+                Some(composed_id) => changed_by_composed[composed_id].push(index),
+                None => changed_primitives.push(index),
+            }
+        }
+
+        for (composed_id, changed) in changed_by_composed.iter().enumerate().filter(|(_, changed)| !changed.is_empty()) {
+            let elements = &self.elements;
+            let bvh = match self.composed_elements[composed_id].bvh_mut() {
+                Some(bvh) => bvh,
+                None => return self.update_bvh(),
+            };
+            if !bvh.refit_with(changed, |index| elements[index].local_aabb().cloned()) {
+                return self.update_bvh();
+            }
+            if bvh.cost() > bvh.build_cost() * BVH_REBUILD_THRESHOLD {
+                let elements_index = self.composed_element_indices(composed_id);
+                self.composed_elements[composed_id].build_bvh(&self.elements, &elements_index);
+            }
+            changed_composed.push(composed_id);
+        }
+
+        changed_composed.sort_unstable();
+        changed_composed.dedup();
+        for composed_id in changed_composed {
+            self.composed_elements[composed_id].update_aabb();
+            changed_primitives.push(self.elements.len() + composed_id);
+        }
+
+        let mut bvh = self.bvh.take();
+        let refitted = match &mut bvh {
+            Some(bvh) => bvh.refit_with(&changed_primitives, |index| self.bvh_primitive_aabb(index))
+                && bvh.cost() <= bvh.build_cost() * BVH_REBUILD_THRESHOLD,
+            None => false,
+        };
+        self.bvh = bvh;
+        if !refitted {
+            self.update_bvh();
+        }
+    }
+
+    /**
+     * Light let through by the elements between the origin of the ray and max_dist, for the shadow rays. The elements
+     * are visited in no particular order: the query stops at the first opaque hit, and every partially opaque one
+     * filters the light by its color.
+     */
+    pub fn transmittance(&self, ray: &Ray, max_dist: f64) -> Vec3 {
+        count(Counter::ShadowRay);
+        let mut transmittance = Vec3::from_value(1.);
+        let mut filter = |index: usize| self.filter_light(&self.elements[index], ray, max_dist, &mut transmittance);
+
+        if !USING_BVH {
+            (0..self.elements.len()).any(&mut filter);
+            return transmittance;
+        }
+        if self.non_bvh_elements_index.iter().any(|index| filter(*index)) {
+            return transmittance;
+        }
+        if let Some(bvh) = &self.bvh {
+            bvh.any_hit_with(ray, 1., max_dist, |indices| {
+                indices.iter().any(|index| match self.bvh_primitive(*index) {
+                    BvhPrimitive::Element(index) => filter(index),
+                    BvhPrimitive::ComposedElement(composed_element) => composed_element.any_hit_with(ray, max_dist, &mut filter),
+                })
+            });
+        }
+        transmittance
+    }
+
+    /**
+     * Filters the light by the hits of the ray with the element before max_dist, returns true once nothing goes through.
+     * The instances only filter it at their first hit.
+     */
+    fn filter_light(&self, element: &Element, ray: &Ray, max_dist: f64, transmittance: &mut Vec3) -> bool {
+        let hits: Vec<Hit> = match element.shape().as_instance() {
+            Some(instance) => instance.get_closest_hit(self, ray, element).into_iter().collect(),
+            None => intersect_element(self, ray, element).map_or(vec![], |t_list| t_list.iter()
+                .filter(|t| **t > 0. && **t < max_dist)
+                .map(|t| Hit::new(element, *t, ray.get_pos() + ray.get_dir() * (t - f64::EPSILON), ray.get_dir(), self.textures(), t_list.clone()))
+                .collect()),
+        };
+
+        for mut hit in hits.into_iter().filter(|hit| *hit.dist() > 0. && *hit.dist() < max_dist) {
+            if hit.opacity() > 1. - f64::EPSILON {
+                *transmittance = Vec3::from_value(0.);
+                return true;
+            }
+            // The fully transparent hits are holes in the element, they don't filter the light
+            if hit.opacity() > f64::EPSILON {
+                hit.map_color(self.textures());
+                *transmittance = *transmittance * (1. - hit.opacity()) * hit.color().to_vec3();
+            }
+        }
+        transmittance.length() <= f64::EPSILON
+    }
+
+    /**
+     * Index in the elements of the scene of the elements of a composed element.
+     */
+    fn composed_element_indices(&self, composed_id: usize) -> Vec<usize> {
+        self.elements.iter()
+            .enumerate()
+            .filter(|(_, element)| element.composed_id() == Some(composed_id))
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    pub fn bvh_primitive(&self, index: usize) -> BvhPrimitive<'_> {
+        match index.checked_sub(self.elements.len()) {
+            Some(composed_id) => BvhPrimitive::ComposedElement(&self.composed_elements[composed_id]),
+            None => BvhPrimitive::Element(index),
+        }
+    }
+
+    fn bvh_primitive_aabb(&self, index: usize) -> Option<Aabb> {
+        match self.bvh_primitive(index) {
+            BvhPrimitive::Element(index) => self.elements[index].aabb().cloned(),
+            BvhPrimitive::ComposedElement(composed_element) => composed_element.aabb().cloned(),
+        }
     }
 
     // Accessors
@@ -317,6 +860,14 @@ impl Scene {
         &mut self.elements
     }
 
+    pub fn groups(&self) -> &Vec<Group> {
+        &self.groups
+    }
+
+    pub fn group_by_id(&self, id: usize) -> Option<&Group> {
+        self.groups.get(id)
+    }
+
     pub fn composed_elements(&self) -> &Vec<ComposedElement> {
         &self.composed_elements
     }
@@ -325,6 +876,49 @@ impl Scene {
         &mut self.composed_elements
     }
 
+    pub fn prototypes(&self) -> &HashMap<String, Arc<Prototype>> {
+        &self.prototypes
+    }
+
+    pub fn prototype(&self, name: &str) -> Option<&Arc<Prototype>> {
+        self.prototypes.get(name)
+    }
+
+    pub fn materials(&self) -> &HashMap<String, Box<dyn Material + Send + Sync>> {
+        &self.materials
+    }
+
+    pub fn material(&self, name: &str) -> Option<&(dyn Material + Send + Sync)> {
+        self.materials.get(name).map(|material| material.as_ref())
+    }
+
+    /**
+     * Lookups by the names given in the scene file, which stay the same when the ids change.
+     */
+    pub fn element_by_name(&self, name: &str) -> Option<&Element> {
+        self.elements.iter().find(|element| element.name().is_some_and(|element_name| element_name == name))
+    }
+    pub fn composed_element_by_name(&self, name: &str) -> Option<&ComposedElement> {
+        self.composed_elements.iter().find(|composed_element| composed_element.name().is_some_and(|composed_name| composed_name == name))
+    }
+    pub fn light_by_name(&self, name: &str) -> Option<&AnyLight> {
+        self.lights.iter().find(|light| light.name().is_some_and(|light_name| light_name == name))
+    }
+
+    /**
+     * Element or composed element with this name, as edited by the UI and the history.
+     */
+    pub fn subject_by_name(&self, name: &str) -> Option<EditSubject> {
+        if let Some(element) = self.element_by_name(name) {
+            return Some(EditSubject::Element(element.id()));
+        }
+        self.composed_element_by_name(name).map(|composed_element| EditSubject::ComposedElement(composed_element.id()))
+    }
+
+    pub fn is_name_used(&self, name: &str) -> bool {
+        self.element_by_name(name).is_some() || self.composed_element_by_name(name).is_some() || self.light_by_name(name).is_some()
+    }
+
     pub fn element_by_id(&self, id: usize) -> Option<&Element> {
         for element in &self.elements {
             if element.id() == id {
@@ -333,15 +927,23 @@ impl Scene {
         }
         None
     }
+    /**
+     * The element is marked as changed, so that the BVH is refitted around it.
+     */
     pub fn element_mut_by_id(&mut self, id: usize) -> Option<&mut Element> {
-        for element in &mut self.elements {
+        for (index, element) in self.elements.iter_mut().enumerate() {
             if element.id() == id {
+                self.changed_elements.push(index);
                 return Some(element);
             }
         }
         None
     }
 
+    pub fn light_by_id(&self, id: usize) -> Option<&AnyLight> {
+        self.lights.iter().find(|light| light.id() == id)
+    }
+
     pub fn light_mut_by_id(&mut self, id: usize) -> Option<&mut AnyLight> {
         for light in &mut self.lights {
             if light.id() == id {
@@ -401,6 +1003,10 @@ impl Scene {
         &self.lights
     }
 
+    pub fn lights_as_mut(&mut self) -> &mut Vec<AnyLight> {
+        &mut self.lights
+    }
+
     pub fn ambient_light(&self) -> &AmbientLight {
         &self.ambient_light
     }
@@ -432,6 +1038,62 @@ impl Scene {
     pub fn set_paused(&mut self, paused: bool) {
         self.paused = paused;
     }
+
+    pub fn path(&self) -> Option<&String> {
+        self.path.as_ref()
+    }
+
+    pub fn set_path(&mut self, path: Option<String>) {
+        self.path = path;
+    }
+
+    /**
+     * The files included by the scene file, directly or through other included files.
+     */
+    pub fn includes(&self) -> &Vec<String> {
+        &self.includes
+    }
+
+    pub fn set_includes(&mut self, includes: Vec<String>) {
+        self.includes = includes;
+    }
+
+    /**
+     * The JSON the scene was parsed from, which the remote workers parse to render it.
+     */
+    pub fn source(&self) -> Option<&String> {
+        self.source.as_ref()
+    }
+
+    pub fn set_source(&mut self, source: Option<String>) {
+        self.source = source;
+    }
+
+    pub fn stats(&self) -> &RenderStats {
+        &self.stats
+    }
+
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+    pub fn history_mut(&mut self) -> &mut History {
+        &mut self.history
+    }
+
+    pub fn texture_memory(&self) -> usize {
+        self.textures.values().map(|texture| texture.as_raw().len()).sum()
+    }
+
+    /**
+     * Memory used by the BVH of the scene and by the ones of its composed elements and prototypes.
+     */
+    pub fn bvh_memory(&self) -> usize {
+        self.bvh.iter()
+            .chain(self.composed_elements.iter().filter_map(|composed_element| composed_element.bvh()))
+            .chain(self.prototypes.values().filter_map(|prototype| prototype.bvh()))
+            .map(|bvh| bvh.memory())
+            .sum()
+    }
     
     pub fn get_element(&self, index: usize) -> &Element {
         &self.elements[index]
@@ -444,11 +1106,11 @@ impl Scene {
     pub fn all_aabb(&self) -> Vec<&crate::model::shapes::aabb::Aabb> {
         self.elements
             .iter()
-            .filter_map(|element| element.shape().as_aabb().or_else(|| element.shape().aabb()) )
+            .filter_map(|element| element.aabb())
             .collect()
     }
 
-    pub fn bvh(&self) -> &Option<bvh::node::Node> {
+    pub fn bvh(&self) -> &Option<bvh::flat::FlatBvh> {
         &self.bvh
     }
 
@@ -486,7 +1148,168 @@ impl Scene {
         self.ambient_light = ambient_light;
     }
 
-    pub fn set_bvh(&mut self, bvh: Option<bvh::node::Node>) {
+    pub fn set_bvh(&mut self, bvh: Option<bvh::flat::FlatBvh>) {
         self.bvh = bvh;
     }
 }
+
+/**
+ * FNV-1a, whose hashes don't change between builds unlike the ones of the standard library.
+ */
+pub(crate) struct Fnv1a(pub(crate) u64);
+
+impl Fnv1a {
+    pub(crate) fn new() -> Self {
+        Fnv1a(0xcbf29ce484222325)
+    }
+}
+
+impl Write for Fnv1a {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x100000001b3);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        model::{history::EditSubject, materials::texture::{Texture, TextureType}, maths::{ray::Ray, vec3::Vec3}},
+        parsing::{add_object, get_scene_from_str, COMPOSED_SHAPE_TYPES, LIGHT_TYPES, SHAPE_TYPES},
+        render::raycasting::get_closest_hit
+    };
+    use super::Scene;
+
+    // The ids of the elements and composed elements are their indices, and the composed elements know their elements
+    fn assert_consistent(scene: &Scene) {
+        for (index, element) in scene.elements().iter().enumerate() {
+            assert_eq!(element.id(), index);
+            if let Some(composed_id) = element.composed_id() {
+                assert!(scene.composed_elements()[composed_id].elements_index().contains(&index));
+            }
+        }
+        for (composed_id, composed_element) in scene.composed_elements().iter().enumerate() {
+            assert_eq!(composed_element.id(), composed_id);
+            for index in composed_element.elements_index() {
+                assert_eq!(scene.elements()[*index].composed_id(), Some(composed_id));
+            }
+        }
+    }
+
+    #[test]
+    fn test_add_remove_duplicate() {
+        let mut scene = Scene::new();
+        for object_type in SHAPE_TYPES.iter().chain(&COMPOSED_SHAPE_TYPES).chain(&LIGHT_TYPES) {
+            match *object_type {
+                "instance" => assert!(add_object(&mut scene, object_type).is_err()),
+                object_type => add_object(&mut scene, object_type).unwrap(),
+            }
+        }
+        assert_eq!(scene.composed_elements().len(), COMPOSED_SHAPE_TYPES.len());
+        assert_eq!(scene.lights().len(), LIGHT_TYPES.len());
+        assert_consistent(&scene);
+
+        let nb_elements = scene.elements().len();
+        let helix_id = scene.composed_elements()[1].elements_index()[0];
+        let nb_helix_elements = scene.composed_elements()[1].elements_index().len();
+        let copy_id = scene.duplicate_element(helix_id).unwrap();
+        assert_eq!(scene.elements()[copy_id].composed_id(), Some(COMPOSED_SHAPE_TYPES.len()));
+        assert_eq!(scene.elements().len(), nb_elements + nb_helix_elements);
+        assert_consistent(&scene);
+
+        scene.remove_element(helix_id);
+        assert_eq!(scene.composed_elements().len(), COMPOSED_SHAPE_TYPES.len());
+        assert_eq!(scene.elements().len(), nb_elements);
+        scene.remove_element(0);
+        assert_eq!(scene.elements().len(), nb_elements - 1);
+        assert_consistent(&scene);
+
+        let light_id = scene.duplicate_light(0).unwrap();
+        scene.remove_light(0);
+        assert_eq!(scene.lights().len(), LIGHT_TYPES.len());
+        assert!(scene.lights().iter().any(|light| light.id() == light_id));
+    }
+
+    #[test]
+    fn test_names_and_materials() {
+        let mut scene = get_scene_from_str(r#"[
+            {"type": "materials", "materials": {"gold": {"color": [255, 200, 50], "metalness": 1}}},
+            {"type": "sphere", "name": "ball", "pos": [0, 0, 0], "dir": [0, 1, 0], "radius": 1, "material": "gold"},
+            {"type": "group", "material": "gold", "objects": [
+                {"type": "helix", "name": "spring", "pos": [0, 0, 0], "dir": [0, 1, 0], "height": 2}
+            ]},
+            {"type": "light", "name": "sun", "pos": [0, 5, 0], "intensity": 1, "color": [255, 255, 255]}
+        ]"#).unwrap();
+        let gold = format!("{:?}", scene.material("gold").unwrap());
+        assert_eq!(format!("{:?}", scene.element_by_name("ball").unwrap().material()), gold);
+        let spring = scene.composed_element_by_name("spring").unwrap();
+        assert!(spring.elements_index().iter().all(|id| format!("{:?}", scene.elements()[*id].material()) == gold));
+        assert_eq!(scene.subject_by_name("spring"), Some(EditSubject::ComposedElement(0)));
+        assert_eq!(EditSubject::Element(spring.elements_index()[0]).label(&scene), "spring");
+        assert_eq!(scene.light_by_name("sun").unwrap().label(), "sun");
+
+        // The names stay with their objects when the ids change, and are unique
+        scene.remove_element(0);
+        assert_eq!(scene.subject_by_name("spring"), Some(EditSubject::ComposedElement(0)));
+        let copy_id = scene.duplicate_element(0).unwrap();
+        assert_eq!(scene.elements()[copy_id].composed_id(), Some(1));
+        assert!(scene.composed_elements()[1].name().is_none());
+        assert!(get_scene_from_str(r#"[
+            {"type": "sphere", "name": "ball", "pos": [0, 0, 0], "dir": [0, 1, 0], "radius": 1},
+            {"type": "cube", "name": "ball", "pos": [0, 0, 0], "dir": [0, 1, 0], "width": 1}
+        ]"#).is_err());
+        assert!(get_scene_from_str(r#"{"type": "sphere", "pos": [0, 0, 0], "dir": [0, 1, 0], "radius": 1, "material": "wood"}"#).is_err());
+        // The lights don't take the material of their group
+        assert!(get_scene_from_str(r#"[
+            {"type": "materials", "materials": {"gold": {"color": [255, 200, 50]}}},
+            {"type": "group", "material": "gold", "objects": [{"type": "light", "pos": [0, 5, 0], "intensity": 1}]}
+        ]"#).is_err_and(|err| err.contains("color")));
+    }
+
+    #[test]
+    fn test_instance_in_group() {
+        let mut scene = get_scene_from_str(r#"[
+            {"type": "prototype", "name": "ball", "object": {"type": "sphere", "pos": [0, 0, 0], "dir": [0, 1, 0], "radius": 1, "color": [255, 255, 255]}},
+            {"type": "group", "transform": {"translate": [5, 0, 0]}, "objects": [
+                {"type": "instance", "prototype": "ball", "pos": [0, 2, 0]}
+            ]}
+        ]"#).unwrap();
+        scene.prepare_render();
+
+        // The instance is moved by its group, the rays and the shadows find it there
+        let ray = Ray::new(Vec3::new(5., 2., -10.), Vec3::new(0., 0., 1.), 0);
+        let hit = get_closest_hit(&scene, &ray).unwrap();
+        assert!((hit.dist() - 9.).abs() < 1e-6);
+        assert!((*hit.norm() - Vec3::new(0., 0., -1.)).length() < 1e-6);
+        assert_eq!(scene.transmittance(&ray, 20.), Vec3::from_value(0.));
+
+        // Hidden in the outliner, it's neither rendered nor casts shadows
+        scene.elements_as_mut()[0].set_visible(false);
+        assert!(get_closest_hit(&scene, &ray).is_none());
+        assert_eq!(scene.transmittance(&ray, 20.), Vec3::from_value(1.));
+
+        let ray = Ray::new(Vec3::new(0., 2., -10.), Vec3::new(0., 0., 1.), 0);
+        assert!(get_closest_hit(&scene, &ray).is_none());
+        assert_eq!(scene.transmittance(&ray, 20.), Vec3::from_value(1.));
+    }
+
+    #[test]
+    fn test_hidden_displaced_element() {
+        let mut scene = get_scene_from_str(r#"{"type": "sphere", "pos": [0, 0, 0], "dir": [0, 1, 0], "radius": 1, "color": [255, 255, 255]}"#).unwrap();
+        scene.load_texture("bump", Some(image::RgbaImage::new(4, 4)));
+        scene.elements_as_mut()[0].material_mut().set_displacement(Texture::Texture("bump".to_string(), TextureType::Float));
+        scene.settings_mut().displacement = true;
+        scene.prepare_render();
+
+        let ray = Ray::new(Vec3::new(0., 0., -10.), Vec3::new(0., 0., 1.), 0);
+        assert!(get_closest_hit(&scene, &ray).is_some());
+        assert_eq!(scene.transmittance(&ray, 20.), Vec3::from_value(0.));
+
+        // The displaced intersections skip the hidden elements too
+        scene.elements_as_mut()[0].set_visible(false);
+        assert!(get_closest_hit(&scene, &ray).is_none());
+        assert_eq!(scene.transmittance(&ray, 20.), Vec3::from_value(1.));
+    }
+}
@@ -186,33 +186,7 @@ impl Aabb {
         2.0 * ((x * y) + (y * z) + (x * z))
     }
 
-    pub fn split_aabb(&mut self, axis: usize, t: f64) -> (Aabb, Aabb) {
-        // Split AABB into two parts along the axis at distance t
-        let mut aabb1 = self.clone();
-        let mut aabb2 = self.clone();
-
-        if axis == 0 {
-            let new_x = self.x_min + (self.x_max - self.x_min) * t;
-            aabb1.set_x_max(new_x);
-            aabb2.set_x_min(new_x);
-        } else if axis == 1 {
-            let new_y = self.y_min + (self.y_max - self.y_min) * t;
-            aabb1.set_y_max(new_y);
-            aabb2.set_y_min(new_y);
-        } else if axis == 2 {
-            let new_z = self.z_min + (self.z_max - self.z_min) * t;
-            aabb1.set_z_max(new_z);
-            aabb2.set_z_min(new_z);
-        }
-
-        aabb1.update_pos();
-        aabb2.update_pos();
-
-        (aabb1, aabb2)
-    }
-
-    fn grow_to_include(&mut self, aabb: &Aabb) {
-        
+    pub fn grow_to_include(&mut self, aabb: &Aabb) {
         if aabb.x_min < self.x_min {
             self.x_min = aabb.x_min;
         }
@@ -231,51 +205,7 @@ impl Aabb {
         if aabb.z_max > self.z_max {
             self.z_max = aabb.z_max;
         }
-    }
-
-    pub fn get_children_and_shrink(&mut self, scene: &Scene, parent_vec: &Vec<usize>) -> Vec<usize> {
-        let mut children = vec![];
-
-        let mut new_aabb = Aabb::new(f64::MAX, f64::MIN, f64::MAX, f64::MIN, f64::MAX, f64::MIN);
-
-        for i in parent_vec {
-            let element = scene.elements().get(*i).unwrap();
-            let mut aabb_shape = None;
-
-            if let Some(aabb) = element.shape().as_aabb() {
-                aabb_shape = Some(aabb);
-            } else if let Some(aabb) = element.shape().aabb() {
-                aabb_shape = Some(aabb);
-            }
-
-            if let Some(aabb_shape) = aabb_shape {
-                if aabb_shape.is_child_of(self) {
-                    children.push(*i);
-                    new_aabb.grow_to_include(aabb_shape);
-                }
-            }
-        }
-        if children.len() > 0 {
-            if new_aabb.x_min > self.x_min {
-                self.x_min = new_aabb.x_min;
-            }
-            if new_aabb.x_max < self.x_max {
-                self.x_max = new_aabb.x_max;
-            }
-            if new_aabb.y_min > self.y_min {
-                self.y_min = new_aabb.y_min;
-            }
-            if new_aabb.y_max < self.y_max {
-                self.y_max = new_aabb.y_max;
-            }
-            if new_aabb.z_min > self.z_min {
-                self.z_min = new_aabb.z_min;
-            }
-            if new_aabb.z_max < self.z_max {
-                self.z_max = new_aabb.z_max;
-            }
-        }
-        children
+        self.update_pos();
     }
 
     pub fn is_child_of(&self, aabb: &Aabb) -> bool {
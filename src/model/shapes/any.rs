@@ -1,48 +1,175 @@
+use std::f64::consts::{E, PI};
 use std::sync::{Arc, RwLock};
 
-use eqsolver::single_variable::FDNewton;
-use meval::{self, eval_str_with_context};
-use crate::{model::{
-    element::Element, materials::material::Projection, maths::{hit::Hit, ray::Ray, vec3::Vec3}, scene::Scene
-}, ui::{ui::UI, uielement::UIElement}};
+use meval::{ContextProvider, Expr, FuncEvalError};
+use crate::{
+    model::{
+        element::Element,
+        materials::material::Projection,
+        maths::{hit::Hit, ray::Ray, vec3::Vec3},
+        scene::Scene
+    },
+    ui::{
+        ui::UI,
+        uielement::{Category, UIElement},
+        utils::misc::{ElemType, Property, Value}
+    },
+    ANY_EPSILON, ANY_MAX_DISTANCE, ANY_MAX_ITERATIONS, ANY_SAMPLES
+};
 
-use super::shape::Shape;
+use super::{aabb::Aabb, shape::Shape};
 
-#[derive(Debug)]
+/**
+ * Implicit surface f(x, y, z) = 0, the inside being where f is negative.
+ * The equation is parsed once and kept as a compiled expression, the string is only kept for the UI.
+ */
+#[derive(Debug, Clone)]
 pub struct Any {
     equation: String,
+    expr: Expr,
     pos: Vec3,
+    bounds: Option<(Vec3, Vec3)>,
+    aabb: Option<Aabb>,
+}
+
+/**
+ * Evaluation context of the compiled expression: the variables of the point and the builtin functions of meval,
+ * without the allocations of meval::Context.
+ * As in the equations written so far, "z" is the vertical axis, so it is bound to the y of the scene.
+ */
+struct AnyContext {
+    x: f64,
+    y: f64,
+    z: f64,
+}
+
+impl ContextProvider for AnyContext {
+    fn get_var(&self, name: &str) -> Option<f64> {
+        match name {
+            "x" => Some(self.x),
+            "y" => Some(self.z),
+            "z" => Some(self.y),
+            "pi" => Some(PI),
+            "e" => Some(E),
+            _ => None,
+        }
+    }
+
+    fn eval_func(&self, name: &str, args: &[f64]) -> Result<f64, FuncEvalError> {
+        match name {
+            "max" | "min" => {
+                if args.is_empty() {
+                    return Err(FuncEvalError::TooFewArguments);
+                }
+                let init = if name == "max" { f64::NEG_INFINITY } else { f64::INFINITY };
+                Ok(args.iter().fold(init, |acc, &arg| if name == "max" { acc.max(arg) } else { acc.min(arg) }))
+            }
+            "atan2" => match args {
+                [y, x] => Ok(y.atan2(*x)),
+                _ => Err(FuncEvalError::NumberArgs(2)),
+            },
+            _ => {
+                let func: fn(f64) -> f64 = match name {
+                    "sqrt" => f64::sqrt,
+                    "exp" => f64::exp,
+                    "ln" => f64::ln,
+                    "abs" => f64::abs,
+                    "sin" => f64::sin,
+                    "cos" => f64::cos,
+                    "tan" => f64::tan,
+                    "asin" => f64::asin,
+                    "acos" => f64::acos,
+                    "atan" => f64::atan,
+                    "sinh" => f64::sinh,
+                    "cosh" => f64::cosh,
+                    "tanh" => f64::tanh,
+                    "asinh" => f64::asinh,
+                    "acosh" => f64::acosh,
+                    "atanh" => f64::atanh,
+                    "floor" => f64::floor,
+                    "ceil" => f64::ceil,
+                    "round" => f64::round,
+                    "signum" => f64::signum,
+                    _ => return Err(FuncEvalError::UnknownFunction),
+                };
+                match args {
+                    [arg] => Ok(func(*arg)),
+                    _ => Err(FuncEvalError::NumberArgs(1)),
+                }
+            }
+        }
+    }
 }
 
 impl Shape for Any {
-    fn distance(&self, _vec: &Vec3) -> f64 {
-        unimplemented!()
+    fn distance(&self, vec: &Vec3) -> f64 {
+        // First order estimation of the distance to the surface
+        let gradient = self.gradient(vec).length();
+        if gradient > 0. {
+            self.evaluate(vec) / gradient
+        } else {
+            self.evaluate(vec)
+        }
     }
 
     fn intersect(&self, r: &Ray) -> Option<Vec<f64>> {
-        // Ray/Any intersection
-        let f = |t: f64| -> f64 {
-            let p = r.get_pos() + r.get_dir() * t;
-            let mut ctx = meval::Context::new();
-            ctx.var("x", *p.x())
-                .var("z", *p.y())
-                .var("y", *p.z());
-
-            eval_str_with_context(&self.equation, ctx).unwrap_or(-1.0)
+        // Ray/Any intersection: the ray is sampled within the bounds, every sign change brackets a root which is then
+        // refined. Two roots closer than a sample step don't change the sign, so the local minimums of |f| are
+        // searched for a point of the opposite sign that splits them into two brackets.
+        let (t_min, t_max) = match &self.aabb {
+            Some(aabb) => match aabb.intersect(r) {
+                Some(t_aabb) => (t_aabb[0].max(0.), t_aabb[1]),
+                None => return None,
+            },
+            None => (0., ANY_MAX_DISTANCE),
         };
+        if t_min > t_max {
+            return None;
+        }
+
+        let f = |t: f64| self.evaluate(&(r.get_pos() + r.get_dir() * t));
+        let step = (t_max - t_min) / ANY_SAMPLES as f64;
+        let samples: Vec<(f64, f64)> = (0..=ANY_SAMPLES)
+            .map(|i| {
+                let t = t_min + step * i as f64;
+                (t, f(t))
+            })
+            .collect();
 
-        let solution = FDNewton::new(f).solve(0.5);
-        let mut t_array: Vec<f64> = Vec::new();
-        
-        match solution.ok() {
-            Some(t) => t_array.push(t),
-            None => {},
+        let mut t_list = vec![];
+        if samples[0].1 < 0. {
+            // The ray starts inside the surface (or inside the bounds), the entry point is behind it
+            t_list.push(if t_min > 0. { t_min } else { -ANY_EPSILON });
         }
 
+        for i in 0..ANY_SAMPLES {
+            let (t0, f0) = samples[i];
+            let (t1, f1) = samples[i + 1];
+            if f0.is_nan() || f1.is_nan() {
+                continue;
+            }
+            if (f0 < 0.) != (f1 < 0.) {
+                t_list.push(Any::refine_root(&f, t0, f0, t1, f1));
+            } else if i + 2 <= ANY_SAMPLES {
+                let (t2, f2) = samples[i + 2];
+                // A local minimum of |f| can only hide two close roots if the field can reach zero at the sampled
+                // slope before the next samples, otherwise the surface is not touched and the search is skipped
+                let plausible = f1.abs() <= (f0.abs() - f1.abs()).max(f2.abs() - f1.abs());
+                if f1.abs() < f0.abs() && f1.abs() <= f2.abs() && (f2 < 0.) == (f1 < 0.) && plausible {
+                    if let Some((tm, fm)) = Any::split_bracket(&f, t0, t2, f1) {
+                        t_list.push(Any::refine_root(&f, t0, f0, tm, fm));
+                        t_list.push(Any::refine_root(&f, tm, fm, t2, f2));
+                    }
+                }
+            }
+        }
+        if samples[ANY_SAMPLES].1 < 0. {
+            t_list.push(t_max);
+        }
 
-        if t_array.len() > 0 {
-            t_array.sort_by(|a, b| a.partial_cmp(b).unwrap());
-            Some(t_array)
+        t_list.dedup_by(|a, b| (*a - *b).abs() < ANY_EPSILON);
+        if !t_list.is_empty() {
+            Some(t_list)
         } else {
             None
         }
@@ -60,7 +187,7 @@ impl Shape for Any {
         projection.i = hit.norm().cross(&constant_axis).normalize();
         projection.j = hit.norm().cross(&projection.i).normalize();
         projection.k = hit.norm().clone();
-        
+
         let normalized_pos = hit.pos().normalize();
         let (x, y, z) = (*normalized_pos.x(), *normalized_pos.y(), *normalized_pos.z());
 
@@ -86,27 +213,24 @@ impl Shape for Any {
     }
 
     fn norm(&self, hit_position: &Vec3) -> Vec3 {
-        let f = |x: f64, y: f64, z: f64| -> f64 {
-            let mut ctx = meval::Context::new();
-            ctx.var("x", x)
-                .var("z", y)
-                .var("y", z);
-
-            eval_str_with_context(&self.equation, ctx).unwrap_or(-1.0)
-        };
-
-        let gradient = Any::gradient(&f, *hit_position);
-        gradient.normalize()
+        self.gradient(hit_position).normalize()
     }
 
     fn as_any(&self) -> Option<&Any> {
         Some(self)
     }
+    fn as_any_mut(&mut self) -> Option<&mut Any> {
+        Some(self)
+    }
 
     fn pos(&self) -> &Vec3 {
         &self.pos
     }
 
+    fn aabb(&self) -> Option<&Aabb> {
+        self.aabb.as_ref()
+    }
+
     fn outer_intersect(&self, ray: &Ray, _displaced_factor: f64) -> Option<Vec<f64>> {
         self.intersect(ray)
     }
@@ -115,8 +239,38 @@ impl Shape for Any {
         self.intersect(ray)
     }
 
-    fn get_ui(&self, _element: &Element, _ui: &mut UI, _scene: &Arc<RwLock<Scene>>) -> UIElement {
-        todo!()
+    fn get_ui(&self, element: &Element, ui: &mut UI, _scene: &Arc<RwLock<Scene>>) -> UIElement {
+        let mut category = UIElement::new("Any", "any", ElemType::Category(Category::default()), ui.uisettings());
+
+        if let Some(any) = element.shape().as_any() {
+            let id = element.id();
+            category.add_element(UIElement::new(
+                "Equation",
+                "equation",
+                ElemType::Property(Property::new(
+                    Value::Text(any.equation.clone()),
+                    Box::new(move |_, value, context, _| {
+                        if let Some(scene) = context.get_active_scene() {
+                            let mut scene = scene.write().unwrap();
+                            let elem = scene.element_mut_by_id(id).unwrap();
+                            if let Some(any) = elem.shape_mut().as_any_mut() {
+                                if let Value::Text(value) = value {
+                                    any.set_equation(value).ok();
+                                }
+                            }
+                            scene.set_dirty(true);
+                        }
+                    }),
+                    Box::new(|value, _, _| {
+                        if let Value::Text(value) = value {
+                            Any::compile(value)?;
+                        }
+                        Ok(())
+                    }),
+                    ui.uisettings())),
+                ui.uisettings()));
+        }
+        category
     }
 }
 
@@ -125,36 +279,192 @@ impl Any {
     pub fn equation(&self) -> &String {
         &self.equation
     }
+    pub fn bounds(&self) -> &Option<(Vec3, Vec3)> {
+        &self.bounds
+    }
 
     // Mutators
-    pub fn set_equation(&mut self, equation: String) {
+    pub fn set_equation(&mut self, equation: String) -> Result<(), String> {
+        self.expr = Any::compile(&equation)?;
         self.equation = equation;
+        Ok(())
+    }
+    pub fn set_bounds(&mut self, bounds: Option<(Vec3, Vec3)>) {
+        self.bounds = bounds;
+        self.aabb = bounds.map(|(min, max)| Aabb::from_min_max(min, max));
     }
 
     // Constructor
-    pub fn new(equation: String) -> Any {
-        self::Any { equation, pos: Vec3::new(0.0, 0.0, 0.0) }
+    /**
+     * Without bounds, the rays are sampled up to ANY_MAX_DISTANCE, which makes the sampling step too coarse for the
+     * small details: the bounds should be given for any finite surface.
+     */
+    pub fn new(equation: String, bounds: Option<(Vec3, Vec3)>) -> Result<Any, String> {
+        let expr = Any::compile(&equation)?;
+        let mut any = self::Any { equation, expr, pos: Vec3::new(0.0, 0.0, 0.0), bounds: None, aabb: None };
+        any.set_bounds(bounds);
+        Ok(any)
     }
 
     // Methods
     pub fn clone(&self) -> Any {
         self::Any {
             equation: self.equation.clone(),
+            expr: self.expr.clone(),
             pos: self.pos.clone(),
+            bounds: self.bounds,
+            aabb: self.aabb.clone(),
         }
     }
 
-    fn gradient(f: &dyn Fn(f64, f64, f64) -> f64, hit: Vec3) -> Vec3 {
-        let delta = 1e-6;
-        let x = *hit.x();
-        let y = *hit.y();
-        let z = *hit.z();
+    fn compile(equation: &str) -> Result<Expr, String> {
+        let expr = equation.parse::<Expr>().map_err(|err| format!("The equation {} is invalid: {}", equation, err))?;
+        // Evaluating it once checks that it only uses known variables and functions
+        expr.eval_with_context(AnyContext { x: 0., y: 0., z: 0. })
+            .map_err(|err| format!("The equation {} is invalid: {}", equation, err))?;
+        Ok(expr)
+    }
 
+    pub fn evaluate(&self, p: &Vec3) -> f64 {
+        self.expr.eval_with_context(AnyContext { x: *p.x(), y: *p.y(), z: *p.z() }).unwrap_or(f64::NAN)
+    }
 
-        let df_dx = (f(x + delta, y, z) - f(x - delta, y, z)) / (2.0 * delta);
-        let df_dy = (f(x, y + delta, z) - f(x, y - delta, z)) / (2.0 * delta);
-        let df_dz = (f(x, y, z + delta) - f(x, y, z - delta)) / (2.0 * delta);
-    
-        Vec3::new(df_dx, df_dy, df_dz)
+    fn gradient(&self, p: &Vec3) -> Vec3 {
+        let h = ANY_EPSILON.sqrt();
+        let dx = Vec3::new(h, 0., 0.);
+        let dy = Vec3::new(0., h, 0.);
+        let dz = Vec3::new(0., 0., h);
+
+        Vec3::new(
+            self.evaluate(&(p + dx)) - self.evaluate(&(p - dx)),
+            self.evaluate(&(p + dy)) - self.evaluate(&(p - dy)),
+            self.evaluate(&(p + dz)) - self.evaluate(&(p - dz)),
+        ) / (2. * h)
     }
-}
\ No newline at end of file
+
+    /**
+     * Illinois variant of the regula falsi: converges like the secant method but keeps the root bracketed.
+     */
+    fn refine_root(f: &dyn Fn(f64) -> f64, mut t0: f64, mut f0: f64, mut t1: f64, mut f1: f64) -> f64 {
+        let mut side = 0;
+        for _ in 0..ANY_MAX_ITERATIONS {
+            if f0 == 0. {
+                return t0;
+            }
+            if f1 == 0. || (t1 - t0).abs() < ANY_EPSILON {
+                break;
+            }
+            let t = (t0 * f1 - t1 * f0) / (f1 - f0);
+            let ft = f(t);
+            if ft.is_nan() {
+                break;
+            }
+            if (ft < 0.) == (f1 < 0.) {
+                t1 = t;
+                f1 = ft;
+                if side == -1 {
+                    f0 /= 2.;
+                }
+                side = -1;
+            } else {
+                t0 = t;
+                f0 = ft;
+                if side == 1 {
+                    f1 /= 2.;
+                }
+                side = 1;
+            }
+        }
+        if f0.abs() < f1.abs() { t0 } else { t1 }
+    }
+
+    /**
+     * Golden section search of the extremum of f between t0 and t1, f having the sign of f_sign at both ends.
+     * Returns the point found if it is of the opposite sign.
+     */
+    fn split_bracket(f: &dyn Fn(f64) -> f64, mut t0: f64, mut t1: f64, f_sign: f64) -> Option<(f64, f64)> {
+        let ratio = (5f64.sqrt() - 1.) / 2.;
+        let g = |t: f64| f(t) * f_sign.signum();
+        let mut a = t1 - ratio * (t1 - t0);
+        let mut b = t0 + ratio * (t1 - t0);
+        let (mut ga, mut gb) = (g(a), g(b));
+        for _ in 0..ANY_MAX_ITERATIONS {
+            if ga < 0. {
+                return Some((a, f(a)));
+            }
+            if gb < 0. {
+                return Some((b, f(b)));
+            }
+            if (t1 - t0).abs() < ANY_EPSILON {
+                break;
+            }
+            if ga < gb {
+                t1 = b;
+                b = a;
+                gb = ga;
+                a = t1 - ratio * (t1 - t0);
+                ga = g(a);
+            } else {
+                t0 = a;
+                a = b;
+                ga = gb;
+                b = t0 + ratio * (t1 - t0);
+                gb = g(b);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::maths::ray::Ray;
+    use crate::model::maths::vec3::Vec3;
+    use crate::model::shapes::any::Any;
+    use crate::model::shapes::shape::Shape;
+
+    #[test]
+    fn test_intersect_all_roots() {
+        // Two nested spheres of radius 1 and 2
+        let any = Any::new("(x^2 + y^2 + z^2 - 1) * (x^2 + y^2 + z^2 - 4)".to_string(), None).unwrap();
+        let ray = Ray::new(Vec3::new(-5., 0., 0.), Vec3::new(1., 0., 0.), 5);
+        let t = any.intersect(&ray).unwrap();
+        assert_eq!(t.len(), 4);
+        for (t, expected) in t.iter().zip([3., 4., 6., 7.]) {
+            assert!((t - expected).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_intersect_thin_shell() {
+        // Both roots are within the same sample step
+        let bounds = Some((Vec3::from_value(-10.), Vec3::from_value(10.)));
+        let any = Any::new("x^2 - 0.000001".to_string(), bounds).unwrap();
+        let ray = Ray::new(Vec3::new(-5., 0., 0.), Vec3::new(1., 0., 0.), 5);
+        let t = any.intersect(&ray).unwrap();
+        assert_eq!(t.len(), 2);
+        assert!((t[0] - 4.999).abs() < 1e-6);
+        assert!((t[1] - 5.001).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_intersect_miss() {
+        let bounds = Some((Vec3::from_value(-2.), Vec3::from_value(2.)));
+        let any = Any::new("x^2 + y^2 + z^2 - 1".to_string(), bounds).unwrap();
+        let ray = Ray::new(Vec3::new(-5., 1.5, 0.), Vec3::new(1., 0., 0.), 5);
+        assert_eq!(any.intersect(&ray), None);
+    }
+
+    #[test]
+    fn test_norm() {
+        let any = Any::new("x^2 + y^2 + z^2 - 1".to_string(), None).unwrap();
+        let norm = any.norm(&Vec3::new(0., 0., -1.));
+        assert!((norm - Vec3::new(0., 0., -1.)).length() < 1e-6);
+    }
+
+    #[test]
+    fn test_invalid_equation() {
+        assert!(Any::new("x^2 + w".to_string(), None).is_err());
+        assert!(Any::new("x^2 + (".to_string(), None).is_err());
+    }
+}
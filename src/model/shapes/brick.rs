@@ -7,7 +7,7 @@ use crate::{model::{
     , maths::vec3::Vec3, scene::Scene, composed_element::ComposedElement, element::Element
 }, ui::{prefabs::vector_ui::get_vector_ui, ui::UI, uielement::{Category, UIElement}, utils::misc::{ElemType, Value}}};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Brick {
     pub pos: Vec3,
     pub dir: Vec3,
@@ -4,9 +4,38 @@ use crate::{model::{composed_element::ComposedElement, element::Element, materia
 
 use super::{brick::Brick, helix::Helix, mobius::Mobius, nagone::Nagone, obj::Obj, torusphere::Torusphere};
 
+/**
+ * Copy of a composed shape behind a box, for the duplication of composed elements.
+ */
+pub trait ComposedShapeClone {
+    fn clone_composed_shape(&self) -> Box<dyn Sync + ComposedShape>;
+}
 
-pub trait ComposedShape: Debug + Sync + Send {
+impl<T: 'static + ComposedShape + Clone> ComposedShapeClone for T {
+    fn clone_composed_shape(&self) -> Box<dyn Sync + ComposedShape> {
+        Box::new(self.clone())
+    }
+}
+
+pub trait ComposedShape: Debug + Sync + Send + ComposedShapeClone {
     fn generate_elements(&self, material: Box<dyn Material + Send +Sync>) -> Vec<Element>;
+    fn shape_name(&self) -> String {
+        if self.as_torusphere().is_some() {
+            "Torusphere".to_string()
+        } else if self.as_helix().is_some() {
+            "Helix".to_string()
+        } else if self.as_brick().is_some() {
+            "Brick".to_string()
+        } else if self.as_nagone().is_some() {
+            "Nagone".to_string()
+        } else if self.as_mobius().is_some() {
+            "Mobius".to_string()
+        } else if self.as_obj().is_some() {
+            "Obj".to_string()
+        } else {
+            "Unknown".to_string()
+        }
+    }
 
     fn as_torusphere(&self) -> Option<&Torusphere> { None }
     fn as_helix(&self) -> Option<&Helix> { None }
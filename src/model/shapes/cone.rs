@@ -11,7 +11,7 @@ use crate::{
     }
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Cone {
     pos: Vec3,
     dir: Vec3,
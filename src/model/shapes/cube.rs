@@ -8,7 +8,7 @@ use crate::{
     }, ui::{prefabs::vector_ui::get_vector_ui, ui::UI, uielement::{Category, UIElement}, utils::misc::{ElemType, Property, Value}}
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Cube {
     pos: Vec3,
     dir: Vec3,
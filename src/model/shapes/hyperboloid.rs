@@ -10,7 +10,7 @@ use crate::{
     }
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Hyperboloid {
     pos: Vec3,
     z_shift: f64,
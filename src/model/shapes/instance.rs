@@ -0,0 +1,277 @@
+use std::f64::consts::PI;
+use std::sync::{Arc, RwLock};
+use super::{aabb::Aabb, shape::Shape};
+use crate::{
+    model::{
+        element::Element,
+        materials::material::Projection,
+        maths::{hit::Hit, ray::Ray, transform::Transform, vec3::Vec3},
+        prototype::Prototype,
+        scene::Scene
+    },
+    ui::{
+        prefabs::vector_ui::get_vector_ui,
+        ui::UI,
+        uielement::{Category, UIElement},
+        utils::misc::{ElemType, FnSubmitValue, Value}
+    }
+};
+
+/**
+ * Reference to a prototype placed in the scene with its own transform. The rays are moved into the local space of
+ * the prototype, so the elements and the BVH of the prototype are shared by every instance.
+ */
+#[derive(Debug, Clone)]
+pub struct Instance {
+    prototype: Arc<Prototype>,
+    transform: Transform,
+    aabb: Option<Aabb>,
+}
+
+impl Shape for Instance {
+    fn distance(&self, vec: &Vec3) -> f64 {
+        // The distances of the local space are shortened by the smallest scale, so they never overshoot the surface
+        let local = self.transform.point_to_local(vec);
+        let distance = self.prototype.elements().iter()
+            .map(|element| match element.world_transform() {
+                Some(transform) => element.shape().distance(&transform.point_to_local(&local)) * min_scale(transform),
+                None => element.shape().distance(&local),
+            })
+            .fold(f64::INFINITY, f64::min);
+        distance * min_scale(&self.transform)
+    }
+
+    fn intersect(&self, ray: &Ray) -> Option<Vec<f64>> {
+        let (local_ray, factor) = self.transform.ray_to_local(ray);
+        self.prototype.intersect(&local_ray)
+            .map(|t_list| t_list.iter().map(|t| t * factor).collect())
+    }
+
+    fn outer_intersect(&self, ray: &Ray, _displaced_factor: f64) -> Option<Vec<f64>> {
+        self.intersect(ray)
+    }
+
+    fn intersect_displacement(&self, ray: &Ray, _element: &Element, _scene: &Scene) -> Option<Vec<f64>> {
+        self.intersect(ray)
+    }
+
+    fn projection(&self, hit: &Hit) -> Projection {
+        // The hits built by get_closest_hit already have the projection of the prototype element, this is only a fallback
+        let mut projection = Projection::default();
+        let constant_axis = if hit.norm().y().abs() > 0.999 {
+            Vec3::new(1., 0., 0.)
+        } else {
+            Vec3::new(0., 1., 0.)
+        };
+        projection.k = *hit.norm();
+        projection.i = hit.norm().cross(&constant_axis).normalize();
+        projection.j = hit.norm().cross(&projection.i).normalize();
+
+        let local = self.transform.point_to_local(hit.pos()).normalize();
+        projection.u = (f64::atan2(*local.x(), *local.z()) + PI) / (2. * PI);
+        projection.v = f64::acos(local.y().clamp(-1., 1.)) / PI;
+        projection
+    }
+
+    fn norm(&self, hit_position: &Vec3) -> Vec3 {
+        // The position alone doesn't tell which element of the prototype has been hit: we take the first one whose
+        // AABB contains it. The raycasting goes through get_closest_hit, which knows the element.
+        let local = self.transform.point_to_local(hit_position);
+        let elements = self.prototype.elements();
+        let element = elements.iter()
+            .find(|element| match element.aabb() {
+                Some(aabb) => {
+                    let (min, max) = (aabb.min(), aabb.max());
+                    (0..3).all(|axis| {
+                        let value = [*local.x(), *local.y(), *local.z()][axis];
+                        value >= min[axis] - 1e-6 && value <= max[axis] + 1e-6
+                    })
+                }
+                None => true,
+            })
+            .unwrap_or(&elements[0]);
+        self.transform.norm_to_world(&element.norm(&local))
+    }
+
+    fn pos(&self) -> &Vec3 {
+        self.transform.translation()
+    }
+
+    fn as_instance(&self) -> Option<&Instance> {
+        Some(self)
+    }
+    fn as_instance_mut(&mut self) -> Option<&mut Instance> {
+        Some(self)
+    }
+
+    fn aabb(&self) -> Option<&Aabb> {
+        self.aabb.as_ref()
+    }
+
+    fn get_ui(&self, element: &Element, ui: &mut UI, _scene: &Arc<RwLock<Scene>>) -> UIElement {
+        let mut category = UIElement::new("Instance", "instance", ElemType::Category(Category::default()), ui.uisettings());
+
+        if let Some(instance) = element.shape().as_instance() {
+            let id = element.id();
+            let prototype_name = format!("Prototype: {}", instance.prototype.name());
+            category.add_element(UIElement::new(&prototype_name, "prototype", ElemType::Text, ui.uisettings()));
+
+            category.add_element(get_vector_ui(*instance.transform.translation(), "Position", "pos", ui.uisettings(),
+                submit_transform(id, |transform, value| transform.set_translation(Vec3::new(value, *transform.translation().y(), *transform.translation().z()))),
+                submit_transform(id, |transform, value| transform.set_translation(Vec3::new(*transform.translation().x(), value, *transform.translation().z()))),
+                submit_transform(id, |transform, value| transform.set_translation(Vec3::new(*transform.translation().x(), *transform.translation().y(), value))),
+                false, None, None));
+            category.add_element(get_vector_ui(*instance.transform.rotation(), "Rotation", "rotation", ui.uisettings(),
+                submit_transform(id, |transform, value| transform.set_rotation(Vec3::new(value, *transform.rotation().y(), *transform.rotation().z()))),
+                submit_transform(id, |transform, value| transform.set_rotation(Vec3::new(*transform.rotation().x(), value, *transform.rotation().z()))),
+                submit_transform(id, |transform, value| transform.set_rotation(Vec3::new(*transform.rotation().x(), *transform.rotation().y(), value))),
+                false, Some(-360.), Some(360.)));
+            category.add_element(get_vector_ui(*instance.transform.scale(), "Scale", "scale", ui.uisettings(),
+                submit_transform(id, |transform, value| transform.set_scale(Vec3::new(value, *transform.scale().y(), *transform.scale().z()))),
+                submit_transform(id, |transform, value| transform.set_scale(Vec3::new(*transform.scale().x(), value, *transform.scale().z()))),
+                submit_transform(id, |transform, value| transform.set_scale(Vec3::new(*transform.scale().x(), *transform.scale().y(), value))),
+                false, Some(0.001), None));
+        }
+        category
+    }
+}
+
+fn min_scale(transform: &Transform) -> f64 {
+    let scale = transform.scale();
+    scale.x().abs().min(scale.y().abs()).min(scale.z().abs())
+}
+
+fn submit_transform(id: usize, apply: fn(&mut Transform, f64)) -> FnSubmitValue {
+    Box::new(move |_, value, context, _| {
+        if let Some(scene) = context.get_active_scene() {
+            let mut scene = scene.write().unwrap();
+            let elem = scene.element_mut_by_id(id).unwrap();
+            if let Some(instance) = elem.shape_mut().as_instance_mut() {
+                if let Value::Float(value) = value {
+                    let mut transform = instance.transform.clone();
+                    apply(&mut transform, value);
+                    instance.set_transform(transform);
+                }
+            }
+        }
+    })
+}
+
+impl Instance {
+    // Accessors
+    pub fn prototype(&self) -> &Arc<Prototype> {
+        &self.prototype
+    }
+    pub fn transform(&self) -> &Transform {
+        &self.transform
+    }
+
+    // Mutators
+    pub fn set_transform(&mut self, transform: Transform) {
+        self.transform = transform;
+        self.update_aabb();
+    }
+
+    // Constructor
+    pub fn new(prototype: Arc<Prototype>, transform: Transform) -> Instance {
+        let mut instance = Instance { prototype, transform, aabb: None };
+        instance.update_aabb();
+        instance
+    }
+
+    // Methods
+    /**
+     * Closest hit of the ray with the prototype, moved back to the world. The hit belongs to the element of the
+     * instance, so its material (which overrides the one of the prototype) is the one used for the shading. The
+     * transform of the element (and of its groups) is applied after the one of the instance.
+     */
+    pub fn get_closest_hit<'a>(&'a self, scene: &'a Scene, ray: &Ray, element: &'a Element) -> Option<Hit<'a>> {
+        // Like Element::intersect, the hidden instances are never hit, which also keeps them from casting shadows
+        if !element.visible() {
+            return None;
+        }
+        let transform = match element.world_transform() {
+            Some(world_transform) => world_transform.compose(&self.transform),
+            None => self.transform.clone(),
+        };
+        let (local_ray, factor) = transform.ray_to_local(ray);
+        let local_hit = self.prototype.get_closest_hit(scene, &local_ray)?;
+        let local_element = local_hit.element();
+
+        let mut norm = transform.norm_to_world(&local_element.norm(local_hit.pos()));
+        if norm.dot(ray.get_dir()) > 0. {
+            norm = -norm;
+        }
+        let mut projection = local_element.projection(&local_hit);
+        projection.i = transform.dir_to_world(&projection.i).normalize();
+        projection.j = transform.dir_to_world(&projection.j).normalize();
+        projection.k = norm;
+
+        let dist = local_hit.dist() * factor;
+        let mut all_dist: Vec<f64> = local_hit.t_list().iter()
+            .flat_map(|(_, t_list)| t_list.iter().map(|t| t * factor))
+            .collect();
+        all_dist.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        Some(Hit::from_geometry(
+            element,
+            dist,
+            ray.get_pos() + ray.get_dir() * (dist - f64::EPSILON),
+            norm,
+            Some(projection),
+            scene.textures(),
+            all_dist,
+        ))
+    }
+
+    fn update_aabb(&mut self) {
+        self.aabb = self.prototype.aabb().map(|aabb| self.transform.aabb_to_world(aabb));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use crate::model::element::Element;
+    use crate::model::materials::diffuse::Diffuse;
+    use crate::model::maths::ray::Ray;
+    use crate::model::maths::transform::Transform;
+    use crate::model::maths::vec3::Vec3;
+    use crate::model::prototype::Prototype;
+    use crate::model::shapes::instance::Instance;
+    use crate::model::shapes::shape::Shape;
+    use crate::model::shapes::sphere::Sphere;
+
+    fn sphere_instance(transform: Transform) -> Instance {
+        let sphere = Sphere::new(Vec3::new(0., 0., 0.), Vec3::new(0., 1., 0.), 1.);
+        let prototype = Prototype::new("sphere".to_string(), vec![Element::new(Box::new(sphere), Diffuse::default())], Diffuse::default());
+        Instance::new(Arc::new(prototype), transform)
+    }
+
+    #[test]
+    fn test_intersect_translated() {
+        let instance = sphere_instance(Transform::new(Vec3::new(5., 0., 0.), Vec3::from_value(0.), Vec3::from_value(1.)));
+        let ray = Ray::new(Vec3::new(0., 0., 0.), Vec3::new(1., 0., 0.), 0);
+        let t = instance.intersect(&ray).unwrap();
+        assert!((t[0] - 4.).abs() < 1e-9);
+        assert!((t[1] - 6.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_intersect_scaled() {
+        let instance = sphere_instance(Transform::new(Vec3::from_value(0.), Vec3::from_value(0.), Vec3::new(3., 1., 1.)));
+        let ray = Ray::new(Vec3::new(-10., 0., 0.), Vec3::new(1., 0., 0.), 0);
+        let t = instance.intersect(&ray).unwrap();
+        assert!((t[0] - 7.).abs() < 1e-9);
+        assert!((t[1] - 13.).abs() < 1e-9);
+
+        let aabb = instance.aabb().unwrap();
+        assert!((aabb.x_max() - 3.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_distance() {
+        let instance = sphere_instance(Transform::new(Vec3::new(5., 0., 0.), Vec3::from_value(0.), Vec3::from_value(2.)));
+        assert!((instance.distance(&Vec3::new(0., 0., 0.)) - 3.).abs() < 1e-9);
+    }
+}
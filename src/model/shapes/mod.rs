@@ -19,6 +19,8 @@ pub mod cube;
 pub mod any;
 pub mod hyperboloid;
 pub mod obj;
+pub mod sdf;
+pub mod instance;
 pub mod shape;
 pub mod composed_shape;
 
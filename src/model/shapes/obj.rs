@@ -6,7 +6,7 @@ use crate::{model::{
         material::Material, maths::vec3::Vec3, scene::Scene
 }, ui::{prefabs::vector_ui::get_vector_ui, ui::UI, uielement::{Category, UIElement}, utils::misc::{ElemType, Property, Value}}};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Obj {
     pub pos: Vec3,
     pub dir: Vec3,
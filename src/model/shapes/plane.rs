@@ -23,8 +23,8 @@ pub struct Plane {
 }
 
 impl Shape for Plane {
-    fn distance(&self, _vec: &Vec3) -> f64 {
-        unimplemented!()
+    fn distance(&self, vec: &Vec3) -> f64 {
+        (vec - self.pos).dot(&self.dir)
     }
     fn intersect(&self, r: &Ray) -> Option<Vec<f64>> {
         let dist = &self.pos - r.get_pos();
@@ -13,7 +13,7 @@ use crate::ui::{
     utils::misc::{ElemType, Property, Value},
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Rectangle {
     pos: Vec3,
     length: f64,
@@ -0,0 +1,365 @@
+use std::f64::consts::PI;
+use std::sync::{Arc, RwLock};
+use super::{aabb::Aabb, shape::Shape};
+use crate::{
+    model::{
+        element::Element,
+        materials::material::Projection,
+        maths::{hit::Hit, ray::Ray, vec3::Vec3},
+        scene::Scene
+    },
+    ui::{
+        prefabs::vector_ui::get_vector_ui,
+        ui::UI,
+        uielement::{Category, UIElement},
+        utils::misc::{ElemType, Value}
+    },
+    SDF_EPSILON, SDF_MAX_DISTANCE, SDF_MAX_STEPS
+};
+
+/**
+ * A node of a signed distance field tree. Primitives are expressed relative to the position of the Sdf shape
+ * that owns the tree, and operators combine the distance of their children.
+ */
+#[derive(Debug, Clone)]
+pub enum SdfNode {
+    Sphere { pos: Vec3, radius: f64 },
+    Box { pos: Vec3, half_size: Vec3 },
+    RoundBox { pos: Vec3, half_size: Vec3, radius: f64 },
+    Torus { pos: Vec3, radius: f64, radius2: f64 },
+    Capsule { a: Vec3, b: Vec3, radius: f64 },
+    Union(Box<SdfNode>, Box<SdfNode>),
+    Subtract(Box<SdfNode>, Box<SdfNode>),
+    Intersect(Box<SdfNode>, Box<SdfNode>),
+    SmoothUnion(Box<SdfNode>, Box<SdfNode>, f64),
+    SmoothSubtract(Box<SdfNode>, Box<SdfNode>, f64),
+    Repeat(Box<SdfNode>, Vec3),
+}
+
+impl SdfNode {
+    pub fn distance(&self, p: &Vec3) -> f64 {
+        match self {
+            SdfNode::Sphere { pos, radius } => (p - pos).length() - radius,
+            SdfNode::Box { pos, half_size } => box_distance(&(p - pos), half_size),
+            SdfNode::RoundBox { pos, half_size, radius } => {
+                box_distance(&(p - pos), &(half_size - Vec3::from_value(*radius))) - radius
+            }
+            SdfNode::Torus { pos, radius, radius2 } => {
+                // The torus lies in the XZ plane, around the Y axis
+                let q = p - pos;
+                let ring = (q.x() * q.x() + q.z() * q.z()).sqrt() - radius;
+                (ring * ring + q.y() * q.y()).sqrt() - radius2
+            }
+            SdfNode::Capsule { a, b, radius } => {
+                let pa = p - a;
+                let ba = b - a;
+                let h = (pa.dot(&ba) / ba.dot(&ba)).clamp(0., 1.);
+                (pa - ba * h).length() - radius
+            }
+            SdfNode::Union(a, b) => a.distance(p).min(b.distance(p)),
+            SdfNode::Subtract(a, b) => a.distance(p).max(-b.distance(p)),
+            SdfNode::Intersect(a, b) => a.distance(p).max(b.distance(p)),
+            SdfNode::SmoothUnion(a, b, k) => {
+                let (d1, d2) = (a.distance(p), b.distance(p));
+                let h = (0.5 + 0.5 * (d2 - d1) / k).clamp(0., 1.);
+                d2 + (d1 - d2) * h - k * h * (1. - h)
+            }
+            SdfNode::SmoothSubtract(a, b, k) => {
+                // Smooth max of a and of b inside out
+                let (d1, d2) = (a.distance(p), -b.distance(p));
+                let h = (0.5 - 0.5 * (d1 - d2) / k).clamp(0., 1.);
+                d1 + (d2 - d1) * h + k * h * (1. - h)
+            }
+            SdfNode::Repeat(node, period) => {
+                let q = Vec3::new(
+                    repeat_component(*p.x(), *period.x()),
+                    repeat_component(*p.y(), *period.y()),
+                    repeat_component(*p.z(), *period.z()),
+                );
+                node.distance(&q)
+            }
+        }
+    }
+}
+
+fn box_distance(p: &Vec3, half_size: &Vec3) -> f64 {
+    let q = p.abs() - half_size;
+    let outside = q.max(Vec3::from_value(0.)).length();
+    let inside = q.x().max(*q.y()).max(*q.z()).min(0.);
+    outside + inside
+}
+
+fn repeat_component(value: f64, period: f64) -> f64 {
+    if period <= 0. {
+        return value;
+    }
+    value - period * (value / period).round()
+}
+
+#[derive(Debug, Clone)]
+pub struct Sdf {
+    pos: Vec3,
+    root: SdfNode,
+    bounds: Option<(Vec3, Vec3)>,
+    aabb: Option<Aabb>,
+}
+
+impl Shape for Sdf {
+    fn distance(&self, vec: &Vec3) -> f64 {
+        self.root.distance(&(vec - self.pos))
+    }
+
+    fn intersect(&self, r: &Ray) -> Option<Vec<f64>> {
+        // Sphere tracing: we step along the ray by the distance to the closest surface, which can never overshoot.
+        // Every sign change of the field is a surface crossing, so we keep marching to collect all of them.
+        let (mut t, t_max) = match &self.aabb {
+            Some(aabb) => match aabb.intersect(r) {
+                Some(t_aabb) => (t_aabb[0].max(0.), t_aabb[1]),
+                None => return None,
+            },
+            None => (0., SDF_MAX_DISTANCE),
+        };
+        if t > t_max {
+            return None;
+        }
+
+        let mut t_list = vec![];
+        let mut previous_t = t;
+        let mut previous_distance = self.distance(&(r.get_pos() + r.get_dir() * t));
+        let mut inside = previous_distance < 0.;
+        if inside {
+            // The ray starts inside the field, the entry point is behind its origin
+            t_list.push(-SDF_EPSILON);
+        }
+        // The first sample is already known, the marching starts at the next one
+        t += previous_distance.abs().max(SDF_EPSILON);
+
+        let mut steps = 1;
+        while t <= t_max && steps < SDF_MAX_STEPS {
+            let distance = self.distance(&(r.get_pos() + r.get_dir() * t));
+            if (distance < 0.) != inside {
+                // Linear interpolation between the two last samples to get closer to the actual surface
+                let ratio = previous_distance / (previous_distance - distance);
+                t_list.push(previous_t + (t - previous_t) * ratio);
+                inside = !inside;
+            }
+            previous_t = t;
+            previous_distance = distance;
+            t += distance.abs().max(SDF_EPSILON);
+            steps += 1;
+        }
+        if inside {
+            t_list.push(t_max);
+        }
+
+        if !t_list.is_empty() {
+            Some(t_list)
+        } else {
+            None
+        }
+    }
+
+    fn outer_intersect(&self, ray: &Ray, _displaced_factor: f64) -> Option<Vec<f64>> {
+        self.intersect(ray)
+    }
+
+    fn intersect_displacement(&self, ray: &Ray, _element: &Element, _scene: &Scene) -> Option<Vec<f64>> {
+        self.intersect(ray)
+    }
+
+    fn projection(&self, hit: &Hit) -> Projection {
+        let mut projection = Projection::default();
+        let constant_axis = if hit.norm().y().abs() > 0.999 {
+            Vec3::new(1., 0., 0.)
+        } else {
+            Vec3::new(0., 1., 0.)
+        };
+        projection.k = *hit.norm();
+        projection.i = hit.norm().cross(&constant_axis).normalize();
+        projection.j = hit.norm().cross(&projection.i).normalize();
+
+        let local = (hit.pos() - self.pos).normalize();
+        projection.u = (f64::atan2(*local.x(), *local.z()) + PI) / (2. * PI);
+        projection.v = f64::acos(local.y().clamp(-1., 1.)) / PI;
+        projection
+    }
+
+    fn norm(&self, hit_position: &Vec3) -> Vec3 {
+        // The gradient of the field is the normal of the surface
+        let h = SDF_EPSILON;
+        let dx = Vec3::new(h, 0., 0.);
+        let dy = Vec3::new(0., h, 0.);
+        let dz = Vec3::new(0., 0., h);
+        Vec3::new(
+            self.distance(&(hit_position + dx)) - self.distance(&(hit_position - dx)),
+            self.distance(&(hit_position + dy)) - self.distance(&(hit_position - dy)),
+            self.distance(&(hit_position + dz)) - self.distance(&(hit_position - dz)),
+        ).normalize()
+    }
+
+    fn pos(&self) -> &Vec3 {
+        &self.pos
+    }
+
+    fn as_sdf(&self) -> Option<&Sdf> {
+        Some(self)
+    }
+    fn as_sdf_mut(&mut self) -> Option<&mut Sdf> {
+        Some(self)
+    }
+
+    fn aabb(&self) -> Option<&Aabb> {
+        self.aabb.as_ref()
+    }
+
+    /**
+     * Only the position can be edited, the nodes of the field are given by the scene file.
+     */
+    fn get_ui(&self, element: &Element, ui: &mut UI, _scene: &Arc<RwLock<Scene>>) -> UIElement {
+        let mut category = UIElement::new("Sdf", "sdf", ElemType::Category(Category::default()), ui.uisettings());
+
+        if let Some(sdf) = element.shape().as_sdf() {
+            let id = element.id();
+            category.add_element(get_vector_ui(sdf.pos, "Position", "pos", ui.uisettings(),
+                Box::new(move |_, value, context, _| {
+                    if let Some(scene) = context.get_active_scene() {
+                        let mut scene = scene.write().unwrap();
+                        let elem = scene.element_mut_by_id(id).unwrap();
+                        if let Some(sdf) = elem.shape_mut().as_sdf_mut() {
+                            if let Value::Float(value) = value {
+                                sdf.set_pos(Vec3::new(value, *sdf.pos.y(), *sdf.pos.z()));
+                            }
+                        }
+                    }
+                }),
+                Box::new(move |_, value, context, _| {
+                    if let Some(scene) = context.get_active_scene() {
+                        let mut scene = scene.write().unwrap();
+                        let elem = scene.element_mut_by_id(id).unwrap();
+                        if let Some(sdf) = elem.shape_mut().as_sdf_mut() {
+                            if let Value::Float(value) = value {
+                                sdf.set_pos(Vec3::new(*sdf.pos.x(), value, *sdf.pos.z()));
+                            }
+                        }
+                    }
+                }),
+                Box::new(move |_, value, context, _| {
+                    if let Some(scene) = context.get_active_scene() {
+                        let mut scene = scene.write().unwrap();
+                        let elem = scene.element_mut_by_id(id).unwrap();
+                        if let Some(sdf) = elem.shape_mut().as_sdf_mut() {
+                            if let Value::Float(value) = value {
+                                sdf.set_pos(Vec3::new(*sdf.pos.x(), *sdf.pos.y(), value));
+                            }
+                        }
+                    }
+                }),
+                false, None, None));
+        }
+        category
+    }
+}
+
+impl Sdf {
+    // Accessors
+    pub fn root(&self) -> &SdfNode {
+        &self.root
+    }
+    pub fn bounds(&self) -> &Option<(Vec3, Vec3)> {
+        &self.bounds
+    }
+
+    // Mutators
+    pub fn set_pos(&mut self, pos: Vec3) {
+        self.pos = pos;
+        self.update_aabb();
+    }
+    pub fn set_root(&mut self, root: SdfNode) {
+        self.root = root;
+    }
+
+    // Constructor
+    /**
+     * The bounds are relative to pos. Without bounds, the shape can't be put in the BVH and the rays are marched up
+     * to SDF_MAX_DISTANCE, so they should only be omitted for infinite fields (e.g. repetitions).
+     */
+    pub fn new(pos: Vec3, root: SdfNode, bounds: Option<(Vec3, Vec3)>) -> Sdf {
+        let mut sdf = Sdf { pos, root, bounds, aabb: None };
+        sdf.update_aabb();
+        sdf
+    }
+
+    fn update_aabb(&mut self) {
+        self.aabb = self.bounds.map(|(min, max)| Aabb::from_min_max(min + self.pos, max + self.pos));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::maths::ray::Ray;
+    use crate::model::maths::vec3::Vec3;
+    use crate::model::shapes::sdf::{Sdf, SdfNode};
+    use crate::model::shapes::shape::Shape;
+
+    fn sphere_sdf() -> Sdf {
+        let root = SdfNode::Sphere { pos: Vec3::new(0., 0., 0.), radius: 1. };
+        Sdf::new(Vec3::new(0., 0., 0.), root, Some((Vec3::from_value(-1.5), Vec3::from_value(1.5))))
+    }
+
+    #[test]
+    fn test_distance() {
+        let sdf = sphere_sdf();
+        assert!((sdf.distance(&Vec3::new(3., 0., 0.)) - 2.).abs() < 1e-9);
+        assert!((sdf.distance(&Vec3::new(0., 0., 0.)) + 1.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_smooth_subtract() {
+        let sphere = Box::new(SdfNode::Sphere { pos: Vec3::new(0., 0., 0.), radius: 1. });
+        let far = Box::new(SdfNode::Sphere { pos: Vec3::new(100., 0., 0.), radius: 1. });
+        let near = Box::new(SdfNode::Sphere { pos: Vec3::new(1., 0., 0.), radius: 0.5 });
+
+        // Subtracting a far away shape leaves the other one as it is
+        let node = SdfNode::SmoothSubtract(sphere.clone(), far, 0.2);
+        for p in [Vec3::new(3., 0., 0.), Vec3::new(0., 0., 0.), Vec3::new(0., 0.9, 0.)] {
+            assert!((node.distance(&p) - sphere.distance(&p)).abs() < 1e-9);
+        }
+        // The inside of the subtracted shape is outside of the result
+        let node = SdfNode::SmoothSubtract(sphere, near, 0.2);
+        assert!(node.distance(&Vec3::new(1., 0., 0.)) > 0.);
+    }
+
+    #[test]
+    fn test_intersect() {
+        let sdf = sphere_sdf();
+        let ray = Ray::new(Vec3::new(-5., 0., 0.), Vec3::new(1., 0., 0.), 5);
+        let t = sdf.intersect(&ray).unwrap();
+        assert_eq!(t.len(), 2);
+        assert!((t[0] - 4.).abs() < 1e-3);
+        assert!((t[1] - 6.).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_intersect_miss() {
+        let sdf = sphere_sdf();
+        let ray = Ray::new(Vec3::new(-5., 2., 0.), Vec3::new(1., 0., 0.), 5);
+        assert_eq!(sdf.intersect(&ray), None);
+    }
+
+    #[test]
+    fn test_norm() {
+        let sdf = sphere_sdf();
+        let norm = sdf.norm(&Vec3::new(0., 1., 0.));
+        assert!((norm - Vec3::new(0., 1., 0.)).length() < 1e-6);
+    }
+
+    #[test]
+    fn test_smooth_union_is_below_union() {
+        let a = SdfNode::Sphere { pos: Vec3::new(-0.5, 0., 0.), radius: 0.6 };
+        let b = SdfNode::Sphere { pos: Vec3::new(0.5, 0., 0.), radius: 0.6 };
+        let p = Vec3::new(0., 0.7, 0.);
+        let union = SdfNode::Union(Box::new(a.clone()), Box::new(b.clone())).distance(&p);
+        let smooth = SdfNode::SmoothUnion(Box::new(a), Box::new(b), 0.3).distance(&p);
+        assert!(smooth < union);
+    }
+}
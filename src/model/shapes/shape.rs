@@ -2,9 +2,22 @@ use std::{fmt::Debug, sync::{Arc, RwLock}};
 
 use crate::{model::{element::Element, materials::material::Projection, maths::{hit::Hit, ray::Ray, vec3::Vec3}, scene::Scene}, ui::{ui::UI, uielement::UIElement}};
 
-use super::{aabb::Aabb, any::Any, capped_cylinder::CappedCylinder, cone::Cone, cube::Cube, cubehole::Cubehole, cylinder::Cylinder, ellipse::Ellipse, hyperboloid::Hyperboloid, obj::Obj, plane::Plane, rectangle::Rectangle, sphere::Sphere, torus::Torus, triangle::Triangle, wireframe::Wireframe};
+use super::{aabb::Aabb, any::Any, capped_cylinder::CappedCylinder, cone::Cone, cube::Cube, cubehole::Cubehole, cylinder::Cylinder, ellipse::Ellipse, hyperboloid::Hyperboloid, instance::Instance, obj::Obj, plane::Plane, rectangle::Rectangle, sdf::Sdf, sphere::Sphere, torus::Torus, triangle::Triangle, wireframe::Wireframe};
 
-pub trait Shape: Debug + Sync + Send {
+/**
+ * Copy of a shape behind a box, for the duplication of elements. Every shape deriving Clone gets it.
+ */
+pub trait ShapeClone {
+    fn clone_shape(&self) -> Box<dyn Shape + Sync>;
+}
+
+impl<T: 'static + Shape + Clone> ShapeClone for T {
+    fn clone_shape(&self) -> Box<dyn Shape + Sync> {
+        Box::new(self.clone())
+    }
+}
+
+pub trait Shape: Debug + Sync + Send + ShapeClone {
     fn distance(&self, vec: &Vec3) -> f64;
     fn intersect(&self, ray: &Ray) -> Option<Vec<f64>>;
     fn outer_intersect(&self, ray: &Ray, displaced_factor: f64) -> Option<Vec<f64>>;
@@ -45,6 +58,10 @@ pub trait Shape: Debug + Sync + Send {
             return "Any".to_string();
         } else if self.as_obj().is_some() {
             return "Obj".to_string(); 
+        } else if self.as_sdf().is_some() {
+            return "Sdf".to_string();
+        } else if self.as_instance().is_some() {
+            return "Instance".to_string();
         } else {
             return "Unknown".to_string();
         }
@@ -66,6 +83,8 @@ pub trait Shape: Debug + Sync + Send {
     fn as_hyperboloid(&self) -> Option<&Hyperboloid> { None }
     fn as_any(&self) -> Option<&Any> { None }
     fn as_obj(&self) -> Option<&Obj> { None }
+    fn as_sdf(&self) -> Option<&Sdf> { None }
+    fn as_instance(&self) -> Option<&Instance> { None }
     fn aabb(&self) -> Option<&Aabb> { None }
 
     fn as_sphere_mut(&mut self) -> Option<&mut Sphere> { None }
@@ -84,6 +103,8 @@ pub trait Shape: Debug + Sync + Send {
     fn as_hyperboloid_mut(&mut self) -> Option<&mut Hyperboloid> { None }
     fn as_any_mut(&mut self) -> Option<&mut Any> { None }
     fn as_obj_mut(&mut self) -> Option<&mut Obj> { None }
+    fn as_sdf_mut(&mut self) -> Option<&mut Sdf> { None }
+    fn as_instance_mut(&mut self) -> Option<&mut Instance> { None }
 
     fn get_ui(&self, element: &Element, ui: &mut UI, scene: &Arc<RwLock<Scene>>) -> UIElement;
 }
\ No newline at end of file
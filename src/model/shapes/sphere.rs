@@ -18,7 +18,7 @@ use crate::{
     render::raycasting::get_sorted_hit_from_t
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Sphere {
     pos: Vec3,
     dir: Vec3,
@@ -27,8 +27,8 @@ pub struct Sphere {
 }
 
 impl Shape for Sphere {
-    fn distance(&self, _vec: &Vec3) -> f64 {
-        unimplemented!()
+    fn distance(&self, vec: &Vec3) -> f64 {
+        (vec - self.pos).length() - self.radius
     }
 
     fn intersect(&self, r: &Ray) -> Option<Vec<f64>> {
@@ -11,7 +11,7 @@ use crate::ui::uielement::{Category, UIElement};
 use crate::ui::utils::misc::{ElemType, Property, Value};
 use roots::{find_roots_quartic, Roots};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Torus {
     pos: Vec3,
     dir: Vec3,
@@ -23,8 +23,11 @@ pub struct Torus {
 unsafe impl Send for Torus {}
 
 impl Shape for Torus {
-    fn distance(&self, _vec: &Vec3) -> f64 {
-        unimplemented!()
+    fn distance(&self, vec: &Vec3) -> f64 {
+        let q = vec - self.pos;
+        let y = q.dot(&self.dir);
+        let ring = (q - self.dir * y).length() - self.radius;
+        (ring * ring + y * y).sqrt() - self.radius2
     }
 
     fn intersect(&self, ray: &Ray) -> Option<Vec<f64>> {
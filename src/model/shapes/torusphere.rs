@@ -14,7 +14,7 @@ use crate::{
     },
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Torusphere {
     pub pos: Vec3,
     pub dir: Vec3,
@@ -15,7 +15,7 @@ use crate::{
     }
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Triangle {
     a: Vec3,
     b: Vec3,
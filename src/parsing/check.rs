@@ -0,0 +1,399 @@
+use std::{collections::HashSet, fmt::{self, Display, Formatter}, path::Path};
+
+use crate::{model::{element::Element, maths::{ray::Ray, vec3::Vec3}, scene::Scene}, ERROR_MARGIN, TEXTURE_FOLDER};
+use super::{
+    json::{self, located, JsonObject, JsonValue, Span}, match_object, new_scene, object_error, parse_scene_content,
+    templates::expand_templates, COMPOSED_SHAPE_TYPES, LIGHT_TYPES, SHAPE_TYPES
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/**
+ * A problem of a scene file, its message starts with the place of the value it's about.
+ */
+#[derive(Debug, Clone)]
+pub struct Lint {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Lint {
+    // Constructor
+    fn warning(message: String) -> Self {
+        Self { severity: Severity::Warning, message }
+    }
+
+    fn error(message: String) -> Self {
+        Self { severity: Severity::Error, message }
+    }
+}
+
+impl Display for Lint {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.severity {
+            Severity::Warning => write!(f, "warning: {}", self.message),
+            Severity::Error => write!(f, "error: {}", self.message),
+        }
+    }
+}
+
+const MATERIAL_KEYS: [&str; 15] = [
+    "color", "metalness", "roughness", "emissive", "transparency", "normal", "opacity", "displacement", "refraction",
+    "reflectivity", "emissive_intensity", "u_scale", "v_scale", "u_shift", "v_shift"
+];
+// The keys of a material that can be the path of a texture file
+const TEXTURE_KEYS: [&str; 8] = ["color", "metalness", "roughness", "emissive", "transparency", "normal", "opacity", "displacement"];
+const TRANSFORM_KEYS: [&str; 5] = ["matrix", "translate", "scale", "quaternion", "rotate"];
+const DIRECTION_KEYS: [&str; 3] = ["dir", "dir_l", "dir_w"];
+const SIZE_KEYS: [&str; 4] = ["radius", "width", "length", "height"];
+
+/**
+ * Keys read by the parsing of each type of object, besides its type, its name, its transform and its material.
+ */
+fn object_keys(object_type: &str) -> Option<&'static [&'static str]> {
+    let keys: &[&str] = match object_type {
+        "skybox" => &["color"],
+        "camera" => &["pos", "dir", "fov", "roll"],
+        "light" => &["pos", "intensity", "color"],
+        "ambient" => &["intensity", "color"],
+        "parallel" => &["dir", "intensity", "color"],
+        "spot" => &["pos", "dir", "intensity", "fov", "color"],
+        "sphere" | "cylinder" => &["pos", "dir", "radius"],
+        "plane" => &["pos", "dir"],
+        "cone" => &["pos", "dir", "angle"],
+        "triangle" => &["a", "b", "c"],
+        "rectangle" => &["pos", "length", "width", "dir_l", "dir_w", "one_sided"],
+        "torus" => &["pos", "dir", "radius", "radius2"],
+        "torusphere" => &["pos", "dir", "radius", "steps"],
+        "helix" => &["pos", "dir", "height"],
+        "brick" => &["pos", "dir", "dimensions"],
+        "nagone" => &["pos", "dir", "radius", "angles"],
+        "mobius" => &["pos", "radius", "half_width"],
+        "ellipse" => &["pos", "dir", "u", "v"],
+        "cube" | "cubehole" => &["pos", "dir", "width"],
+        "hyperboloid" => &["pos", "z_shift"],
+        "any" => &["equation", "bounds_min", "bounds_max"],
+        "sdf" => &["pos", "sdf", "bounds_min", "bounds_max"],
+        "obj" => &["pos", "dir", "scale", "rotation", "file"],
+        "instance" => &["prototype", "pos", "rotation", "scale"],
+        "group" => &["objects", "material", "transform"],
+        "prototype" => &["object"],
+        "texture" => &[
+            "pattern", "res_x", "res_y", "u_min", "u_max", "v_min", "v_max", "u_variation", "v_variation", "color_a",
+            "color_b", "dots"
+        ],
+        "materials" => &["materials"],
+        "viewmode" => &["value"],
+        _ => return None,
+    };
+    Some(keys)
+}
+
+fn is_shape(object_type: &str) -> bool {
+    SHAPE_TYPES.iter().chain(&COMPOSED_SHAPE_TYPES).any(|shape_type| *shape_type == object_type)
+}
+
+/**
+ * Runs the parsing of a scene file and lints its objects: unknown keys, missing texture files, values that aren't
+ * numbers, degenerate geometry, lights without intensity, a camera inside a shape and coplanar surfaces. The parsing
+ * goes on after an object with an error, to report the errors of the next ones.
+ */
+pub fn check_scene(scene_file: &String) -> Vec<Lint> {
+    let folder = Path::new(scene_file).parent().unwrap_or(Path::new("."));
+    check_json(json::parse_json_file(scene_file), folder)
+}
+
+/**
+ * Same as check_scene, from the content of a scene file. Its includes are relative to the current directory.
+ */
+pub fn check_scene_from_str(scene_content: &str) -> Vec<Lint> {
+    check_json(json::parse_json(scene_content.to_string()), Path::new("."))
+}
+
+fn check_json(json_value: Result<JsonValue, String>, folder: &Path) -> Vec<Lint> {
+    let json_value = match json_value.and_then(|json_value| expand_templates(json_value, folder)) {
+        Ok(json_value) => json_value,
+        Err(err) => return vec![Lint::error(err)],
+    };
+    let objects = match json_value {
+        JsonValue::Array(objects) => objects,
+        json_value => vec![json_value],
+    };
+
+    let mut lints = vec![];
+    let textures: HashSet<String> = objects.iter()
+        .filter_map(|object| match object {
+            JsonValue::Object(object) if matches!(object.get("type"), Some(JsonValue::String(object_type)) if object_type == "texture") => {
+                match object.get("name") {
+                    Some(JsonValue::String(name)) => Some(name.clone()),
+                    _ => None,
+                }
+            }
+            _ => None,
+        })
+        .collect();
+    for (index, object) in objects.iter().enumerate() {
+        if let JsonValue::Object(object) = object {
+            lint_object(object, index, &textures, &mut lints);
+        }
+    }
+
+    // The elements keep the place of the object of the scene they come from
+    let mut scene = new_scene();
+    let mut sources = vec![];
+    for (index, object) in objects.into_iter().enumerate() {
+        let span = match &object {
+            JsonValue::Object(object) => object.span().clone(),
+            _ => Span::default(),
+        };
+        let result = match object {
+            JsonValue::Object(object) => match_object(&mut scene, object, index),
+            object => parse_scene_content(&mut scene, object),
+        };
+        if let Err(err) = result {
+            lints.push(Lint::error(err));
+        }
+        sources.resize(scene.elements().len(), span);
+    }
+    lint_scene(&scene, &sources, &mut lints);
+    lints
+}
+
+fn lint_object(object: &JsonObject, index: usize, textures: &HashSet<String>, lints: &mut Vec<Lint>) {
+    let object_type = match object.get("type") {
+        Some(JsonValue::String(object_type)) => object_type.as_str(),
+        // The parsing reports the objects without a type
+        _ => return,
+    };
+    let Some(keys) = object_keys(object_type) else {
+        return;
+    };
+    let located = |msg: String| object_error(object.span(), Some(object_type), index, msg);
+    let warning = |key: &str, msg: String| Lint::warning(located(object.error(key, &msg)));
+    let error = |key: &str, msg: String| Lint::error(located(object.error(key, &msg)));
+
+    for key in object.keys() {
+        let known = ["type", "name"].contains(&key.as_str()) || keys.contains(&key.as_str())
+            || (is_shape(object_type) && (["transform", "material"].contains(&key.as_str()) || MATERIAL_KEYS.contains(&key.as_str())));
+        if !known {
+            lints.push(warning(key, format!("The key {} isn't used by an object of type {}", key, object_type)));
+        }
+    }
+    if let Some(JsonValue::Object(transform)) = object.get("transform") {
+        lints.extend(lint_keys(transform, &TRANSFORM_KEYS, "transform").into_iter().map(|msg| Lint::warning(located(msg))));
+    }
+    for (key, value) in object {
+        if !is_finite(value) {
+            lints.push(error(key, format!("The {} of an object isn't a number", key)));
+        }
+    }
+
+    // Degenerate geometry
+    for key in DIRECTION_KEYS {
+        if get_vec3(object, key).is_some_and(|dir| dir.length() < ERROR_MARGIN) {
+            lints.push(error(key, format!("The {} of an object has a length of 0", key)));
+        }
+    }
+    if let (Some(dir_l), Some(dir_w)) = (get_vec3(object, "dir_l"), get_vec3(object, "dir_w")) {
+        if dir_l.length() >= ERROR_MARGIN && dir_w.length() >= ERROR_MARGIN && dir_l.normalize().cross(&dir_w.normalize()).length() < ERROR_MARGIN {
+            lints.push(error("dir_w", "The dir_l and the dir_w of an object are parallel".to_string()));
+        }
+    }
+    if let (Some(a), Some(b), Some(c)) = (get_vec3(object, "a"), get_vec3(object, "b"), get_vec3(object, "c")) {
+        if object_type == "triangle" && (b - a).cross(&(c - a)).length() < ERROR_MARGIN {
+            lints.push(error("a", "The points of the triangle are aligned".to_string()));
+        }
+    }
+    if is_shape(object_type) {
+        for key in SIZE_KEYS {
+            if matches!(object.get(key), Some(JsonValue::Number(size)) if *size == 0.) {
+                lints.push(warning(key, format!("The {} of an object is 0, it can't be seen", key)));
+            }
+        }
+    }
+    if LIGHT_TYPES.contains(&object_type) || object_type == "ambient" {
+        if matches!(object.get("intensity"), Some(JsonValue::Number(intensity)) if *intensity == 0.) {
+            lints.push(warning("intensity", "The intensity of the light is 0, it doesn't light anything".to_string()));
+        }
+        if get_vec3(object, "color").is_some_and(|color| color.length() == 0.) {
+            lints.push(warning("color", "The color of the light is black, it doesn't light anything".to_string()));
+        }
+    }
+
+    // Missing files, in the object and in its materials
+    let mut materials = vec![];
+    if is_shape(object_type) || object_type == "skybox" {
+        lints.extend(lint_textures(object, textures).into_iter().map(|msg| Lint::error(located(msg))));
+    }
+    if let ("group" | "instance", Some(JsonValue::Object(material))) = (object_type, object.get("material")) {
+        materials.push(material);
+    }
+    if let ("materials", Some(JsonValue::Object(library))) = (object_type, object.get("materials")) {
+        materials.extend(library.values().filter_map(|material| match material {
+            JsonValue::Object(material) => Some(material),
+            _ => None,
+        }));
+    }
+    for material in materials {
+        lints.extend(lint_keys(material, &MATERIAL_KEYS, "material").into_iter().map(|msg| Lint::warning(located(msg))));
+        lints.extend(lint_textures(material, textures).into_iter().map(|msg| Lint::error(located(msg))));
+    }
+
+    match object_type {
+        "group" => {
+            if let Some(JsonValue::Array(objects)) = object.get("objects") {
+                for (index, object) in objects.iter().enumerate() {
+                    if let JsonValue::Object(object) = object {
+                        lint_object(object, index, textures, lints);
+                    }
+                }
+            }
+        }
+        "prototype" => {
+            if let Some(JsonValue::Object(object)) = object.get("object") {
+                lint_object(object, 0, textures, lints);
+            }
+        }
+        _ => (),
+    }
+}
+
+fn lint_keys(object: &JsonObject, keys: &[&str], name: &str) -> Vec<String> {
+    object.keys()
+        .filter(|key| !keys.contains(&key.as_str()))
+        .map(|key| object.error(key, &format!("The key {} isn't used by a {}", key, name)))
+        .collect()
+}
+
+// The textures are either files of the texture folder, or generated by a texture object
+fn lint_textures(object: &JsonObject, textures: &HashSet<String>) -> Vec<String> {
+    TEXTURE_KEYS.iter()
+        .filter_map(|key| match object.get(*key) {
+            Some(JsonValue::String(path)) if !textures.contains(path) && !Path::new(TEXTURE_FOLDER).join(path).exists() => {
+                Some(object.error(key, &format!("The texture file {}/{} doesn't exist", TEXTURE_FOLDER, path)))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn is_finite(value: &JsonValue) -> bool {
+    match value {
+        JsonValue::Number(number) => number.is_finite(),
+        JsonValue::Array(values) => values.iter().all(is_finite),
+        _ => true,
+    }
+}
+
+fn get_vec3(object: &JsonObject, key: &str) -> Option<Vec3> {
+    match object.get(key) {
+        Some(JsonValue::Array(values)) => match values.as_slice() {
+            [JsonValue::Number(x), JsonValue::Number(y), JsonValue::Number(z)] => Some(Vec3::new(*x, *y, *z)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/**
+ * Lints of the parsed scene, the elements are located by the object of the scene they come from.
+ */
+fn lint_scene(scene: &Scene, sources: &[Span], lints: &mut Vec<Lint>) {
+    let label = |element: &Element| match element.name() {
+        Some(name) => name.clone(),
+        None => format!("{} {}", element.shape().shape_name(), element.id()),
+    };
+    let source = |element: &Element| sources.get(element.id()).cloned().unwrap_or_default();
+
+    // A ray leaving a closed shape crosses it an odd number of times
+    let camera = scene.camera();
+    let ray = Ray::new(*camera.pos(), Vec3::new(0.577, 0.578, 0.576).normalize(), 0);
+    for element in scene.elements() {
+        if element.shape().as_sphere().is_none() && element.shape().as_cube().is_none() {
+            continue;
+        }
+        let hits = element.intersect(&ray).map_or(0, |hits| hits.iter().filter(|t| **t > ERROR_MARGIN).count());
+        if hits % 2 == 1 {
+            let msg = format!("The camera is inside the {}, it only sees its inside", label(element));
+            lints.push(Lint::warning(located(&source(element), msg)));
+        }
+    }
+
+    // The surfaces lying on a plane fight over the same pixels
+    let surfaces: Vec<(&Element, Vec3, Vec3)> = scene.elements().iter()
+        .filter(|element| element.world_transform().is_none() && element.composed_id().is_none())
+        .filter(|element| {
+            let shape = element.shape();
+            shape.as_plane().is_some() || shape.as_rectangle().is_some() || shape.as_triangle().is_some()
+        })
+        .map(|element| (element, *element.shape().pos(), element.shape().norm(element.shape().pos())))
+        .collect();
+    for (index, (element, pos, norm)) in surfaces.iter().enumerate() {
+        for (other, other_pos, other_norm) in surfaces.iter().skip(index + 1) {
+            if element.shape().as_plane().is_none() && other.shape().as_plane().is_none() {
+                continue;
+            }
+            if norm.dot(other_norm).abs() > 1. - ERROR_MARGIN && (*other_pos - *pos).dot(norm).abs() < ERROR_MARGIN {
+                let msg = format!("The {} and the {} are in the same plane", label(element), label(other));
+                lints.push(Lint::warning(located(&source(other), msg)));
+            }
+        }
+    }
+}
+
+/**
+ * Checks a scene file without rendering it, and writes its problems. Fails if it has errors.
+ * Usage: rt check <scene file>
+ */
+pub fn check_command(args: &[String]) -> Result<(), String> {
+    let scene_path = match args {
+        [scene_path] => scene_path,
+        [] => return Err("The scene file is missing".to_string()),
+        [_, arg, ..] => return Err(format!("Unexpected argument {}", arg)),
+    };
+    let lints = check_scene(scene_path);
+    for lint in &lints {
+        println!("{}", lint);
+    }
+    let errors = lints.iter().filter(|lint| lint.severity == Severity::Error).count();
+    println!("{}: {} errors, {} warnings", scene_path, errors, lints.len() - errors);
+    match errors {
+        0 => Ok(()),
+        _ => Err(format!("The scene {} has errors", scene_path)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_scene_from_str, Severity};
+
+    #[test]
+    fn test_check() {
+        let lints = check_scene_from_str(r#"[
+            {"type": "camera", "pos": [0, 1, 0], "dir": [0, 0, 1], "fov": 60},
+            {"type": "sphere", "pos": [0, 0, 0], "dir": [0, 1, 0], "radius": 3, "colour": [1, 2, 3], "color": [255, 0, 0]},
+            {"type": "plane", "pos": [0, -1, 0], "dir": [0, 1, 0], "color": "missing.png"},
+            {"type": "plane", "pos": [4, -1, 2], "dir": [0, -1, 0], "color": [255, 255, 255]},
+            {"type": "light", "pos": [0, 5, 0], "intensity": 0, "color": [255, 255, 255]},
+            {"type": "triangle", "a": [0, 0, 0], "b": [1, 1, 1], "c": [2, 2, 2], "color": [255, 255, 255]},
+            {"type": "cube", "pos": [0, 0, 0], "dir": [0, 0, 0], "width": "= 0 / 0", "color": [255, 255, 255]}
+        ]"#);
+        let messages: Vec<String> = lints.iter().map(|lint| lint.to_string()).collect();
+        let has = |severity: Severity, text: &str| lints.iter().any(|lint| lint.severity == severity && lint.message.contains(text));
+        assert!(has(Severity::Warning, "3:91: The key colour isn't used by an object of type sphere (object 1 of type sphere)"), "{:?}", messages);
+        assert!(has(Severity::Error, "The texture file textures/missing.png doesn't exist"), "{:?}", messages);
+        assert!(has(Severity::Warning, "The intensity of the light is 0"), "{:?}", messages);
+        assert!(has(Severity::Error, "The points of the triangle are aligned"), "{:?}", messages);
+        assert!(has(Severity::Error, "The dir of an object has a length of 0"), "{:?}", messages);
+        assert!(has(Severity::Error, "The width of an object isn't a number"), "{:?}", messages);
+        assert!(has(Severity::Warning, "The camera is inside the Sphere 0"), "{:?}", messages);
+        assert!(has(Severity::Warning, "are in the same plane"), "{:?}", messages);
+        // The parsing goes on after the cube
+        let lints = check_scene_from_str(r#"[{"type": "cube"}, {"type": "sphere"}]"#);
+        assert_eq!(lints.len(), 2, "{:?}", lints);
+    }
+}
@@ -1,6 +1,6 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 
-use crate::{model::{composed_element::ComposedElement, element::Element, materials::{color::Color, diffuse::Diffuse, material::Material}, maths::vec3::Vec3, objects::{camera::Camera, lights::{ambient_light::AmbientLight, light::AnyLight, parallel_light::ParallelLight, point_light::PointLight, spot_light::SpotLight}}, shapes::{any::Any, brick::Brick, composed_shape::ComposedShape, cone::Cone, cube::Cube, cubehole::Cubehole, cylinder::Cylinder, ellipse::Ellipse, helix::Helix, hyperboloid::Hyperboloid, mobius::Mobius, nagone::Nagone, obj::Obj, plane::Plane, rectangle::Rectangle, sphere::Sphere, torus::Torus, torusphere::Torusphere, triangle::Triangle}}, render::settings::ViewMode};
+use crate::{model::{composed_element::ComposedElement, element::Element, materials::{color::Color, diffuse::Diffuse, material::Material}, maths::{quaternion::Quaternion, transform::Transform, vec3::Vec3}, prototype::Prototype, objects::{camera::Camera, lights::{ambient_light::AmbientLight, light::AnyLight, parallel_light::ParallelLight, point_light::PointLight, spot_light::SpotLight}}, shapes::{any::Any, brick::Brick, composed_shape::ComposedShape, cone::Cone, cube::Cube, cubehole::Cubehole, cylinder::Cylinder, ellipse::Ellipse, helix::Helix, hyperboloid::Hyperboloid, instance::Instance, mobius::Mobius, nagone::Nagone, obj::Obj, plane::Plane, rectangle::Rectangle, sdf::{Sdf, SdfNode}, sphere::Sphere, torus::Torus, torusphere::Torusphere, triangle::Triangle}}, render::settings::ViewMode};
 use super::{
     basic::{
         get_color, get_color_texture, get_displacement_texture, get_normal_texture, get_number, get_opacity_texture, get_string, get_vec1_texture, get_vec3
@@ -48,7 +48,10 @@ pub fn get_camera(json_camera: &HashMap<String, JsonValue>) -> Result<Camera, St
     let pos = get_vec3(json_camera, "pos", None, None, None)?;
     let dir = get_vec3(json_camera, "dir", None, None, None)?.normalize();
     let fov = get_number(json_camera, "fov", Some(0.), Some(360.), None)?;
-    Ok(Camera::new(pos, dir, fov))
+    let roll = get_number(json_camera, "roll", Some(-360.), Some(360.), Some(0.))?;
+    let mut camera = Camera::new(pos, dir, fov);
+    camera.set_roll(roll.to_radians());
+    Ok(camera)
 }
 
 pub fn get_light(json_light: &HashMap<String, JsonValue>) -> Result<AnyLight, String> {
@@ -264,13 +267,102 @@ pub fn get_hyperboloid(json_hyperboloid: &HashMap<String, JsonValue>) -> Result<
 
 pub fn get_any(json_any: &HashMap<String, JsonValue>) -> Result<Element, String> {
     let equation = get_string(&json_any, "equation", None)?;
-
-    let shape = Box::new(Any::new(equation));
+    let bounds = match (json_any.get("bounds_min"), json_any.get("bounds_max")) {
+        (None, None) => None,
+        _ => Some((
+            get_vec3(json_any, "bounds_min", None, None, None)?,
+            get_vec3(json_any, "bounds_max", None, None, None)?
+        )),
+    };
+
+    let shape = Box::new(Any::new(equation, bounds)?);
     let material = get_material(&json_any)?;
     let element = Element::new(shape, material);
     Ok(element)
 }
 
+pub fn get_sdf(json_sdf: &HashMap<String, JsonValue>) -> Result<Element, String> {
+    let pos = get_vec3(json_sdf, "pos", None, None, Some(Vec3::from_value(0.)))?;
+    let root = match json_sdf.get("sdf") {
+        Some(JsonValue::Object(json_node)) => get_sdf_node(json_node)?,
+        Some(_) => return Err("The sdf of an object must be an object".to_string()),
+        None => return Err("The sdf of an object is missing".to_string()),
+    };
+    let bounds = match (json_sdf.get("bounds_min"), json_sdf.get("bounds_max")) {
+        (None, None) => None,
+        _ => Some((
+            get_vec3(json_sdf, "bounds_min", None, None, None)?,
+            get_vec3(json_sdf, "bounds_max", None, None, None)?
+        )),
+    };
+
+    let shape = Box::new(Sdf::new(pos, root, bounds));
+    let material = get_material(json_sdf)?;
+    let element = Element::new(shape, material);
+    Ok(element)
+}
+
+fn get_sdf_child(json_node: &HashMap<String, JsonValue>, key: &str) -> Result<Box<SdfNode>, String> {
+    match json_node.get(key) {
+        Some(JsonValue::Object(json_child)) => Ok(Box::new(get_sdf_node(json_child)?)),
+        Some(_) => Err(format!("The {} of an sdf operation must be an object", key)),
+        None => Err(format!("The {} of an sdf operation is missing", key)),
+    }
+}
+
+pub fn get_sdf_node(json_node: &HashMap<String, JsonValue>) -> Result<SdfNode, String> {
+    if json_node.contains_key("op") {
+        let op = get_string(json_node, "op", None)?;
+        return match op.as_str() {
+            "union" => Ok(SdfNode::Union(get_sdf_child(json_node, "a")?, get_sdf_child(json_node, "b")?)),
+            "subtract" => Ok(SdfNode::Subtract(get_sdf_child(json_node, "a")?, get_sdf_child(json_node, "b")?)),
+            "intersect" => Ok(SdfNode::Intersect(get_sdf_child(json_node, "a")?, get_sdf_child(json_node, "b")?)),
+            "smooth_union" => {
+                let k = get_number(json_node, "k", Some(f64::EPSILON), None, None)?;
+                Ok(SdfNode::SmoothUnion(get_sdf_child(json_node, "a")?, get_sdf_child(json_node, "b")?, k))
+            }
+            "smooth_subtract" => {
+                let k = get_number(json_node, "k", Some(f64::EPSILON), None, None)?;
+                Ok(SdfNode::SmoothSubtract(get_sdf_child(json_node, "a")?, get_sdf_child(json_node, "b")?, k))
+            }
+            "repeat" => {
+                let period = get_vec3(json_node, "period", Some(0.), None, None)?;
+                Ok(SdfNode::Repeat(get_sdf_child(json_node, "node")?, period))
+            }
+            _ => Err(format!("Unknown sdf operation: {}", op)),
+        };
+    }
+    let shape = get_string(json_node, "shape", None)?;
+    let pos = get_vec3(json_node, "pos", None, None, Some(Vec3::from_value(0.)))?;
+    match shape.as_str() {
+        "sphere" => {
+            let radius = get_number(json_node, "radius", Some(0.), None, None)?;
+            Ok(SdfNode::Sphere { pos, radius })
+        }
+        "box" => {
+            let dimensions = get_vec3(json_node, "dimensions", Some(0.), None, None)?;
+            Ok(SdfNode::Box { pos, half_size: dimensions / 2. })
+        }
+        "round_box" => {
+            let dimensions = get_vec3(json_node, "dimensions", Some(0.), None, None)?;
+            let radius = get_number(json_node, "radius", Some(0.), None, None)?;
+            Ok(SdfNode::RoundBox { pos, half_size: dimensions / 2., radius })
+        }
+        "torus" => {
+            let radius = get_number(json_node, "radius", Some(0.), None, None)?;
+            let radius2 = get_number(json_node, "radius2", Some(0.), None, None)?;
+            Ok(SdfNode::Torus { pos, radius, radius2 })
+        }
+        "capsule" => {
+            let a = get_vec3(json_node, "a", None, None, None)?;
+            let b = get_vec3(json_node, "b", None, None, None)?;
+            let radius = get_number(json_node, "radius", Some(0.), None, None)?;
+            Ok(SdfNode::Capsule { a, b, radius })
+        }
+        _ => Err(format!("Unknown sdf shape: {}", shape)),
+    }
+}
+
 pub fn get_obj(json_obj: &HashMap<String, JsonValue>) -> Result<ComposedElement, String> {
     let pos = get_vec3(&json_obj, "pos", None, None, None)?;
     let dir = get_vec3(&json_obj, "dir", None, None, None)?.normalize();
@@ -289,6 +381,117 @@ pub fn get_obj(json_obj: &HashMap<String, JsonValue>) -> Result<ComposedElement,
     Ok(element)
 }
 
+pub fn get_instance(json_instance: &HashMap<String, JsonValue>, prototypes: &HashMap<String, Arc<Prototype>>) -> Result<Element, String> {
+    let name = get_string(json_instance, "prototype", None)?;
+    let prototype = match prototypes.get(&name) {
+        Some(prototype) => prototype.clone(),
+        None => return Err(format!("The prototype {} must be defined before its instances", name)),
+    };
+    let pos = get_vec3(json_instance, "pos", None, None, Some(Vec3::from_value(0.)))?;
+    let rotation = get_vec3(json_instance, "rotation", None, None, Some(Vec3::from_value(0.)))?;
+    let scale = match json_instance.get("scale") {
+        Some(JsonValue::Number(scale)) => Vec3::from_value(*scale),
+        _ => get_vec3(json_instance, "scale", None, None, Some(Vec3::from_value(1.)))?,
+    };
+    if *scale.x() == 0. || *scale.y() == 0. || *scale.z() == 0. {
+        return Err("The scale of an object can't be 0".to_string());
+    }
+
+    // Without its own material, the instance takes the one of the prototype, the one of a library is set afterwards
+    let material = match json_instance.get("material") {
+        Some(JsonValue::Object(json_material)) => get_material(json_material)?,
+        Some(JsonValue::String(_)) | None => prototype.material().clone(),
+        Some(_) => return Err("The material of an object must be an object or the name of a material".to_string()),
+    };
+
+    let shape = Box::new(Instance::new(prototype, Transform::new(pos, rotation, scale)));
+    let element = Element::new(shape, material);
+    Ok(element)
+}
+
+/**
+ * Optional transform block of an element: either a matrix, or a translation, a rotation (euler angles in degrees or
+ * a quaternion) and a scale, all optional.
+ */
+pub fn get_transform(json_object: &HashMap<String, JsonValue>) -> Result<Option<Transform>, String> {
+    let json_transform = match json_object.get("transform") {
+        Some(JsonValue::Object(json_transform)) => json_transform,
+        Some(_) => return Err("The transform of an object must be an object".to_string()),
+        None => return Ok(None),
+    };
+
+    if let Some(json_matrix) = json_transform.get("matrix") {
+        return Ok(Some(Transform::from_matrix(get_matrix(json_matrix)?)?));
+    }
+
+    let translation = get_vec3(json_transform, "translate", None, None, Some(Vec3::from_value(0.)))?;
+    let scale = match json_transform.get("scale") {
+        Some(JsonValue::Number(scale)) => Vec3::from_value(*scale),
+        _ => get_vec3(json_transform, "scale", None, None, Some(Vec3::from_value(1.)))?,
+    };
+    if *scale.x() == 0. || *scale.y() == 0. || *scale.z() == 0. {
+        return Err("The scale of an object can't be 0".to_string());
+    }
+
+    match json_transform.get("quaternion") {
+        Some(JsonValue::Array(json_quaternion)) => {
+            let components = get_numbers(json_quaternion, "quaternion")?;
+            if components.len() != 4 {
+                return Err("The quaternion of an object must have 4 components".to_string());
+            }
+            if components.iter().all(|component| *component == 0.) {
+                return Err("The quaternion of an object can't be 0".to_string());
+            }
+            let quaternion = Quaternion::new(components[0], components[1], components[2], components[3]);
+            Ok(Some(Transform::from_quaternion(translation, &quaternion, scale)))
+        }
+        Some(_) => Err("The quaternion of an object must be an array".to_string()),
+        None => {
+            let rotation = get_vec3(json_transform, "rotate", None, None, Some(Vec3::from_value(0.)))?;
+            Ok(Some(Transform::new(translation, rotation, scale)))
+        }
+    }
+}
+
+// The matrix is either 4 rows of 4 numbers, or the 16 numbers row by row
+fn get_matrix(json_matrix: &JsonValue) -> Result<[[f64; 4]; 4], String> {
+    let json_rows = match json_matrix {
+        JsonValue::Array(json_rows) => json_rows,
+        _ => return Err("The matrix of an object must be an array".to_string()),
+    };
+    let components = match json_rows.first() {
+        Some(JsonValue::Array(_)) => {
+            let mut components = vec![];
+            for json_row in json_rows {
+                match json_row {
+                    JsonValue::Array(json_row) if json_row.len() == 4 => components.extend(get_numbers(json_row, "matrix")?),
+                    _ => return Err("The rows of the matrix of an object must have 4 numbers".to_string()),
+                }
+            }
+            components
+        }
+        _ => get_numbers(json_rows, "matrix")?,
+    };
+    if components.len() != 16 {
+        return Err("The matrix of an object must have 4 rows of 4 numbers".to_string());
+    }
+
+    let mut matrix = [[0.; 4]; 4];
+    for (index, component) in components.iter().enumerate() {
+        matrix[index / 4][index % 4] = *component;
+    }
+    Ok(matrix)
+}
+
+fn get_numbers(json_numbers: &[JsonValue], key: &str) -> Result<Vec<f64>, String> {
+    json_numbers.iter()
+        .map(|json_number| match json_number {
+            JsonValue::Number(number) => Ok(*number),
+            _ => Err(format!("The {} of an object must only contains number", key)),
+        })
+        .collect()
+}
+
 pub fn get_viewmode(json_obj: &HashMap<String, JsonValue>) -> Result<ViewMode, String> {
     let viewmode = get_string(&json_obj, "value", Some("simple".to_string()))?;
     match viewmode.to_lowercase().as_str() {
@@ -1,148 +1,417 @@
-use std::{collections::HashMap, fs::read_to_string};
+use std::{collections::HashMap, fmt::{self, Display, Formatter}, fs::read_to_string, ops::{Deref, DerefMut}, sync::Arc};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum JsonValue {
     String(String),
     Number(f64),
     Bool(bool),
-    Object(HashMap<String, JsonValue>),
+    Object(JsonObject),
     Array(Vec<JsonValue>),
     Null
 }
 
+/**
+ * Place of a value in the file it was parsed from, written as `file:line:column` in the errors.
+ */
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Span {
+    file: Option<Arc<str>>,
+    line: usize,
+    column: usize,
+}
+
+impl Span {
+    // Constructor
+    pub fn new(file: Option<Arc<str>>, line: usize, column: usize) -> Self {
+        Self { file, line, column }
+    }
+
+    // Accessors
+    pub fn file(&self) -> Option<&str> { self.file.as_deref() }
+    pub fn line(&self) -> usize { self.line }
+    pub fn column(&self) -> usize { self.column }
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{}:{}:{}", file, self.line, self.column),
+            None => write!(f, "{}:{}", self.line, self.column),
+        }
+    }
+}
+
+/**
+ * The keys of an object with their values, and where the object and each of its values start. It can be used as the
+ * map of its values.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct JsonObject {
+    values: HashMap<String, JsonValue>,
+    span: Span,
+    spans: HashMap<String, Span>,
+}
+
+impl JsonObject {
+    // Constructor
+    pub fn new(span: Span) -> Self {
+        Self { values: HashMap::new(), span, spans: HashMap::new() }
+    }
+
+    // Accessors
+    pub fn span(&self) -> &Span { &self.span }
+
+    // The values added after the parsing, like the defaults of a group, are at the place of their object
+    pub fn key_span(&self, key: &str) -> &Span {
+        self.spans.get(key).unwrap_or(&self.span)
+    }
+
+    // Mutators
+    pub fn insert_at(&mut self, key: String, value: JsonValue, span: Span) {
+        self.spans.insert(key.clone(), span);
+        self.values.insert(key, value);
+    }
+
+    // Methods
+    pub fn error(&self, key: &str, msg: &str) -> String {
+        located(self.key_span(key), msg.to_string())
+    }
+
+    /**
+     * Replaces each value by the result of the function, keeping the places of the values.
+     */
+    pub fn try_map_values<F>(self, mut function: F) -> Result<JsonObject, String>
+        where F: FnMut(JsonValue) -> Result<JsonValue, String> {
+        let values = self.values.into_iter()
+            .map(|(key, value)| {
+                let value = function(value).map_err(|err| located(self.spans.get(&key).unwrap_or(&self.span), err))?;
+                Ok((key, value))
+            })
+            .collect::<Result<HashMap<String, JsonValue>, String>>()?;
+        Ok(JsonObject { values, span: self.span, spans: self.spans })
+    }
+}
+
+impl Deref for JsonObject {
+    type Target = HashMap<String, JsonValue>;
+
+    fn deref(&self) -> &Self::Target { &self.values }
+}
+
+impl DerefMut for JsonObject {
+    fn deref_mut(&mut self) -> &mut Self::Target { &mut self.values }
+}
+
+impl From<HashMap<String, JsonValue>> for JsonObject {
+    fn from(values: HashMap<String, JsonValue>) -> Self {
+        Self { values, span: Span::default(), spans: HashMap::new() }
+    }
+}
+
+impl IntoIterator for JsonObject {
+    type Item = (String, JsonValue);
+    type IntoIter = std::collections::hash_map::IntoIter<String, JsonValue>;
+
+    fn into_iter(self) -> Self::IntoIter { self.values.into_iter() }
+}
+
+impl<'a> IntoIterator for &'a JsonObject {
+    type Item = (&'a String, &'a JsonValue);
+    type IntoIter = std::collections::hash_map::Iter<'a, String, JsonValue>;
+
+    fn into_iter(self) -> Self::IntoIter { self.values.iter() }
+}
+
+/**
+ * Puts the place of a value in front of an error about it, unless the error is about a value inside it and already
+ * starts with the place of that one.
+ */
+pub fn located(span: &Span, err: String) -> String {
+    let is_located = err.split_once(": ").is_some_and(|(place, _)| {
+        let mut parts = place.rsplit(':');
+        parts.next().is_some_and(|column| column.parse::<usize>().is_ok())
+            && parts.next().is_some_and(|line| line.parse::<usize>().is_ok())
+    });
+    match is_located {
+        true => err,
+        false => format!("{}: {}", span, err),
+    }
+}
+
+/**
+ * Written back as JSON, for the remote workers to parse the scene once its templates are expanded.
+ */
+impl Display for JsonValue {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            JsonValue::String(string) => write!(f, "\"{}\"", string.replace('\\', "\\\\").replace('"', "\\\"")),
+            JsonValue::Number(number) => write!(f, "{}", number),
+            JsonValue::Bool(boolean) => write!(f, "{}", boolean),
+            JsonValue::Object(object) => {
+                write!(f, "{{")?;
+                for (index, (key, value)) in object.iter().enumerate() {
+                    write!(f, "{}\"{}\": {}", if index > 0 { ", " } else { "" }, key, value)?;
+                }
+                write!(f, "}}")
+            }
+            JsonValue::Array(values) => {
+                write!(f, "[")?;
+                for (index, value) in values.iter().enumerate() {
+                    write!(f, "{}{}", if index > 0 { ", " } else { "" }, value)?;
+                }
+                write!(f, "]")
+            }
+            JsonValue::Null => write!(f, "null"),
+        }
+    }
+}
+
 pub fn parse_json_file(path: &String) -> Result<JsonValue, String> {
     match read_to_string(path) {
-        Ok(mut content) => {
-            remove_json_comments(&mut content);
-            parse_json_content(&content)
+        Ok(content) => {
+            Parser::new(&content, Some(Arc::from(path.as_str()))).parse()
         }
         Err(err) => {
-            Err(format!("Failed to open file: {}", err.to_string()))
-        }
-    }
-}
-
-fn remove_json_comments(content: &mut String) {
-    let mut new_content = String::new();
-    let mut in_string = false;
-    let mut in_comment = false;
-    let mut in_multiline_comment = false;
-    let mut previous_char = '\0';
-    for c in content.chars() {
-        if !in_comment && !in_multiline_comment {
-            new_content.push(c);
-        }
-        if c == '\"' && !in_comment && !in_multiline_comment {
-            in_string = !in_string;
-        } else if previous_char == '/' && c == '/' && !in_string && !in_multiline_comment {
-            in_comment = true;
-            new_content.pop();
-            new_content.pop();
-        } else if c == '\n' && in_comment {
-            in_comment = false;
-        } else if previous_char == '/' && c == '*' && !in_string && !in_comment {
-            in_multiline_comment = true;
-            new_content.pop();
-            new_content.pop();
-        } else if previous_char == '*' && c == '/' && in_multiline_comment {
-            in_multiline_comment = false;
-        }
-        previous_char = c;
-    }
-    *content = new_content
-}
-
-fn parse_json_content(content: &str) -> Result<JsonValue, String> {
-    let content = content.trim();
-    if content.starts_with('{') && content.ends_with('}') {
-        // it's an object
-        let mut object: HashMap<String, JsonValue> = HashMap::new();
-        let inner = content[1 .. content.len() - 1].trim();
-        let pairs = split_at_comma(inner);
-        for pair in pairs {
-            let pair = pair.trim();
-            if pair.len() > 0 {
-                if let Some((key, value_str)) = pair.split_once(':') {
-                    let key = key.trim();
-                    if !key.starts_with('\"') || !key.ends_with('\"') || key.len() < 2 {
-                        return Err(format!("Failed to parse key: {}", key));
-                    }
-                    let key = key[1..key.len()-1].to_string();
-                    match parse_json_content(value_str) {
-                        Ok(json_value) => {
-                            object.insert(key, json_value);
-                        }
-                        Err(err) => {
-                            return Err(err)
-                        }
-                    }
-                } else {
-                    return Err("Failed to parse json".to_string());
-                }
+            Err(format!("Failed to open file {}: {}", path, err))
+        }
+    }
+}
+
+pub fn parse_json(content: String) -> Result<JsonValue, String> {
+    Parser::new(&content, None).parse()
+}
+
+/**
+ * Reads the values one character at a time, skipping the comments, and counts the lines and columns to locate them.
+ */
+struct Parser {
+    chars: Vec<char>,
+    index: usize,
+    file: Option<Arc<str>>,
+    line: usize,
+    column: usize,
+}
+
+impl Parser {
+    // Constructor
+    fn new(content: &str, file: Option<Arc<str>>) -> Self {
+        Self { chars: content.chars().collect(), index: 0, file, line: 1, column: 1 }
+    }
+
+    // Accessors
+    fn span(&self) -> Span {
+        Span::new(self.file.clone(), self.line, self.column)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.index).copied()
+    }
+
+    // Methods
+    fn parse(&mut self) -> Result<JsonValue, String> {
+        let value = self.parse_value()?;
+        self.skip_blanks();
+        match self.peek() {
+            Some(c) => Err(self.error(&format!("unexpected {} after the value", c))),
+            None => Ok(value),
+        }
+    }
+
+    fn error(&self, msg: &str) -> String {
+        format!("{}: Failed to parse json, {}", self.span(), msg)
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.index += 1;
+        match c {
+            '\n' => {
+                self.line += 1;
+                self.column = 1;
             }
+            _ => self.column += 1,
         }
-        return Ok(JsonValue::Object(object));
-    } else if content.starts_with('[') && content.ends_with(']') {
-        // it's an array
-        let mut object: Vec<JsonValue> = Vec::new();
-        let inner = content[1 .. content.len() - 1].trim();
-        let values = split_at_comma(inner);
-        for value in &values {
-            let value = value.trim();
-            if value.len() > 0 {
-                match parse_json_content(value) {
-                    Ok(json_value) => {
-                        object.push(json_value);
+        Some(c)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_blanks();
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.next();
+                Ok(())
+            }
+            Some(c) => Err(self.error(&format!("expected {} instead of {}", expected, c))),
+            None => Err(self.error(&format!("expected {} before the end of the file", expected))),
+        }
+    }
+
+    // Spaces and comments
+    fn skip_blanks(&mut self) {
+        loop {
+            match (self.peek(), self.chars.get(self.index + 1)) {
+                (Some(c), _) if c.is_whitespace() => {
+                    self.next();
+                }
+                (Some('/'), Some('/')) => {
+                    while self.peek().is_some_and(|c| c != '\n') {
+                        self.next();
                     }
-                    Err(err) => {
-                        return Err(err)
+                }
+                (Some('/'), Some('*')) => {
+                    self.next();
+                    self.next();
+                    while self.peek().is_some() && !(self.peek() == Some('*') && self.chars.get(self.index + 1) == Some(&'/')) {
+                        self.next();
                     }
+                    self.next();
+                    self.next();
                 }
+                _ => return,
             }
         }
-        return Ok(JsonValue::Array(object));
-    } else if content.starts_with('\"') && content.ends_with('\"') && content.len() > 1 {
-        // it's a string
-        return Ok(JsonValue::String(content[1..content.len()-1].to_string()));
-    } else if let Ok(value) = content.parse::<f64>() {
-        // it's a number
-        return Ok(JsonValue::Number(value));
-    } else if let Ok(value) = content.parse::<bool>() {
-        // it's a bool
-        return Ok(JsonValue::Bool(value));
-    } else if content == "null" {
-        // it's a null
-        return Ok(JsonValue::Null)
-    }
-    Err("Failed to parse json".to_string())
-}
-
-fn split_at_comma(content: &str) -> Vec<String> {
-    let mut pairs: Vec<String> = Vec::new();
-    let mut token_stack: Vec<char> = Vec::new();
-    let mut last_pair_index = 0;
-    for (i, c) in content.char_indices() {
-        if c == '[' || c == '{' {
-            token_stack.push(c);
-        } else if c == ']' {
-            if let Some(last) = token_stack.last() {
-                if last == &'[' {
-                    token_stack.pop();
+    }
+
+    fn parse_value(&mut self) -> Result<JsonValue, String> {
+        self.skip_blanks();
+        match self.peek() {
+            Some('{') => self.parse_object(),
+            Some('[') => self.parse_array(),
+            Some('"') => Ok(JsonValue::String(self.parse_string()?)),
+            Some(c) if c == '-' || c == '+' || c == '.' || c.is_ascii_digit() => self.parse_number(),
+            Some(c) if c.is_alphabetic() => self.parse_literal(),
+            Some(c) => Err(self.error(&format!("unexpected {}", c))),
+            None => Err(self.error("a value is missing at the end of the file")),
+        }
+    }
+
+    // A comma can follow the last value
+    fn parse_object(&mut self) -> Result<JsonValue, String> {
+        let mut object = JsonObject::new(self.span());
+        self.next();
+        loop {
+            self.skip_blanks();
+            match self.peek() {
+                Some('}') => {
+                    self.next();
+                    return Ok(JsonValue::Object(object));
+                }
+                Some('"') => (),
+                Some(c) => return Err(self.error(&format!("expected a key between quotes instead of {}", c))),
+                None => return Err(self.error("expected } before the end of the file")),
+            }
+            let key = self.parse_string()?;
+            self.expect(':')?;
+            self.skip_blanks();
+            let span = self.span();
+            let value = self.parse_value()?;
+            object.insert_at(key, value, span);
+            self.skip_blanks();
+            match self.peek() {
+                Some(',') => {
+                    self.next();
                 }
+                Some('}') => (),
+                _ => self.expect('}')?,
+            }
+        }
+    }
+
+    fn parse_array(&mut self) -> Result<JsonValue, String> {
+        let mut values = vec![];
+        self.next();
+        loop {
+            self.skip_blanks();
+            if self.peek() == Some(']') {
+                self.next();
+                return Ok(JsonValue::Array(values));
             }
-        } else if c == '}' {
-            if let Some(last) = token_stack.last() {
-                if last == &'{' {
-                    token_stack.pop();
+            values.push(self.parse_value()?);
+            self.skip_blanks();
+            match self.peek() {
+                Some(',') => {
+                    self.next();
                 }
+                Some(']') => (),
+                _ => self.expect(']')?,
             }
-        } else if c == ',' {
-            if token_stack.len() == 0 {
-                pairs.push(content[last_pair_index..i].to_string());
-                last_pair_index = i + 1;
+        }
+    }
+
+    fn parse_string(&mut self) -> Result<String, String> {
+        let start = self.span();
+        let mut string = String::new();
+        self.next();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(string),
+                Some('\\') => match self.next() {
+                    Some('n') => string.push('\n'),
+                    Some('t') => string.push('\t'),
+                    Some(c) => string.push(c),
+                    None => break,
+                },
+                Some(c) => string.push(c),
+                None => break,
             }
         }
+        Err(format!("{}: Failed to parse json, the string is never closed", start))
     }
-    pairs.push(content[last_pair_index..content.len()].to_string());
-    pairs
-}
\ No newline at end of file
+
+    fn parse_number(&mut self) -> Result<JsonValue, String> {
+        let start = self.span();
+        let mut number = String::new();
+        while let Some(c) = self.peek().filter(|c| c.is_ascii_digit() || ['-', '+', '.', 'e', 'E'].contains(c)) {
+            number.push(c);
+            self.next();
+        }
+        match number.parse::<f64>() {
+            Ok(number) => Ok(JsonValue::Number(number)),
+            Err(_) => Err(format!("{}: Failed to parse json, {} is not a number", start, number)),
+        }
+    }
+
+    fn parse_literal(&mut self) -> Result<JsonValue, String> {
+        let start = self.span();
+        let mut literal = String::new();
+        while let Some(c) = self.peek().filter(|c| c.is_alphanumeric() || *c == '_') {
+            literal.push(c);
+            self.next();
+        }
+        match literal.as_str() {
+            "true" => Ok(JsonValue::Bool(true)),
+            "false" => Ok(JsonValue::Bool(false)),
+            "null" => Ok(JsonValue::Null),
+            _ => Err(format!("{}: Failed to parse json, unexpected {}", start, literal)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parsing::get_scene_from_str;
+    use super::{parse_json, JsonValue};
+
+    #[test]
+    fn test_spans() {
+        let content = "[\n  // a comment, with {\n  {\"type\": \"sphere\", \"name\": \"a, b }\",\n   \"radius\": 2},\n]";
+        let JsonValue::Array(objects) = parse_json(content.to_string()).unwrap() else { panic!() };
+        let JsonValue::Object(sphere) = &objects[0] else { panic!() };
+        assert_eq!((sphere.span().line(), sphere.span().column()), (3, 3));
+        assert_eq!((sphere.key_span("radius").line(), sphere.key_span("radius").column()), (4, 14));
+        assert!(matches!(sphere.get("name"), Some(JsonValue::String(name)) if name == "a, b }"));
+
+        let err = parse_json("{\"radius\": 2\n \"pos\": [0, 0, 0]}".to_string()).unwrap_err();
+        assert!(err.starts_with("2:2: "), "{}", err);
+        assert!(parse_json("{\"radius\": tru}".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_object_errors() {
+        let content = "[\n{\"type\": \"group\", \"objects\": [\n  {\"type\": \"sphere\", \"pos\": [0, 0, 0], \"dir\": [0, 1, 0], \"radius\": -1}]}\n]";
+        let err = get_scene_from_str(content).unwrap_err();
+        assert_eq!(err, "3:3: The radius of an object must be greater than 0 (object 0 of type sphere)");
+        let err = get_scene_from_str("[{\"type\": \"group\", \"objects\": []},\n  {\"type\": \"sphre\"}]").unwrap_err();
+        assert_eq!(err, "2:12: Unknown type detected: sphre (object 1 of type sphre)");
+    }
+}
@@ -2,22 +2,56 @@ pub mod json;
 pub mod basic;
 pub mod elements;
 pub mod textures;
+pub mod templates;
+pub mod check;
 
-use elements::{get_ambient, get_any, get_brick, get_camera, get_cone, get_cube, get_cubehole, get_cylinder, get_ellipse, get_helix, get_hyperboloid, get_light, get_mobius, get_nagone, get_obj, get_parallel, get_plane, get_rectangle, get_sphere, get_spot, get_torus, get_torusphere, get_triangle, get_viewmode};
-use json::JsonValue;
-use basic::get_color_texture;
+use elements::{get_ambient, get_material, get_any, get_brick, get_camera, get_cone, get_cube, get_cubehole, get_cylinder, get_ellipse, get_helix, get_hyperboloid, get_instance, get_light, get_mobius, get_nagone, get_obj, get_parallel, get_plane, get_rectangle, get_sdf, get_sphere, get_spot, get_torus, get_torusphere, get_transform, get_triangle, get_viewmode};
+use json::{located, JsonObject, JsonValue, Span};
+use basic::{get_color_texture, get_string};
+use templates::{expand_templates, expand_templates_with_includes};
 use textures::get_texture;
-use crate::{model::{materials::texture::{Texture, TextureType}, scene::Scene}, DEFAULT_SKYBOX_TEXTURE, TEXTURE_FOLDER};
-use std::{collections::HashMap, io::{stdout, Write}};
+use crate::{model::{materials::texture::{Texture, TextureType}, group::Group, maths::transform::Transform, prototype::Prototype, scene::Scene}, DEFAULT_SKYBOX_TEXTURE, TEXTURE_FOLDER};
+use std::{collections::HashMap, io::{stdout, Write}, path::Path};
 
 pub fn print_scene(scene: &Scene) {
     write!(stdout(), "{:#?}\n", scene).expect("Error printing scene");
 }
 
-fn match_object(scene: &mut Scene, object: HashMap<String, JsonValue>) -> Result<(), String>
+/**
+ * Parses an object of the scene. Its errors start with the place of the object in its file, and end with its index
+ * in its array and its type. The objects of a group name themselves in their errors.
+ */
+fn match_object(scene: &mut Scene, object: JsonObject, index: usize) -> Result<(), String> {
+    let span = object.span().clone();
+    let object_type = match object.get("type") {
+        Some(JsonValue::String(object_type)) => Some(object_type.clone()),
+        _ => None,
+    };
+    match object_type.as_deref() {
+        Some("group") => get_group(scene, &object, index, None, &HashMap::new()),
+        _ => parse_object(scene, object).map_err(|err| object_error(&span, object_type.as_deref(), index, err)),
+    }
+}
+
+fn object_error(span: &Span, object_type: Option<&str>, index: usize, err: String) -> String {
+    match object_type {
+        Some(object_type) => format!("{} (object {} of type {})", located(span, err), index, object_type),
+        None => format!("{} (object {})", located(span, err), index),
+    }
+}
+
+fn parse_object(scene: &mut Scene, mut object: JsonObject) -> Result<(), String>
 {
+    // A shape with a material of the library doesn't need a color, the material replaces its own
+    if let Some(JsonValue::String(_)) = object.get("material") {
+        object.entry("color".to_string()).or_insert(JsonValue::Array(vec![JsonValue::Number(0.); 3]));
+    }
     if let Some(object_type) = object.get("type") {
         if let JsonValue::String(object_type) = object_type {
+            let transform = get_transform(&object)?;
+            let first_element = scene.elements().len();
+            let first_composed_element = scene.composed_elements().len();
+            let first_light = scene.lights().len();
             match object_type.as_str() {
                 "skybox" => {
                     let skybox_texture = get_color_texture(&object)?;
@@ -130,23 +164,45 @@ fn match_object(scene: &mut Scene, object: HashMap<String, JsonValue>) -> Result
                     scene.load_material_textures(any.material());
                     scene.add_element(any);
                 }
+                "sdf" => {
+                    let sdf = get_sdf(&object)?;
+                    scene.load_material_textures(sdf.material());
+                    scene.add_element(sdf);
+                }
                 "obj" => {
                     let obj = get_obj(&object)?;
                     scene.load_material_textures(obj.material());
                     scene.add_composed_element(obj);
                 }
+                "prototype" => {
+                    let prototype = get_prototype(scene, &object)?;
+                    scene.add_prototype(prototype);
+                }
+                "instance" => {
+                    let instance = get_instance(&object, scene.prototypes())?;
+                    scene.load_material_textures(instance.material());
+                    scene.add_element(instance);
+                }
                 "texture" => {
                     let (name, img) = get_texture(&object)?;
                     scene.load_texture(&name, Some(img));
                 }
+                "materials" => {
+                    get_materials(scene, &object)?;
+                }
                 "viewmode" => {
                     let viewmode = get_viewmode(&object)?;
                     scene.settings_mut().view_mode = viewmode;
                 }
                 _ => {
-                    return Err(format!("Unknown type detected: {}", object_type));
+                    return Err(object.error("type", &format!("Unknown type detected: {}", object_type)));
                 }
             }
+            if let Some(transform) = transform {
+                set_transform(scene, transform, first_element, first_composed_element);
+            }
+            set_library_material(scene, &object, first_element, first_composed_element)?;
+            set_name(scene, &object, object_type, first_element, first_composed_element, first_light)?;
             return Ok(());
         } else {
             return Err("The type of an object must be a string !".to_string());
@@ -156,12 +212,238 @@ fn match_object(scene: &mut Scene, object: HashMap<String, JsonValue>) -> Result
     }
 }
 
+/**
+ * Types of objects that can be added from the UI, with default values, in the order of their menus.
+ */
+pub const SHAPE_TYPES: [&str; 14] = [
+    "sphere", "plane", "cylinder", "cone", "triangle", "rectangle", "torus", "ellipse", "cube", "cubehole",
+    "hyperboloid", "any", "sdf", "instance"
+];
+pub const COMPOSED_SHAPE_TYPES: [&str; 6] = ["torusphere", "helix", "brick", "nagone", "mobius", "obj"];
+pub const LIGHT_TYPES: [&str; 3] = ["light", "parallel", "spot"];
+
+/**
+ * Adds an object of one of the types above, parsed like in a scene file from a template put in front of the camera.
+ * An instance is made of the first prototype of the scene.
+ */
+pub fn add_object(scene: &mut Scene, object_type: &str) -> Result<(), String> {
+    let pos = *scene.camera().pos() + *scene.camera().dir() * 5.;
+    let pos = format!("[{}, {}, {}]", pos.x(), pos.y(), pos.z());
+    let material = "\"color\": [200, 200, 200]";
+    let template = match object_type {
+        "light" => format!("\"pos\": {}, \"intensity\": 1, \"color\": [255, 255, 255]", pos),
+        "parallel" => "\"dir\": [0, -1, 0], \"intensity\": 1, \"color\": [255, 255, 255]".to_string(),
+        "spot" => format!("\"pos\": {}, \"dir\": [0, -1, 0], \"intensity\": 1, \"fov\": 45, \"color\": [255, 255, 255]", pos),
+        "sphere" => format!("\"pos\": {}, \"dir\": [0, 1, 0], \"radius\": 1, {}", pos, material),
+        "plane" => format!("\"pos\": {}, \"dir\": [0, 1, 0], {}", pos, material),
+        "cylinder" => format!("\"pos\": {}, \"dir\": [0, 1, 0], \"radius\": 0.5, {}", pos, material),
+        "cone" => format!("\"pos\": {}, \"dir\": [0, 1, 0], \"angle\": 30, {}", pos, material),
+        "triangle" => format!("\"a\": [-1, 0, 0], \"b\": [1, 0, 0], \"c\": [0, 1.5, 0], \"transform\": {{\"translate\": {}}}, {}", pos, material),
+        "rectangle" => format!("\"pos\": {}, \"length\": 2, \"width\": 1, \"dir_l\": [1, 0, 0], \"dir_w\": [0, 1, 0], {}", pos, material),
+        "torus" => format!("\"pos\": {}, \"dir\": [0, 1, 0], \"radius\": 1, \"radius2\": 0.3, {}", pos, material),
+        "ellipse" => format!("\"pos\": {}, \"dir\": [0, 0, 1], \"u\": 1.5, \"v\": 1, {}", pos, material),
+        "cube" | "cubehole" => format!("\"pos\": {}, \"dir\": [0, 1, 0], \"width\": 1, {}", pos, material),
+        "hyperboloid" => format!("\"pos\": {}, \"z_shift\": 0.5, {}", pos, material),
+        "any" => format!("\"equation\": \"x^2 + y^2 + z^2 - 1\", \"bounds_min\": [-1.5, -1.5, -1.5], \"bounds_max\": [1.5, 1.5, 1.5], \"transform\": {{\"translate\": {}}}, {}", pos, material),
+        "sdf" => format!("\"pos\": {}, \"sdf\": {{\"shape\": \"sphere\", \"radius\": 1}}, {}", pos, material),
+        "instance" => {
+            let name = scene.prototypes().keys().min().ok_or("The scene has no prototype to instantiate")?;
+            format!("\"prototype\": \"{}\", \"pos\": {}", name, pos)
+        }
+        "torusphere" => format!("\"pos\": {}, \"dir\": [0, 1, 0], \"radius\": 1, \"steps\": 12, {}", pos, material),
+        "helix" => format!("\"pos\": {}, \"dir\": [0, 1, 0], \"height\": 2, {}", pos, material),
+        "brick" => format!("\"pos\": {}, \"dir\": [0, 1, 0], \"dimensions\": [1, 0.5, 0.5], {}", pos, material),
+        "nagone" => format!("\"pos\": {}, \"dir\": [0, 0, 1], \"radius\": 1, \"angles\": 6, {}", pos, material),
+        "mobius" => format!("\"pos\": {}, \"radius\": 1, \"half_width\": 0.3, {}", pos, material),
+        "obj" => format!("\"pos\": {}, \"dir\": [0, 1, 0], \"file\": \"obj/cube.obj\", {}", pos, material),
+        _ => return Err(format!("Unknown type detected: {}", object_type)),
+    };
+    let object = match json::parse_json(format!("{{\"type\": \"{}\", {}}}", object_type, template))? {
+        JsonValue::Object(object) => object,
+        _ => return Err(format!("The template of {} must be an object", object_type)),
+    };
+    parse_object(scene, object)?;
+    scene.update_bvh();
+    Ok(())
+}
+
+// Every element added by the object gets the transform
+fn set_transform(scene: &mut Scene, transform: Transform, first_element: usize, first_composed_element: usize) {
+    for composed_element in scene.composed_elements_as_mut().iter_mut().skip(first_composed_element) {
+        composed_element.set_transform(Some(transform.clone()));
+    }
+    for element in scene.elements_as_mut().iter_mut().skip(first_element) {
+        element.set_transform(Some(transform.clone()));
+    }
+}
+
+/**
+ * The materials of the library are named by the keys of its object, and defined like the ones of the objects. They
+ * must be defined before the objects using them.
+ */
+fn get_materials(scene: &mut Scene, json_materials: &JsonObject) -> Result<(), String> {
+    let json_materials = match json_materials.get("materials") {
+        Some(JsonValue::Object(json_materials)) => json_materials,
+        Some(_) => return Err("The materials of a library must be an object".to_string()),
+        None => return Err("The materials of a library are missing".to_string()),
+    };
+    for (name, json_material) in json_materials {
+        if scene.material(name).is_some() {
+            return Err(json_materials.error(name, &format!("The material {} is defined twice", name)));
+        }
+        let material = match json_material {
+            JsonValue::Object(json_material) => get_material(json_material).map_err(|err| located(json_material.span(), err))?,
+            _ => return Err(json_materials.error(name, &format!("The material {} must be an object", name))),
+        };
+        scene.load_material_textures(&material);
+        scene.add_material(name, material);
+    }
+    Ok(())
+}
+
+// A shape can take a material of the library instead of its own
+fn set_library_material(scene: &mut Scene, object: &JsonObject, first_element: usize, first_composed_element: usize) -> Result<(), String> {
+    let name = match object.get("material") {
+        Some(JsonValue::String(name)) => name,
+        _ => return Ok(()),
+    };
+    let material = match scene.material(name) {
+        Some(material) => material.clone(),
+        None => return Err(object.error("material", &format!("The material {} must be defined in a library before its objects", name))),
+    };
+    for composed_id in first_composed_element..scene.composed_elements().len() {
+        *scene.composed_elements_as_mut()[composed_id].material_mut() = material.clone();
+        scene.update_composed_element_material(composed_id);
+    }
+    for element in scene.elements_as_mut().iter_mut().skip(first_element) {
+        element.set_material(material.clone());
+    }
+    Ok(())
+}
+
+// The prototypes and the textures use their name for themselves, the other objects don't have one
+fn set_name(scene: &mut Scene, object: &JsonObject, object_type: &str, first_element: usize, first_composed_element: usize, first_light: usize) -> Result<(), String> {
+    if !object.contains_key("name") || !SHAPE_TYPES.iter().chain(&COMPOSED_SHAPE_TYPES).chain(&LIGHT_TYPES).any(|shape_type| *shape_type == object_type) {
+        return Ok(());
+    }
+    let name = get_string(object, "name", None)?;
+    if scene.is_name_used(&name) {
+        return Err(object.error("name", &format!("The name {} is given to two objects", name)));
+    }
+    if let Some(light) = scene.lights_as_mut().get_mut(first_light) {
+        light.set_name(Some(name));
+    } else if let Some(composed_element) = scene.composed_elements_as_mut().get_mut(first_composed_element) {
+        composed_element.set_name(Some(name));
+    } else if let Some(element) = scene.elements_as_mut().get_mut(first_element) {
+        element.set_name(Some(name));
+    }
+    Ok(())
+}
+
+/**
+ * The objects of a group are parsed like the ones of the scene, then put in the group. Its material is a set of
+ * default values for the keys that its objects (and the ones of its subgroups) don't define.
+ */
+fn get_group(scene: &mut Scene, json_group: &JsonObject, index: usize, parent_id: Option<usize>, defaults: &HashMap<String, JsonValue>) -> Result<(), String> {
+    let group_error = |err: String| object_error(json_group.span(), Some("group"), index, err);
+    let transform = get_transform(json_group).map_err(group_error)?;
+    let mut defaults = defaults.clone();
+    match json_group.get("material") {
+        Some(JsonValue::Object(json_material)) => {
+            defaults.remove("material");
+            defaults.extend(json_material.clone());
+        }
+        // The objects of the group take the material of the library
+        Some(JsonValue::String(name)) => {
+            defaults.insert("material".to_string(), JsonValue::String(name.clone()));
+        }
+        Some(_) => return Err(group_error("The material of a group must be an object or the name of a material".to_string())),
+        None => (),
+    }
+    let json_objects = match json_group.get("objects") {
+        Some(JsonValue::Array(json_objects)) => json_objects,
+        Some(_) => return Err(group_error("The objects of a group must be an array".to_string())),
+        None => return Err(group_error("The objects of a group are missing".to_string())),
+    };
+
+    let group_id = scene.groups().len();
+    let name = get_string(json_group, "name", Some(format!("Group {}", group_id))).map_err(group_error)?;
+    scene.add_group(Group::new(name, parent_id, transform));
+
+    for (index, json_object) in json_objects.iter().enumerate() {
+        let mut json_object = match json_object {
+            JsonValue::Object(json_object) => json_object.clone(),
+            _ => return Err(group_error("The objects of a group must be objects".to_string())),
+        };
+        let object_type = match json_object.get("type") {
+            Some(JsonValue::String(object_type)) => object_type.clone(),
+            _ => String::new(),
+        };
+        if object_type == "group" {
+            get_group(scene, &json_object, index, Some(group_id), &defaults)?;
+            continue;
+        }
+        // Only the shapes have a material, the color of the defaults would become the one of a light
+        if SHAPE_TYPES.iter().chain(&COMPOSED_SHAPE_TYPES).any(|shape_type| *shape_type == object_type) {
+            for (key, value) in &defaults {
+                json_object.entry(key.clone()).or_insert_with(|| value.clone());
+            }
+        }
+
+        let first_element = scene.elements().len();
+        let first_composed_element = scene.composed_elements().len();
+        let first_light = scene.lights().len();
+        match_object(scene, json_object, index)?;
+        scene.set_group_from(group_id, first_element, first_composed_element, first_light);
+    }
+    Ok(())
+}
+
+/**
+ * The object of a prototype is parsed like any other object, but in a scene of its own so that it isn't rendered.
+ */
+fn get_prototype(scene: &mut Scene, json_prototype: &JsonObject) -> Result<Prototype, String> {
+    let name = get_string(json_prototype, "name", None)?;
+    if scene.prototype(&name).is_some() {
+        return Err(format!("The prototype {} is defined twice", name));
+    }
+    let json_object = match json_prototype.get("object") {
+        Some(JsonValue::Object(json_object)) => json_object.clone(),
+        Some(_) => return Err("The object of a prototype must be an object".to_string()),
+        None => return Err("The object of a prototype is missing".to_string()),
+    };
+
+    let mut prototype_scene = Scene::new();
+    for (name, prototype) in scene.prototypes() {
+        prototype_scene.add_shared_prototype(name, prototype.clone());
+    }
+    for (name, material) in scene.materials() {
+        prototype_scene.add_material(name, (*material).clone());
+    }
+    let span = json_object.span().clone();
+    parse_object(&mut prototype_scene, json_object).map_err(|err| located(&span, err))?;
+    for (texture_name, img) in prototype_scene.textures() {
+        scene.load_texture(texture_name, Some(img.clone()));
+    }
+
+    let material = match (prototype_scene.composed_elements().first(), prototype_scene.elements().first()) {
+        (Some(composed_element), _) => (*composed_element.material()).clone(),
+        (None, Some(element)) => (*element.material()).clone(),
+        (None, None) => return Err(format!("The object of the prototype {} must be a shape", name)),
+    };
+    let elements = std::mem::take(prototype_scene.elements_as_mut());
+    Ok(Prototype::new(name, elements, material))
+}
+
 pub fn parse_scene_content(scene: &mut Scene, scene_content: JsonValue) -> Result<(), String> {
     if let JsonValue::Object(json_object) = scene_content {
-        return match_object(scene, json_object);
+        match_object(scene, json_object, 0)
     } else if let JsonValue::Array(objects) = scene_content {
-        for object in objects {
-            parse_scene_content(scene, object)?;
+        for (index, object) in objects.into_iter().enumerate() {
+            match object {
+                JsonValue::Object(json_object) => match_object(scene, json_object, index)?,
+                object => parse_scene_content(scene, object)?,
+            }
         }
         return Ok(());
     } else {
@@ -170,23 +452,47 @@ pub fn parse_scene_content(scene: &mut Scene, scene_content: JsonValue) -> Resul
 }
 
 pub fn get_scene(scene_file: &String) -> Result<Scene, String> {
+    let folder = Path::new(scene_file).parent().unwrap_or(Path::new("."));
+    let mut includes = vec![];
+    let json_value = expand_templates_with_includes(json::parse_json_file(scene_file)?, folder, &mut includes)?;
+    let mut scene = scene_from_json(json_value)?;
+    scene.set_path(Some(scene_file.clone()));
+    scene.set_includes(includes.iter().map(|path| path.to_string_lossy().to_string()).collect());
+    Ok(scene)
+}
+
+/**
+ * Files included by a scene file, including the ones reached before an error of its templates.
+ */
+pub fn get_scene_includes(scene_file: &String) -> Vec<String> {
+    let folder = Path::new(scene_file).parent().unwrap_or(Path::new("."));
+    let mut includes = vec![];
+    if let Ok(json_value) = json::parse_json_file(scene_file) {
+        let _ = expand_templates_with_includes(json_value, folder, &mut includes);
+    }
+    includes.iter().map(|path| path.to_string_lossy().to_string()).collect()
+}
+
+/**
+ * Same as get_scene, from the content of a scene file. Its includes are relative to the current directory.
+ */
+pub fn get_scene_from_str(scene_content: &str) -> Result<Scene, String> {
+    scene_from_json(expand_templates(json::parse_json(scene_content.to_string())?, Path::new("."))?)
+}
+
+/**
+ * Same as get_scene, from the objects of a scene file once its templates are expanded.
+ */
+pub fn scene_from_json(json_value: JsonValue) -> Result<Scene, String> {
+    let mut scene = new_scene();
+    parse_scene_content(&mut scene, json_value)?;
+    Ok(scene)
+}
+
+fn new_scene() -> Scene {
     let mut scene = Scene::new();
     let default_skybox = format!("{}/{}", TEXTURE_FOLDER, DEFAULT_SKYBOX_TEXTURE).to_string();
     scene.load_texture(&default_skybox, None);
     scene.set_skybox(Texture::Texture(default_skybox, TextureType::Color));
-    match json::parse_json_file(scene_file) {
-        Ok(json_value) => {
-            match parse_scene_content(&mut scene, json_value) {
-                Ok(_) => {
-                    return Ok(scene);
-                }
-                Err(err) => {
-                    return Err(err);
-                }
-            }
-        }
-        Err(err) => {
-            return Err(err);
-        }
-    }
-}
\ No newline at end of file
+    scene
+}
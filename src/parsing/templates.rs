@@ -0,0 +1,275 @@
+use std::{cell::RefCell, collections::HashMap, path::{Path, PathBuf}, rc::Rc};
+
+use meval::{Context, Expr};
+use super::json::{parse_json_file, JsonObject, JsonValue};
+
+/**
+ * Variables of the objects being expanded, and the files included to reach them. Every file included so far is shared
+ * by the scopes.
+ */
+#[derive(Clone)]
+struct Scope {
+    variables: HashMap<String, f64>,
+    folder: PathBuf,
+    includes: Vec<PathBuf>,
+    included: Rc<RefCell<Vec<PathBuf>>>,
+}
+
+/**
+ * Expands the templates of a scene file before its objects are parsed. In the arrays of objects:
+ * - `{"include": "file.json"}` is replaced by the objects of the file, relative to the one including it
+ * - `{"define": {"size": 2, "half": "= size / 2"}}` defines variables for the next objects of the array
+ * - `{"repeat": {"index": "i", "from": 0, "to": 8, "step": 1}, "object": {...}}` adds the object once per index
+ *
+ * A string starting with `=` is an expression of the variables, replaced by its value, and the variables between
+ * braces in the other strings are replaced by their value, like in `"name": "pawn {i}"`.
+ */
+pub fn expand_templates(json_value: JsonValue, folder: &Path) -> Result<JsonValue, String> {
+    expand_templates_with_includes(json_value, folder, &mut vec![])
+}
+
+/**
+ * Same as expand_templates, and gives the files included, once each. They are given after an error too, with the
+ * file that couldn't be opened or parsed, so that the window can wait for them to be fixed.
+ */
+pub fn expand_templates_with_includes(json_value: JsonValue, folder: &Path, included: &mut Vec<PathBuf>) -> Result<JsonValue, String> {
+    let shared_included = Rc::new(RefCell::new(vec![]));
+    let scope = Scope { variables: HashMap::new(), folder: folder.to_path_buf(), includes: vec![], included: shared_included.clone() };
+    let objects = expand_objects(json_value, &scope);
+    *included = shared_included.take();
+    let mut objects = objects?;
+    let json_value = match objects.len() {
+        1 => objects.remove(0),
+        _ => JsonValue::Array(objects),
+    };
+    Ok(json_value)
+}
+
+fn expand_value(json_value: JsonValue, scope: &Scope) -> Result<JsonValue, String> {
+    match json_value {
+        JsonValue::String(string) => match string.strip_prefix('=') {
+            Some(expression) => Ok(JsonValue::Number(evaluate(expression, &scope.variables)?)),
+            None => Ok(JsonValue::String(interpolate(&string, &scope.variables))),
+        },
+        JsonValue::Array(values) => Ok(JsonValue::Array(expand_array(values, scope)?)),
+        JsonValue::Object(object) => Ok(JsonValue::Object(object.try_map_values(|value| expand_value(value, scope))?)),
+        json_value => Ok(json_value),
+    }
+}
+
+// The objects of an array are put in the one around it, so that they can be in a group
+fn expand_objects(json_value: JsonValue, scope: &Scope) -> Result<Vec<JsonValue>, String> {
+    match json_value {
+        JsonValue::Array(values) => expand_array(values, scope),
+        json_value => expand_array(vec![json_value], scope),
+    }
+}
+
+fn expand_array(values: Vec<JsonValue>, scope: &Scope) -> Result<Vec<JsonValue>, String> {
+    let mut scope = scope.clone();
+    let mut expanded = vec![];
+    for value in values {
+        let object = match value {
+            JsonValue::Object(object) if !object.contains_key("type") => object,
+            value => {
+                expanded.push(expand_value(value, &scope)?);
+                continue;
+            }
+        };
+        if let Some(definitions) = object.get("define") {
+            define(definitions, &mut scope.variables).map_err(|err| object.error("define", &err))?;
+        } else if let Some(path) = object.get("include") {
+            expanded.extend(include(path, &scope).map_err(|err| object.error("include", &err))?);
+        } else if let Some(range) = object.get("repeat") {
+            expanded.extend(repeat(range, &object, &scope).map_err(|err| object.error("repeat", &err))?);
+        } else {
+            expanded.push(expand_value(JsonValue::Object(object), &scope)?);
+        }
+    }
+    Ok(expanded)
+}
+
+// The variables can depend on each other in the same block, whatever their order
+fn define(definitions: &JsonValue, variables: &mut HashMap<String, f64>) -> Result<(), String> {
+    let JsonValue::Object(definitions) = definitions else {
+        return Err("The variables of a define must be an object".to_string());
+    };
+    let mut remaining: Vec<(&String, &JsonValue)> = definitions.iter().collect();
+    while !remaining.is_empty() {
+        let mut error = None;
+        let count = remaining.len();
+        remaining.retain(|(name, value)| {
+            let result = match value {
+                JsonValue::Number(number) => Ok(*number),
+                JsonValue::String(expression) => evaluate(expression.strip_prefix('=').unwrap_or(expression), variables),
+                _ => Err(format!("The variable {} must be a number or an expression", name)),
+            };
+            match result {
+                Ok(number) => {
+                    variables.insert(name.to_string(), number);
+                    false
+                }
+                Err(err) => {
+                    error = Some(err);
+                    true
+                }
+            }
+        });
+        if remaining.len() == count {
+            return Err(error.unwrap_or_default());
+        }
+    }
+    Ok(())
+}
+
+fn include(path: &JsonValue, scope: &Scope) -> Result<Vec<JsonValue>, String> {
+    let JsonValue::String(path) = path else {
+        return Err("The file of an include must be a string".to_string());
+    };
+    let path = scope.folder.join(interpolate(path, &scope.variables));
+    let record = |path: &PathBuf| {
+        if !scope.included.borrow().contains(path) {
+            scope.included.borrow_mut().push(path.clone());
+        }
+    };
+    let path = match path.canonicalize() {
+        Ok(path) => path,
+        Err(err) => {
+            record(&path);
+            return Err(format!("Failed to open file {}: {}", path.display(), err));
+        }
+    };
+    if scope.includes.contains(&path) {
+        return Err(format!("The file {} includes itself", path.display()));
+    }
+    record(&path);
+    let json_value = parse_json_file(&path.to_string_lossy().to_string())?;
+    let mut included_scope = scope.clone();
+    included_scope.folder = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    included_scope.includes.push(path);
+    expand_objects(json_value, &included_scope)
+}
+
+fn repeat(range: &JsonValue, object: &JsonObject, scope: &Scope) -> Result<Vec<JsonValue>, String> {
+    let JsonValue::Object(range) = range else {
+        return Err("The range of a repeat must be an object".to_string());
+    };
+    let index = match range.get("index") {
+        Some(JsonValue::String(index)) => index.clone(),
+        Some(_) => return Err("The index of a repeat must be a string".to_string()),
+        None => "i".to_string(),
+    };
+    let bound = |key: &str, default: Option<f64>| match (range.get(key).cloned().map(|value| expand_value(value, scope)), default) {
+        (Some(Ok(JsonValue::Number(number))), _) => Ok(number),
+        (Some(Err(err)), _) => Err(err),
+        (Some(_), _) => Err(format!("The {} of a repeat must be a number or an expression", key)),
+        (None, Some(default)) => Ok(default),
+        (None, None) => Err(format!("The {} of a repeat is missing", key)),
+    };
+    let (from, to, step) = (bound("from", Some(0.))?, bound("to", None)?, bound("step", Some(1.))?);
+    if step <= 0. {
+        return Err("The step of a repeat must be greater than 0".to_string());
+    }
+    let template = object.get("object").ok_or("The object of a repeat is missing")?;
+
+    let mut expanded = vec![];
+    let mut scope = scope.clone();
+    let count = ((to - from) / step).ceil().max(0.) as usize;
+    for n in 0..count {
+        scope.variables.insert(index.clone(), from + n as f64 * step);
+        expanded.extend(expand_objects(template.clone(), &scope)?);
+    }
+    Ok(expanded)
+}
+
+fn evaluate(expression: &str, variables: &HashMap<String, f64>) -> Result<f64, String> {
+    let expr = expression.parse::<Expr>().map_err(|err| format!("The expression {} is invalid: {}", expression, err))?;
+    expr.eval_with_context((variables, Context::new()))
+        .map_err(|err| format!("The expression {} is invalid: {}", expression, err))
+}
+
+// Replaces the known variables between braces, the other braces are left as they are
+fn interpolate(string: &str, variables: &HashMap<String, f64>) -> String {
+    let mut result = String::new();
+    let mut rest = string;
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let value = rest.find('}').and_then(|end| variables.get(&rest[1..end]).map(|value| (end, value)));
+        match value {
+            Some((end, value)) => {
+                result.push_str(&value.to_string());
+                rest = &rest[end + 1..];
+            }
+            None => {
+                result.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::parsing::json::{parse_json, JsonValue};
+    use super::{expand_templates, expand_templates_with_includes};
+
+    fn expand(content: &str) -> Result<Vec<JsonValue>, String> {
+        match expand_templates(parse_json(content.to_string())?, Path::new("."))? {
+            JsonValue::Array(values) => Ok(values),
+            value => Ok(vec![value]),
+        }
+    }
+
+    #[test]
+    fn test_define_and_repeat() {
+        let objects = expand(r#"[
+            {"define": {"half": "= size / 2", "size": 4}},
+            {"repeat": {"index": "k", "from": 1, "to": "= size"}, "object": {"type": "sphere", "name": "ball {k}", "radius": "= half * k"}},
+            {"type": "group", "objects": [{"define": {"size": 1}}, {"type": "cube", "width": "= size"}]},
+            {"type": "cube", "width": "= size"}
+        ]"#).unwrap();
+        assert_eq!(objects.len(), 5);
+        let JsonValue::Object(last_ball) = &objects[2] else { panic!() };
+        assert!(matches!(last_ball.get("radius"), Some(JsonValue::Number(radius)) if *radius == 6.));
+        assert!(matches!(last_ball.get("name"), Some(JsonValue::String(name)) if name == "ball 3"));
+        // The variables of a group stay in it
+        let JsonValue::Object(cube) = &objects[4] else { panic!() };
+        assert!(matches!(cube.get("width"), Some(JsonValue::Number(width)) if *width == 4.));
+
+        assert!(expand(r#"[{"type": "cube", "width": "= unknown * 2"}]"#).is_err());
+        assert!(expand(r#"[{"define": {"a": "= b", "b": "= a"}}]"#).is_err());
+    }
+
+    #[test]
+    fn test_include() {
+        let folder = std::env::temp_dir().join(format!("rt_include_{}", std::process::id()));
+        std::fs::create_dir_all(folder.join("parts")).unwrap();
+        std::fs::write(folder.join("parts/balls.json"), r#"[{"type": "sphere", "radius": "= size"}, {"include": "../loop.json"}]"#).unwrap();
+        std::fs::write(folder.join("loop.json"), r#"{"include": "parts/balls.json"}"#).unwrap();
+
+        let content = r#"[{"define": {"size": 3}}, {"include": "parts/balls.json"}]"#;
+        let mut included = vec![];
+        let result = expand_templates_with_includes(parse_json(content.to_string()).unwrap(), &folder, &mut included);
+        // The included file includes itself through another one, the files reached before the error are given anyway
+        assert!(result.is_err_and(|err| err.contains("includes itself")));
+        assert_eq!(included.len(), 2);
+        // A file that doesn't exist yet is given too, it's watched until it's created
+        let result = expand_templates_with_includes(parse_json(r#"{"include": "new.json"}"#.to_string()).unwrap(), &folder, &mut included);
+        assert!(result.is_err());
+        assert_eq!(included, vec![folder.join("new.json")]);
+
+        std::fs::write(folder.join("loop.json"), r#"[]"#).unwrap();
+        let result = expand_templates_with_includes(parse_json(content.to_string()).unwrap(), &folder, &mut included).unwrap();
+        std::fs::remove_dir_all(&folder).unwrap();
+        // Each included file is listed once, to be watched by the window
+        assert_eq!(included.len(), 2);
+        assert!(included.iter().any(|path| path.ends_with("parts/balls.json")));
+        // The define only leaves the ball of the included file
+        let JsonValue::Object(ball) = result else { panic!() };
+        assert!(matches!(ball.get("radius"), Some(JsonValue::Number(radius)) if *radius == 3.));
+    }
+}
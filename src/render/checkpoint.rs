@@ -0,0 +1,130 @@
+use std::{
+    fmt::Write as _,
+    fs::{self, File},
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf}
+};
+
+use crate::{model::{materials::color::Color, scene::Fnv1a}, CHECKPOINT_FOLDER, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+const MAGIC: &[u8; 4] = b"RTC1";
+
+/**
+ * State of a HighDef render between two iterations: every pixel of the image is the average of iterations_done samples.
+ * No seed is kept: the samples are drawn from the unseeded thread_rng, so a seed couldn't replay them, and the next
+ * iterations only need to add new samples to the average.
+ */
+pub struct Checkpoint {
+    pub scene_hash: u64,
+    pub iterations_done: usize,
+    pub img: Vec<Vec<Color>>,
+}
+
+/**
+ * Where the checkpoints of the render of a scene file are written. The name of the file is followed by a hash of its
+ * full path, so that two scenes with the same name in different folders don't share their checkpoint.
+ */
+pub fn checkpoint_path(scene_path: &str) -> PathBuf {
+    let path = Path::new(scene_path);
+    let name = path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+    let full_path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut hasher = Fnv1a::new();
+    write!(hasher, "{}", full_path.display()).unwrap();
+    Path::new(CHECKPOINT_FOLDER).join(format!("{}-{:016x}.checkpoint", name, hasher.0))
+}
+
+/**
+ * Writes the checkpoint next to its destination first, so that a render stopped while writing keeps its last checkpoint.
+ */
+pub fn write_checkpoint(path: &Path, scene_hash: u64, iterations_done: usize, img: &[Vec<Color>]) -> Result<(), String> {
+    let error = |err: std::io::Error| format!("Can't write the checkpoint {}: {}", path.display(), err);
+    if let Some(folder) = path.parent() {
+        fs::create_dir_all(folder).map_err(error)?;
+    }
+    let tmp_path = path.with_extension("tmp");
+    let mut writer = BufWriter::new(File::create(&tmp_path).map_err(error)?);
+    writer.write_all(MAGIC).map_err(error)?;
+    for value in [scene_hash, iterations_done as u64, SCREEN_WIDTH as u64, SCREEN_HEIGHT as u64] {
+        writer.write_all(&value.to_le_bytes()).map_err(error)?;
+    }
+    for column in img {
+        for color in column {
+            for value in [color.r(), color.g(), color.b()] {
+                writer.write_all(&value.to_le_bytes()).map_err(error)?;
+            }
+        }
+    }
+    writer.flush().map_err(error)?;
+    drop(writer);
+    fs::rename(&tmp_path, path).map_err(error)
+}
+
+pub fn read_checkpoint(path: &Path) -> Result<Checkpoint, String> {
+    let error = |err: std::io::Error| format!("Can't read the checkpoint {}: {}", path.display(), err);
+    let mut reader = BufReader::new(File::open(path).map_err(error)?);
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic).map_err(error)?;
+    if &magic != MAGIC {
+        return Err(format!("{} is not a checkpoint", path.display()));
+    }
+    let read_u64 = |reader: &mut BufReader<File>| -> Result<u64, String> {
+        let mut bytes = [0u8; 8];
+        reader.read_exact(&mut bytes).map_err(error)?;
+        Ok(u64::from_le_bytes(bytes))
+    };
+    let scene_hash = read_u64(&mut reader)?;
+    let iterations_done = read_u64(&mut reader)? as usize;
+    if read_u64(&mut reader)? != SCREEN_WIDTH as u64 || read_u64(&mut reader)? != SCREEN_HEIGHT as u64 {
+        return Err(format!("The checkpoint {} was rendered with another screen size", path.display()));
+    }
+    let mut img = Vec::with_capacity(SCREEN_WIDTH);
+    for _ in 0..SCREEN_WIDTH {
+        let mut column = Vec::with_capacity(SCREEN_HEIGHT);
+        for _ in 0..SCREEN_HEIGHT {
+            let r = f64::from_bits(read_u64(&mut reader)?);
+            let g = f64::from_bits(read_u64(&mut reader)?);
+            let b = f64::from_bits(read_u64(&mut reader)?);
+            column.push(Color::new(r, g, b));
+        }
+        img.push(column);
+    }
+    Ok(Checkpoint { scene_hash, iterations_done, img })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use crate::{model::{materials::color::Color, objects::camera::Camera, maths::vec3::Vec3, scene::Scene}, SCREEN_HEIGHT, SCREEN_WIDTH};
+    use super::{checkpoint_path, read_checkpoint, write_checkpoint};
+
+    #[test]
+    fn test_checkpoint() {
+        let img: Vec<Vec<Color>> = (0..SCREEN_WIDTH)
+            .map(|x| (0..SCREEN_HEIGHT).map(|y| Color::new(x as f64 / 3., y as f64 / 7., 0.1)).collect())
+            .collect();
+        let path = temp_dir().join(format!("rt_test_{}.checkpoint", std::process::id()));
+        write_checkpoint(&path, 42, 900, &img).unwrap();
+        let checkpoint = read_checkpoint(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(checkpoint.scene_hash, 42);
+        assert_eq!(checkpoint.iterations_done, 900);
+        let same_color = |(a, b): (&Color, &Color)| a.r() == b.r() && a.g() == b.g() && a.b() == b.b();
+        assert!(checkpoint.img.iter().flatten().zip(img.iter().flatten()).all(same_color));
+
+        let path = checkpoint_path("scenes/chessboard.json");
+        assert!(path.starts_with("checkpoints"));
+        assert!(path.file_name().unwrap().to_string_lossy().starts_with("chessboard-"));
+        assert_eq!(path, checkpoint_path("scenes/../scenes/chessboard.json"));
+        assert_ne!(checkpoint_path("a/scene.json"), checkpoint_path("b/scene.json"));
+    }
+
+    #[test]
+    fn test_scene_hash() {
+        let mut scene = Scene::new();
+        let hash = scene.content_hash();
+        scene.settings_mut().iterations += 10;
+        assert_eq!(scene.content_hash(), hash);
+        scene.add_camera(Camera::new(Vec3::new(0., 0., 1.), Vec3::new(0., 0., 1.), 75.));
+        assert_ne!(scene.content_hash(), hash);
+    }
+}
@@ -1,10 +1,17 @@
-use std::{collections::VecDeque, sync::{mpsc::{self, Receiver, Sender}, Arc, RwLock}, thread};
+use std::{
+    cmp::min,
+    collections::VecDeque,
+    net::TcpListener,
+    str::FromStr,
+    sync::{atomic::{AtomicUsize, Ordering}, mpsc::{self, Receiver, Sender}, Arc, Condvar, Mutex, MutexGuard, RwLock},
+    thread
+};
 
 use image::RgbaImage;
 
-use crate::{model::scene::Scene, MAX_THREADS};
+use crate::{model::{materials::color::Color, scene::Scene}, DEFAULT_THREADS, SCREEN_HEIGHT, SCREEN_WIDTH};
 
-use super::{render_thread::{render_thread, UIOrder}, worker_threads::worker_thread};
+use super::{remote::serve_remote_worker, render_thread::{render_thread, UIOrder}, worker_threads::worker_thread};
 
 
 
@@ -22,6 +29,72 @@ pub struct Tile {
 }
 
 
+/**
+ * Rectangle of the screen to render alone, the rest of the image keeps its last render.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Region {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize
+}
+
+impl Region {
+    // Constructor
+    /**
+     * Only the part of the rectangle inside the screen is kept, and None is returned if it is empty.
+     */
+    pub fn new(x: usize, y: usize, width: usize, height: usize) -> Option<Region> {
+        if x >= SCREEN_WIDTH || y >= SCREEN_HEIGHT || width == 0 || height == 0 {
+            return None;
+        }
+        Some(Region {
+            x,
+            y,
+            width: min(width, SCREEN_WIDTH - x),
+            height: min(height, SCREEN_HEIGHT - y)
+        })
+    }
+
+    pub fn from_corners(a: (usize, usize), b: (usize, usize)) -> Option<Region> {
+        let x = min(a.0, b.0);
+        let y = min(a.1, b.1);
+        Region::new(x, y, a.0.max(b.0) - x, a.1.max(b.1) - y)
+    }
+
+    // Methods
+    pub fn overlaps(&self, x: usize, y: usize, width: usize, height: usize) -> bool {
+        x < self.x + self.width && self.x < x + width && y < self.y + self.height && self.y < y + height
+    }
+
+    pub fn on_border(&self, x: usize, y: usize) -> bool {
+        let (right, bottom) = (self.x + self.width - 1, self.y + self.height - 1);
+        (x == self.x || x == right) && (self.y..=bottom).contains(&y)
+            || (y == self.y || y == bottom) && (self.x..=right).contains(&x)
+    }
+}
+
+/**
+ * Parses "x,y,width,height".
+ */
+impl FromStr for Region {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s
+            .split(',')
+            .map(|value| value.trim().parse::<usize>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| format!("Invalid region {}, expected x,y,width,height", s))?;
+        match values[..] {
+            [x, y, width, height] => Region::new(x, y, width, height).ok_or(format!("The region {} is outside of the screen", s)),
+            _ => Err(format!("Invalid region {}, expected x,y,width,height", s)),
+        }
+    }
+}
+
+
 pub struct QueueContext {
     pub queue: VecDeque<Tile>,
     pub render_id: usize,
@@ -30,6 +103,104 @@ pub struct QueueContext {
     pub active: bool
 }
 
+/**
+ * Queues of tiles of every scene, shared by the render thread and the workers. The idle workers wait on the condition
+ * variable, so the render thread must notify them when it gives them something to do. The workers above the wanted
+ * number stop when they are woken up.
+ */
+pub struct WorkQueues {
+    queues: Mutex<Vec<QueueContext>>,
+    work_available: Condvar,
+    nb_threads: AtomicUsize,
+    running_threads: AtomicUsize,
+}
+
+impl WorkQueues {
+    // Constructor
+    pub fn new(nb_threads: usize) -> WorkQueues {
+        WorkQueues {
+            queues: Mutex::new(vec![]),
+            work_available: Condvar::new(),
+            nb_threads: AtomicUsize::new(nb_threads),
+            running_threads: AtomicUsize::new(0),
+        }
+    }
+
+    // Accessors
+    pub fn lock(&self) -> MutexGuard<'_, Vec<QueueContext>> {
+        self.queues.lock().unwrap()
+    }
+    pub fn nb_threads(&self) -> usize {
+        self.nb_threads.load(Ordering::SeqCst)
+    }
+
+    // Methods
+    pub fn notify(&self) {
+        self.work_available.notify_all();
+    }
+
+    /**
+     * Releases the queues until the render thread notifies that there is something to do.
+     */
+    pub fn wait<'a>(&self, queues: MutexGuard<'a, Vec<QueueContext>>) -> MutexGuard<'a, Vec<QueueContext>> {
+        self.work_available.wait(queues).unwrap()
+    }
+
+    /**
+     * Puts tiles that were taken but not rendered back at the front of their queues.
+     */
+    pub fn give_back(&self, tiles: impl DoubleEndedIterator<Item = Tile>) {
+        let mut queues = self.lock();
+        for tile in tiles.rev() {
+            if let Some(queue_context) = queues.iter_mut().find(|queue_context| queue_context.render_id == tile.render_id) {
+                queue_context.queue.push_front(tile);
+            }
+        }
+        drop(queues);
+        self.notify();
+    }
+
+    /**
+     * Starts workers up to the wanted number, the extra ones stop on their own. Without any local worker, only the
+     * remote workers render.
+     */
+    pub fn set_nb_threads(self: &Arc<Self>, nb_threads: usize, tx: &Sender<(Tile, Vec<Color>)>) {
+        self.nb_threads.store(nb_threads, Ordering::SeqCst);
+        while self.running_threads.load(Ordering::SeqCst) < self.nb_threads() {
+            self.running_threads.fetch_add(1, Ordering::SeqCst);
+            let work_queues = Arc::clone(self);
+            // Chaque worker_thread a son propre emetteur de channel, mais il n'existe qu'un seul receiver (le render_thread)
+            let tx = tx.clone();
+            thread::spawn(move || worker_thread(work_queues, tx));
+        }
+        // Taking the lock makes sure that no worker is between its check and its wait
+        drop(self.lock());
+        self.notify();
+    }
+
+    /**
+     * Whether the worker calling it must stop, in which case it is no longer counted as running.
+     */
+    pub fn should_stop(&self) -> bool {
+        loop {
+            let running_threads = self.running_threads.load(Ordering::SeqCst);
+            if running_threads <= self.nb_threads() {
+                return false;
+            }
+            if self.running_threads.compare_exchange(running_threads, running_threads - 1, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                return true;
+            }
+        }
+    }
+}
+
+/**
+ * One worker per core by default.
+ */
+pub fn default_nb_threads() -> usize {
+    thread::available_parallelism().map_or(DEFAULT_THREADS, |nb_threads| nb_threads.get())
+}
+
 pub fn for_each_uncalculated_pixel<F>(tile: &Tile, mut f: F)
 where
     F: FnMut(usize, usize),
@@ -55,7 +226,10 @@ where
 
 
 
-pub fn start_threads() -> (Receiver<(RgbaImage, bool)>, Sender<UIOrder>) {
+/**
+ * Starts the render thread and its workers. Remote workers connecting to the listener render with them.
+ */
+pub fn start_threads(nb_threads: usize, listener: Option<TcpListener>) -> (Receiver<(RgbaImage, bool)>, Sender<UIOrder>) {
     // Channel render_thread -> main_thread pour envoyer l'image du thread de render au main thread
     let (ta, ra) = mpsc::channel();
     // Channel main_thread -> render_thread pour recevoir les demandes d'images du main thread, avec ou sans changement de scene
@@ -69,18 +243,44 @@ pub fn start_threads() -> (Receiver<(RgbaImage, bool)>, Sender<UIOrder>) {
     // va en rajouter a chaque demande d'image
 
 
-    let work_queues: Arc<RwLock<Vec<QueueContext>>> = Arc::new(RwLock::new(vec![]));
+    let work_queues = Arc::new(WorkQueues::new(nb_threads));
+    if let Some(listener) = listener {
+        let work_queues = work_queues.clone();
+        let tc = tc.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let work_queues = work_queues.clone();
+                let tc = tc.clone();
+                thread::spawn(move || serve_remote_worker(stream, work_queues, tc));
+            }
+        });
+    }
     // Generation du thread de render qui va lui-meme lancer les worker threads
     thread::spawn(move || {
-        for _ in 0..MAX_THREADS {
-            // Chaque worker_thread a son propre emetteur de channel, mais il n'existe qu'un seul receiver (le render_thread)
-            let cur_tx = tc.clone();
-            let work_queues_clone = Arc::clone(&work_queues);
-            thread::spawn(move || {
-                worker_thread(Arc::clone(&work_queues_clone), cur_tx);
-            });
-        }
-        render_thread(rc, rb, ta, work_queues);
+        work_queues.set_nb_threads(work_queues.nb_threads(), &tc);
+        render_thread(rc, rb, ta, tc, work_queues);
     });
     (ra, tb)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{SCREEN_HEIGHT, SCREEN_WIDTH};
+    use super::Region;
+
+    #[test]
+    fn test_region() {
+        let region: Region = "10, 20, 30, 40".parse().unwrap();
+        assert_eq!(region, Region { x: 10, y: 20, width: 30, height: 40 });
+        assert_eq!(Region::from_corners((40, 60), (10, 20)), Some(region));
+        assert!(region.overlaps(0, 0, 11, 21));
+        assert!(!region.overlaps(0, 0, 10, 100));
+        assert!(!region.overlaps(40, 20, 8, 8));
+        assert!(region.on_border(39, 30) && region.on_border(25, 20) && !region.on_border(25, 30));
+
+        let clamped = Region::new(SCREEN_WIDTH - 5, 0, 100, SCREEN_HEIGHT + 1).unwrap();
+        assert_eq!((clamped.width, clamped.height), (5, SCREEN_HEIGHT));
+        assert!("10,20,30".parse::<Region>().is_err());
+        assert!(format!("{},0,10,10", SCREEN_WIDTH).parse::<Region>().is_err());
+    }
+}
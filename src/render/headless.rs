@@ -0,0 +1,137 @@
+use image::imageops::crop_imm;
+use std::{
+    fs::write,
+    net::TcpListener,
+    path::Path,
+    str::FromStr,
+    sync::{Arc, RwLock},
+    thread,
+    time::Duration
+};
+
+use crate::{parsing::{json::{parse_json, parse_json_file, JsonValue}, scene_from_json, templates::expand_templates}, HEADLESS_REFRESH_TIME};
+
+use super::{common::{default_nb_threads, start_threads, Region}, remote::run_worker, render_thread::UIOrder, stats::StatsReport};
+
+/**
+ * Renders a scene in HighDef without a window until it reaches its iterations, and saves the image. Remote workers
+ * started with `rt worker` can help through the address given to --listen. With --resume, the render continues from
+ * the checkpoint of the scene if it didn't change since. With --region, only the given rectangle of the screen is
+ * rendered and saved. With --stats, the stats of the render are written as JSON at the end.
+ * Usage: rt render <scene file> [--output <image>] [--iterations <n>] [--threads <n>] [--listen <address>] [--resume]
+ *     [--region <x,y,width,height>] [--stats <json file>]
+ */
+pub fn render_command(args: &[String]) -> Result<(), String> {
+    let mut scene_path = None;
+    let mut output = "render.png".to_string();
+    let mut iterations = None;
+    let mut nb_threads = default_nb_threads();
+    let mut listen = None;
+    let mut resume = false;
+    let mut region: Option<Region> = None;
+    let mut stats = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--output" => output = parse_value(arg, args.next())?,
+            "--iterations" => iterations = Some(parse_value(arg, args.next())?),
+            "--threads" => nb_threads = parse_value(arg, args.next())?,
+            "--listen" => listen = Some(parse_value::<String>(arg, args.next())?),
+            "--resume" => resume = true,
+            "--region" => region = Some(parse_value(arg, args.next())?),
+            "--stats" => stats = Some(parse_value::<String>(arg, args.next())?),
+            _ if scene_path.is_none() && !arg.starts_with("--") => scene_path = Some(arg.clone()),
+            _ => return Err(format!("Unexpected argument {}", arg)),
+        }
+    }
+    let scene_path = scene_path.ok_or("The scene file is missing")?;
+    if nb_threads == 0 && listen.is_none() {
+        return Err("Without remote workers, at least one thread is needed".to_string());
+    }
+    if resume && region.is_some() {
+        return Err("A render can't be resumed with a region".to_string());
+    }
+
+    // The workers don't have the included files, they get the scene with its templates already expanded
+    let folder = Path::new(&scene_path).parent().unwrap_or(Path::new("."));
+    let json_value = expand_templates(parse_json_file(&scene_path)?, folder)?;
+    // The remote workers parse the same JSON, so the settings of the render are added to it
+    let viewmode = parse_json("{\"type\": \"viewmode\", \"value\": \"global\"}".to_string())?;
+    let source = format!("[\n{}\n, {}]", json_value, viewmode);
+    let mut scene = scene_from_json(JsonValue::Array(vec![json_value, viewmode]))?;
+    if let Some(iterations) = iterations {
+        scene.settings_mut().iterations = iterations;
+    }
+    scene.set_source(Some(source));
+    scene.set_path(Some(scene_path));
+    scene.prepare_render();
+    // The change is sent to the render below
+    scene.set_dirty(false);
+
+    let listener = match listen {
+        Some(address) => {
+            let listener = TcpListener::bind(&address).map_err(|err| format!("Can't listen on {}: {}", address, err))?;
+            println!("Waiting for remote workers on {}", address);
+            Some(listener)
+        }
+        None => None,
+    };
+    let scene = Arc::new(RwLock::new(scene));
+    let (ra, tb) = start_threads(nb_threads, listener);
+    tb.send(UIOrder::NewScene(scene.clone())).unwrap();
+    if resume {
+        tb.send(UIOrder::Resume(0)).unwrap();
+    }
+    if region.is_some() {
+        tb.send(UIOrder::SetRegion(0, region)).unwrap();
+    } else {
+        tb.send(UIOrder::SceneChange(0)).unwrap();
+    }
+    loop {
+        tb.send(UIOrder::AskImage(0)).unwrap();
+        let (img, final_img) = ra.recv().map_err(|_| "The render stopped")?;
+        if final_img {
+            let img = match region {
+                Some(region) => crop_imm(&img, region.x as u32, region.y as u32, region.width as u32, region.height as u32).to_image(),
+                None => img,
+            };
+            img.save(&output).map_err(|err| format!("Can't save {}: {}", output, err))?;
+            println!("Saved {}", output);
+            if let Some(stats) = stats {
+                let report = StatsReport::new(&scene.read().unwrap());
+                write(&stats, report.to_json()).map_err(|err| format!("Can't save {}: {}", stats, err))?;
+                println!("Saved {}", stats);
+            }
+            return Ok(());
+        }
+        thread::sleep(Duration::from_millis(HEADLESS_REFRESH_TIME));
+    }
+}
+
+/**
+ * Renders the tiles of a coordinator started with `rt render --listen`, until it is stopped.
+ * Usage: rt worker <address> [--threads <n>]
+ */
+pub fn worker_command(args: &[String]) -> Result<(), String> {
+    let mut address = None;
+    let mut nb_threads = default_nb_threads();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--threads" => nb_threads = parse_value(arg, args.next())?,
+            _ if address.is_none() && !arg.starts_with("--") => address = Some(arg.clone()),
+            _ => return Err(format!("Unexpected argument {}", arg)),
+        }
+    }
+    let address = address.ok_or("The address of the coordinator is missing")?;
+    if nb_threads == 0 {
+        return Err("A worker needs at least one thread".to_string());
+    }
+    run_worker(&address, nb_threads);
+    Ok(())
+}
+
+fn parse_value<T: FromStr>(flag: &str, value: Option<&String>) -> Result<T, String> {
+    let value = value.ok_or(format!("The value of {} is missing", flag))?;
+    value.parse().map_err(|_| format!("Invalid value for {}: {}", flag, value))
+}
@@ -2,7 +2,7 @@ use core::f64;
 
 use crate::{model::{
     element::Element, materials::{color::Color, diffuse::Diffuse}, maths::{hit::Hit, ray::Ray, vec_utils::reflect_dir}, scene::Scene, shapes::plane::Plane
-}, render::{raycasting::get_lighting_from_ray, skybox::get_skybox_color}, BOUNCE_OFFSET};
+}, render::{raycasting::get_lighting_from_ray, skybox::get_skybox_color, stats::{count, Counter}}, BOUNCE_OFFSET};
 
 pub fn phong_lighting_from_hit(scene: &Scene, hit: &Option<Hit>, ray: &Ray) -> Color {
 	let mut cam_color = Color::new(0., 0., 0.);
@@ -12,7 +12,7 @@ pub fn phong_lighting_from_hit(scene: &Scene, hit: &Option<Hit>, ray: &Ray) -> C
 		let elem = Element::new(Box::new(
 			Plane::new(*scene.camera().pos(), *scene.camera().dir())), Diffuse::default());
 		let hit: Hit<'_> = Hit::new(&elem, 0., *scene.camera().pos(), fake_ray.get_dir(), scene.textures(), vec![0.]);
-		for light in scene.lights() {
+		for light in scene.lights().iter().filter(|light| light.visible()) {
 			let throughput = light.light().throughput(scene, &hit);
 			if throughput.length() > f64::EPSILON {
 				if ray.debug {
@@ -28,7 +28,7 @@ pub fn phong_lighting_from_hit(scene: &Scene, hit: &Option<Hit>, ray: &Ray) -> C
 	if let Some(hit) = hit {
 		let mut color: Color = scene.ambient_light().color() * scene.ambient_light().intensity() * hit.color();
 	
-		for light in scene.lights() {
+		for light in scene.lights().iter().filter(|light| light.visible()) {
 			let throughput = light.light().throughput(scene, &hit);
 			if throughput.length() > f64::EPSILON {
 				color += light.light().get_diffuse(&hit) * hit.color();
@@ -37,6 +37,7 @@ pub fn phong_lighting_from_hit(scene: &Scene, hit: &Option<Hit>, ray: &Ray) -> C
 			}
 		}
 		if hit.opacity() < f64::EPSILON {
+			count(Counter::SecondaryRay);
 			let light_through = get_lighting_from_ray(scene, &Ray::new(hit.pos().clone() + *ray.get_dir() * BOUNCE_OFFSET, ray.get_dir().clone(), ray.get_depth()));
 			color = color * hit.opacity() + hit.color() * light_through * (1. - hit.opacity());
 		}
@@ -11,7 +11,7 @@ use crate::{
             vec_utils::{random_unit_vector, reflect_dir}
         }, scene::Scene
     },
-    render::{raycasting::get_lighting_from_ray, skybox::get_skybox_color}, BOUNCE_OFFSET
+    render::{raycasting::get_lighting_from_ray, skybox::get_skybox_color, stats::{count, Counter}}, BOUNCE_OFFSET
 };
 pub fn fresnel_reflect_ratio(n1: f64, n2: f64, norm: &Vec3, ray: &Vec3, reflectivity: f64) -> f64 {
     // Schlick aproximation
@@ -113,6 +113,7 @@ pub fn global_lighting_from_hit(scene: &Scene, hit: &Option<Hit>, ray: &Ray) ->
 			}
 		}
 		if hit.opacity() < 1. - f64::EPSILON {
+			count(Counter::SecondaryRay);
 			let light_through = get_lighting_from_ray(scene, &Ray::new(hit.pos().clone() + *ray.get_dir() * BOUNCE_OFFSET, ray.get_dir().clone(), ray.get_depth()));
 			return light_color * hit.opacity() + hit.color() * light_through * (1. - hit.opacity());
 		}
@@ -136,6 +137,7 @@ fn get_indirect_light_color(scene: &Scene, hit: &Hit, ray: &Ray) -> Color
 		indirect_dir = indirect_dir.normalize();
 		let mut indirect_ray = Ray::new(hit.pos().clone() + hit.norm() * BOUNCE_OFFSET, indirect_dir, ray.get_depth() + 1);
 		indirect_ray.debug = ray.debug;
+		count(Counter::SecondaryRay);
 		light_color = get_lighting_from_ray(scene, &indirect_ray) * hit.color();
 	}
 	light_color
@@ -148,6 +150,7 @@ fn get_reflected_light_color(scene: &Scene, hit: &Hit, ray: &Ray) -> Color
 		.normalize();
 	if dir.dot(hit.norm()) > f64::EPSILON {
 		let reflect_ray = Ray::new(hit.pos().clone() + hit.norm() * BOUNCE_OFFSET, dir, ray.get_depth() + 1);
+		count(Counter::SecondaryRay);
 		reflect_color = get_lighting_from_ray(scene, &reflect_ray);
 	} else {
 		reflect_color = Color::new(0., 0., 0.);
@@ -185,6 +188,7 @@ fn get_refracted_light_color(scene: &Scene, hit: &Hit, ray: &Ray, n1: f64, n2: f
 	if let Some(refracted_ray) = refraction_result {
 		let mut refract_ray = Ray::new(hit.pos().clone() - normal * BOUNCE_OFFSET, refracted_ray.clone(), ray.get_depth() + 1);
 		refract_ray.debug = ray.debug;
+		count(Counter::SecondaryRay);
 		refract_color = get_lighting_from_ray(scene, &refract_ray);
 	}
 	refract_color * hit.color()
@@ -5,4 +5,8 @@ pub mod settings;
 pub mod render_thread;
 pub mod common;
 pub mod tile_queue;
+pub mod remote;
+pub mod headless;
+pub mod checkpoint;
 pub mod skybox;
+pub mod stats;
@@ -1,17 +1,18 @@
 use rand::Rng;
 use crate::{
-    bvh::traversal::recursive_traversal, model::{
+    bvh::flat::FlatBvh, model::{
         element::Element, materials::{
             color::Color,
             texture::{Texture, TextureType}
-        }, maths::{hit::Hit, ray::Ray, vec3::Vec3}, scene::Scene, shapes::shape::Shape
+        }, maths::{hit::Hit, ray::Ray, vec3::Vec3}, scene::{BvhPrimitive, Scene}
     }, ANTIALIASING, SCREEN_HEIGHT, SCREEN_WIDTH, USING_BVH
 };
 use super::{
     lighting::{
         lighting_phong::phong_lighting_from_hit, lighting_real::global_lighting_from_hit, lighting_simple::simple_lighting_from_hit, lighting_norm::norm_lighting_from_hit, lighting_projection::projection_lighting_from_hit
     },
-    settings::ViewMode
+    settings::ViewMode,
+    stats::{count, Counter}
 };
 
 pub fn get_ray_debug(scene: &Scene, x: usize, y: usize, debug: bool) -> Ray {
@@ -65,12 +66,30 @@ pub fn get_sorted_hit_from_t<'a>(scene: &'a Scene, ray: &Ray, t: &Option<Vec<f64
 	Some(hits)
 }
 
-pub fn get_closest_hit_from_elements<'a>(scene: &'a Scene, ray: &Ray, closest: Option<Hit<'a>>, elements: &'a Vec<Element>) -> Option<Hit<'a>> {
-    let elements_index = (0..elements.len()).collect();
+/**
+ * Every intersection of the ray with the element, displaced if the scene and the material ask for it.
+ */
+pub fn intersect_element(scene: &Scene, ray: &Ray, element: &Element) -> Option<Vec<f64>> {
+    // The displaced intersections don't go through Element::intersect, which skips the hidden elements
+    if !element.visible() {
+        return None;
+    }
+    // The displacement builds hits along the ray, so it's only supported in the world space
+    if scene.settings().displacement && element.world_transform().is_none() {
+        if let Texture::Texture(_file, TextureType::Float) = element.material().displacement() {
+            count(Counter::IntersectionTest);
+            return element.shape().intersect_displacement(ray, element, scene);
+        }
+    }
+    element.intersect(ray)
+}
+
+pub fn get_closest_hit_from_elements<'a>(scene: &'a Scene, ray: &Ray, closest: Option<Hit<'a>>, elements: &'a [Element]) -> Option<Hit<'a>> {
+    let elements_index: Vec<usize> = (0..elements.len()).collect();
     return get_closest_hit_from_elements_with_index(scene, ray, closest, elements, &elements_index);
 }
 
-pub fn get_closest_hit_from_elements_with_index<'a>(scene: &'a Scene, ray: &Ray, mut closest: Option<Hit<'a>>, elements: &'a Vec<Element>, elements_index: &Vec<usize>) -> Option<Hit<'a>> {
+pub fn get_closest_hit_from_elements_with_index<'a>(scene: &'a Scene, ray: &Ray, mut closest: Option<Hit<'a>>, elements: &'a [Element], elements_index: &[usize]) -> Option<Hit<'a>> {
     let mut t_list = match &closest {
         Some(hit) => hit.t_list().clone(),
         _ => vec![]
@@ -78,17 +97,22 @@ pub fn get_closest_hit_from_elements_with_index<'a>(scene: &'a Scene, ray: &Ray,
     for index in elements_index {
         let element = &elements[*index];
 
-        let t;
-		if scene.settings().displacement {
-            if let Texture::Texture(_file, TextureType::Float) = element.material().displacement() {
-                t = element.shape().intersect_displacement(ray, &element, scene);
-            }
-            else {
-                t = element.shape().intersect(ray);
+        if let Some(instance) = element.shape().as_instance() {
+            // The instances can't compute their norm from the position alone, so they build their hit themselves
+            if let Some(hit) = instance.get_closest_hit(scene, ray, element) {
+                t_list.push((element, hit.all_dist().clone()));
+                let is_closer = match &closest {
+                    None => true,
+                    Some(previous) => hit.dist() > &0. && (previous.dist() < &0. || hit.dist() < previous.dist()),
+                };
+                if is_closer && hit.opacity() > f64::EPSILON {
+                    closest = Some(hit);
+                }
             }
-        } else {
-        	t = element.shape().intersect(ray);
+            continue;
         }
+
+        let t = intersect_element(scene, ray, element);
         if let Some(t) = &t {
             t_list.push((element, t.clone()));
             for dist in t {
@@ -115,29 +139,44 @@ pub fn get_closest_hit_from_elements_with_index<'a>(scene: &'a Scene, ray: &Ray,
     closest
 }
 
-pub fn get_closest_hit<'a>(scene: &'a Scene, ray: &Ray) -> Option<Hit<'a>> {
-    let mut closest: Option<Hit> = None;
-    
-    if USING_BVH {
-        // We first check every element that is not supported by the BVH (i.e. infinite shapes that cannot be contained in an AABB)
-        closest = get_closest_hit_from_elements_with_index(scene, ray, closest, scene.elements(), scene.non_bvh_elements());
-        // We then do a BVH traversal to check for intersections
-        if let Some(root_node) = scene.bvh() {
-            if let Some(hit) = recursive_traversal(ray, root_node, scene, closest.clone(), root_node.aabb().intersect(ray).unwrap_or(vec![]), 1) {
-                if let Some(previous) = &closest {
-                    if hit.dist() > &0. && (hit.dist() < previous.dist() || previous.dist() < &0.) {
-                        closest = Some(hit);
-                    }
-                } else {
-                    closest = Some(hit);
-                }
-            }
-            
+pub fn get_closest_hit_from_bvh<'a>(scene: &'a Scene, ray: &Ray, elements: &'a [Element], non_bvh_elements: &[usize], bvh: &Option<FlatBvh>) -> Option<Hit<'a>> {
+    // We first check every element that is not supported by the BVH (i.e. infinite shapes that cannot be contained in an AABB)
+    let closest = get_closest_hit_from_elements_with_index(scene, ray, None, elements, non_bvh_elements);
+    // We then do a BVH traversal to check for intersections
+    match bvh {
+        Some(bvh) => bvh.closest_hit(scene, ray, elements, closest),
+        None => closest,
+    }
+}
+
+/**
+ * Closest hit with the elements of the scene, through its two levels of BVH: the leaves of the BVH of the scene are
+ * either elements or composed elements, which have a BVH of their own.
+ */
+pub fn get_closest_hit_from_scene_bvh<'a>(scene: &'a Scene, ray: &Ray) -> Option<Hit<'a>> {
+    let closest = get_closest_hit_from_elements_with_index(scene, ray, None, scene.elements(), scene.non_bvh_elements());
+    let bvh = match scene.bvh() {
+        Some(bvh) => bvh,
+        None => return closest,
+    };
+    bvh.closest_hit_with(ray, 1., scene.settings().bvh_full_traversal, closest, |indices, mut closest| {
+        for index in indices {
+            closest = match scene.bvh_primitive(*index) {
+                BvhPrimitive::Element(index) => get_closest_hit_from_elements_with_index(scene, ray, closest, scene.elements(), &[index]),
+                BvhPrimitive::ComposedElement(composed_element) => composed_element.get_closest_hit(scene, ray, closest),
+            };
         }
+        closest
+    })
+}
+
+pub fn get_closest_hit<'a>(scene: &'a Scene, ray: &Ray) -> Option<Hit<'a>> {
+    let closest = if USING_BVH {
+        get_closest_hit_from_scene_bvh(scene, ray)
     } else {
         // When we are not using the bvh, we just check for every element intersection
-        closest = get_closest_hit_from_elements(scene, ray, closest, scene.elements());
-    }
+        get_closest_hit_from_elements(scene, ray, None, scene.elements())
+    };
     
     match closest {
         None => None,
@@ -0,0 +1,445 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io::{self, BufReader, BufWriter, Read, Write},
+    net::TcpStream,
+    sync::{mpsc::Sender, Arc, Mutex, RwLock},
+    thread,
+    time::{Duration, Instant}
+};
+
+use crate::{model::{materials::color::Color, scene::Scene}, parsing::get_scene_from_str, REMOTE_RECONNECT_DELAY, REMOTE_TILES_IN_FLIGHT, REMOTE_TIMEOUT};
+
+use super::{common::{for_each_uncalculated_pixel, Tile, WorkQueues}, stats::{take_counters, Counters}, worker_threads::{render_tile, TileScheduler}};
+
+// Sent first by both sides, so that a worker and a coordinator of different versions refuse each other
+const MAGIC: &[u8; 4] = b"RTW2";
+const SCENE_MESSAGE: u8 = 1;
+const TILE_MESSAGE: u8 = 2;
+const RESULT_MESSAGE: u8 = 3;
+// Longest scene source accepted from the other side, which is read in memory at once
+const MAX_SCENE_SIZE: usize = 256 * 1024 * 1024;
+
+// The last scene received by a worker with its source, so that it is parsed once for every connection
+type LastScene = Mutex<Option<(String, Arc<RwLock<Scene>>)>>;
+
+/**
+ * What a remote worker needs to know about a tile, the scene being sent once beforehand.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TileJob {
+    pub render_id: usize,
+    pub version: usize,
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    pub factor: usize,
+    pub base_factor: usize,
+}
+
+impl TileJob {
+    pub fn from_tile(tile: &Tile) -> TileJob {
+        TileJob {
+            render_id: tile.render_id,
+            version: tile.version,
+            x: tile.x,
+            y: tile.y,
+            width: tile.width,
+            height: tile.height,
+            factor: tile.factor,
+            base_factor: tile.base_factor,
+        }
+    }
+
+    pub fn to_tile(&self, scene: Arc<RwLock<Scene>>) -> Tile {
+        Tile {
+            x: self.x,
+            y: self.y,
+            width: self.width,
+            height: self.height,
+            factor: self.factor,
+            base_factor: self.base_factor,
+            scene,
+            render_id: self.render_id,
+            version: self.version,
+        }
+    }
+
+    fn fields(&self) -> [usize; 8] {
+        [self.render_id, self.version, self.x, self.y, self.width, self.height, self.factor, self.base_factor]
+    }
+}
+
+/**
+ * Messages between the coordinator, which sends scenes and tiles, and the remote workers, which send back the colors of
+ * the tiles in the order they received them, with what rendering them took for the stats.
+ */
+#[derive(Debug)]
+pub enum Message {
+    Scene { render_id: usize, version: usize, source: String },
+    Tile(TileJob),
+    Result(TileJob, Counters, Vec<Color>),
+}
+
+pub fn write_message(writer: &mut impl Write, message: &Message) -> io::Result<()> {
+    match message {
+        Message::Scene { render_id, version, source } => {
+            writer.write_all(&[SCENE_MESSAGE])?;
+            write_usize(writer, *render_id)?;
+            write_usize(writer, *version)?;
+            write_usize(writer, source.len())?;
+            writer.write_all(source.as_bytes())
+        }
+        Message::Tile(job) => {
+            writer.write_all(&[TILE_MESSAGE])?;
+            write_job(writer, job)
+        }
+        Message::Result(job, counters, colors) => {
+            writer.write_all(&[RESULT_MESSAGE])?;
+            write_job(writer, job)?;
+            for value in counters.to_array() {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+            write_usize(writer, colors.len())?;
+            for color in colors {
+                for value in [color.r(), color.g(), color.b()] {
+                    writer.write_all(&value.to_le_bytes())?;
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+pub fn read_message(reader: &mut impl Read) -> io::Result<Message> {
+    let mut kind = [0u8];
+    reader.read_exact(&mut kind)?;
+    match kind[0] {
+        SCENE_MESSAGE => {
+            let render_id = read_usize(reader)?;
+            let version = read_usize(reader)?;
+            let size = read_usize(reader)?;
+            if size > MAX_SCENE_SIZE {
+                return Err(invalid_data("The scene is too large"));
+            }
+            let mut source = vec![0u8; size];
+            reader.read_exact(&mut source)?;
+            let source = String::from_utf8(source).map_err(|_| invalid_data("The scene is not valid UTF-8"))?;
+            Ok(Message::Scene { render_id, version, source })
+        }
+        TILE_MESSAGE => Ok(Message::Tile(read_job(reader)?)),
+        RESULT_MESSAGE => {
+            let job = read_job(reader)?;
+            let mut counters = [0; 5];
+            for value in counters.iter_mut() {
+                *value = read_usize(reader)? as u64;
+            }
+            let nb_colors = read_usize(reader)?;
+            if nb_colors > job.width * job.height {
+                return Err(invalid_data("Too many colors for the tile"));
+            }
+            let mut colors = Vec::with_capacity(nb_colors);
+            for _ in 0..nb_colors {
+                colors.push(Color::new(read_f64(reader)?, read_f64(reader)?, read_f64(reader)?));
+            }
+            Ok(Message::Result(job, Counters::from_array(counters), colors))
+        }
+        kind => Err(invalid_data(&format!("Unknown message {}", kind))),
+    }
+}
+
+fn write_job(writer: &mut impl Write, job: &TileJob) -> io::Result<()> {
+    for field in job.fields() {
+        write_usize(writer, field)?;
+    }
+    Ok(())
+}
+
+fn read_job(reader: &mut impl Read) -> io::Result<TileJob> {
+    let mut fields = [0; 8];
+    for field in fields.iter_mut() {
+        *field = read_usize(reader)?;
+    }
+    let [render_id, version, x, y, width, height, factor, base_factor] = fields;
+    Ok(TileJob { render_id, version, x, y, width, height, factor, base_factor })
+}
+
+fn write_usize(writer: &mut impl Write, value: usize) -> io::Result<()> {
+    writer.write_all(&(value as u64).to_le_bytes())
+}
+
+fn read_usize(reader: &mut impl Read) -> io::Result<usize> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes) as usize)
+}
+
+fn read_f64(reader: &mut impl Read) -> io::Result<f64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(f64::from_le_bytes(bytes))
+}
+
+fn read_magic(reader: &mut impl Read) -> io::Result<()> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_data("The other side is not a compatible rt"));
+    }
+    Ok(())
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/**
+ * Coordinator side of a remote worker: takes tiles from the work queues like a local worker would, and forwards the
+ * colors the remote worker sends back to the render thread. The tiles the worker didn't answer are given back to the
+ * queues when the connection is lost.
+ */
+pub fn serve_remote_worker(stream: TcpStream, work_queues: Arc<WorkQueues>, tx: Sender<(Tile, Vec<Color>)>) {
+    let peer = stream.peer_addr().map(|address| address.to_string()).unwrap_or_default();
+    println!("Remote worker {} connected", peer);
+    let mut in_flight = VecDeque::new();
+    if let Err(err) = serve(&stream, &work_queues, &tx, &mut in_flight) {
+        println!("Remote worker {} lost: {}", peer, err);
+    }
+    work_queues.give_back(in_flight.into_iter().map(|(tile, _)| tile));
+}
+
+fn serve(stream: &TcpStream, work_queues: &WorkQueues, tx: &Sender<(Tile, Vec<Color>)>, in_flight: &mut VecDeque<(Tile, Instant)>) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(Some(Duration::from_secs(REMOTE_TIMEOUT)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(REMOTE_TIMEOUT)))?;
+    let mut reader = BufReader::new(stream);
+    let mut writer = BufWriter::new(stream);
+    writer.write_all(MAGIC)?;
+    writer.flush()?;
+    read_magic(&mut reader)?;
+
+    let mut scheduler = TileScheduler::default();
+    let mut sent_scenes = HashSet::new();
+    let mut last_answer = Instant::now();
+    loop {
+        while in_flight.len() < REMOTE_TILES_IN_FLIGHT {
+            // Sleeps only when the worker has nothing left to render
+            let tile = match in_flight.is_empty() {
+                true => scheduler.next_tile(work_queues, false),
+                false => scheduler.try_next_tile(work_queues),
+            };
+            let tile = match tile {
+                Some(tile) => tile,
+                None => break,
+            };
+            let job = TileJob::from_tile(&tile);
+            let source = match sent_scenes.insert((job.render_id, job.version)) {
+                true => Some(tile.scene.read().unwrap().source().cloned()),
+                false => None,
+            };
+            in_flight.push_back((tile, Instant::now()));
+            if let Some(source) = source {
+                let source = source.ok_or_else(|| invalid_data("The scene can't be sent to remote workers"))?;
+                write_message(&mut writer, &Message::Scene { render_id: job.render_id, version: job.version, source })?;
+            }
+            write_message(&mut writer, &Message::Tile(job))?;
+        }
+        writer.flush()?;
+
+        let (job, counters, colors) = match read_message(&mut reader)? {
+            Message::Result(job, counters, colors) => (job, counters, colors),
+            _ => return Err(invalid_data("A worker can only send results")),
+        };
+        let (tile, sent) = in_flight.pop_front().ok_or_else(|| invalid_data("Result of a tile that wasn't sent"))?;
+        let mut nb_pixels = 0;
+        for_each_uncalculated_pixel(&tile, |_, _| nb_pixels += 1);
+        if job != TileJob::from_tile(&tile) || colors.len() != nb_pixels {
+            in_flight.push_front((tile, sent));
+            return Err(invalid_data("Result of another tile"));
+        }
+        tile.scene.read().unwrap().stats().add_tile(tile.version, &counters, colors.len());
+        if tx.send((tile, colors)).is_err() {
+            return Ok(());
+        }
+        // The worker renders the tiles one after the other, so it started this one when it sent the previous one
+        let now = Instant::now();
+        scheduler.tile_done(work_queues, now - sent.max(last_answer));
+        last_answer = now;
+    }
+}
+
+/**
+ * Worker side: opens one connection to the coordinator per thread and renders the tiles it sends, connecting again
+ * whenever the connection is lost. The scenes are parsed from the JSON sent by the coordinator, so the textures and
+ * models they use must be found at the same paths as on the coordinator.
+ */
+pub fn run_worker(address: &str, nb_threads: usize) {
+    let last_scene: Arc<LastScene> = Arc::new(Mutex::new(None));
+    let mut handles = Vec::new();
+    for thread_index in 0..nb_threads {
+        let address = address.to_string();
+        let last_scene = last_scene.clone();
+        handles.push(thread::spawn(move || {
+            let mut waiting = false;
+            loop {
+                match TcpStream::connect(&address) {
+                    Ok(stream) => {
+                        if thread_index == 0 {
+                            println!("Connected to {}", address);
+                        }
+                        waiting = false;
+                        if let Err(err) = work_for(&stream, &last_scene) {
+                            println!("Connection to {} lost: {}", address, err);
+                        }
+                    },
+                    Err(err) => {
+                        if thread_index == 0 && !waiting {
+                            println!("Waiting for the coordinator at {}: {}", address, err);
+                        }
+                        waiting = true;
+                    }
+                }
+                thread::sleep(Duration::from_secs(REMOTE_RECONNECT_DELAY));
+            }
+        }));
+    }
+    for handle in handles {
+        handle.join().ok();
+    }
+}
+
+fn work_for(stream: &TcpStream, last_scene: &LastScene) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    let mut reader = BufReader::new(stream);
+    let mut writer = BufWriter::new(stream);
+    writer.write_all(MAGIC)?;
+    writer.flush()?;
+    read_magic(&mut reader)?;
+
+    let mut scenes = HashMap::new();
+    loop {
+        match read_message(&mut reader)? {
+            Message::Scene { render_id, version, source } => {
+                let scene = load_scene(source, last_scene).map_err(|err| invalid_data(&err))?;
+                scenes.insert((render_id, version), scene);
+            }
+            Message::Tile(job) => {
+                let scene = scenes.get(&(job.render_id, job.version)).ok_or_else(|| invalid_data("Tile of an unknown scene"))?;
+                let colors = render_tile(&job.to_tile(scene.clone()));
+                write_message(&mut writer, &Message::Result(job, take_counters(), colors))?;
+                writer.flush()?;
+            }
+            Message::Result(..) => return Err(invalid_data("The coordinator can't send results")),
+        }
+    }
+}
+
+fn load_scene(source: String, last_scene: &LastScene) -> Result<Arc<RwLock<Scene>>, String> {
+    let mut last_scene = last_scene.lock().unwrap();
+    if let Some((last_source, scene)) = last_scene.as_ref() {
+        if *last_source == source {
+            return Ok(scene.clone());
+        }
+    }
+    let mut scene = get_scene_from_str(&source)?;
+    scene.prepare_render();
+    let scene = Arc::new(RwLock::new(scene));
+    *last_scene = Some((source, scene.clone()));
+    Ok(scene)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::VecDeque, io::{BufReader, BufWriter, Cursor, Write}, net::{TcpListener, TcpStream}, sync::{mpsc, Arc, RwLock}, thread};
+    use crate::{model::{materials::color::Color, scene::Scene}, render::common::{for_each_uncalculated_pixel, QueueContext, Tile, WorkQueues}, REMOTE_TILES_IN_FLIGHT};
+    use super::{read_magic, read_message, serve_remote_worker, write_message, write_usize, Counters, Message, TileJob, MAGIC, SCENE_MESSAGE};
+
+    fn tiles(scene: &Arc<RwLock<Scene>>, nb_tiles: usize) -> VecDeque<Tile> {
+        (0..nb_tiles).map(|index| Tile {
+            x: index * 8,
+            y: 0,
+            width: 8,
+            height: 8,
+            factor: 1,
+            base_factor: 1,
+            scene: scene.clone(),
+            render_id: 3,
+            version: 2,
+        }).collect()
+    }
+
+    #[test]
+    fn test_messages() {
+        let job = TileJob { render_id: 1, version: 2, x: 3, y: 4, width: 5, height: 6, factor: 1, base_factor: 2 };
+        let messages = [
+            Message::Scene { render_id: 1, version: 2, source: "[{\"type\": \"sphere\"}]".to_string() },
+            Message::Tile(job),
+            Message::Result(job, Counters::from_array([1, 2, 3, 4, 5]), vec![Color::new(0.25, 1., 3.5), Color::new(0., 0.5, 0.125)]),
+        ];
+        let mut bytes = vec![];
+        for message in &messages {
+            write_message(&mut bytes, message).unwrap();
+        }
+        let mut reader = Cursor::new(bytes);
+        for message in &messages {
+            assert_eq!(format!("{:?}", read_message(&mut reader).unwrap()), format!("{:?}", message));
+        }
+        assert!(read_message(&mut reader).is_err());
+
+        // A huge length is refused before anything is allocated
+        let mut bytes = vec![SCENE_MESSAGE];
+        for value in [1, 2, usize::MAX] {
+            write_usize(&mut bytes, value).unwrap();
+        }
+        assert!(read_message(&mut Cursor::new(bytes)).is_err());
+    }
+
+    #[test]
+    fn test_lost_worker() {
+        let mut scene = Scene::new();
+        scene.set_source(Some("[]".to_string()));
+        let scene = Arc::new(RwLock::new(scene));
+        let work_queues = Arc::new(WorkQueues::new(0));
+        work_queues.lock().push(QueueContext { queue: tiles(&scene, 10), render_id: 3, avg_time: 0., tiles_done: 0., active: true });
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (tx, rx) = mpsc::channel();
+        let coordinator = {
+            let work_queues = work_queues.clone();
+            thread::spawn(move || serve_remote_worker(listener.accept().unwrap().0, work_queues, tx))
+        };
+
+        // A worker that renders the first tile, then disconnects without answering the others
+        let stream = TcpStream::connect(address).unwrap();
+        let mut reader = BufReader::new(&stream);
+        let mut writer = BufWriter::new(&stream);
+        writer.write_all(MAGIC).unwrap();
+        writer.flush().unwrap();
+        read_magic(&mut reader).unwrap();
+        assert!(matches!(read_message(&mut reader).unwrap(), Message::Scene { render_id: 3, version: 2, .. }));
+        let mut jobs = vec![];
+        for _ in 0..REMOTE_TILES_IN_FLIGHT {
+            match read_message(&mut reader).unwrap() {
+                Message::Tile(job) => jobs.push(job),
+                message => panic!("Unexpected message {:?}", message),
+            }
+        }
+        let mut nb_pixels = 0;
+        for_each_uncalculated_pixel(&jobs[0].to_tile(scene.clone()), |_, _| nb_pixels += 1);
+        write_message(&mut writer, &Message::Result(jobs[0], Counters::default(), vec![Color::new(1., 1., 1.); nb_pixels])).unwrap();
+        writer.flush().unwrap();
+        let (tile, colors) = rx.recv().unwrap();
+        assert_eq!(TileJob::from_tile(&tile), jobs[0]);
+        assert_eq!(colors.len(), nb_pixels);
+        drop(reader);
+        drop(writer);
+        drop(stream);
+        coordinator.join().unwrap();
+
+        let queues = work_queues.lock();
+        let remaining: Vec<TileJob> = queues[0].queue.iter().map(TileJob::from_tile).collect();
+        let expected: Vec<TileJob> = tiles(&scene, 10).iter().skip(1).map(TileJob::from_tile).collect();
+        assert_eq!(remaining, expected);
+    }
+}
@@ -1,9 +1,9 @@
-use std::{cmp::min, collections::{HashMap, VecDeque}, sync::{mpsc::{Receiver, Sender}, Arc, RwLock}, time::Instant};
+use std::{cmp::min, collections::{HashMap, VecDeque}, path::PathBuf, sync::{mpsc::{Receiver, Sender}, Arc, RwLock}, time::{Duration, Instant}};
 use image::RgbaImage;
 
-use crate::{model::{materials::color::Color, scene::Scene}, BASE_SIMPLIFICATION, SCREEN_HEIGHT, SCREEN_WIDTH, TILE_SIZE};
+use crate::{error, model::{materials::color::Color, scene::Scene}, BASE_SIMPLIFICATION, CHECKPOINT_INTERVAL, SCREEN_HEIGHT, SCREEN_WIDTH, TILE_SIZE};
 
-use super::{common::{for_each_uncalculated_pixel, QueueContext, Tile}, settings::ViewMode, tile_queue::fill_work_queue};
+use super::{checkpoint::{checkpoint_path, read_checkpoint, write_checkpoint}, common::{for_each_uncalculated_pixel, QueueContext, Region, Tile, WorkQueues}, settings::ViewMode, tile_queue::fill_work_queue};
 
 pub enum UIOrder {
     SceneChange(usize),
@@ -11,7 +11,10 @@ pub enum UIOrder {
     ScenePause(usize),
     SceneStart(usize),
     AskImage(usize),
-    CloseScene(usize)
+    CloseScene(usize),
+    SetThreads(usize),
+    Resume(usize),
+    SetRegion(usize, Option<Region>)
 }
 
 pub struct SceneRender {
@@ -21,7 +24,11 @@ pub struct SceneRender {
     pub max_res_to_do: u32,
     iterations_done: usize,
     img: Vec<Vec<Color>>,
-    pub version: usize
+    pub version: usize,
+    checkpoint: Option<PathBuf>,
+    last_checkpoint: Instant,
+    resume: bool,
+    pub region: Option<Region>
 }
 
 /**
@@ -33,7 +40,8 @@ pub fn render_thread(
     rc: Receiver<(Tile, Vec<Color>)>,
     rb: Receiver<UIOrder>,
     ta: Sender<(RgbaImage, bool)>,
-    work_queue: Arc<RwLock<Vec<QueueContext>>>
+    tc: Sender<(Tile, Vec<Color>)>,
+    work_queue: Arc<WorkQueues>
 ) {
     let mut render_list: HashMap<usize, SceneRender> = HashMap::new();
     let mut next_render_id: usize = 0;
@@ -58,15 +66,7 @@ pub fn render_thread(
                 },
                 UIOrder::SceneChange(scene_id) => {
                     if let Some(render) = render_list.get_mut(&scene_id) {
-                        render.img = vec![vec![Color::new(0., 0., 0.); SCREEN_HEIGHT]; SCREEN_WIDTH];
-                        render.iterations_done = 0;
-                        render.version += 1;
-
-                        for queue_context in work_queue.write().unwrap().iter_mut() {
-                            queue_context.avg_time = 0.;
-                            queue_context.tiles_done = 0.;
-                        }
-                        fill_work_queue(render, &work_queue, min(BASE_SIMPLIFICATION, TILE_SIZE));
+                        restart_render(render, &work_queue);
                     }
                 },
                 UIOrder::NewScene(scene) => {
@@ -74,16 +74,17 @@ pub fn render_thread(
                 },
                 UIOrder::SceneStart(scene_id) => {
                     if let Some(render) = render_list.get_mut(&scene_id) {
-                        for queue in work_queue.write().unwrap().iter_mut() {
+                        for queue in work_queue.lock().iter_mut() {
                             if queue.render_id == render.render_id && !queue.active {
                                 queue.active = true;
                             }
                         }
+                        work_queue.notify();
                     }
                 },
                 UIOrder::ScenePause(scene_id) => {
                     if let Some(render) = render_list.get_mut(&scene_id) {
-                        for queue in work_queue.write().unwrap().iter_mut() {
+                        for queue in work_queue.lock().iter_mut() {
                             if queue.render_id == render.render_id && queue.active {
                                 queue.active = false;
                             }
@@ -91,7 +92,7 @@ pub fn render_thread(
                     }
                 },
                 UIOrder::CloseScene(scene_id) => {
-                    let mut work_queue = work_queue.write().unwrap();
+                    let mut work_queue = work_queue.lock();
                     let mut index = 0;
                     for queue in work_queue.iter() {
                         if queue.render_id == scene_id {
@@ -103,6 +104,20 @@ pub fn render_thread(
                         work_queue.remove(index);
                     }
                     render_list.remove(&scene_id);
+                },
+                UIOrder::SetThreads(nb_threads) => {
+                    work_queue.set_nb_threads(nb_threads, &tc);
+                },
+                UIOrder::Resume(scene_id) => {
+                    if let Some(render) = render_list.get_mut(&scene_id) {
+                        render.resume = true;
+                    }
+                },
+                UIOrder::SetRegion(scene_id, region) => {
+                    if let Some(render) = render_list.get_mut(&scene_id) {
+                        render.region = region;
+                        restart_render(render, &work_queue);
+                    }
                 }
             }
             // Si la scene a change entre temps depuis le GUI, on reset tout
@@ -153,10 +168,15 @@ pub fn render_thread(
                             match viewmode {
                                 ViewMode::HighDef => {
                                     render.iterations_done += 1;
-                                    if render.iterations_done < render.scene.read().unwrap().settings().iterations
+                                    render.scene.read().unwrap().stats().set_iterations_done(render.iterations_done);
+                                    let iterations = render.scene.read().unwrap().settings().iterations;
+                                    if render.iterations_done < iterations
                                     {
                                         fill_work_queue(&mut render, &work_queue, 1);
                                     }
+                                    if render.iterations_done >= iterations || render.last_checkpoint.elapsed() > Duration::from_secs(CHECKPOINT_INTERVAL) {
+                                        save_checkpoint(render);
+                                    }
                                     println!("{} iterations done - {:?}", render.iterations_done, perf.elapsed());
                                     perf = Instant::now();
                                 }
@@ -191,7 +211,7 @@ pub fn render_thread(
                     ViewMode::HighDef => {
                         ta.send((
                             img,
-                            render.iterations_done == render.scene.read().unwrap().settings().iterations,
+                            render.iterations_done >= render.scene.read().unwrap().settings().iterations,
                         ))
                         .ok();
                     }
@@ -207,7 +227,7 @@ pub fn render_thread(
     }
 }
 
-fn create_scene_render(scene: Arc<RwLock<Scene>>, render_list: &mut HashMap<usize, SceneRender>, next_render_id: &mut usize, queues: &Arc<RwLock<Vec<QueueContext>>>) {
+fn create_scene_render(scene: Arc<RwLock<Scene>>, render_list: &mut HashMap<usize, SceneRender>, next_render_id: &mut usize, queues: &WorkQueues) {
     let render = SceneRender {
         render_id: *next_render_id,
         low_res_to_do: 0,
@@ -215,10 +235,14 @@ fn create_scene_render(scene: Arc<RwLock<Scene>>, render_list: &mut HashMap<usiz
         iterations_done: 0,
         img: vec![vec![Color::new(0., 0., 0.); SCREEN_HEIGHT]; SCREEN_WIDTH],
         version: 0,
+        checkpoint: scene.read().unwrap().path().map(|path| checkpoint_path(path)),
+        last_checkpoint: Instant::now(),
+        resume: false,
+        region: None,
         scene : scene.clone()
     };
 
-    queues.write().unwrap().push(QueueContext{
+    queues.lock().push(QueueContext{
         queue: VecDeque::new(),
         active: true,
         render_id: *next_render_id,
@@ -229,6 +253,79 @@ fn create_scene_render(scene: Arc<RwLock<Scene>>, render_list: &mut HashMap<usiz
     *next_render_id += 1;
 }
 
+/**
+ * Starts the render over. With a region, only its tiles are rendered again and the rest of the image is kept.
+ */
+fn restart_render(render: &mut SceneRender, work_queue: &WorkQueues) {
+    if render.region.is_none() {
+        render.img = vec![vec![Color::new(0., 0., 0.); SCREEN_HEIGHT]; SCREEN_WIDTH];
+    }
+    render.iterations_done = 0;
+    render.version += 1;
+
+    for queue_context in work_queue.lock().iter_mut() {
+        queue_context.avg_time = 0.;
+        queue_context.tiles_done = 0.;
+    }
+    let resumed = resume_from_checkpoint(render);
+    render.scene.read().unwrap().stats().reset(render.version, render.iterations_done);
+    if resumed {
+        if render.iterations_done < render.scene.read().unwrap().settings().iterations {
+            fill_work_queue(render, work_queue, 1);
+        }
+    } else {
+        fill_work_queue(render, work_queue, min(BASE_SIMPLIFICATION, TILE_SIZE));
+    }
+}
+
+/**
+ * Continues a HighDef render from the checkpoint of its scene, when it was asked to and the scene didn't change since.
+ */
+fn resume_from_checkpoint(render: &mut SceneRender) -> bool {
+    let scene = render.scene.read().unwrap();
+    // The pixels of a region don't have the same number of samples as the rest of the image
+    if !render.resume || render.region.is_some() || !matches!(scene.settings().view_mode, ViewMode::HighDef) {
+        return false;
+    }
+    render.resume = false;
+    let path = match &render.checkpoint {
+        Some(path) if path.exists() => path,
+        _ => return false,
+    };
+    let checkpoint = match read_checkpoint(path) {
+        Ok(checkpoint) => checkpoint,
+        Err(err) => {
+            error(&err);
+            return false;
+        }
+    };
+    if checkpoint.scene_hash != scene.content_hash() {
+        error(&format!("The checkpoint {} was rendered from another version of the scene, the render starts over", path.display()));
+        return false;
+    }
+    println!("Resuming from {} after {} iterations", path.display(), checkpoint.iterations_done);
+    drop(scene);
+    render.img = checkpoint.img;
+    render.iterations_done = checkpoint.iterations_done;
+    render.last_checkpoint = Instant::now();
+    true
+}
+
+fn save_checkpoint(render: &mut SceneRender) {
+    let scene = render.scene.read().unwrap();
+    // The image of a scene edited since the last change sent to the render doesn't match it
+    if scene.dirty() || render.region.is_some() {
+        return;
+    }
+    if let Some(path) = &render.checkpoint {
+        if let Err(err) = write_checkpoint(path, scene.content_hash(), render.iterations_done, &render.img) {
+            error(&err);
+        }
+    }
+    drop(scene);
+    render.last_checkpoint = Instant::now();
+}
+
 fn vec_to_image(vec: &Vec<Vec<Color>>) -> RgbaImage {
     let mut image = RgbaImage::new(SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32);
     for x in 0..SCREEN_WIDTH {
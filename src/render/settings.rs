@@ -4,7 +4,7 @@ use crate::{
     display::{anaglyph::Coloring, filters::ColorFilter}, model::{
         materials::color::Color,
         maths::vec3::Vec3, objects::lights::parallel_light::ParallelLight,
-    }, ui::{
+    }, render::render_thread::UIOrder, ui::{
         uielement::{Category, UIElement}, uisettings::UISettings, utils::{
             misc::{ElemType, Property, Value}, style::StyleBuilder, ui_utils::UIContext, Displayable
         }
@@ -21,7 +21,7 @@ pub enum ViewMode {
     BVH,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Settings {
     pub reflections: bool,
     pub indirect: bool,
@@ -220,9 +220,31 @@ fn get_filter_ui(settings: &UISettings) -> UIElement {
 }
 
 impl Displayable for Settings {
-    fn get_fields(&self, name: &str, _: &UIContext, settings: &UISettings) -> Vec<UIElement> {
+    fn get_fields(&self, name: &str, context: &UIContext, settings: &UISettings) -> Vec<UIElement> {
         let mut category = Category::default();
 
+        category.elems.push(UIElement::new(
+            "Threads",
+            "threads",
+            ElemType::Property(Property::new(
+                Value::Unsigned(context.nb_threads as u32),
+                Box::new(|_, value: Value, context, _| {
+                    if let Value::Unsigned(value) = value {
+                        context.nb_threads = value as usize;
+                        context.transmitter.send(UIOrder::SetThreads(value as usize)).ok();
+                    }
+                }),
+                Box::new(|value, _, _| {
+                    if let Value::Unsigned(0) = value {
+                        return Err("The number of threads must be positive".to_string());
+                    }
+                    Ok(())
+                }),
+                settings,
+            )),
+            settings,
+        ));
+
         category.elems.push(UIElement::new(
             "Iterations",
             "iterations",
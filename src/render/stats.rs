@@ -0,0 +1,257 @@
+use std::{
+    cell::Cell,
+    sync::Mutex,
+    time::{Duration, Instant}
+};
+
+use crate::model::scene::Scene;
+
+use super::settings::ViewMode;
+
+/**
+ * Work done by the raycasting. Every thread counts its own in COUNTERS, and the workers add them to the stats of the
+ * render after each tile.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Counters {
+    pub camera_rays: u64,
+    pub secondary_rays: u64,
+    pub shadow_rays: u64,
+    pub bvh_nodes: u64,
+    pub intersection_tests: u64,
+}
+
+#[derive(Clone, Copy)]
+pub enum Counter {
+    CameraRay,
+    SecondaryRay,
+    ShadowRay,
+    BvhNode,
+    IntersectionTest,
+}
+
+thread_local! {
+    static COUNTERS: Cell<Counters> = const { Cell::new(Counters::ZERO) };
+}
+
+impl Counters {
+    const ZERO: Counters = Counters { camera_rays: 0, secondary_rays: 0, shadow_rays: 0, bvh_nodes: 0, intersection_tests: 0 };
+
+    // Accessors
+    pub fn to_array(&self) -> [u64; 5] {
+        [self.camera_rays, self.secondary_rays, self.shadow_rays, self.bvh_nodes, self.intersection_tests]
+    }
+
+    // Constructor
+    pub fn from_array(values: [u64; 5]) -> Counters {
+        let [camera_rays, secondary_rays, shadow_rays, bvh_nodes, intersection_tests] = values;
+        Counters { camera_rays, secondary_rays, shadow_rays, bvh_nodes, intersection_tests }
+    }
+
+    // Methods
+    pub fn add(&mut self, other: &Counters) {
+        let mut values = self.to_array();
+        for (value, other) in values.iter_mut().zip(other.to_array()) {
+            *value += other;
+        }
+        *self = Counters::from_array(values);
+    }
+}
+
+pub fn count(counter: Counter) {
+    COUNTERS.with(|counters| {
+        let mut value = counters.get();
+        match counter {
+            Counter::CameraRay => value.camera_rays += 1,
+            Counter::SecondaryRay => value.secondary_rays += 1,
+            Counter::ShadowRay => value.shadow_rays += 1,
+            Counter::BvhNode => value.bvh_nodes += 1,
+            Counter::IntersectionTest => value.intersection_tests += 1,
+        }
+        counters.set(value);
+    });
+}
+
+/**
+ * What the current thread counted since the last call.
+ */
+pub fn take_counters() -> Counters {
+    COUNTERS.with(|counters| counters.replace(Counters::ZERO))
+}
+
+/**
+ * Stats of the render of a scene since its last change. The tiles of a previous version of the scene are not counted.
+ */
+#[derive(Debug)]
+pub struct RenderStats {
+    state: Mutex<StatsState>,
+}
+
+#[derive(Clone, Debug)]
+struct StatsState {
+    version: usize,
+    counters: Counters,
+    samples: u64,
+    iterations_done: usize,
+    // Iterations done before the start, when the render was resumed from a checkpoint
+    first_iteration: usize,
+    start: Instant,
+    last_tile: Instant,
+}
+
+impl RenderStats {
+    // Constructor
+    pub fn new() -> RenderStats {
+        RenderStats {
+            state: Mutex::new(StatsState {
+                version: 0,
+                counters: Counters::default(),
+                samples: 0,
+                iterations_done: 0,
+                first_iteration: 0,
+                start: Instant::now(),
+                last_tile: Instant::now(),
+            })
+        }
+    }
+
+    // Mutators
+    pub fn set_iterations_done(&self, iterations_done: usize) {
+        self.state.lock().unwrap().iterations_done = iterations_done;
+    }
+
+    // Methods
+    pub fn reset(&self, version: usize, iterations_done: usize) {
+        let mut state = self.state.lock().unwrap();
+        *state = RenderStats::new().state.into_inner().unwrap();
+        state.version = version;
+        state.iterations_done = iterations_done;
+        state.first_iteration = iterations_done;
+    }
+
+    pub fn add_tile(&self, version: usize, counters: &Counters, samples: usize) {
+        let mut state = self.state.lock().unwrap();
+        if state.version == version {
+            state.counters.add(counters);
+            state.samples += samples as u64;
+            state.last_tile = Instant::now();
+        }
+    }
+}
+
+impl Default for RenderStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/**
+ * Stats of a render, with what is known of its scene.
+ */
+#[derive(Clone, Debug)]
+pub struct StatsReport {
+    pub counters: Counters,
+    pub samples: u64,
+    // From the start of the render to its last tile
+    pub elapsed: Duration,
+    pub iterations_done: usize,
+    pub iterations: usize,
+    pub eta: Option<Duration>,
+    pub texture_memory: usize,
+    pub bvh_memory: usize,
+}
+
+impl StatsReport {
+    // Constructor
+    pub fn new(scene: &Scene) -> StatsReport {
+        let state = scene.stats().state.lock().unwrap().clone();
+        let iterations = match scene.settings().view_mode {
+            ViewMode::HighDef => scene.settings().iterations,
+            _ => 1,
+        };
+        let elapsed = state.last_tile.saturating_duration_since(state.start);
+        let done = state.iterations_done - state.first_iteration;
+        let eta = match done {
+            0 => None,
+            done => Some(elapsed.mul_f64(iterations.saturating_sub(state.iterations_done) as f64 / done as f64)),
+        };
+        StatsReport {
+            counters: state.counters,
+            samples: state.samples,
+            elapsed,
+            iterations_done: state.iterations_done,
+            iterations,
+            eta,
+            texture_memory: scene.texture_memory(),
+            bvh_memory: scene.bvh_memory(),
+        }
+    }
+
+    // Accessors
+    pub fn samples_per_second(&self) -> f64 {
+        match self.elapsed.as_secs_f64() {
+            elapsed if elapsed > 0. => self.samples as f64 / elapsed,
+            _ => 0.,
+        }
+    }
+
+    // Methods
+    pub fn to_json(&self) -> String {
+        let counters = &self.counters;
+        format!(
+            "{{\n\t\"rays\": {{\"camera\": {}, \"secondary\": {}, \"shadow\": {}}},\n\t\"bvh_nodes_visited\": {},\n\t\"intersection_tests\": {},\n\t\"samples\": {},\n\t\"samples_per_second\": {:.1},\n\t\"render_time\": {:.3},\n\t\"iterations_done\": {},\n\t\"iterations\": {},\n\t\"eta\": {},\n\t\"memory\": {{\"textures\": {}, \"bvh\": {}}}\n}}\n",
+            counters.camera_rays,
+            counters.secondary_rays,
+            counters.shadow_rays,
+            counters.bvh_nodes,
+            counters.intersection_tests,
+            self.samples,
+            self.samples_per_second(),
+            self.elapsed.as_secs_f64(),
+            self.iterations_done,
+            self.iterations,
+            self.eta.map_or("null".to_string(), |eta| format!("{:.3}", eta.as_secs_f64())),
+            self.texture_memory,
+            self.bvh_memory,
+        )
+    }
+}
+
+/**
+ * 1234567 -> "1.23M", for the stats shown in the UI.
+ */
+pub fn human_count(value: f64) -> String {
+    match value {
+        value if value >= 1e9 => format!("{:.2}G", value / 1e9),
+        value if value >= 1e6 => format!("{:.2}M", value / 1e6),
+        value if value >= 1e3 => format!("{:.2}k", value / 1e3),
+        value => format!("{:.0}", value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{model::scene::Scene, parsing::json::parse_json};
+    use super::{count, take_counters, Counter, Counters, StatsReport};
+
+    #[test]
+    fn test_counters() {
+        take_counters();
+        count(Counter::CameraRay);
+        count(Counter::ShadowRay);
+        count(Counter::ShadowRay);
+        let counters = take_counters();
+        assert_eq!(counters.to_array(), [1, 0, 2, 0, 0]);
+        assert_eq!(take_counters(), Counters::default());
+
+        let scene = Scene::new();
+        scene.stats().reset(3, 0);
+        scene.stats().add_tile(3, &counters, 64);
+        scene.stats().add_tile(2, &counters, 64);
+        scene.stats().set_iterations_done(1);
+        let report = StatsReport::new(&scene);
+        assert_eq!(report.counters, counters);
+        assert_eq!(report.samples, 64);
+        assert!(parse_json(report.to_json()).is_ok());
+    }
+}
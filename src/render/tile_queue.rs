@@ -1,13 +1,13 @@
-use std::{sync::{Arc, RwLock}, cmp::min};
+use std::cmp::min;
 
 use crate::{SCREEN_HEIGHT, SCREEN_WIDTH, TILE_SIZE};
 
-use super::{common::{QueueContext, Tile}, render_thread::SceneRender};
+use super::{common::{Tile, WorkQueues}, render_thread::SceneRender};
 
 
-pub fn fill_work_queue(render: &mut SceneRender, scene_queue_list: &Arc<RwLock<Vec<QueueContext>>>,simplification_factor: usize) {
+pub fn fill_work_queue(render: &mut SceneRender, scene_queue_list: &WorkQueues, simplification_factor: usize) {
     {
-        for queue_context in scene_queue_list.write().unwrap().iter_mut() {
+        for queue_context in scene_queue_list.lock().iter_mut() {
             if render.render_id == queue_context.render_id {
                 queue_context.queue.clear();
                 break;
@@ -17,7 +17,7 @@ pub fn fill_work_queue(render: &mut SceneRender, scene_queue_list: &Arc<RwLock<V
 
     render.low_res_to_do = generate_tiles_for(
         render,
-        &scene_queue_list,
+        scene_queue_list,
         TILE_SIZE,
         simplification_factor,
 
@@ -27,7 +27,7 @@ pub fn fill_work_queue(render: &mut SceneRender, scene_queue_list: &Arc<RwLock<V
 
 fn generate_tiles_for(
     render: &mut SceneRender,
-    queue: &Arc<RwLock<Vec<QueueContext>>>,
+    queue: &WorkQueues,
     size: usize,
     base_factor: usize
 ) -> u32 {
@@ -37,7 +37,7 @@ fn generate_tiles_for(
     // par la resolution la plus basse pour qu'elle soit render en premier.
     // On a beau tout envoyer d'un coup dans la queue, les worker_threads sont eveilles pendant ce temps
     // et prennent les ordres au fur et a mesure qu'ils sont ajoutes ce qui evite une perte de performance.
-    let mut queue_list = queue.write().unwrap();
+    let mut queue_list = queue.lock();
     let mut work_queue = None;
     for queue in queue_list.iter_mut() {
         if queue.render_id == render.render_id {
@@ -53,6 +53,10 @@ fn generate_tiles_for(
                 while y < SCREEN_HEIGHT {
                     let width = min(SCREEN_WIDTH - x, size);
                     let height = min(SCREEN_HEIGHT - y, size);
+                    if render.region.is_some_and(|region| !region.overlaps(x, y, width, height)) {
+                        y += size;
+                        continue;
+                    }
                     if factor == 1 {
                         cpt += 1;
                     }
@@ -74,5 +78,7 @@ fn generate_tiles_for(
             factor /= 2;
         }
     }
+    drop(queue_list);
+    queue.notify();
     cpt
 }
\ No newline at end of file
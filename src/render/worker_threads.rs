@@ -1,77 +1,117 @@
 use std::{
     sync::{
         mpsc::Sender,
-        Arc, RwLock,
-    }, time::Instant
+        Arc,
+    }, time::{Duration, Instant}
 };
 use crate::model::materials::color::Color;
 use super::{
-    common::{for_each_uncalculated_pixel, QueueContext, Tile}, raycasting::{get_lighting_from_ray, get_ray}
+    common::{for_each_uncalculated_pixel, QueueContext, Tile, WorkQueues}, raycasting::{get_lighting_from_ray, get_ray}, stats::{count, take_counters, Counter}
 };
 
-pub fn worker_thread (queue_context_list: Arc<RwLock<Vec<QueueContext>>>, cur_tx: Sender<(Tile, Vec<Color>)>) {
-    let mut scene_index = 0;
-    let mut tile_found: bool;
-    let mut time_for_current_scene = 0.;
-    let mut time_per_scene = 0.;
-    loop {
-        tile_found = false;
-        while let Some(tile) = {
-            get_next_tile(&mut scene_index, &queue_context_list)
-        } {
-            tile_found = true;
-            let time = Instant::now();
-            let mut colors = Vec::new();
-            {
-                let scene = tile.scene.read().unwrap();
-                // Pour chaque pixel de cette tile qui n'a pas deja ete calcule par une taille de plus haut factor
-                // Sur une tile 64x64 avec un factor de 64, il n'y aura qu'un ray de lance. Pour un factor de 32
-                // 4 rays seront lancers (4 x 32x32 = 64x64)
+pub fn worker_thread (work_queues: Arc<WorkQueues>, cur_tx: Sender<(Tile, Vec<Color>)>) {
+    let mut scheduler = TileScheduler::default();
+    while let Some(tile) = scheduler.next_tile(&work_queues, true) {
+        let time = Instant::now();
+        let colors = render_tile(&tile);
+        tile.scene.read().unwrap().stats().add_tile(tile.version, &take_counters(), colors.len());
+        cur_tx.send((tile, colors)).ok();
+        scheduler.tile_done(&work_queues, time.elapsed());
+    }
+}
 
-                for_each_uncalculated_pixel(&tile, |x, y| {
-                    // On calcule le ray et on le cast
-                    let ray = get_ray(&scene, x, y);
-                    colors.push(get_lighting_from_ray(&scene, &ray))
-                });
-            }
-            cur_tx.send((tile, colors)).ok();
+pub fn render_tile(tile: &Tile) -> Vec<Color> {
+    let mut colors = Vec::new();
+    let scene = tile.scene.read().unwrap();
+    // Pour chaque pixel de cette tile qui n'a pas deja ete calcule par une taille de plus haut factor
+    // Sur une tile 64x64 avec un factor de 64, il n'y aura qu'un ray de lance. Pour un factor de 32
+    // 4 rays seront lancers (4 x 32x32 = 64x64)
 
-            // Pour pouvoir diviser le temps de compute entre toutes les scenes ouvertes equitablement, meme lorsqu'une scene prend plus de temps qu'une autre,
-            // on doit mesurer le temps de render d'une tile pour chaque scene et render plusieurs tiles des scenes moins couteuse pour une seule des plus couteuses.
-            // Ca nous permet de naviguer facilement dans une scene simple quand une scene complexe se render en arriere plan (mais avec un
-            // temps de render plus eleve pour la scene complexe)
-            if let Some(queue_context) = queue_context_list.write().unwrap().get_mut(scene_index) {
-                queue_context.avg_time = (queue_context.avg_time * queue_context.tiles_done + time.elapsed().as_micros() as f64)/ (queue_context.tiles_done + 1.);
-                queue_context.tiles_done += 1.;
-                if queue_context.avg_time > time_per_scene {
-                    time_per_scene = queue_context.avg_time;
-                }
-                time_for_current_scene += time.elapsed().as_micros() as f64;
-                if time_for_current_scene > time_per_scene {
-                    scene_index += 1;
-                    time_for_current_scene = 0.;
-                }
+    for_each_uncalculated_pixel(tile, |x, y| {
+        // On calcule le ray et on le cast
+        let ray = get_ray(&scene, x, y);
+        count(Counter::CameraRay);
+        colors.push(get_lighting_from_ray(&scene, &ray))
+    });
+    colors
+}
+
+/**
+ * Chooses the tiles rendered by a worker, local or remote, among the queues of every scene.
+ */
+#[derive(Default)]
+pub struct TileScheduler {
+    scene_index: usize,
+    time_for_current_scene: f64,
+    time_per_scene: f64,
+}
+
+impl TileScheduler {
+    // Methods
+
+    /**
+     * Next tile of the current scene. When it has nothing to render, the tile is stolen from the scene that got the least
+     * render time since its last change, and when no scene has anything to render, the worker sleeps until there is.
+     * Returns None when a local worker must stop.
+     */
+    pub fn next_tile(&mut self, work_queues: &WorkQueues, local: bool) -> Option<Tile> {
+        let mut queue_context_list = work_queues.lock();
+        loop {
+            if local && work_queues.should_stop() {
+                return None;
             }
+            if let Some(tile) = self.take_tile(&mut queue_context_list) {
+                return Some(tile);
+            }
+            queue_context_list = work_queues.wait(queue_context_list);
         }
-        // thread::sleep(Duration::from_millis(1));
-        if !tile_found {
-            scene_index += 1;
-        }
-        // Lorsqu'il n'y a plus de travail de disponible pour le moment, on ne surcharge pas la queue avec des reads constants.
     }
-}
 
-fn get_next_tile(scene_index: &mut usize, queue_context_list: &Arc<RwLock<Vec<QueueContext>>>) -> Option<Tile> {
-    let mut queue_context_list = queue_context_list.write().unwrap();
-    if queue_context_list.len() == 0 {
-        return None;
+    /**
+     * Same as next_tile, but returns None instead of sleeping.
+     */
+    pub fn try_next_tile(&mut self, work_queues: &WorkQueues) -> Option<Tile> {
+        self.take_tile(&mut work_queues.lock())
     }
-    if *scene_index >= queue_context_list.len() {
-        *scene_index = 0;
+
+    fn take_tile(&mut self, queue_context_list: &mut [QueueContext]) -> Option<Tile> {
+        if self.scene_index >= queue_context_list.len() {
+            self.scene_index = 0;
+        }
+        let index = find_scene_with_work(self.scene_index, queue_context_list)?;
+        self.scene_index = index;
+        queue_context_list[index].queue.pop_front()
+    }
+
+    pub fn tile_done(&mut self, work_queues: &WorkQueues, elapsed: Duration) {
+        // Pour pouvoir diviser le temps de compute entre toutes les scenes ouvertes equitablement, meme lorsqu'une scene prend plus de temps qu'une autre,
+        // on doit mesurer le temps de render d'une tile pour chaque scene et render plusieurs tiles des scenes moins couteuse pour une seule des plus couteuses.
+        // Ca nous permet de naviguer facilement dans une scene simple quand une scene complexe se render en arriere plan (mais avec un
+        // temps de render plus eleve pour la scene complexe)
+        let elapsed = elapsed.as_micros() as f64;
+        if let Some(queue_context) = work_queues.lock().get_mut(self.scene_index) {
+            queue_context.avg_time = (queue_context.avg_time * queue_context.tiles_done + elapsed) / (queue_context.tiles_done + 1.);
+            queue_context.tiles_done += 1.;
+            if queue_context.avg_time > self.time_per_scene {
+                self.time_per_scene = queue_context.avg_time;
+            }
+            self.time_for_current_scene += elapsed;
+            if self.time_for_current_scene > self.time_per_scene {
+                self.scene_index += 1;
+                self.time_for_current_scene = 0.;
+            }
+        }
     }
-    let queue_context = &mut queue_context_list[*scene_index];
-    if !queue_context.active {
-        return None;
+}
+
+fn find_scene_with_work(scene_index: usize, queue_context_list: &[QueueContext]) -> Option<usize> {
+    let has_work = |queue_context: &QueueContext| queue_context.active && !queue_context.queue.is_empty();
+    if queue_context_list.get(scene_index).is_some_and(has_work) {
+        return Some(scene_index);
     }
-    queue_context.queue.remove(0)
-}
\ No newline at end of file
+    queue_context_list.iter()
+        .enumerate()
+        .filter(|(_, queue_context)| has_work(queue_context))
+        .min_by(|(_, a), (_, b)| (a.avg_time * a.tiles_done).total_cmp(&(b.avg_time * b.tiles_done)))
+        .map(|(index, _)| index)
+}
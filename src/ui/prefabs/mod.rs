@@ -2,4 +2,5 @@ pub mod vector_ui;
 pub mod file_ui;
 pub mod texture_ui;
 pub mod material_ui;
+pub mod transform_ui;
 pub mod shape_ui;
\ No newline at end of file
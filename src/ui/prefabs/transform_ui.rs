@@ -0,0 +1,92 @@
+use std::sync::{Arc, RwLock};
+use crate::{
+    model::{element::Element, group::Group, maths::{transform::Transform, vec3::Vec3}, scene::Scene},
+    ui::{
+        prefabs::vector_ui::get_vector_ui,
+        ui::UI,
+        uielement::{Category, UIElement},
+        utils::misc::{FnSubmitValue, ElemType, Value}
+    }
+};
+
+pub fn get_transform_ui(element: &Element, ui: &mut UI, scene: &Arc<RwLock<Scene>>) -> UIElement {
+    let id = element.id();
+    let transform = {
+        let scene = scene.read().unwrap();
+        match scene.composed_element_by_element_id(id) {
+            Some(composed_element) => composed_element.transform().cloned(),
+            None => element.transform().cloned(),
+        }
+    }.unwrap_or_default();
+    get_transform_category(&transform, ui, id, submit_transform)
+}
+
+/**
+ * The transform of a group is given in the space of its parent.
+ */
+pub fn get_group_transform_ui(group: &Group, ui: &mut UI) -> UIElement {
+    let transform = group.transform().cloned().unwrap_or_default();
+    get_transform_category(&transform, ui, group.id(), submit_group_transform)
+}
+
+type FnApplyTransform = fn(&mut Transform, f64);
+
+fn get_transform_category(transform: &Transform, ui: &mut UI, id: usize, submit: fn(usize, FnApplyTransform) -> FnSubmitValue) -> UIElement {
+    let mut category = UIElement::new("Transform", "transform", ElemType::Category(Category::default()), ui.uisettings());
+
+    category.add_element(get_vector_ui(*transform.translation(), "Translate", "translate", ui.uisettings(),
+        submit(id, |transform, value| transform.set_translation(Vec3::new(value, *transform.translation().y(), *transform.translation().z()))),
+        submit(id, |transform, value| transform.set_translation(Vec3::new(*transform.translation().x(), value, *transform.translation().z()))),
+        submit(id, |transform, value| transform.set_translation(Vec3::new(*transform.translation().x(), *transform.translation().y(), value))),
+        false, None, None));
+    category.add_element(get_vector_ui(*transform.rotation(), "Rotate", "rotate", ui.uisettings(),
+        submit(id, |transform, value| transform.set_rotation(Vec3::new(value, *transform.rotation().y(), *transform.rotation().z()))),
+        submit(id, |transform, value| transform.set_rotation(Vec3::new(*transform.rotation().x(), value, *transform.rotation().z()))),
+        submit(id, |transform, value| transform.set_rotation(Vec3::new(*transform.rotation().x(), *transform.rotation().y(), value))),
+        false, Some(-360.), Some(360.)));
+    category.add_element(get_vector_ui(*transform.scale(), "Scale", "scale", ui.uisettings(),
+        submit(id, |transform, value| transform.set_scale(Vec3::new(value, *transform.scale().y(), *transform.scale().z()))),
+        submit(id, |transform, value| transform.set_scale(Vec3::new(*transform.scale().x(), value, *transform.scale().z()))),
+        submit(id, |transform, value| transform.set_scale(Vec3::new(*transform.scale().x(), *transform.scale().y(), value))),
+        false, Some(0.001), None));
+
+    category
+}
+
+// The objects box has no edit bar, so the group is moved right away
+fn submit_group_transform(id: usize, apply: FnApplyTransform) -> FnSubmitValue {
+    Box::new(move |_, value, context, _| {
+        if let (Some(scene), Value::Float(value)) = (context.get_active_scene(), value) {
+            let mut scene = scene.write().unwrap();
+            let mut transform = scene.group_by_id(id).and_then(|group| group.transform().cloned()).unwrap_or_default();
+            apply(&mut transform, value);
+            scene.set_group_transform(id, Some(transform));
+            scene.set_dirty(true);
+        }
+    })
+}
+
+// The edit bar generates the elements of a composed element again with the same transform
+fn submit_transform(id: usize, apply: FnApplyTransform) -> FnSubmitValue {
+    Box::new(move |_, value, context, _| {
+        if let (Some(scene), Value::Float(value)) = (context.get_active_scene(), value) {
+            apply_transform(&mut scene.write().unwrap(), id, |transform| apply(transform, value));
+        }
+    })
+}
+
+/**
+ * Changes the transform of an element, or of its composed element which is moved with all its elements.
+ */
+pub fn apply_transform(scene: &mut Scene, id: usize, apply: impl FnOnce(&mut Transform)) {
+    if let Some(composed_element) = scene.composed_element_by_element_id(id) {
+        let composed_id = composed_element.id();
+        let mut transform = composed_element.transform().cloned().unwrap_or_default();
+        apply(&mut transform);
+        scene.set_composed_element_transform(composed_id, Some(transform));
+    } else if let Some(element) = scene.element_mut_by_id(id) {
+        let mut transform = element.transform().cloned().unwrap_or_default();
+        apply(&mut transform);
+        element.set_transform(Some(transform));
+    }
+}
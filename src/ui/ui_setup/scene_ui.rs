@@ -1,5 +1,5 @@
 use image::Rgba;
-use crate::{display::{mainloop::load_scene, ui_setup::{setup_objects_ui, setup_settings}}, render::render_thread::UIOrder, ui::{prefabs::file_ui::get_file_box, ui::UI, uibox::{BoxPosition, UIBox}, uielement::UIElement, uisettings::UISettings, utils::{misc::{ElemType, Value}, style::{Style, StyleBuilder}, ui_utils::UIContext}}, ELEMENT, MAX_OPENED_SCENES, OBJECTS, SCENE_FOLDER, SCENE_TOOLBAR, SCREEN_WIDTH_U32, SETTINGS, TOOLBAR};
+use crate::{display::{mainloop::load_scene, ui_setup::{setup_history_ui, setup_objects_ui, setup_settings, setup_stats_ui}}, render::render_thread::UIOrder, ui::{prefabs::file_ui::get_file_box, ui::UI, uibox::{BoxPosition, UIBox}, uielement::UIElement, uisettings::UISettings, utils::{misc::{ElemType, Value}, style::{Style, StyleBuilder}, ui_utils::UIContext}}, ELEMENT, MAX_OPENED_SCENES, OBJECTS, SCENE_FOLDER, SCENE_TOOLBAR, SCREEN_WIDTH_U32, SETTINGS, STATS, HISTORY, TOOLBAR};
 
 
 pub fn change_scene(context: &mut UIContext, ui: &mut UI, render_id: Option<usize>, element: Option<&mut UIElement>) {
@@ -19,6 +19,8 @@ pub fn change_scene(context: &mut UIContext, ui: &mut UI, render_id: Option<usiz
     ui.set_editing(None);
     ui.destroy_box(ELEMENT);
     ui.destroy_box(SETTINGS);
+    ui.destroy_box(STATS);
+    ui.destroy_box(HISTORY);
 
 
     if let Some(uibox) = ui.get_box_mut(TOOLBAR) {
@@ -134,6 +136,46 @@ pub fn setup_scene_options(ui: &mut UI, _context: &UIContext, _render_id: usize)
             }
     }))), ui.uisettings());
     
+    let btn_stats = UIElement::new("Statistics", STATS, ElemType::Button(Some(Box::new(
+        move |elem, _, ui| {
+            if let Some(elem) = elem {
+                let bg_color = match ui.get_box(STATS) {
+                    Some(_) => {
+                        ui.destroy_box(STATS);
+                        Rgba([200, 200, 200, 255])
+                    }
+                    None => {
+                        setup_stats_ui(ui);
+                        Rgba([100, 100, 100, 255])
+                    }
+                };
+                elem.set_style(StyleBuilder::from_existing(&elem.style, ui.uisettings())
+                    .bg_color(Some(bg_color))
+                    .build()
+                );
+            }
+    }))), ui.uisettings());
+
+    let btn_history = UIElement::new("History", HISTORY, ElemType::Button(Some(Box::new(
+        move |elem, context, ui| {
+            if let Some(elem) = elem {
+                let bg_color = match ui.get_box(HISTORY) {
+                    Some(_) => {
+                        ui.destroy_box(HISTORY);
+                        Rgba([200, 200, 200, 255])
+                    }
+                    None => {
+                        setup_history_ui(ui, context);
+                        Rgba([100, 100, 100, 255])
+                    }
+                };
+                elem.set_style(StyleBuilder::from_existing(&elem.style, ui.uisettings())
+                    .bg_color(Some(bg_color))
+                    .build()
+                );
+            }
+    }))), ui.uisettings());
+
     let row_reference = row.reference.clone();
     
     let btn_close = UIElement::new("Close", "close", ElemType::Button(Some(Box::new(
@@ -141,6 +183,8 @@ pub fn setup_scene_options(ui: &mut UI, _context: &UIContext, _render_id: usize)
             if let Some(elem) = elem {
                 let id = context.active_scene.unwrap();
                 context.scene_list.remove(&id);
+                context.regions.remove(&id);
+                context.scene_watches.remove(&id);
                 context.transmitter.send(UIOrder::CloseScene(id)).unwrap();
                 ui.remove_element_by_reference(format!("{}.{}.scene_{}",TOOLBAR, row_reference, id));
                 
@@ -168,6 +212,8 @@ pub fn setup_scene_options(ui: &mut UI, _context: &UIContext, _render_id: usize)
         
     row.add_element(btn_settings);
     row.add_element(btn_objects);
+    row.add_element(btn_stats);
+    row.add_element(btn_history);
     row.add_element(btn_close);
 
     toolbar_box.add_elements(vec![row]);
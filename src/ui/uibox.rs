@@ -1,5 +1,5 @@
 use crate::{
-    ui::uisettings::UISettings, SCREEN_HEIGHT_U32, SCREEN_WIDTH_U32
+    model::history::EditSubject, ui::uisettings::UISettings, SCREEN_HEIGHT_U32, SCREEN_WIDTH_U32
 };
 use super::{
     ui::UI,
@@ -50,6 +50,15 @@ impl UIBox {
         self.edit_bar = Some(UIEditBar::new(self.reference.clone(), settings, on_apply))
     }
 
+    /**
+     * The changes applied with the edit bar are added to the history of the active scene.
+     */
+    pub fn set_edit_subject(&mut self, subject: EditSubject) {
+        if let Some(edit_bar) = &mut self.edit_bar {
+            edit_bar.subject = Some(subject);
+        }
+    }
+
     pub fn validate_properties(
         &self,
         ui: &mut UI,
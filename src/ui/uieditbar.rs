@@ -1,5 +1,7 @@
 use image::RgbaImage;
 
+use crate::model::history::EditSubject;
+
 use super::{
     ui::UI,
     uisettings::UISettings,
@@ -18,6 +20,8 @@ pub struct UIEditBar {
     pub cancel: (String, Style, Option<HitBox>),
     pub on_apply: Option<FnAny>,
     pub reference: String,
+    // What the box edits in the active scene, to add the applied changes to its history
+    pub subject: Option<EditSubject>,
 }
 
 impl UIEditBar {
@@ -36,6 +40,11 @@ impl UIEditBar {
     pub fn apply(scene: &mut UIContext, ui: &mut UI, reference: String) {
         let mut properties_vec = vec![];
         if ui.validate_properties(reference.clone()) {
+            let subject = ui.get_box(&reference).and_then(|uibox| uibox.edit_bar.as_ref()).and_then(|edit_bar| edit_bar.subject);
+            let before = match (subject, scene.get_active_scene()) {
+                (Some(subject), Some(active_scene)) => active_scene.read().unwrap().snapshot(subject),
+                _ => None,
+            };
             let uibox = ui.get_box(&reference);
             if let Some(uibox) = uibox {
                 for elem in &uibox.elems {
@@ -64,6 +73,12 @@ impl UIEditBar {
                     }
                 }
             }
+            if let (Some(subject), Some(before), Some(active_scene)) = (subject, before, scene.get_active_scene()) {
+                let mut active_scene = active_scene.write().unwrap();
+                let after = active_scene.snapshot(subject);
+                let name = format!("Edit {}", subject.label(&active_scene));
+                active_scene.record(name, Some(before), after);
+            }
         }
     }
 
@@ -187,6 +202,7 @@ impl UIEditBar {
             text: (None, textformat, None),
             reference,
             on_apply,
+            subject: None,
         }
     }
 }
@@ -4,7 +4,7 @@ use std::{
     collections::HashMap, sync::{mpsc::{Receiver, Sender}, Arc, RwLock}, time::Instant
 };
 use crate::{
-    model::scene::Scene, render::render_thread::UIOrder, ui::{ui::UI, uielement::UIElement}, SCREEN_HEIGHT_U32, SCREEN_WIDTH_U32
+    display::{camera_controls::CameraControls, gizmo::Gizmo, hot_reload::SceneWatch, selection::Selection}, model::scene::Scene, render::{common::{default_nb_threads, Region}, render_thread::UIOrder}, ui::{ui::UI, uielement::UIElement}, SCREEN_HEIGHT_U32, SCREEN_WIDTH_U32
 };
 
 #[derive(Clone)]
@@ -31,6 +31,22 @@ pub struct UIContext {
     pub previous_active_scene: Option<usize>,
     pub next_scene_id: usize,
     pub scene_list: HashMap<usize, Arc<RwLock<Scene>>>,
+    pub nb_threads: usize,
+    // Region rendered alone in each scene, and the corners of the one being selected
+    pub regions: HashMap<usize, Region>,
+    pub region_drag: Option<((u32, u32), (u32, u32))>,
+    // Scene and version of the history shown in the history box
+    pub history_shown: Option<(usize, usize)>,
+    // Mode, snapping and drag of the gizmo of the selected element
+    pub gizmo: Gizmo,
+    // Selected elements and their outline
+    pub selection: Selection,
+    // Pivot of the camera and its drag with the mouse
+    pub camera_controls: CameraControls,
+    // Errors shown in the error box since it was closed
+    pub errors: Vec<String>,
+    // Files of each opened scene, to parse it again when they change
+    pub scene_watches: HashMap<usize, SceneWatch>,
 }
 
 impl UIContext {
@@ -51,7 +67,16 @@ impl UIContext {
             active_scene: None,
             previous_active_scene: None,
             next_scene_id: 0,
-            scene_list: HashMap::new()
+            scene_list: HashMap::new(),
+            nb_threads: default_nb_threads(),
+            regions: HashMap::new(),
+            region_drag: None,
+            history_shown: None,
+            gizmo: Gizmo::default(),
+            selection: Selection::default(),
+            camera_controls: CameraControls::default(),
+            errors: vec![],
+            scene_watches: HashMap::new(),
         }
     }
 
@@ -138,6 +163,10 @@ pub fn take_element(ui: &mut UI, reference: String) -> Option<(UIElement, String
 }
 
 pub fn give_back_element(ui: &mut UI, elem: UIElement, parent_ref: String, index: usize) {
+    // The click built the box of the element again, which already holds a new one
+    if ui.get_element(elem.reference.clone()).is_some() {
+        return;
+    }
     if !parent_ref.contains(".") {
         let uibox = ui.get_box_mut(&parent_ref);
         if let Some(uibox) = uibox {